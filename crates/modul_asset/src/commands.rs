@@ -0,0 +1,105 @@
+use crate::{AssetId, AssetIdAllocator, Assets};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// [SystemSet] within [Redraw](modul_core::Redraw) that applies the queued operations of all [AssetCommandQueues](AssetCommandQueue), it runs before [AssetLoadSet](crate::AssetLoadSet)
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApplyAssetCommandsSet;
+
+enum AssetCommand<T: Send + Sync + 'static> {
+    Replace(AssetId<T>, T),
+    Remove(AssetId<T>),
+    Modify(AssetId<T>, Box<dyn FnOnce(&mut T) + Send + Sync>),
+}
+
+/// Queues changes to an [Assets] using a shared reference, they are applied during [ApplyAssetCommandsSet].
+/// In systems use [AssetCommands], otherwise get it using `world.resource::<AssetCommandQueue<T>>()`.
+#[derive(Resource)]
+pub struct AssetCommandQueue<T: Send + Sync + 'static> {
+    allocator: AssetIdAllocator<T>,
+    commands: Mutex<Vec<AssetCommand<T>>>,
+}
+
+impl<T: Send + Sync + 'static> AssetCommandQueue<T> {
+    pub fn new(assets: &Assets<T>) -> Self {
+        Self {
+            allocator: assets.allocator(),
+            commands: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the id the asset will be put at, it is empty until the commands are applied
+    pub fn add(&self, asset: T) -> AssetId<T> {
+        let id = self.allocator.reserve();
+        self.push(AssetCommand::Replace(id, asset));
+        id
+    }
+
+    /// Queues [Assets::replace]
+    pub fn replace(&self, asset_id: AssetId<T>, asset: T) {
+        self.push(AssetCommand::Replace(asset_id, asset));
+    }
+
+    /// Queues [Assets::remove]
+    pub fn remove(&self, asset_id: AssetId<T>) {
+        self.push(AssetCommand::Remove(asset_id));
+    }
+
+    /// Queues a function to run on the asset, it is not run if the asset does not exist when applying
+    pub fn modify(&self, asset_id: AssetId<T>, f: impl FnOnce(&mut T) + Send + Sync + 'static) {
+        self.push(AssetCommand::Modify(asset_id, Box::new(f)));
+    }
+
+    /// Applies all queued commands in the order they were queued
+    pub fn apply(&mut self, assets: &mut Assets<T>) {
+        let commands = self
+            .commands
+            .get_mut()
+            .expect("asset command lock poisoned");
+        for command in commands.drain(..) {
+            match command {
+                AssetCommand::Replace(id, asset) => {
+                    assets.replace(id, asset);
+                }
+                AssetCommand::Remove(id) => {
+                    assets.remove(id);
+                }
+                AssetCommand::Modify(id, f) => {
+                    if let Some(asset) = assets.get_mut(id) {
+                        f(asset);
+                    }
+                }
+            }
+        }
+    }
+
+    fn push(&self, command: AssetCommand<T>) {
+        self.commands
+            .lock()
+            .expect("asset command lock poisoned")
+            .push(command);
+    }
+}
+
+/// [SystemParam] for queueing changes to an [Assets] without mutable access, see [AssetCommandQueue]
+#[derive(SystemParam)]
+pub struct AssetCommands<'w, T: Send + Sync + 'static> {
+    queue: Res<'w, AssetCommandQueue<T>>,
+}
+
+impl<T: Send + Sync + 'static> Deref for AssetCommands<'_, T> {
+    type Target = AssetCommandQueue<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.queue
+    }
+}
+
+pub(crate) fn apply_asset_commands<T: Send + Sync + 'static>(
+    mut queue: ResMut<AssetCommandQueue<T>>,
+    mut assets: ResMut<Assets<T>>,
+) {
+    queue.apply(&mut assets);
+}
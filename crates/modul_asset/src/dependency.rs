@@ -0,0 +1,168 @@
+use crate::{AssetEvent, AssetId};
+use bevy_ecs::prelude::*;
+use modul_util::{HashMap, HashSet};
+use std::any::TypeId;
+
+/// An [AssetId] with its type erased, used to refer to assets of different types together
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct UntypedAssetId {
+    type_id: TypeId,
+    index: usize,
+    generation: u32,
+}
+
+impl UntypedAssetId {
+    /// The [TypeId] of the asset type
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Returns the typed id if the asset type is T
+    pub fn typed<T: Send + Sync + 'static>(&self) -> Option<AssetId<T>> {
+        (self.type_id == TypeId::of::<T>()).then(|| AssetId::new(self.index, self.generation))
+    }
+}
+
+impl<T: Send + Sync + 'static> From<AssetId<T>> for UntypedAssetId {
+    fn from(value: AssetId<T>) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            index: value.0,
+            generation: value.1,
+        }
+    }
+}
+
+/// Writes [AssetEvent::DependencyModified] for an asset, registered per dependent type
+type DependentNotifier = fn(&mut World, UntypedAssetId);
+
+/// Tracks which assets are built from other assets.
+/// When an asset changes (it is reloaded by the [AssetServer](crate::AssetServer), or [mark_asset_changed](crate::AssetWorldExt::mark_asset_changed) is called)
+/// an [AssetEvent::DependencyModified] is written for every asset depending on it, directly or through other assets.
+#[derive(Resource, Default)]
+pub struct AssetDependencies {
+    dependents: HashMap<UntypedAssetId, HashSet<UntypedAssetId>>,
+    // used for clearing the dependencies of an asset
+    dependencies: HashMap<UntypedAssetId, HashSet<UntypedAssetId>>,
+    notifiers: HashMap<TypeId, DependentNotifier>,
+}
+
+impl AssetDependencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `dependent` depend on `dependency`
+    pub fn add_dependency<T: Send + Sync + 'static>(
+        &mut self,
+        dependent: AssetId<T>,
+        dependency: impl Into<UntypedAssetId>,
+    ) {
+        self.notifiers
+            .insert(TypeId::of::<T>(), notify_dependent::<T>);
+        let dependent = dependent.into();
+        let dependency = dependency.into();
+        self.dependents
+            .entry(dependency)
+            .or_default()
+            .insert(dependent);
+        self.dependencies
+            .entry(dependent)
+            .or_default()
+            .insert(dependency);
+    }
+
+    /// Makes `dependent` depend on all of the given assets
+    pub fn add_dependencies<T: Send + Sync + 'static>(
+        &mut self,
+        dependent: AssetId<T>,
+        dependencies: impl IntoIterator<Item = UntypedAssetId>,
+    ) {
+        for dependency in dependencies {
+            self.add_dependency(dependent, dependency);
+        }
+    }
+
+    /// Removes all dependencies of an asset, this should be done when it is removed or rebuilt from other assets
+    pub fn clear_dependencies(&mut self, dependent: impl Into<UntypedAssetId>) {
+        let dependent = dependent.into();
+        let Some(dependencies) = self.dependencies.remove(&dependent) else {
+            return;
+        };
+        for dependency in dependencies {
+            if let Some(dependents) = self.dependents.get_mut(&dependency) {
+                dependents.remove(&dependent);
+                if dependents.is_empty() {
+                    self.dependents.remove(&dependency);
+                }
+            }
+        }
+    }
+
+    /// The assets directly depending on an asset
+    pub fn dependents(
+        &self,
+        id: impl Into<UntypedAssetId>,
+    ) -> impl Iterator<Item = UntypedAssetId> + '_ {
+        self.dependents
+            .get(&id.into())
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// The assets an asset directly depends on
+    pub fn dependencies(
+        &self,
+        id: impl Into<UntypedAssetId>,
+    ) -> impl Iterator<Item = UntypedAssetId> + '_ {
+        self.dependencies
+            .get(&id.into())
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// All assets depending on an asset, directly or through other assets, each one is only returned once
+    pub fn all_dependents(&self, id: impl Into<UntypedAssetId>) -> Vec<UntypedAssetId> {
+        let id = id.into();
+        let mut visited = HashSet::new();
+        visited.insert(id);
+        let mut result = Vec::new();
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            for dependent in self.dependents(current) {
+                // cycles would otherwise never end
+                if visited.insert(dependent) {
+                    result.push(dependent);
+                    stack.push(dependent);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Writes [AssetEvent::DependencyModified] for everything depending on the asset
+pub(crate) fn propagate_asset_change(world: &mut World, id: UntypedAssetId) {
+    let Some(dependencies) = world.get_resource::<AssetDependencies>() else {
+        return;
+    };
+    let notify: Vec<_> = dependencies
+        .all_dependents(id)
+        .into_iter()
+        .filter_map(|d| Some((*dependencies.notifiers.get(&d.type_id)?, d)))
+        .collect();
+    for (notifier, dependent) in notify {
+        notifier(world, dependent);
+    }
+}
+
+fn notify_dependent<T: Send + Sync + 'static>(world: &mut World, id: UntypedAssetId) {
+    let Some(id) = id.typed::<T>() else {
+        return;
+    };
+    if let Some(mut events) = world.get_resource_mut::<Messages<AssetEvent<T>>>() {
+        events.write(AssetEvent::DependencyModified(id));
+    }
+}
@@ -1,27 +1,88 @@
+mod commands;
+mod dependency;
+mod server;
+
 use bevy_app::App;
 use bevy_ecs::prelude::*;
+use log::warn;
+use modul_core::Redraw;
 use modul_util::HashMap;
+use std::fmt::{self, Debug, Formatter};
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub use commands::*;
+pub use dependency::*;
+pub use server::*;
+
+/// Shared access to an asset, returned by [Assets::get]
+pub type AssetReadGuard<'a, T> = RwLockReadGuard<'a, T>;
+
+/// Exclusive access to an asset, returned by [Assets::write]
+pub type AssetWriteGuard<'a, T> = RwLockWriteGuard<'a, T>;
 
+/// Every asset is behind its own lock, so systems only needing [Res<Assets<T>>](Res) can run in parallel while accessing different assets.
+/// Getting two guards for the same asset on the same thread will deadlock if one of them is a [write](Assets::write) guard.
+/// Assets are stored in a vec of slots indexed by the [AssetId], slots freed by [remove](Assets::remove) are reused by [add](Assets::add).
 #[derive(Resource)]
 pub struct Assets<T> {
-    next: usize,
-    // FIXME maybe use RwLock per asset instead of map of assets...
-    assets: HashMap<usize, T>,
+    next: Arc<AtomicUsize>,
+    // indexed by the slot index of the ids, reserved ids that were never filled may be past the end
+    slots: Vec<Slot<T>>,
+    // may contain slots that have been filled again since, they are skipped when reusing, every index is in it at most once
+    free: Vec<usize>,
+    len: usize,
+    labels: HashMap<String, usize>,
+    // used for getting the label of an id, and removing old labels
+    inverse_labels: HashMap<usize, String>,
+}
+
+struct Slot<T> {
+    generation: u32,
+    // the highest generation the slot had, removing continues after it so old generations are never current again
+    max_generation: u32,
+    // the generation add last put an asset in the slot with, older ids can not refill it
+    added_generation: u32,
+    asset: Option<RwLock<T>>,
+    // set while the index is in the free list of the assets
+    free: bool,
+}
+
+impl<T> Slot<T> {
+    fn empty() -> Self {
+        Self {
+            generation: 0,
+            max_generation: 0,
+            added_generation: 0,
+            asset: None,
+            free: false,
+        }
+    }
+
+    // makes the ids of the current generation stale
+    fn next_generation(&mut self) {
+        self.max_generation += 1;
+        self.generation = self.max_generation;
+    }
 }
 
-pub struct AssetId<T: Send + Sync + 'static>(usize, PhantomData<T>);
+/// A handle to an asset, consisting of a slot index and a generation.
+/// The generation of a slot is increased when its asset is removed, so ids to removed assets are stale and will not find assets put in the slot later.
+pub struct AssetId<T: Send + Sync + 'static>(usize, u32, PhantomData<T>);
 
 impl<T: Send + Sync + 'static> Hash for AssetId<T> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.0.hash(state);
+        self.1.hash(state);
     }
 }
 
 impl<T: Send + Sync + 'static> PartialEq for AssetId<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.0 == other.0 && self.1 == other.1
     }
 }
 
@@ -35,49 +96,308 @@ impl<T: Send + Sync + 'static> Clone for AssetId<T> {
 
 impl<T: Send + Sync + 'static> Copy for AssetId<T> {}
 
+impl<T: Send + Sync + 'static> Debug for AssetId<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "AssetId({}, {})", self.0, self.1)
+    }
+}
+
+impl<T: Send + Sync + 'static> AssetId<T> {
+    pub(crate) fn new(index: usize, generation: u32) -> Self {
+        Self(index, generation, PhantomData)
+    }
+
+    /// The generation of the id, see [AssetId]
+    pub fn generation(&self) -> u32 {
+        self.1
+    }
+}
+
+/// Reserves empty [AssetIds](AssetId) for an [Assets] without access to it, this can be cloned and sent to other threads.
+/// Get it using [Assets::allocator].
+pub struct AssetIdAllocator<T: Send + Sync + 'static> {
+    next: Arc<AtomicUsize>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Send + Sync + 'static> Clone for AssetIdAllocator<T> {
+    fn clone(&self) -> Self {
+        Self {
+            next: self.next.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> AssetIdAllocator<T> {
+    /// Returns an empty [AssetId], it can be filled later using [Assets::replace]
+    pub fn reserve(&self) -> AssetId<T> {
+        AssetId::new(self.next.fetch_add(1, Ordering::Relaxed), 0)
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for Assets<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Send + Sync + 'static> Assets<T> {
     pub fn new() -> Self {
         Self {
-            next: 0,
-            assets: HashMap::new(),
+            next: Arc::new(AtomicUsize::new(0)),
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            labels: HashMap::new(),
+            inverse_labels: HashMap::new(),
         }
     }
 
     /// Returns an empty [AssetId]
-    pub fn add_empty(&mut self) -> AssetId<T> {
-        self.next += 1;
-        AssetId(self.next - 1, PhantomData)
+    pub fn add_empty(&self) -> AssetId<T> {
+        AssetId::new(self.next.fetch_add(1, Ordering::Relaxed), 0)
     }
 
-    /// Adds an asset and returns its id
+    /// An [AssetIdAllocator] sharing the id counter of this [Assets]
+    pub fn allocator(&self) -> AssetIdAllocator<T> {
+        AssetIdAllocator {
+            next: self.next.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Adds an asset and returns its id, reusing the slot of a removed asset if there is one
     pub fn add(&mut self, asset: T) -> AssetId<T> {
-        let id = self.add_empty();
-        self.replace(id, asset);
+        let id = match self.pop_free() {
+            Some(index) => self.current_id(index),
+            None => self.add_empty(),
+        };
+        self.fill(id, asset);
+        self.slots[id.0].added_generation = id.1;
         id
     }
 
+    /// Adds an asset with a label, see [set_label](Self::set_label)
+    pub fn add_labeled(&mut self, label: impl Into<String>, asset: T) -> AssetId<T> {
+        let id = self.add(asset);
+        self.set_label(id, label);
+        id
+    }
+
+    /// Gives the slot of the id a label, so it can be found using [get_by_label](Self::get_by_label).
+    /// The slot loses its old label, and if another slot had the label it is taken from it.
+    /// Labels stay when an asset is removed, [id_by_label](Self::id_by_label) will then return an id of the new generation that can be filled using [replace](Self::replace).
+    pub fn set_label(&mut self, asset_id: AssetId<T>, label: impl Into<String>) {
+        let label = label.into();
+        if let Some(old) = self.inverse_labels.remove(&asset_id.0) {
+            self.labels.remove(&old);
+        }
+        if let Some(old_id) = self.labels.insert(label.clone(), asset_id.0) {
+            self.inverse_labels.remove(&old_id);
+        }
+        self.inverse_labels.insert(asset_id.0, label);
+    }
+
+    /// The label of an id, if it has one
+    pub fn label(&self, asset_id: AssetId<T>) -> Option<&str> {
+        self.inverse_labels.get(&asset_id.0).map(String::as_str)
+    }
+
+    /// The current id with the given label
+    pub fn id_by_label(&self, label: &str) -> Option<AssetId<T>> {
+        self.labels.get(label).map(|i| self.current_id(*i))
+    }
+
+    /// Immutably gets the asset with the given label, see [get](Self::get)
+    pub fn get_by_label(&self, label: &str) -> Option<AssetReadGuard<'_, T>> {
+        self.get(self.id_by_label(label)?)
+    }
+
+    /// Returns false if the id is empty or stale
     pub fn contains(&self, id: &AssetId<T>) -> bool {
-        self.assets.contains_key(&id.0)
+        self.asset(*id).is_some()
+    }
+
+    /// Returns true if the asset of the id has been removed, see [AssetId]
+    pub fn is_stale(&self, id: AssetId<T>) -> bool {
+        !self.is_current(id)
+    }
+
+    /// Immutably gets an asset from an id, blocks while the asset is being written to.
+    /// Returns None if the id is stale.
+    pub fn get(&self, asset_id: AssetId<T>) -> Option<AssetReadGuard<'_, T>> {
+        self.asset(asset_id)
+            .map(|a| a.read().expect("asset lock poisoned"))
     }
 
-    /// Immutably gets an asset from an id
-    pub fn get(&self, asset_id: AssetId<T>) -> Option<&T> {
-        self.assets.get(&asset_id.0)
+    /// Mutably gets an asset from a shared reference, blocks while the asset is being read or written to.
+    /// Returns None if the id is stale.
+    pub fn write(&self, asset_id: AssetId<T>) -> Option<AssetWriteGuard<'_, T>> {
+        self.asset(asset_id)
+            .map(|a| a.write().expect("asset lock poisoned"))
     }
 
-    /// Mutably gets an asset from an id
+    /// Mutably gets an asset from an id, this does not lock as access is already exclusive.
+    /// Returns None if the id is stale.
     pub fn get_mut(&mut self, asset_id: AssetId<T>) -> Option<&mut T> {
-        self.assets.get_mut(&asset_id.0)
+        self.slots
+            .get_mut(asset_id.0)
+            .filter(|slot| slot.generation == asset_id.1)
+            .and_then(|slot| slot.asset.as_mut())
+            .map(|a| a.get_mut().expect("asset lock poisoned"))
     }
 
-    /// Puts a new value in an asset, all AssetIds pointing to the old asset will now point to the new asset
+    /// Puts a new value in an asset, all AssetIds pointing to the old asset will now point to the new asset.
+    /// If the id is stale the asset is rejected and dropped, use [replace_in_place](Self::replace_in_place) to fill the slot of a removed asset.
     pub fn replace(&mut self, asset_id: AssetId<T>, asset: T) -> Option<T> {
-        self.assets.insert(asset_id.0, asset)
+        if !self.is_current(asset_id) {
+            return None;
+        }
+        self.fill(asset_id, asset)
+    }
+
+    /// Like [replace](Self::replace), but also accepts stale ids, returning the old asset if there was one.
+    /// The generation of the slot is set to that of the id, so ids from before the asset was removed will point to the new asset.
+    /// If the slot has been reused by [add](Self::add) or filled with a newer id since, the asset is handed back as the error, use [take](Self::take) to keep the slot.
+    pub fn replace_in_place(&mut self, asset_id: AssetId<T>, asset: T) -> Result<Option<T>, T> {
+        if let Some(slot) = self.slots.get(asset_id.0) {
+            let newer = slot.asset.is_some() && slot.generation > asset_id.1;
+            if newer || slot.added_generation > asset_id.1 {
+                return Err(asset);
+            }
+        }
+        Ok(self.fill(asset_id, asset))
     }
 
-    /// Removes an asset leaving None in its place, a new asset can be put in its place using replace
+    fn fill(&mut self, asset_id: AssetId<T>, asset: T) -> Option<T> {
+        if asset_id.0 >= self.slots.len() {
+            self.slots.resize_with(asset_id.0 + 1, Slot::empty);
+        }
+        let slot = &mut self.slots[asset_id.0];
+        slot.generation = asset_id.1;
+        slot.max_generation = slot.max_generation.max(asset_id.1);
+        let old = slot.asset.replace(RwLock::new(asset));
+        if old.is_none() {
+            self.len += 1;
+        }
+        old.map(|a| a.into_inner().expect("asset lock poisoned"))
+    }
+
+    /// Removes an asset leaving None in its place, this makes all ids to the asset stale.
+    /// Unless it has a label, the slot can be reused by [add](Self::add), until then a new asset can be put in it using [replace_in_place](Self::replace_in_place).
     pub fn remove(&mut self, asset_id: AssetId<T>) -> Option<T> {
-        self.assets.remove(&asset_id.0)
+        let asset = self.take(asset_id)?;
+        self.free_slot(asset_id.0);
+        Some(asset)
+    }
+
+    /// Like [remove](Self::remove), but the slot is not reused, so the asset can always be put back with [replace_in_place](Self::replace_in_place).
+    pub fn take(&mut self, asset_id: AssetId<T>) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(asset_id.0)
+            .filter(|slot| slot.generation == asset_id.1)?;
+        let asset = slot.asset.take()?;
+        slot.next_generation();
+        self.len -= 1;
+        Some(asset.into_inner().expect("asset lock poisoned"))
+    }
+
+    /// The amount of assets, empty ids are not counted
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The ids of all assets, empty ids are skipped, ordered by slot index
+    pub fn ids(&self) -> impl Iterator<Item = AssetId<T>> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.asset.is_some())
+            .map(|(i, slot)| AssetId::new(i, slot.generation))
+    }
+
+    /// Iterates over all assets, locking each one while it is yielded, ordered by slot index
+    pub fn iter(&self) -> impl Iterator<Item = (AssetId<T>, AssetReadGuard<'_, T>)> {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            let asset = slot.asset.as_ref()?;
+            Some((
+                AssetId::new(i, slot.generation),
+                asset.read().expect("asset lock poisoned"),
+            ))
+        })
+    }
+
+    /// Mutably iterates over all assets, ordered by slot index
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (AssetId<T>, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(i, slot)| {
+            let asset = slot.asset.as_mut()?;
+            Some((
+                AssetId::new(i, slot.generation),
+                asset.get_mut().expect("asset lock poisoned"),
+            ))
+        })
+    }
+
+    /// Removes all assets for which the function returns false, leaving their ids empty and stale
+    pub fn retain(&mut self, mut f: impl FnMut(AssetId<T>, &mut T) -> bool) {
+        for i in 0..self.slots.len() {
+            let slot = &mut self.slots[i];
+            let Some(asset) = &mut slot.asset else {
+                continue;
+            };
+            let id = AssetId::new(i, slot.generation);
+            if !f(id, asset.get_mut().expect("asset lock poisoned")) {
+                slot.asset = None;
+                slot.next_generation();
+                self.len -= 1;
+                self.free_slot(i);
+            }
+        }
+    }
+
+    fn asset(&self, asset_id: AssetId<T>) -> Option<&RwLock<T>> {
+        self.slots
+            .get(asset_id.0)
+            .filter(|slot| slot.generation == asset_id.1)?
+            .asset
+            .as_ref()
+    }
+
+    // labeled slots are kept, so the label does not move to an unrelated asset
+    fn free_slot(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        if !slot.free && !self.inverse_labels.contains_key(&index) {
+            slot.free = true;
+            self.free.push(index);
+        }
+    }
+
+    fn pop_free(&mut self) -> Option<usize> {
+        while let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index];
+            slot.free = false;
+            if slot.asset.is_none() && !self.inverse_labels.contains_key(&index) {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn current_id(&self, index: usize) -> AssetId<T> {
+        AssetId::new(
+            index,
+            self.slots.get(index).map_or(0, |slot| slot.generation),
+        )
+    }
+
+    fn is_current(&self, id: AssetId<T>) -> bool {
+        self.current_id(id.0).1 == id.1
     }
 }
 
@@ -93,16 +413,22 @@ pub trait AssetWorldExt {
     fn has_asset<T: Send + Sync + 'static>(&self, asset: AssetId<T>) -> bool;
 
     /// Gets an asset from an id
-    fn get_asset<T: Send + Sync + 'static>(&self, asset_id: AssetId<T>) -> Option<&T>;
+    fn get_asset<T: Send + Sync + 'static>(
+        &self,
+        asset_id: AssetId<T>,
+    ) -> Option<AssetReadGuard<'_, T>>;
 
     /// Gets an asset from an id
-    fn get_asset_mut<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Option<Mut<T>>;
+    fn get_asset_mut<T: Send + Sync + 'static>(
+        &mut self,
+        asset_id: AssetId<T>,
+    ) -> Option<Mut<'_, T>>;
 
     /// gets and unwraps the given asset id
-    fn asset<T: Send + Sync + 'static>(&self, asset_id: AssetId<T>) -> &T;
+    fn asset<T: Send + Sync + 'static>(&self, asset_id: AssetId<T>) -> AssetReadGuard<'_, T>;
 
     /// get and unwraps the given asset id mutably
-    fn asset_mut<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Mut<T>;
+    fn asset_mut<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Mut<'_, T>;
 
     /// Gets an asset from an id and runs a function on it, if the asset is not found the function is not run
     fn with_asset<T: Send + Sync + 'static, F: FnOnce(&mut T)>(
@@ -124,12 +450,17 @@ pub trait AssetWorldExt {
     ) -> Option<T>;
     /// Removes an asset using [Assets::remove]
     fn remove_asset<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Option<T>;
+    /// Loads an asset using [AssetServer::load]
+    fn load_asset<T: Send + Sync + 'static>(&mut self, path: impl AsRef<Path>) -> AssetId<T>;
+    /// Writes [AssetEvent::DependencyModified] for all assets depending on the asset, see [AssetDependencies].
+    /// This should be called after changing an asset other assets are built from.
+    fn mark_asset_changed<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>);
 }
 
 impl AssetWorldExt for World {
     #[inline]
     fn add_empty_asset<T: Send + Sync + 'static>(&mut self) -> AssetId<T> {
-        self.resource_mut::<Assets<T>>().add_empty()
+        self.resource::<Assets<T>>().add_empty()
     }
 
     #[inline]
@@ -143,12 +474,18 @@ impl AssetWorldExt for World {
     }
 
     #[inline]
-    fn get_asset<T: Send + Sync + 'static>(&self, asset_id: AssetId<T>) -> Option<&T> {
+    fn get_asset<T: Send + Sync + 'static>(
+        &self,
+        asset_id: AssetId<T>,
+    ) -> Option<AssetReadGuard<'_, T>> {
         self.get_resource::<Assets<T>>()?.get(asset_id)
     }
 
     #[inline]
-    fn get_asset_mut<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Option<Mut<T>> {
+    fn get_asset_mut<T: Send + Sync + 'static>(
+        &mut self,
+        asset_id: AssetId<T>,
+    ) -> Option<Mut<'_, T>> {
         if self.has_asset(asset_id) {
             Some(
                 self.resource_mut::<Assets<T>>()
@@ -160,12 +497,12 @@ impl AssetWorldExt for World {
     }
 
     #[inline]
-    fn asset<T: Send + Sync + 'static>(&self, asset_id: AssetId<T>) -> &T {
+    fn asset<T: Send + Sync + 'static>(&self, asset_id: AssetId<T>) -> AssetReadGuard<'_, T> {
         self.get_asset(asset_id).unwrap()
     }
 
     #[inline]
-    fn asset_mut<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Mut<T> {
+    fn asset_mut<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Mut<'_, T> {
         self.get_asset_mut(asset_id).unwrap()
     }
 
@@ -185,12 +522,24 @@ impl AssetWorldExt for World {
         asset_id: AssetId<T>,
         f: F,
     ) {
-        let mut assset = match self.remove_asset(asset_id) {
+        let taken = self
+            .get_resource_mut::<Assets<T>>()
+            .and_then(|mut assets| assets.take(asset_id));
+        let mut assset = match taken {
             Some(a) => a,
             None => return,
         };
         f(self, &mut assset);
-        self.resource_mut::<Assets<T>>().replace(asset_id, assset);
+        // the id became stale when taking
+        let result = self
+            .resource_mut::<Assets<T>>()
+            .replace_in_place(asset_id, assset);
+        if result.is_err() {
+            warn!(
+                "{:?} was filled again during asset_scope, dropping the scoped asset",
+                asset_id
+            );
+        }
     }
 
     #[inline]
@@ -207,15 +556,45 @@ impl AssetWorldExt for World {
     fn remove_asset<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) -> Option<T> {
         self.get_resource_mut::<Assets<T>>()?.remove(asset_id)
     }
+
+    #[inline]
+    fn load_asset<T: Send + Sync + 'static>(&mut self, path: impl AsRef<Path>) -> AssetId<T> {
+        self.resource_scope(|world, mut server: Mut<AssetServer>| {
+            server.load(world.resource::<Assets<T>>(), path)
+        })
+    }
+
+    #[inline]
+    fn mark_asset_changed<T: Send + Sync + 'static>(&mut self, asset_id: AssetId<T>) {
+        propagate_asset_change(self, asset_id.into());
+    }
 }
 
 pub trait AssetAppExt {
     fn init_assets<T: Send + Sync + 'static>(&mut self);
+    /// Registers a loader on the [AssetServer], [AssetServerPlugin] must be added first
+    fn register_asset_loader(&mut self, loader: impl AssetLoader);
 }
 
 impl AssetAppExt for App {
     #[inline]
     fn init_assets<T: Send + Sync + 'static>(&mut self) {
-        self.world_mut().insert_resource(Assets::<T>::new());
+        let assets = Assets::<T>::new();
+        self.insert_resource(AssetCommandQueue::new(&assets));
+        self.insert_resource(assets);
+        self.add_message::<AssetEvent<T>>();
+        self.init_resource::<AssetDependencies>();
+        self.add_systems(
+            Redraw,
+            apply_asset_commands::<T>.in_set(ApplyAssetCommandsSet),
+        );
+        self.configure_sets(Redraw, ApplyAssetCommandsSet.before(AssetLoadSet));
+    }
+
+    #[inline]
+    fn register_asset_loader(&mut self, loader: impl AssetLoader) {
+        self.world_mut()
+            .resource_mut::<AssetServer>()
+            .register_loader(loader);
     }
 }
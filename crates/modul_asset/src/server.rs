@@ -0,0 +1,436 @@
+use crate::{propagate_asset_change, AssetId, Assets, UntypedAssetId};
+use async_channel::{Receiver, Sender};
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use bevy_tasks::{IoTaskPool, TaskPool};
+use log::warn;
+use modul_core::{profile_span, Redraw};
+use modul_util::{HashMap, HashSet};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, io};
+
+/// [SystemSet] within [Redraw] that moves finished loads from the [AssetServer] into their [Assets]
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AssetLoadSet;
+
+/// Adds the [AssetServer] resource and the system applying finished loads
+pub struct AssetServerPlugin {
+    /// Directory that paths passed to [AssetServer::load] are relative to
+    pub root: PathBuf,
+    /// If true, assets are reloaded when their file changes, see [AssetServer::watch_for_changes]
+    pub watch_for_changes: bool,
+}
+
+impl Default for AssetServerPlugin {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("assets"),
+            watch_for_changes: false,
+        }
+    }
+}
+
+impl Plugin for AssetServerPlugin {
+    fn build(&self, app: &mut App) {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let mut server = AssetServer::new(self.root.clone());
+        if self.watch_for_changes {
+            if let Err(e) = server.watch_for_changes() {
+                warn!("Failed to watch asset directory: {}", e);
+            }
+        }
+        app.insert_resource(server);
+        app.add_systems(Redraw, apply_loaded_assets.in_set(AssetLoadSet));
+    }
+}
+
+/// Written when the [AssetServer] puts an asset in its [Assets], or when an asset it depends on changes.
+/// Registered for every type initialized with [init_assets](crate::AssetAppExt::init_assets).
+#[derive(Message)]
+pub enum AssetEvent<T: Send + Sync + 'static> {
+    /// The asset finished loading for the first time
+    Loaded(AssetId<T>),
+    /// The asset was reloaded because its file changed
+    Modified(AssetId<T>),
+    /// An asset this asset depends on changed, see [AssetDependencies](crate::AssetDependencies)
+    DependencyModified(AssetId<T>),
+}
+
+/// Turns the bytes of a file into an asset.
+/// Register it using [AssetAppExt::register_asset_loader](crate::AssetAppExt::register_asset_loader).
+pub trait AssetLoader: Send + Sync + 'static {
+    type Asset: Send + Sync + 'static;
+
+    /// The file extensions (without the dot) this loader should be used for
+    fn extensions(&self) -> &[&str];
+
+    /// Creates the asset from the contents of a file, this runs on the [IoTaskPool]
+    fn load(&self, bytes: Vec<u8>, path: &Path) -> Result<Self::Asset, AssetLoadError>;
+}
+
+#[derive(Debug)]
+pub enum AssetLoadError {
+    /// The file could not be read
+    IOError(io::Error),
+    /// No loader for the asset type is registered for the extension of the path
+    NoLoader(PathBuf),
+    /// The loader failed to create the asset
+    LoaderError(Box<dyn Error + Send + Sync>),
+}
+
+impl Error for AssetLoadError {}
+
+impl Display for AssetLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetLoadError::IOError(e) => write!(f, "Asset load IOError: {}", e),
+            AssetLoadError::NoLoader(p) => write!(f, "No asset loader for: {}", p.display()),
+            AssetLoadError::LoaderError(e) => write!(f, "Asset loader error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for AssetLoadError {
+    fn from(value: io::Error) -> Self {
+        Self::IOError(value)
+    }
+}
+
+/// Registers a file as an embedded asset of the [AssetServer], see [AssetServer::register_embedded].
+/// The file is included using [include_bytes], so its path is relative to the current source file.
+/// `embedded_asset!(app, "file.wgsl")` uses the file path as the asset path, `embedded_asset!(app, "asset.wgsl", "file.wgsl")` sets it explicitly.
+#[macro_export]
+macro_rules! embedded_asset {
+    ($app:expr, $path:expr) => {
+        $crate::embedded_asset!($app, $path, $path)
+    };
+    ($app:expr, $asset_path:expr, $file:expr) => {
+        $app.world_mut()
+            .resource_mut::<$crate::AssetServer>()
+            .register_embedded($asset_path, include_bytes!($file))
+    };
+}
+
+/// The state of an asset loaded by the [AssetServer]
+#[derive(Debug, Clone)]
+pub enum LoadState {
+    /// The asset was not loaded by the [AssetServer]
+    NotLoaded,
+    /// The asset is being loaded, the [AssetId] is empty until loading finishes
+    Loading,
+    /// The asset is loaded and in its [Assets]
+    Loaded,
+    /// Loading failed, the [AssetId] stays empty.
+    /// A failed reload keeps the state [Loaded](LoadState::Loaded), as the previous asset is still in place.
+    Failed(Arc<AssetLoadError>),
+}
+
+/// Loads assets from files on the [IoTaskPool].
+/// [AssetIds](AssetId) are returned immediately, and the assets are put in place during [AssetLoadSet] when loading is done.
+#[derive(Resource)]
+pub struct AssetServer {
+    root: PathBuf,
+    loaders: HashMap<(TypeId, String), Arc<dyn ErasedAssetLoader>>,
+    appliers: HashMap<TypeId, AssetApplier>,
+    states: HashMap<UntypedAssetId, LoadState>,
+    /// the assets loaded from each path, used for reloading
+    loaded_paths: HashMap<PathBuf, Vec<UntypedAssetId>>,
+    embedded: HashMap<PathBuf, &'static [u8]>,
+    sender: Sender<LoadResult>,
+    receiver: Receiver<LoadResult>,
+    watcher: Option<AssetWatcher>,
+}
+
+impl AssetServer {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let (sender, receiver) = async_channel::unbounded();
+        Self {
+            root: root.into(),
+            loaders: HashMap::new(),
+            appliers: HashMap::new(),
+            states: HashMap::new(),
+            loaded_paths: HashMap::new(),
+            embedded: HashMap::new(),
+            sender,
+            receiver,
+            watcher: None,
+        }
+    }
+
+    /// The directory paths are relative to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Starts watching [root](Self::root), assets loaded from files that change are reloaded and an [AssetEvent::Modified] is written
+    pub fn watch_for_changes(&mut self) -> notify::Result<()> {
+        if self.watcher.is_some() {
+            return Ok(());
+        }
+        let root = fs::canonicalize(&self.root)?;
+        let (sender, changed) = async_channel::unbounded();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                let Ok(event) = res else {
+                    return;
+                };
+                if let EventKind::Modify(_) | EventKind::Create(_) = event.kind {
+                    for path in event.paths {
+                        let _ = sender.try_send(path);
+                    }
+                }
+            })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        self.watcher = Some(AssetWatcher {
+            _watcher: watcher,
+            root,
+            changed,
+        });
+        Ok(())
+    }
+
+    /// Returns true if [watch_for_changes](Self::watch_for_changes) has been called successfully
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// Makes the bytes loadable from the path, as if they were a file in [root](Self::root).
+    /// Embedded assets take priority over files, and are not reloaded when a file with the same path changes.
+    /// Usually used through [embedded_asset](crate::embedded_asset).
+    pub fn register_embedded(&mut self, path: impl AsRef<Path>, bytes: &'static [u8]) {
+        self.embedded.insert(normalize_path(path.as_ref()), bytes);
+    }
+
+    /// Returns true if the path was registered using [register_embedded](Self::register_embedded)
+    pub fn is_embedded(&self, path: impl AsRef<Path>) -> bool {
+        self.embedded.contains_key(&normalize_path(path.as_ref()))
+    }
+
+    /// Registers a loader for all of its extensions, replacing loaders of the same asset type with overlapping extensions
+    pub fn register_loader<L: AssetLoader>(&mut self, loader: L) {
+        let type_id = TypeId::of::<L::Asset>();
+        let extensions: Vec<String> = loader.extensions().iter().map(|e| e.to_string()).collect();
+        let loader: Arc<dyn ErasedAssetLoader> = Arc::new(loader);
+        for ext in extensions {
+            self.loaders.insert((type_id, ext), loader.clone());
+        }
+        self.appliers.insert(type_id, apply_loaded::<L::Asset>);
+    }
+
+    /// Starts loading the file at the path (relative to [root](Self::root)), and returns the id it will be put at.
+    /// If the path was already loaded as the same type, the existing id is returned instead, see [get_named](Self::get_named).
+    /// If no loader exists for the extension the load fails immediately, see [load_state](Self::load_state).
+    pub fn load<T: Send + Sync + 'static>(
+        &mut self,
+        assets: &Assets<T>,
+        path: impl AsRef<Path>,
+    ) -> AssetId<T> {
+        if let Some(id) = self.get_named(&path) {
+            return id;
+        }
+        let id = assets.add_empty();
+        let path = normalize_path(path.as_ref());
+        let key = UntypedAssetId::from(id);
+        let Some(loader) = self.loader_for(key.type_id(), &path) else {
+            self.states.insert(
+                key,
+                LoadState::Failed(Arc::new(AssetLoadError::NoLoader(path))),
+            );
+            return id;
+        };
+        self.loaded_paths.entry(path.clone()).or_default().push(key);
+        self.spawn_load(key, path, loader, false);
+        id
+    }
+
+    /// The id an asset of the given type was loaded to from the path, paths are used as the names of loaded assets.
+    /// This also finds assets that are still loading.
+    pub fn get_named<T: Send + Sync + 'static>(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Option<AssetId<T>> {
+        self.loaded_paths
+            .get(&normalize_path(path.as_ref()))?
+            .iter()
+            .find_map(|key| key.typed::<T>())
+    }
+
+    /// The [LoadState] of an asset, [NotLoaded](LoadState::NotLoaded) if it was not loaded by this server
+    pub fn load_state<T: Send + Sync + 'static>(&self, id: AssetId<T>) -> LoadState {
+        self.states
+            .get(&UntypedAssetId::from(id))
+            .cloned()
+            .unwrap_or(LoadState::NotLoaded)
+    }
+
+    /// Shorthand for checking if [load_state](Self::load_state) is [Loaded](LoadState::Loaded)
+    pub fn is_loaded<T: Send + Sync + 'static>(&self, id: AssetId<T>) -> bool {
+        matches!(self.load_state(id), LoadState::Loaded)
+    }
+
+    fn loader_for(&self, type_id: TypeId, path: &Path) -> Option<Arc<dyn ErasedAssetLoader>> {
+        let ext = path.extension()?.to_str()?;
+        self.loaders.get(&(type_id, ext.to_string())).cloned()
+    }
+
+    fn spawn_load(
+        &mut self,
+        key: UntypedAssetId,
+        path: PathBuf,
+        loader: Arc<dyn ErasedAssetLoader>,
+        reload: bool,
+    ) {
+        // keep the old state while reloading, the asset is still usable
+        if !reload {
+            self.states.insert(key, LoadState::Loading);
+        }
+        let full_path = self.root.join(&path);
+        let embedded = self.embedded.get(&path).copied();
+        let sender = self.sender.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                profile_span!("load asset", path = %path.display(), reload = reload);
+                let bytes = match embedded {
+                    Some(bytes) => Ok(bytes.to_vec()),
+                    None => fs::read(&full_path),
+                };
+                let asset = bytes
+                    .map_err(AssetLoadError::from)
+                    .and_then(|bytes| loader.load(bytes, &path));
+                // the receiver lives in the server, so it is only closed if the server is gone
+                let _ = sender.try_send(LoadResult { key, asset, reload });
+            })
+            .detach();
+    }
+
+    /// Reloads every asset loaded from a file that changed since the last call
+    fn reload_changed(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        // editors often write a file multiple times when saving
+        let mut changed = HashSet::new();
+        while let Ok(path) = watcher.changed.try_recv() {
+            if let Ok(path) = path.strip_prefix(&watcher.root) {
+                changed.insert(normalize_path(path));
+            }
+        }
+        for path in changed {
+            if self.embedded.contains_key(&path) {
+                continue;
+            }
+            let Some(keys) = self.loaded_paths.get(&path).cloned() else {
+                continue;
+            };
+            for key in keys {
+                if let Some(loader) = self.loader_for(key.type_id(), &path) {
+                    self.spawn_load(key, path.clone(), loader, true);
+                }
+            }
+        }
+    }
+}
+
+/// Paths are used as keys, so `./a/b.png`, `a//b.png` and `a\b.png` all become `a/b.png`
+fn normalize_path(path: &Path) -> PathBuf {
+    // non UTF-8 paths keep their separators
+    let unified = path.to_str().map(|p| PathBuf::from(p.replace('\\', "/")));
+    unified
+        .as_deref()
+        .unwrap_or(path)
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+struct AssetWatcher {
+    // only kept to not stop watching
+    _watcher: RecommendedWatcher,
+    root: PathBuf,
+    changed: Receiver<PathBuf>,
+}
+
+/// Returns false if the id was removed in the meantime
+type AssetApplier = fn(&mut World, UntypedAssetId, Box<dyn Any + Send>, bool) -> bool;
+
+struct LoadResult {
+    key: UntypedAssetId,
+    asset: Result<Box<dyn Any + Send>, AssetLoadError>,
+    reload: bool,
+}
+
+// to get around AssetLoader having an associated type
+trait ErasedAssetLoader: Send + Sync + 'static {
+    fn load(&self, bytes: Vec<u8>, path: &Path) -> Result<Box<dyn Any + Send>, AssetLoadError>;
+}
+
+impl<L: AssetLoader> ErasedAssetLoader for L {
+    fn load(&self, bytes: Vec<u8>, path: &Path) -> Result<Box<dyn Any + Send>, AssetLoadError> {
+        AssetLoader::load(self, bytes, path).map(|a| Box::new(a) as Box<dyn Any + Send>)
+    }
+}
+
+fn apply_loaded<T: Send + Sync + 'static>(
+    world: &mut World,
+    key: UntypedAssetId,
+    asset: Box<dyn Any + Send>,
+    reload: bool,
+) -> bool {
+    let Some(id) = key.typed::<T>() else {
+        return false;
+    };
+    let Some(mut assets) = world.get_resource_mut::<Assets<T>>() else {
+        return false;
+    };
+    // replace rejects stale ids
+    if assets.is_stale(id) {
+        return false;
+    }
+    let asset = asset
+        .downcast::<T>()
+        .expect("loader returned wrong asset type");
+    assets.replace(id, *asset);
+    if let Some(mut events) = world.get_resource_mut::<Messages<AssetEvent<T>>>() {
+        events.write(if reload {
+            AssetEvent::Modified(id)
+        } else {
+            AssetEvent::Loaded(id)
+        });
+    }
+    if reload {
+        propagate_asset_change(world, key);
+    }
+    true
+}
+
+fn apply_loaded_assets(world: &mut World) {
+    world.resource_scope(|world, mut server: Mut<AssetServer>| {
+        server.reload_changed();
+        while let Ok(result) = server.receiver.try_recv() {
+            let applier = server.appliers[&result.key.type_id()];
+            match result.asset {
+                Ok(asset) => match applier(world, result.key, asset, result.reload) {
+                    true => {
+                        server.states.insert(result.key, LoadState::Loaded);
+                    }
+                    false => {
+                        server.states.remove(&result.key);
+                    }
+                },
+                // the previous asset is still in place
+                Err(e) if result.reload => warn!("Failed to reload asset: {}", e),
+                Err(e) => {
+                    warn!("Failed to load asset: {}", e);
+                    server
+                        .states
+                        .insert(result.key, LoadState::Failed(Arc::new(e)));
+                }
+            }
+        }
+    });
+}
@@ -5,8 +5,31 @@
 pub use wgpu;
 pub use winit;
 
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracing;
+
+/// Enters a `tracing` span until the end of the current scope when the `tracing` feature is enabled,
+/// does nothing otherwise. Takes the same arguments as `tracing::info_span!`.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! profile_span {
+    ($($args:tt)*) => {
+        let _span = $crate::tracing::info_span!($($args)*).entered();
+    };
+}
+
+/// Enters a `tracing` span until the end of the current scope when the `tracing` feature is enabled,
+/// does nothing otherwise. Takes the same arguments as `tracing::info_span!`.
+#[cfg(not(feature = "tracing"))]
+#[macro_export]
+macro_rules! profile_span {
+    ($($args:tt)*) => {};
+}
+
 use bevy_app::{PluginsState, SubApp};
 use bevy_ecs::entity::EntityHashMap;
+use bevy_ecs::message::message_update_system;
 use bevy_ecs::prelude::*;
 use bevy_ecs::schedule::ScheduleLabel;
 use std::collections::HashMap;
@@ -14,7 +37,7 @@ use std::mem;
 use std::sync::Arc;
 use wgpu::{
     Adapter, Backends, Device, DeviceDescriptor, Instance, InstanceDescriptor, PowerPreference,
-    PresentMode, Queue, RequestAdapterOptions, Surface, SurfaceConfiguration, TextureFormat,
+    Queue, RequestAdapterOptions, Surface, SurfaceConfiguration, TextureFormat,
 };
 use winit::application::ApplicationHandler;
 use winit::event::{DeviceEvent, DeviceId, Event, StartCause, WindowEvent};
@@ -23,6 +46,45 @@ use winit::window::{Window, WindowAttributes, WindowId};
 
 /// Not using apps, so instead of a runner you should pass a [SubApp] to this
 pub fn run_app(graphics_initializer: impl GraphicsInitializer, setup: impl FnOnce(&mut SubApp)) {
+    let app = build_app(setup);
+    EventLoop::new()
+        .expect("failed to build event loop")
+        .run_app(&mut WinitApp {
+            app,
+            _instance: None,
+            _adapter: None,
+            _main_window: None,
+            initializer: Some(graphics_initializer),
+            buffer: EventBuffer(Vec::new()),
+        })
+        .expect("unable to run event loop");
+}
+
+/// Sets up the app like [run_app], but without an event loop or windows, for tests and offline rendering.
+/// [PreInit] and [Init] have run when this returns, [Redraw] runs every time [SubApp::update] is called.
+/// Returns None if no adapter is available.
+pub fn headless_app(
+    graphics_initializer: HeadlessGraphicsInitializer,
+    setup: impl FnOnce(&mut SubApp),
+) -> Option<SubApp> {
+    let mut app = build_app(setup);
+    let world = app.world_mut();
+    world.insert_resource(graphics_initializer.initialize()?);
+    world.insert_resource(SurfaceFormat(graphics_initializer.surface_format));
+    world.insert_resource(IsSrgbSurface(graphics_initializer.surface_format.is_srgb()));
+    world.insert_resource(EventBuffer(Vec::new()));
+    world.insert_resource(WindowMap {
+        map: HashMap::new(),
+        inverse: EntityHashMap::default(),
+        primary: None,
+    });
+    world.run_schedule(Init);
+    world.clear_trackers();
+    Some(app)
+}
+
+/// Creates the app and runs [PreInit]
+fn build_app(setup: impl FnOnce(&mut SubApp)) -> SubApp {
     let mut app = SubApp::new();
     app.init_schedule(PreInit);
     app.init_schedule(Init);
@@ -31,9 +93,11 @@ pub fn run_app(graphics_initializer: impl GraphicsInitializer, setup: impl FnOnc
     app.insert_resource(CreatedWindows(Vec::new()));
 
     app.update_schedule = Some(Redraw.intern());
+    // there is no First schedule, so messages are updated in Redraw
     app.add_systems(
         Redraw,
         (
+            message_update_system,
             window_insert_system,
             window_request_system,
             window_map_removal,
@@ -49,17 +113,7 @@ pub fn run_app(graphics_initializer: impl GraphicsInitializer, setup: impl FnOnc
     let world = app.world_mut();
     world.run_schedule(PreInit);
     world.clear_trackers();
-    EventLoop::new()
-        .expect("failed to build event loop")
-        .run_app(&mut WinitApp {
-            app,
-            _instance: None,
-            _adapter: None,
-            _main_window: None,
-            initializer: Some(graphics_initializer),
-            buffer: EventBuffer(Vec::new()),
-        })
-        .expect("unable to run event loop");
+    app
 }
 
 /// Runs before WGPU and winit are set up, for loading stuff before the window appears
@@ -106,18 +160,86 @@ pub struct DefaultSurfaceConfig(pub SurfaceConfiguration);
 #[derive(Resource)]
 pub struct SurfaceFormat(pub TextureFormat);
 
-/// Map from [WindowId] to [Entity]
+impl SurfaceFormat {
+    /// How the values written to a surface with this format are displayed
+    pub fn color_space(&self) -> SurfaceColorSpace {
+        SurfaceColorSpace::of(self.0)
+    }
+}
+
+/// Whether the [SurfaceFormat] is an sRGB format, it is false if the surface only offered other formats.
+/// Colors written to such a surface are not encoded, `BlitOperation`s of modul_render encode them automatically.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IsSrgbSurface(pub bool);
+
+/// How the values written to a surface are displayed, decided by its format
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SurfaceColorSpace {
+    /// Values are linear and encoded to sRGB by the format, 0..1
+    Srgb,
+    /// Values are displayed as they are, so shaders must write sRGB encoded values, 0..1
+    SrgbNonlinear,
+    /// Values are linear and may exceed 1 for HDR (extended sRGB), used by float formats
+    ExtendedLinear,
+}
+
+impl SurfaceColorSpace {
+    pub fn of(format: TextureFormat) -> Self {
+        if format.is_srgb() {
+            SurfaceColorSpace::Srgb
+        } else if HDR_SURFACE_FORMATS.contains(&format) {
+            SurfaceColorSpace::ExtendedLinear
+        } else {
+            SurfaceColorSpace::SrgbNonlinear
+        }
+    }
+
+    pub fn is_hdr(self) -> bool {
+        self == SurfaceColorSpace::ExtendedLinear
+    }
+}
+
+/// Surface formats able to display HDR values, in order of preference
+pub const HDR_SURFACE_FORMATS: &[TextureFormat] = &[TextureFormat::Rgba16Float];
+
+/// The first HDR format the surface supports, see [HDR_SURFACE_FORMATS]
+pub fn pick_hdr_surface_format(caps: &wgpu::SurfaceCapabilities) -> Option<TextureFormat> {
+    HDR_SURFACE_FORMATS
+        .iter()
+        .copied()
+        .find(|f| caps.formats.contains(f))
+}
+
+/// Map between [WindowId] and [Entity], for routing window events to the entities of their windows.
+/// Windows are added when their [WindowComponent] is inserted and removed with it.
 #[derive(Resource)]
 pub struct WindowMap {
     map: HashMap<WindowId, Entity>,
-    // not exposed publicly, as the id can be gotten from the window in the component, this is just used for removing items from the map
     inverse: EntityHashMap<WindowId>,
+    // the window created by run_app, None in headless apps or after it is closed
+    primary: Option<Entity>,
 }
 
 impl WindowMap {
     pub fn get(&self, window: &WindowId) -> Option<Entity> {
         self.map.get(window).copied()
     }
+
+    /// The [WindowId] of a window entity, None if it has no [WindowComponent] (yet)
+    pub fn entity_to_window(&self, entity: Entity) -> Option<WindowId> {
+        self.inverse.get(&entity).copied()
+    }
+
+    /// All windows, the order is arbitrary
+    pub fn iter(&self) -> impl Iterator<Item = (WindowId, Entity)> + '_ {
+        self.map.iter().map(|(window, entity)| (*window, *entity))
+    }
+
+    /// The entity of the window created by [run_app], which has the [MainWindow] marker.
+    /// None in [headless_app] or after the window is closed.
+    pub fn primary(&self) -> Option<Entity> {
+        self.primary
+    }
 }
 
 /// Marker for the main window
@@ -130,7 +252,7 @@ pub struct MainWindow;
 pub struct ImportantWindow;
 
 /// This indicates that a window should request a redraw every frame
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct UpdatingWindow;
 
 #[derive(Component)]
@@ -185,7 +307,13 @@ pub struct DefaultGraphicsInitializer {
     pub power_preference: PowerPreference,
     pub window_attribs: WindowAttributes,
     pub required_features: wgpu::Features,
+    /// Enabled if the adapter supports them, by default texture compression formats
+    pub optional_features: wgpu::Features,
     pub required_limits: wgpu::Limits,
+    /// Pick an HDR surface format if the surface supports one, see [pick_hdr_surface_format]
+    pub prefer_hdr: bool,
+    /// Writes a wgpu API trace of the device to a directory, needs the `trace` feature for [wgpu::Trace::Directory]
+    pub trace: wgpu::Trace,
 }
 
 impl Default for DefaultGraphicsInitializer {
@@ -194,7 +322,12 @@ impl Default for DefaultGraphicsInitializer {
             power_preference: PowerPreference::default(),
             window_attribs: WindowAttributes::default(),
             required_features: wgpu::Features::empty(),
+            optional_features: wgpu::Features::TEXTURE_COMPRESSION_BC
+                | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+                | wgpu::Features::TEXTURE_COMPRESSION_ASTC,
             required_limits: wgpu::Limits::default(),
+            prefer_hdr: false,
+            trace: wgpu::Trace::Off,
         }
     }
 }
@@ -226,8 +359,10 @@ impl GraphicsInitializer for DefaultGraphicsInitializer {
 
         let (device, queue) = pollster::block_on(adapter.request_device(&DeviceDescriptor {
             label: None,
-            required_features: self.required_features,
+            required_features: self.required_features
+                | (self.optional_features & adapter.features()),
             required_limits: self.required_limits.clone(),
+            trace: self.trace.clone(),
             ..Default::default()
         }))
         .expect("no device?");
@@ -245,6 +380,73 @@ impl GraphicsInitializer for DefaultGraphicsInitializer {
             surface_format,
         }
     }
+
+    fn pick_surface_format(&self, caps: &wgpu::SurfaceCapabilities) -> TextureFormat {
+        let hdr = self.prefer_hdr.then(|| pick_hdr_surface_format(caps)).flatten();
+        hdr.unwrap_or_else(|| {
+            caps.formats
+                .iter()
+                .copied()
+                .find(|f| f.is_srgb())
+                .unwrap_or_else(|| caps.formats[0])
+        })
+    }
+}
+
+/// Creates the [RenderContext] of a [headless_app], without a surface
+pub struct HeadlessGraphicsInitializer {
+    pub power_preference: PowerPreference,
+    pub required_features: wgpu::Features,
+    /// Enabled if the adapter supports them
+    pub optional_features: wgpu::Features,
+    pub required_limits: wgpu::Limits,
+    /// Inserted as the [SurfaceFormat], as there is no surface to pick it from
+    pub surface_format: TextureFormat,
+    /// Writes a wgpu API trace of the device to a directory, needs the `trace` feature for [wgpu::Trace::Directory]
+    pub trace: wgpu::Trace,
+}
+
+impl Default for HeadlessGraphicsInitializer {
+    fn default() -> Self {
+        Self {
+            power_preference: PowerPreference::default(),
+            required_features: wgpu::Features::empty(),
+            optional_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::default(),
+            surface_format: TextureFormat::Rgba8UnormSrgb,
+            trace: wgpu::Trace::Off,
+        }
+    }
+}
+
+impl HeadlessGraphicsInitializer {
+    fn initialize(&self) -> Option<RenderContext> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::all(),
+            ..InstanceDescriptor::new_without_display_handle()
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&RequestAdapterOptions {
+            power_preference: self.power_preference,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok()?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&DeviceDescriptor {
+            label: None,
+            required_features: self.required_features
+                | (self.optional_features & adapter.features()),
+            required_limits: self.required_limits.clone(),
+            trace: self.trace.clone(),
+            ..Default::default()
+        }))
+        .ok()?;
+        Some(RenderContext {
+            instance: Arc::new(instance),
+            adapter,
+            device,
+            queue,
+        })
+    }
 }
 
 struct WinitApp<I: GraphicsInitializer> {
@@ -273,7 +475,7 @@ impl<I: GraphicsInitializer> ApplicationHandler for WinitApp<I> {
         let mut res = init.initialize(event_loop);
         // Hold references that must outlive the World to ensure correct drop order.
         // Instance must outlive all GPU objects; Window must outlive Instance (Wayland display).
-        let instance = Arc::new(mem::replace(&mut res.instance, Instance::default()));
+        let instance = Arc::new(mem::take(&mut res.instance));
         self._instance = Some(Arc::clone(&instance));
         self._main_window = Some(Arc::clone(&res.window));
         add_resources(self.app.world_mut(), res, instance);
@@ -353,6 +555,7 @@ fn add_resources(world: &mut World, init_res: GraphicsInitializerResult, instanc
         queue: init_res.queue,
     });
     world.insert_resource(SurfaceFormat(init_res.surface_format));
+    world.insert_resource(IsSrgbSurface(init_res.surface_format.is_srgb()));
     let e = world.spawn((
         WindowComponent {
             window: init_res.window,
@@ -370,6 +573,7 @@ fn add_resources(world: &mut World, init_res: GraphicsInitializerResult, instanc
     world.insert_resource(WindowMap {
         map: [(id, e)].into(),
         inverse,
+        primary: Some(e),
     });
 }
 
@@ -412,5 +616,8 @@ fn window_map_removal(
             .map
             .remove(&win_id)
             .expect("in inverse but not main");
+        if window_map.primary == Some(ent) {
+            window_map.primary = None;
+        }
     }
 }
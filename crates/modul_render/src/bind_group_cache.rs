@@ -0,0 +1,220 @@
+use crate::BindGroupLayoutProvider;
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use modul_util::HashMap;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, Buffer,
+    BufferAddress, BufferBinding, BufferSize, Device, RenderPass, Sampler, TextureView,
+};
+
+/// The resource at a binding of a bind group, given by a [BindGroupResourceProvider]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BoundResource {
+    /// The entire buffer
+    Buffer(Buffer),
+    /// A range of the buffer, a size of None binds the rest of the buffer
+    BufferRange {
+        buffer: Buffer,
+        offset: BufferAddress,
+        size: Option<BufferSize>,
+    },
+    TextureView(TextureView),
+    TextureViewArray(Vec<TextureView>),
+    Sampler(Sampler),
+}
+
+impl BoundResource {
+    fn as_binding_resource<'a>(&'a self, views: &'a [&'a TextureView]) -> BindingResource<'a> {
+        match self {
+            Self::Buffer(buffer) => buffer.as_entire_binding(),
+            Self::BufferRange {
+                buffer,
+                offset,
+                size,
+            } => BindingResource::Buffer(BufferBinding {
+                buffer,
+                offset: *offset,
+                size: *size,
+            }),
+            Self::TextureView(view) => BindingResource::TextureView(view),
+            Self::TextureViewArray(_) => BindingResource::TextureViewArray(views),
+            Self::Sampler(sampler) => BindingResource::Sampler(sampler),
+        }
+    }
+}
+
+/// Provides the resources of a bind group by binding index, the companion of a [BindGroupLayoutProvider].
+/// The bind group is created by the [BindGroupCache], so it is created again when a resource is replaced.
+/// Implemented for closures taking the world.
+pub trait BindGroupResourceProvider: Send + Sync + 'static {
+    /// The resources of every binding of the layout, None if they are not available (for example a texture that is still loading)
+    fn resources(&self, world: &World) -> Option<Vec<(u32, BoundResource)>>;
+}
+
+impl<F> BindGroupResourceProvider for F
+where
+    F: Fn(&World) -> Option<Vec<(u32, BoundResource)>> + Send + Sync + 'static,
+{
+    fn resources(&self, world: &World) -> Option<Vec<(u32, BoundResource)>> {
+        self(world)
+    }
+}
+
+/// Bind groups are dropped when they have not been used for this many draws
+pub const BIND_GROUP_CACHE_LIFETIME: u64 = 3;
+
+/// Deduplicates [BindGroups](BindGroup), a layout with the same resources shares one bind group.
+/// Bind groups not used for [BIND_GROUP_CACHE_LIFETIME] draws are dropped during [PostDraw](crate::PostDraw),
+/// so the bind groups of replaced resources (like the texture of a resized render target) do not keep them alive.
+#[derive(Resource, Default)]
+pub struct BindGroupCache {
+    bind_groups: HashMap<BindGroupKey, (BindGroup, u64)>,
+    draw: u64,
+}
+
+#[derive(Hash, PartialEq, Eq)]
+struct BindGroupKey {
+    layout: BindGroupLayout,
+    resources: Vec<(u32, BoundResource)>,
+}
+
+impl BindGroupCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets a bind group from the cache, or creates and stores one given the layout and resources.
+    /// [BindGroup] is reference counted, so the result can be cloned to keep it.
+    pub fn get(
+        &mut self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        resources: &[(u32, BoundResource)],
+    ) -> &BindGroup {
+        let key = BindGroupKey {
+            layout: layout.clone(),
+            resources: resources.to_vec(),
+        };
+        let (bind_group, last_used) = self
+            .bind_groups
+            .entry(key)
+            .or_insert_with(|| (create_bind_group(device, layout, resources), 0));
+        *last_used = self.draw;
+        bind_group
+    }
+
+    /// The amount of cached bind groups
+    pub fn len(&self) -> usize {
+        self.bind_groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bind_groups.is_empty()
+    }
+
+    /// Removes all bind groups, bind groups still in use elsewhere are kept alive by their handles
+    pub fn clear(&mut self) {
+        self.bind_groups.clear();
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    resources: &[(u32, BoundResource)],
+) -> BindGroup {
+    let views: Vec<Vec<&TextureView>> = resources
+        .iter()
+        .map(|(_, resource)| match resource {
+            BoundResource::TextureViewArray(views) => views.iter().collect(),
+            _ => Vec::new(),
+        })
+        .collect();
+    let entries: Vec<BindGroupEntry> = resources
+        .iter()
+        .zip(&views)
+        .map(|((binding, resource), views)| BindGroupEntry {
+            binding: *binding,
+            resource: resource.as_binding_resource(views),
+        })
+        .collect();
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Cached bind group"),
+        layout,
+        entries: &entries,
+    })
+}
+
+pub(crate) fn evict_bind_groups(mut cache: ResMut<BindGroupCache>) {
+    let draw = cache.draw;
+    cache
+        .bind_groups
+        .retain(|_, (_, last_used)| draw - *last_used < BIND_GROUP_CACHE_LIFETIME);
+    cache.draw += 1;
+}
+
+/// The bind groups of a pipeline by group index, created from [BindGroupResourceProviders](BindGroupResourceProvider) through the [BindGroupCache].
+/// Each group is added with the layout provider added to the [PipelineLayoutComposer](crate::PipelineLayoutComposer) at the same index.
+#[derive(Default)]
+pub struct PipelineBindGroups {
+    groups: Vec<(u32, BindGroupLayout, Box<dyn BindGroupResourceProvider>)>,
+}
+
+impl PipelineBindGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the group at the index, replacing the group already at it
+    pub fn add(
+        &mut self,
+        group: u32,
+        layout: &impl BindGroupLayoutProvider,
+        resources: impl BindGroupResourceProvider,
+    ) -> &mut Self {
+        self.groups.retain(|(index, _, _)| *index != group);
+        self.groups
+            .push((group, layout.layout().clone(), Box::new(resources)));
+        self
+    }
+
+    /// Gets the bind groups from the [BindGroupCache], None if the resources of a group are not available
+    pub fn prepare(&self, world: &mut World) -> Option<BoundBindGroups> {
+        let resources = self
+            .groups
+            .iter()
+            .map(|(_, _, provider)| provider.resources(world))
+            .collect::<Option<Vec<_>>>()?;
+        let device = world.resource::<RenderContext>().device.clone();
+        let mut cache = world.resource_mut::<BindGroupCache>();
+        let bind_groups = self
+            .groups
+            .iter()
+            .zip(&resources)
+            .map(|((group, layout, _), resources)| {
+                (*group, cache.get(&device, layout, resources).clone())
+            })
+            .collect();
+        Some(BoundBindGroups(bind_groups))
+    }
+}
+
+/// The bind groups prepared by [PipelineBindGroups] for a draw
+pub struct BoundBindGroups(Vec<(u32, BindGroup)>);
+
+impl BoundBindGroups {
+    /// The bind group at the index
+    pub fn get(&self, group: u32) -> Option<&BindGroup> {
+        self.0
+            .iter()
+            .find(|(index, _)| *index == group)
+            .map(|(_, bind_group)| bind_group)
+    }
+
+    /// Sets every bind group at its index
+    pub fn set(&self, pass: &mut RenderPass) {
+        for (group, bind_group) in &self.0 {
+            pass.set_bind_group(*group, bind_group, &[]);
+        }
+    }
+}
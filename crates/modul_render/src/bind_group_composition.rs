@@ -1,14 +1,20 @@
+use crate::{ShaderCompileError, ShaderDefError, ShaderDefs};
 use bevy_app::{App, Plugin};
 use bevy_ecs::prelude::*;
-use modul_core::{Init, RenderContext};
+use bytemuck::Pod;
+use modul_core::{profile_span, Init, RenderContext};
+use modul_util::HashMap;
 use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::marker::PhantomData;
 use std::num::NonZero;
+use std::sync::Arc;
 use wgpu::{
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferDescriptor, BufferUsages,
-    Device, PipelineLayout, PipelineLayoutDescriptor, Queue, ShaderModule, ShaderModuleDescriptor,
-    ShaderRuntimeChecks, ShaderSource, ShaderStages,
+    util::RenderEncoder, BindGroup, BindGroupDescriptor, BindGroupEntry,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+    BufferDescriptor, BufferUsages, Device, Features, PipelineLayout, PipelineLayoutDescriptor,
+    Queue, ShaderModule, ShaderModuleDescriptor, ShaderRuntimeChecks, ShaderSource, ShaderStages,
 };
 
 /// Provides a created bind group layout and its WGSL library source.
@@ -20,6 +26,38 @@ pub trait BindGroupLayoutProvider {
     /// WGSL source declaring the bind group's bindings.
     /// Use `#BIND_GROUP` as the group index placeholder.
     fn library(&self) -> &str;
+
+    /// GLSL source declaring the bind group's bindings, used by
+    /// [`PipelineLayoutComposer::compose_glsl_source`]. Use `#BIND_GROUP` as the set index placeholder.
+    fn glsl_library(&self) -> Option<&str> {
+        None
+    }
+
+    /// Shader defs enabled in shaders composed with this provider,
+    /// so the library and the main source can have `#ifdef` blocks depending on the provider.
+    /// See [`PipelineLayoutComposer::shader_defs`].
+    fn shader_defs(&self) -> ShaderDefs {
+        ShaderDefs::new()
+    }
+}
+
+// shared providers, for example a global group 0 added to many composers
+impl<P: BindGroupLayoutProvider + ?Sized> BindGroupLayoutProvider for Arc<P> {
+    fn layout(&self) -> &wgpu::BindGroupLayout {
+        (**self).layout()
+    }
+
+    fn library(&self) -> &str {
+        (**self).library()
+    }
+
+    fn glsl_library(&self) -> Option<&str> {
+        (**self).glsl_library()
+    }
+
+    fn shader_defs(&self) -> ShaderDefs {
+        (**self).shader_defs()
+    }
 }
 
 /// Static definition of a bind group layout. Use with [`CachedBindGroupLayout`] and
@@ -27,6 +65,10 @@ pub trait BindGroupLayoutProvider {
 pub trait BindGroupLayoutDef {
     const LAYOUT: &'static BindGroupLayoutDescriptor<'static>;
     const LIBRARY: &'static str;
+    /// See [`BindGroupLayoutProvider::glsl_library`]
+    const GLSL_LIBRARY: Option<&'static str> = None;
+    /// See [`BindGroupLayoutProvider::shader_defs`]
+    const SHADER_DEFS: &'static [&'static str] = &[];
 }
 
 /// Cached bind group layout created from a [`BindGroupLayoutDef`].
@@ -37,6 +79,16 @@ pub struct CachedBindGroupLayout<P: BindGroupLayoutDef> {
     _marker: PhantomData<P>,
 }
 
+// manual impl so P does not have to be Clone
+impl<P: BindGroupLayoutDef> Clone for CachedBindGroupLayout<P> {
+    fn clone(&self) -> Self {
+        Self {
+            layout: self.layout.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<P: BindGroupLayoutDef> CachedBindGroupLayout<P> {
     pub fn new(device: &Device) -> Self {
         Self {
@@ -56,6 +108,14 @@ impl<P: BindGroupLayoutDef + Send + Sync + 'static> BindGroupLayoutProvider
     fn library(&self) -> &str {
         P::LIBRARY
     }
+
+    fn glsl_library(&self) -> Option<&str> {
+        P::GLSL_LIBRARY
+    }
+
+    fn shader_defs(&self) -> ShaderDefs {
+        P::SHADER_DEFS.iter().copied().collect()
+    }
 }
 
 /// Plugin that creates a [`CachedBindGroupLayout<P>`] resource during [`Init`].
@@ -63,6 +123,12 @@ pub struct BindGroupLayoutInitPlugin<P: BindGroupLayoutDef> {
     _marker: PhantomData<P>,
 }
 
+impl<P: BindGroupLayoutDef> Default for BindGroupLayoutInitPlugin<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<P: BindGroupLayoutDef> BindGroupLayoutInitPlugin<P> {
     pub fn new() -> Self {
         Self {
@@ -186,6 +252,12 @@ pub struct SimpleBindGroupLayoutBuilder {
     uniform_count: u32,
 }
 
+impl Default for SimpleBindGroupLayoutBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SimpleBindGroupLayoutBuilder {
     pub fn new() -> Self {
         Self {
@@ -321,6 +393,38 @@ impl WgslUniformType {
     }
 }
 
+/// Typed handle for a range of immediate data (push constants), returned by
+/// [`PipelineLayoutComposer::add_immediates`]. Stores the byte offset of the range.
+pub struct ImmediateRange<T: Pod>(u32, PhantomData<T>);
+
+impl<T: Pod> Clone for ImmediateRange<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Pod> Copy for ImmediateRange<T> {}
+
+impl<T: Pod> ImmediateRange<T> {
+    /// The byte offset of the range in the immediate data.
+    #[inline]
+    pub fn offset(&self) -> u32 {
+        self.0
+    }
+
+    /// Sets the range for the following draws of a pass or render bundle.
+    /// The pipeline must use the layout composed with this range.
+    #[inline]
+    pub fn set<'a>(&self, encoder: &mut impl RenderEncoder<'a>, value: &T) {
+        encoder.set_immediates(self.0, bytemuck::bytes_of(value));
+    }
+}
+
+/// Returns if the device supports immediate data, see [`PipelineLayoutComposer::add_immediates`].
+pub fn supports_immediates(device: &Device) -> bool {
+    device.features().contains(Features::IMMEDIATES)
+}
+
 enum EntryData {
     Binding((String, String), BindGroupLayoutEntry),
     Uniform(String, WgslUniformType),
@@ -328,15 +432,83 @@ enum EntryData {
 
 // --- PipelineLayoutComposer ---
 
+type SharedBindGroupLayoutProvider = Arc<dyn BindGroupLayoutProvider + Send + Sync>;
+
+/// Returned by [`PipelineLayoutComposer::compose_glsl_source`] when a bind group has no GLSL library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingGlslLibrary {
+    /// The index of the bind group
+    pub group: usize,
+}
+
+impl Error for MissingGlslLibrary {}
+
+impl Display for MissingGlslLibrary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "bind group {} has no GLSL library", self.group)
+    }
+}
+
+/// Returned by [`PipelineLayoutComposer::compose_compute_shader`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComputeShaderError {
+    /// The composed source failed to parse or validate
+    Compile(ShaderCompileError),
+    /// The shader def directives of the composed source are unbalanced
+    ShaderDefs(ShaderDefError),
+    /// The composed source has no `@compute` function with the name
+    MissingEntryPoint(String),
+}
+
+impl Error for ComputeShaderError {}
+
+impl Display for ComputeShaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ComputeShaderError::Compile(error) => write!(f, "{}", error),
+            ComputeShaderError::ShaderDefs(error) => write!(f, "{}", error),
+            ComputeShaderError::MissingEntryPoint(name) => {
+                write!(f, "no compute entry point named {}", name)
+            }
+        }
+    }
+}
+
+impl From<ShaderCompileError> for ComputeShaderError {
+    fn from(error: ShaderCompileError) -> Self {
+        ComputeShaderError::Compile(error)
+    }
+}
+
+impl From<ShaderDefError> for ComputeShaderError {
+    fn from(error: ShaderDefError) -> Self {
+        ComputeShaderError::ShaderDefs(error)
+    }
+}
+
 /// Composes a pipeline layout and shader from multiple [`BindGroupLayoutProvider`]s.
 /// Bind group WGSL libraries are concatenated with `#BIND_GROUP` replaced by the group index.
 /// Additional WGSL snippets can be prepended before the main shader source.
+/// Shader modules are cached per main source, so one composer can compose a shared vertex shader
+/// and multiple fragment shaders, each only once.
+/// Groups can be placed at explicit indices, leaving unused indices empty, and a composer for a
+/// subset of the groups can be created with [`Self::subset`] or [`Self::select`].
 pub struct PipelineLayoutComposer {
-    source: Vec<Box<dyn BindGroupLayoutProvider + Send + Sync>>,
+    /// the providers by group index
+    source: Vec<Option<SharedBindGroupLayoutProvider>>,
     snippets: Vec<String>,
     composed: Option<PipelineLayout>,
-    compiled_shader: Option<ShaderModule>,
+    compiled_shaders: HashMap<String, ShaderModule>,
+    /// the `@compute` functions of the main sources composed with [`Self::compose_compute_shader`]
+    compute_entry_points: HashMap<String, Vec<String>>,
     checks: Option<ShaderRuntimeChecks>,
+    immediate_size: u32,
+}
+
+impl Default for PipelineLayoutComposer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PipelineLayoutComposer {
@@ -345,8 +517,10 @@ impl PipelineLayoutComposer {
             source: Vec::new(),
             snippets: Vec::new(),
             composed: None,
-            compiled_shader: None,
+            compiled_shaders: HashMap::new(),
+            compute_entry_points: HashMap::new(),
             checks: None,
+            immediate_size: 0,
         }
     }
 
@@ -359,37 +533,148 @@ impl PipelineLayoutComposer {
         }
     }
 
-    /// Add a bind group layout provider. The provider's WGSL library will be included
-    /// in composed shaders with `#BIND_GROUP` replaced by the group index.
+    /// Add a bind group layout provider after the last group. The provider's WGSL library will be
+    /// included in composed shaders with `#BIND_GROUP` replaced by the group index.
+    /// Add an `Arc` of a provider to share it between composers.
     #[inline]
     pub fn add_bind_group(
         &mut self,
         group: impl BindGroupLayoutProvider + Send + Sync + 'static,
     ) -> &mut Self {
-        self.source.push(Box::new(group));
+        let index = self.source.len();
+        self.set_bind_group(index, group)
+    }
+
+    /// Place a bind group layout provider at the group index, replacing the provider there.
+    /// Indices before it without a provider stay empty in the pipeline layout.
+    pub fn set_bind_group(
+        &mut self,
+        index: usize,
+        group: impl BindGroupLayoutProvider + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.insert(index, Arc::new(group))
+    }
+
+    fn insert(&mut self, index: usize, group: SharedBindGroupLayoutProvider) -> &mut Self {
+        if self.source.len() <= index {
+            self.source.resize_with(index + 1, || None);
+        }
+        self.source[index] = Some(group);
+        self.composed = None;
+        self.compiled_shaders.clear();
+        self.compute_entry_points.clear();
+        self
+    }
+
+    /// Removes the provider at the group index, leaving it empty.
+    pub fn remove_bind_group(&mut self, index: usize) -> &mut Self {
+        if let Some(group) = self.source.get_mut(index) {
+            *group = None;
+        }
+        while self.source.last().is_some_and(Option::is_none) {
+            self.source.pop();
+        }
         self.composed = None;
-        self.compiled_shader = None;
+        self.compiled_shaders.clear();
+        self.compute_entry_points.clear();
         self
     }
 
+    /// The group indices that have a provider.
+    pub fn bind_group_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.groups().map(|(index, _)| index)
+    }
+
+    /// A composer with only the providers at the group indices, at the same indices,
+    /// and the snippets, immediates and runtime checks of this one.
+    /// Providers are shared, so creating it does not create any layouts.
+    pub fn subset(&self, groups: impl IntoIterator<Item = usize>) -> Self {
+        self.select(groups.into_iter().map(|index| (index, index)))
+    }
+
+    /// Like [`Self::subset`], with each provider moved from the first group index to the second.
+    /// Indices without a provider are skipped.
+    pub fn select(&self, groups: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let mut composer = Self {
+            snippets: self.snippets.clone(),
+            checks: self.checks,
+            immediate_size: self.immediate_size,
+            ..Self::new()
+        };
+        for (from, to) in groups {
+            if let Some(Some(group)) = self.source.get(from) {
+                composer.insert(to, group.clone());
+            }
+        }
+        composer
+    }
+
+    fn groups(&self) -> impl Iterator<Item = (usize, &SharedBindGroupLayoutProvider)> {
+        self.source
+            .iter()
+            .enumerate()
+            .filter_map(|(index, group)| Some((index, group.as_ref()?)))
+    }
+
     /// Add a WGSL snippet that will be included before the main shader source.
     /// Use this for shared utility functions (e.g. lighting, depth reconstruction).
     #[inline]
     pub fn add_snippet(&mut self, source: impl Into<String>) -> &mut Self {
         self.snippets.push(source.into());
-        self.compiled_shader = None;
+        self.compiled_shaders.clear();
+        self.compute_entry_points.clear();
         self
     }
 
+    /// Add a range of immediate data (push constants) after the previously added ranges.
+    /// The shader declares all ranges in one `var<immediate>` struct,
+    /// with fields at the same offsets.
+    /// Composing a layout with immediates panics if the device does not support them,
+    /// check with [`supports_immediates`] first.
+    pub fn add_immediates<T: Pod>(&mut self) -> ImmediateRange<T> {
+        let size = size_of::<T>() as u32;
+        assert!(
+            size.is_multiple_of(wgpu::IMMEDIATE_DATA_ALIGNMENT),
+            "immediate data size must be a multiple of {}",
+            wgpu::IMMEDIATE_DATA_ALIGNMENT
+        );
+        let offset = self.immediate_size;
+        self.immediate_size += size;
+        self.composed = None;
+        ImmediateRange(offset, PhantomData)
+    }
+
+    /// The total size in bytes of the added immediate ranges.
+    #[inline]
+    pub fn immediate_size(&self) -> u32 {
+        self.immediate_size
+    }
+
     /// Compose and cache the pipeline layout from the added bind group providers.
     pub fn compose_pipeline_layout(&mut self, device: &Device) -> &PipelineLayout {
+        if self.immediate_size > 0 && self.composed.is_none() {
+            assert!(
+                supports_immediates(device),
+                "immediates require the IMMEDIATES device feature"
+            );
+            let max = device.limits().max_immediate_size;
+            assert!(
+                self.immediate_size <= max,
+                "immediate size {} exceeds the device limit of {}",
+                self.immediate_size,
+                max
+            );
+        }
         self.composed.get_or_insert_with(|| {
-            let layouts: Vec<Option<&wgpu::BindGroupLayout>> =
-                self.source.iter().map(|p| Some(p.layout())).collect();
+            let layouts: Vec<Option<&wgpu::BindGroupLayout>> = self
+                .source
+                .iter()
+                .map(|group| group.as_ref().map(|p| p.layout()))
+                .collect();
             device.create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("Composed pipeline layout"),
                 bind_group_layouts: &layouts,
-                immediate_size: 0,
+                immediate_size: self.immediate_size,
             })
         })
     }
@@ -400,21 +685,27 @@ impl PipelineLayoutComposer {
         self.composed.as_ref()
     }
 
-    /// Compose and cache a shader module from bind group libraries, snippets, and the main source.
-    pub fn compose_shader(
-        &mut self,
-        device: &Device,
-        label: &str,
-        main_source: &str,
-    ) -> &ShaderModule {
-        if self.compiled_shader.is_some() {
-            return self.compiled_shader.as_ref().unwrap();
+    /// The shader defs of all added bind group providers.
+    /// [`Self::compose_shader`] applies them, sources from [`Self::compose_shader_source`] are
+    /// preprocessed later, so pass them to the provider (for example
+    /// [`SpecializedRenderPipelineResourceProvider::shader_defs`](crate::SpecializedRenderPipelineResourceProvider::shader_defs)).
+    pub fn shader_defs(&self) -> ShaderDefs {
+        let mut defs = ShaderDefs::new();
+        for (_, provider) in self.groups() {
+            defs.extend(provider.shader_defs().iter());
         }
+        defs
+    }
 
+    /// The WGSL source of the bind group libraries, snippets, and the main source,
+    /// as used by [`Self::compose_shader`] before applying the [`Self::shader_defs`]. Use with
+    /// [`SpecializedRenderPipelineResourceProvider`](crate::SpecializedRenderPipelineResourceProvider)
+    /// to create a shader module per set of shader defs.
+    pub fn compose_shader_source(&self, main_source: &str) -> String {
         let mut full_source = String::new();
 
         // Bind group declarations
-        for (i, group) in self.source.iter().enumerate() {
+        for (i, group) in self.groups() {
             let lib = group.library().replace("#BIND_GROUP", &i.to_string());
             full_source.push_str(&lib);
             full_source.push('\n');
@@ -428,23 +719,129 @@ impl PipelineLayoutComposer {
 
         // Main shader
         full_source.push_str(main_source);
+        full_source
+    }
 
-        let desc = ShaderModuleDescriptor {
-            label: Some(label),
-            source: ShaderSource::Wgsl(Cow::Owned(full_source)),
-        };
+    /// The GLSL source of the bind group GLSL libraries and the main source, for porting GLSL shaders.
+    /// The `#version` line of the main source is moved to the top, `#version 450` is used if there is none.
+    /// Snippets are WGSL, so they are not included.
+    /// The libraries come before the main source, so line numbers in errors are offset by their lines.
+    /// Create the module with `ShaderSource::Glsl`, which needs the `glsl` feature of wgpu.
+    pub fn compose_glsl_source(&self, main_source: &str) -> Result<String, MissingGlslLibrary> {
+        let version = main_source
+            .lines()
+            .find(|line| line.trim_start().starts_with("#version"))
+            .unwrap_or("#version 450");
+        let mut full_source = format!("{version}\n");
+
+        for (i, group) in self.groups() {
+            let lib = group.glsl_library().ok_or(MissingGlslLibrary { group: i })?;
+            full_source.push_str(&lib.replace("#BIND_GROUP", &i.to_string()));
+            full_source.push('\n');
+        }
 
-        self.compiled_shader = Some(match self.checks {
-            None => device.create_shader_module(desc),
-            Some(checks) => unsafe { device.create_shader_module_trusted(desc, checks) },
-        });
+        for line in main_source
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("#version"))
+        {
+            full_source.push_str(line);
+            full_source.push('\n');
+        }
+        Ok(full_source)
+    }
 
-        self.compiled_shader.as_ref().unwrap()
+    /// Compose and cache a shader module from bind group libraries, snippets, and the main source.
+    /// Modules are cached by main source, composing the same source again returns the cached module.
+    /// Returns an error if the shader def directives are unbalanced.
+    pub fn compose_shader(
+        &mut self,
+        device: &Device,
+        label: &str,
+        main_source: &str,
+    ) -> Result<&ShaderModule, ShaderDefError> {
+        if !self.compiled_shaders.contains_key(main_source) {
+            profile_span!("compile shader", label = label);
+            let desc = ShaderModuleDescriptor {
+                label: Some(label),
+                source: ShaderSource::Wgsl(Cow::Owned(self.preprocessed_source(main_source)?)),
+            };
+
+            let module = match self.checks {
+                None => device.create_shader_module(desc),
+                Some(checks) => unsafe { device.create_shader_module_trusted(desc, checks) },
+            };
+            self.compiled_shaders.insert(main_source.to_owned(), module);
+        }
+        Ok(&self.compiled_shaders[main_source])
+    }
+
+    /// The composed source with the shader defs applied
+    fn preprocessed_source(&self, main_source: &str) -> Result<String, ShaderDefError> {
+        self.shader_defs()
+            .preprocess(&self.compose_shader_source(main_source))
+    }
+
+    /// Compose and cache separate vertex and fragment shader modules, sharing the bind group libraries
+    /// and snippets. Use with [`DirectRenderPipelineResourceProvider`](crate::DirectRenderPipelineResourceProvider)
+    /// to pair a shared vertex shader with different fragment shaders.
+    pub fn compose_stage_shaders(
+        &mut self,
+        device: &Device,
+        label: &str,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<(ShaderModule, ShaderModule), ShaderDefError> {
+        let vertex = self
+            .compose_shader(device, &format!("{label} vertex"), vertex_source)?
+            .clone();
+        let fragment = self
+            .compose_shader(device, &format!("{label} fragment"), fragment_source)?
+            .clone();
+        Ok((vertex, fragment))
+    }
+
+    /// Compose and cache a compute shader module like [`Self::compose_shader`], sharing the bind group
+    /// libraries with render shaders. The composed source is checked with naga, and must have a
+    /// `@compute` function named `entry_point`. Use with
+    /// [`DirectComputePipelineResourceProvider`](crate::DirectComputePipelineResourceProvider)
+    /// and [`Self::compose_pipeline_layout`] for a [`ComputePipelineManager`](crate::ComputePipelineManager).
+    pub fn compose_compute_shader(
+        &mut self,
+        device: &Device,
+        label: &str,
+        main_source: &str,
+        entry_point: &str,
+    ) -> Result<&ShaderModule, ComputeShaderError> {
+        if !self.compute_entry_points.contains_key(main_source) {
+            let source = self.preprocessed_source(main_source)?;
+            let module = ShaderCompileError::checked_module(
+                Some(label),
+                &source,
+                wgpu::naga::valid::Capabilities::all(),
+            )?;
+            let entry_points = module
+                .entry_points
+                .iter()
+                .filter(|e| e.stage == wgpu::naga::ShaderStage::Compute)
+                .map(|e| e.name.clone())
+                .collect();
+            self.compute_entry_points
+                .insert(main_source.to_owned(), entry_points);
+        }
+        if !self.compute_entry_points[main_source]
+            .iter()
+            .any(|name| name == entry_point)
+        {
+            return Err(ComputeShaderError::MissingEntryPoint(
+                entry_point.to_owned(),
+            ));
+        }
+        Ok(self.compose_shader(device, label, main_source)?)
     }
 
-    /// Gets the currently cached shader module.
+    /// Gets the cached shader module composed from the main source.
     #[inline]
-    pub fn get_shader_module(&self) -> Option<&ShaderModule> {
-        self.compiled_shader.as_ref()
+    pub fn get_shader_module(&self, main_source: &str) -> Option<&ShaderModule> {
+        self.compiled_shaders.get(main_source)
     }
 }
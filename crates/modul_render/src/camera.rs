@@ -0,0 +1,300 @@
+use crate::{
+    camera_view_size, BindGroupLayoutProvider, BindGroupProvider, CameraJitter, GlobalTransform,
+    RenderTargetSource,
+};
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2, Vec3};
+use modul_core::RenderContext;
+use std::num::NonZero;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    Device, ShaderStages,
+};
+
+/// [SystemSet] within [Draw](crate::Draw) that uploads the [CameraUniform] of every [Camera].
+/// It runs after [ApplyOffscreenTargetsSystemSet](crate::ApplyOffscreenTargetsSystemSet) (so the aspect ratio is up to date) and before the sequences.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CameraUploadSet;
+
+/// The projection of a [Camera], using a right-handed coordinate system and a depth range of 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// `fov_y` is the vertical field of view in radians, a `far` of [f32::INFINITY] uses an infinite projection
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// `height` is the visible height in world units, the width follows from the aspect ratio
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    /// The projection matrix for a target with the aspect ratio (width / height)
+    pub fn matrix(&self, aspect_ratio: f32) -> Mat4 {
+        match *self {
+            Self::Perspective { fov_y, near, far } if far.is_infinite() => {
+                Mat4::perspective_infinite_rh(fov_y, aspect_ratio, near)
+            }
+            Self::Perspective { fov_y, near, far } => {
+                Mat4::perspective_rh(fov_y, aspect_ratio, near, far)
+            }
+            Self::Orthographic { height, near, far } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+        }
+    }
+}
+
+/// A camera rendering to a target, its [CameraUniform] is uploaded to a [CameraBindGroup] on the same entity every frame.
+/// The aspect ratio of the projection is the one of the render target, or of its [CameraViewport](crate::CameraViewport), so it follows resizes.
+/// If the entity has a [GlobalTransform] it is used instead of [transform](Self::transform).
+#[derive(Component, Clone, Debug)]
+pub struct Camera {
+    pub render_target: RenderTargetSource,
+    pub projection: Projection,
+    /// Camera to world transform, the camera looks along its -Z axis with +Y up
+    pub transform: Mat4,
+}
+
+impl Camera {
+    pub fn new(render_target: RenderTargetSource, projection: Projection) -> Self {
+        Self {
+            render_target,
+            projection,
+            transform: Mat4::IDENTITY,
+        }
+    }
+
+    /// Places the camera at `eye`, looking at `target`
+    pub fn looking_at(mut self, eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        self.transform = Mat4::look_at_rh(eye, target, up).inverse();
+        self
+    }
+
+    /// The world to camera transform
+    pub fn view(&self) -> Mat4 {
+        self.transform.inverse()
+    }
+
+    /// The uniform for a target with the aspect ratio (width / height)
+    pub fn uniform(&self, aspect_ratio: f32) -> CameraUniform {
+        self.uniform_with_transform(self.transform, aspect_ratio)
+    }
+
+    fn uniform_with_transform(&self, transform: Mat4, aspect_ratio: f32) -> CameraUniform {
+        let view = transform.inverse();
+        let projection = self.projection.matrix(aspect_ratio);
+        let view_projection = projection * view;
+        CameraUniform {
+            view_projection,
+            inverse_view_projection: view_projection.inverse(),
+            view,
+            projection,
+            position: transform.w_axis.truncate().to_array(),
+            _padding: 0.0,
+            previous_view_projection: view_projection,
+            jitter: [0.0; 2],
+            _jitter_padding: [0.0; 2],
+        }
+    }
+}
+
+/// The data of a [Camera] as seen by shaders, declared as the `Camera` struct by the [CameraUniformProvider]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct CameraUniform {
+    pub view_projection: Mat4,
+    pub inverse_view_projection: Mat4,
+    pub view: Mat4,
+    pub projection: Mat4,
+    /// World space position of the camera
+    pub position: [f32; 3],
+    _padding: f32,
+    /// The `view_projection` of the last frame without jitter, for velocity buffers
+    pub previous_view_projection: Mat4,
+    /// The offset the [CameraJitter] adds to NDC positions, subtract it for unjittered positions
+    pub jitter: [f32; 2],
+    _jitter_padding: [f32; 2],
+}
+
+impl CameraUniform {
+    /// Moves the projection by the offset in NDC
+    fn jittered(mut self, offset: Vec2) -> Self {
+        let translation = Mat4::from_translation(offset.extend(0.0));
+        self.projection = translation * self.projection;
+        self.view_projection = translation * self.view_projection;
+        self.inverse_view_projection = self.view_projection.inverse();
+        self.jitter = offset.to_array();
+        self
+    }
+}
+
+/// The bind group layout of [CameraBindGroups](CameraBindGroup), inserted during [Init](modul_core::Init).
+/// Add a clone to a [PipelineLayoutComposer](crate::PipelineLayoutComposer) to use `camera` in shaders.
+#[derive(Resource, Clone)]
+pub struct CameraUniformProvider {
+    layout: BindGroupLayout,
+}
+
+impl CameraUniformProvider {
+    const LIBRARY: &'static str = "struct Camera {
+    view_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    position: vec3<f32>,
+    previous_view_projection: mat4x4<f32>,
+    jitter: vec2<f32>,
+}
+@group(#BIND_GROUP) @binding(0)
+var<uniform> camera: Camera;";
+    const GLSL_LIBRARY: &'static str = "layout(set = #BIND_GROUP, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    mat4 view;
+    mat4 projection;
+    vec3 position;
+    mat4 previous_view_projection;
+    vec2 jitter;
+} camera;";
+
+    pub fn new(device: &Device) -> Self {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Camera BGLayout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT.union(ShaderStages::COMPUTE),
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZero::new(size_of::<CameraUniform>() as u64),
+                },
+                count: None,
+            }],
+        });
+        Self { layout }
+    }
+
+    /// Creates a bind group with its own buffer, containing the uniform
+    pub fn create_bind_group(&self, device: &Device, uniform: &CameraUniform) -> CameraBindGroup {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Camera buffer"),
+            size: size_of::<CameraUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: true,
+        });
+        buffer
+            .slice(..)
+            .get_mapped_range_mut()
+            .copy_from_slice(bytemuck::bytes_of(uniform));
+        buffer.unmap();
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Camera bind group"),
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        CameraBindGroup {
+            buffer,
+            bind_group,
+            view_projection: uniform.previous_view_projection,
+        }
+    }
+}
+
+impl BindGroupLayoutProvider for CameraUniformProvider {
+    fn layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    fn library(&self) -> &str {
+        Self::LIBRARY
+    }
+
+    fn glsl_library(&self) -> Option<&str> {
+        Some(Self::GLSL_LIBRARY)
+    }
+}
+
+/// The uniform buffer and bind group of a [Camera], inserted and updated during [CameraUploadSet]
+#[derive(Component)]
+pub struct CameraBindGroup {
+    buffer: Buffer,
+    bind_group: BindGroup,
+    // the unjittered view projection of the last upload
+    view_projection: Mat4,
+}
+
+impl CameraBindGroup {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+impl BindGroupProvider for CameraBindGroup {
+    fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
+
+pub(crate) fn init_camera_provider(mut commands: Commands, ctx: Res<RenderContext>) {
+    commands.insert_resource(CameraUniformProvider::new(&ctx.device));
+}
+
+pub(crate) fn upload_cameras(world: &mut World) {
+    let mut cameras = world.query::<(
+        Entity,
+        &Camera,
+        Option<&GlobalTransform>,
+        Option<&CameraJitter>,
+        Option<&CameraBindGroup>,
+    )>();
+    let ctx = world.resource::<RenderContext>();
+    let provider = world.resource::<CameraUniformProvider>();
+    let mut created = Vec::new();
+    let mut uploaded = Vec::new();
+    for (entity, camera, global, jitter, bind_group) in cameras.iter(world) {
+        // suspended surfaces keep the last uniform
+        let Some((width, height)) = camera_view_size(world, entity) else {
+            continue;
+        };
+        let transform = global.map_or(camera.transform, |g| g.0);
+        let mut uniform = camera.uniform_with_transform(transform, width as f32 / height as f32);
+        let view_projection = uniform.view_projection;
+        if let Some(bind_group) = bind_group {
+            uniform.previous_view_projection = bind_group.view_projection;
+        }
+        if let Some(jitter) = jitter {
+            // from pixels to NDC, where y points up
+            let offset = jitter.offset() * Vec2::new(2.0 / width as f32, -2.0 / height as f32);
+            uniform = uniform.jittered(offset);
+        }
+        match bind_group {
+            Some(bind_group) => {
+                ctx.queue
+                    .write_buffer(&bind_group.buffer, 0, bytemuck::bytes_of(&uniform));
+                uploaded.push((entity, view_projection));
+            }
+            None => created.push((entity, provider.create_bind_group(&ctx.device, &uniform))),
+        }
+    }
+    for (entity, view_projection) in uploaded {
+        world
+            .get_mut::<CameraBindGroup>(entity)
+            .unwrap()
+            .view_projection = view_projection;
+    }
+    for (entity, bind_group) in created {
+        world.entity_mut(entity).insert(bind_group);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::RedrawSet;
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use modul_core::{EventBuffer, Redraw, RenderContext};
+use wgpu::PollType;
+use winit::event::{ElementState, Event, WindowEvent};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
+
+/// Starts and stops captures in an attached graphics debugger (RenderDoc or Xcode) with a key chord, Ctrl+F12 by default.
+/// The capture contains every frame between pressing the chord twice, nothing happens without a debugger.
+/// For a wgpu API trace, which can be replayed without a debugger, set the `trace` of the
+/// [DefaultGraphicsInitializer](modul_core::DefaultGraphicsInitializer) instead.
+pub struct GpuCapturePlugin {
+    pub key: KeyCode,
+    pub modifiers: ModifiersState,
+}
+
+impl Default for GpuCapturePlugin {
+    fn default() -> Self {
+        Self {
+            key: KeyCode::F12,
+            modifiers: ModifiersState::CONTROL,
+        }
+    }
+}
+
+impl Plugin for GpuCapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GpuCapture {
+            chord: Some((self.modifiers, self.key)),
+            requested: false,
+            capturing: false,
+        });
+        app.add_systems(
+            Redraw,
+            (toggle_capture_on_chord, apply_gpu_capture)
+                .chain()
+                .in_set(RedrawSet::Prepare),
+        );
+    }
+}
+
+/// Inserted by the [GpuCapturePlugin], captures can also be started and stopped from systems
+#[derive(Resource, Debug)]
+pub struct GpuCapture {
+    chord: Option<(ModifiersState, KeyCode)>,
+    requested: bool,
+    capturing: bool,
+}
+
+impl GpuCapture {
+    /// If a capture is running
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    /// Starts or stops the capture before the next frame is rendered
+    pub fn set_capturing(&mut self, capturing: bool) {
+        self.requested = capturing;
+    }
+
+    pub fn toggle(&mut self) {
+        self.requested = !self.requested;
+    }
+
+    /// The modifiers and key that toggle the capture, None to only toggle it from systems
+    pub fn set_chord(&mut self, chord: Option<(ModifiersState, KeyCode)>) {
+        self.chord = chord;
+    }
+}
+
+fn toggle_capture_on_chord(
+    mut modifiers: Local<ModifiersState>,
+    events: Res<EventBuffer>,
+    mut capture: ResMut<GpuCapture>,
+) {
+    for e in events.events() {
+        let Event::WindowEvent { event, .. } = e else {
+            continue;
+        };
+        match event {
+            WindowEvent::ModifiersChanged(changed) => *modifiers = changed.state(),
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed && !event.repeat =>
+            {
+                if let (Some((chord_modifiers, key)), PhysicalKey::Code(code)) =
+                    (capture.chord, event.physical_key)
+                {
+                    if code == key && *modifiers == chord_modifiers {
+                        capture.toggle();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn apply_gpu_capture(mut capture: ResMut<GpuCapture>, ctx: Res<RenderContext>) {
+    if capture.requested == capture.capturing {
+        return;
+    }
+    // SAFETY: only one capture is active at a time, as it is stopped before another is started
+    unsafe {
+        if capture.requested {
+            log::info!("starting graphics debugger capture");
+            ctx.device.start_graphics_debugger_capture();
+        } else {
+            // the submitted work is part of the capture
+            let _ = ctx.device.poll(PollType::wait_indefinitely());
+            ctx.device.stop_graphics_debugger_capture();
+            log::info!("stopped graphics debugger capture");
+        }
+    }
+    capture.capturing = capture.requested;
+}
@@ -0,0 +1,172 @@
+use crate::PersistentPipelineCache;
+use bevy_ecs::world::World;
+use modul_asset::{AssetId, AssetWorldExt, UntypedAssetId};
+use modul_core::{profile_span, RenderContext};
+use modul_util::HashMap;
+use std::hash::{Hash, Hasher};
+use wgpu::{
+    ComputePipeline, ComputePipelineDescriptor, PipelineCompilationOptions, PipelineLayout,
+    ShaderModule,
+};
+
+/// Provides the [PipelineLayout] and [ShaderModule] for a [ComputePipeline].
+/// Works like [RenderPipelineResourceProvider](crate::RenderPipelineResourceProvider), but with a single shader module.
+pub trait ComputePipelineResourceProvider {
+    /// Should always be called before getting resources.
+    fn update(&self, world: &mut World);
+
+    fn get_pipeline_layout(&self, world: &World) -> PipelineLayout;
+
+    fn get_shader_module(&self, world: &World) -> ShaderModule;
+
+    /// The assets the resources are created from, see [AssetDependencies](modul_asset::AssetDependencies)
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        Vec::new()
+    }
+}
+
+pub struct DirectComputePipelineResourceProvider {
+    pub layout: AssetId<PipelineLayout>,
+    pub shader_module: AssetId<ShaderModule>,
+}
+
+impl ComputePipelineResourceProvider for DirectComputePipelineResourceProvider {
+    fn update(&self, _world: &mut World) {}
+
+    fn get_pipeline_layout(&self, world: &World) -> PipelineLayout {
+        world.asset(self.layout).clone()
+    }
+
+    fn get_shader_module(&self, world: &World) -> ShaderModule {
+        world.asset(self.shader_module).clone()
+    }
+
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        vec![self.layout.into(), self.shader_module.into()]
+    }
+}
+
+/// The parts of a [ComputePipelineDescriptor] that are the same for every instance of a [ComputePipelineManager]
+pub struct GenericComputePipelineDescriptor {
+    pub resource_provider: Box<dyn ComputePipelineResourceProvider + Send + Sync + 'static>,
+    pub label: Option<String>,
+}
+
+/// Used with [ComputePipelineManager] to select an instance of the pipeline
+#[derive(Clone, Debug)]
+pub struct ComputePipelineParameters {
+    pub entry_point: String,
+    /// Values for pipeline-overridable constants (`override` declarations in WGSL)
+    pub constants: Vec<(String, f64)>,
+}
+
+impl ComputePipelineParameters {
+    /// Parameters with no constants
+    pub fn new(entry_point: impl Into<String>) -> Self {
+        Self {
+            entry_point: entry_point.into(),
+            constants: Vec::new(),
+        }
+    }
+
+    /// Builder method to add a constant
+    pub fn with_constant(mut self, name: impl Into<String>, value: f64) -> Self {
+        self.constants.push((name.into(), value));
+        self
+    }
+}
+
+// f64 is not Hash or Eq, so the bits are compared instead
+impl PartialEq for ComputePipelineParameters {
+    fn eq(&self, other: &Self) -> bool {
+        self.entry_point == other.entry_point
+            && self.constants.len() == other.constants.len()
+            && self
+                .constants
+                .iter()
+                .zip(&other.constants)
+                .all(|((n1, v1), (n2, v2))| n1 == n2 && v1.to_bits() == v2.to_bits())
+    }
+}
+
+impl Eq for ComputePipelineParameters {}
+
+impl Hash for ComputePipelineParameters {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.entry_point.hash(state);
+        for (name, value) in &self.constants {
+            name.hash(state);
+            value.to_bits().hash(state);
+        }
+    }
+}
+
+/// Used to manage instances of a [GenericComputePipelineDescriptor], mirroring [RenderPipelineManager](crate::RenderPipelineManager)
+pub struct ComputePipelineManager {
+    desc: GenericComputePipelineDescriptor,
+    instances: HashMap<ComputePipelineParameters, ComputePipeline>,
+}
+
+impl ComputePipelineManager {
+    pub fn new(desc: GenericComputePipelineDescriptor) -> Self {
+        Self {
+            desc,
+            instances: HashMap::new(),
+        }
+    }
+
+    /// The assets the pipelines are created from, given by the [ComputePipelineResourceProvider].
+    /// The [RenderPlugin](crate::RenderPlugin) registers them in [AssetDependencies](modul_asset::AssetDependencies) for managers in `Assets<ComputePipelineManager>`, so the pipelines are rebuilt when they change.
+    pub fn dependencies(&self) -> Vec<UntypedAssetId> {
+        self.desc.resource_provider.dependencies()
+    }
+
+    /// Removes all created pipelines, they will be created again when requested.
+    /// This is done automatically when an [AssetEvent::DependencyModified](modul_asset::AssetEvent::DependencyModified) is written for the manager.
+    pub fn clear(&mut self) {
+        self.instances.clear();
+    }
+
+    /// Gets a pipeline from the internal cache, or creates and stores one given the parameters.
+    /// The returned value can be ignored if you just want to init the pipeline.
+    pub fn get(
+        &mut self,
+        world: &mut World,
+        params: &ComputePipelineParameters,
+    ) -> &ComputePipeline {
+        self.instances.entry(params.clone()).or_insert_with(|| {
+            profile_span!("create compute pipeline", label = ?self.desc.label);
+            self.desc.resource_provider.update(world);
+
+            let device = &world.resource::<RenderContext>().device;
+            let module = self.desc.resource_provider.get_shader_module(world);
+            let layout = self.desc.resource_provider.get_pipeline_layout(world);
+            let cache = world
+                .get_resource::<PersistentPipelineCache>()
+                .and_then(|c| c.cache());
+            let constants = params
+                .constants
+                .iter()
+                .map(|(name, value)| (name.as_str(), *value))
+                .collect::<Vec<_>>();
+
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: self.desc.label.as_deref(),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(params.entry_point.as_str()),
+                compilation_options: PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                cache,
+            })
+        })
+    }
+
+    /// Gets a pipeline if it exists, otherwise will return None.
+    /// Using [get](Self::get) will create the desired pipeline instead of returning an option.
+    pub fn try_get(&self, params: &ComputePipelineParameters) -> Option<&ComputePipeline> {
+        self.instances.get(params)
+    }
+}
@@ -0,0 +1,510 @@
+use crate::BindGroupLayoutDef;
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use wgpu::util::{BufferInitDescriptor, DeviceExt, TextureDataOrder};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages,
+    Color, ColorTargetState, ColorWrites, CommandEncoder, Device, Extent3d, FilterMode,
+    FragmentState, LoadOp, MipmapFilterMode, MultisampleState, Operations,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+    SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// The format of the cubemaps created by [EnvironmentMapFilter]
+pub const ENVIRONMENT_MAP_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// A cube texture with a cube view, stored as an asset and drawn by [SkyboxOperation](crate::SkyboxOperation).
+/// The faces are the array layers in the order +X, -X, +Y, -Y, +Z, -Z, sampled with world space directions.
+pub struct Cubemap {
+    pub texture: Texture,
+    pub view: TextureView,
+}
+
+impl Cubemap {
+    /// Creates an empty cubemap with square faces of `size`.
+    /// [TEXTURE_BINDING](TextureUsages::TEXTURE_BINDING) is always added to the usages.
+    pub fn new(
+        device: &Device,
+        size: u32,
+        format: TextureFormat,
+        mip_levels: u32,
+        usages: TextureUsages,
+    ) -> Self {
+        Self::from_texture(device.create_texture(&Self::descriptor(
+            size,
+            format,
+            mip_levels,
+            usages | TextureUsages::TEXTURE_BINDING,
+        )))
+    }
+
+    /// Creates a cubemap from the data of all faces, face after face with all mip levels of a face before the next
+    pub fn with_data(
+        device: &Device,
+        queue: &Queue,
+        size: u32,
+        format: TextureFormat,
+        mip_levels: u32,
+        data: &[u8],
+    ) -> Self {
+        Self::from_texture(device.create_texture_with_data(
+            queue,
+            &Self::descriptor(
+                size,
+                format,
+                mip_levels,
+                TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            ),
+            TextureDataOrder::LayerMajor,
+            data,
+        ))
+    }
+
+    fn descriptor(
+        size: u32,
+        format: TextureFormat,
+        mip_levels: u32,
+        usage: TextureUsages,
+    ) -> TextureDescriptor<'static> {
+        let full_chain = u32::BITS - size.max(1).leading_zeros();
+        TextureDescriptor {
+            label: Some("Cubemap"),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: mip_levels.clamp(1, full_chain),
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        }
+    }
+
+    fn from_texture(texture: Texture) -> Self {
+        Self {
+            view: texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::Cube),
+                ..Default::default()
+            }),
+            texture,
+        }
+    }
+
+    /// The width and height of the faces
+    pub fn size(&self) -> u32 {
+        self.texture.width()
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.texture.mip_level_count()
+    }
+
+    /// A 2D view of one mip level of a face, for rendering to it
+    pub fn face_view(&self, face: u32, mip_level: u32) -> TextureView {
+        self.texture.create_view(&TextureViewDescriptor {
+            label: Some("Cubemap face"),
+            dimension: Some(TextureViewDimension::D2),
+            base_mip_level: mip_level,
+            mip_level_count: Some(1),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    /// Creates a bind group of the [CubemapLayout]
+    pub fn create_bind_group(
+        &self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        sampler: &Sampler,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Cubemap bind group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&self.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+}
+
+/// Bind group layout of a [Cubemap] with a filtering sampler, declaring `environment_map` and `environment_sampler`.
+/// Can be used to sample prefiltered maps from [EnvironmentMapFilter] for image based lighting.
+pub struct CubemapLayout;
+
+impl BindGroupLayoutDef for CubemapLayout {
+    const LAYOUT: &'static BindGroupLayoutDescriptor<'static> = &BindGroupLayoutDescriptor {
+        label: Some("Cubemap BGLayout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    };
+    const LIBRARY: &'static str = "@group(#BIND_GROUP) @binding(0)
+var environment_map: texture_cube<f32>;
+@group(#BIND_GROUP) @binding(1)
+var environment_sampler: sampler;";
+}
+
+/// Converts and prefilters [Cubemaps](Cubemap) on the GPU, inserted during [Init](modul_core::Init).
+/// The commands are recorded to the encoder, the results can be used once it is submitted.
+/// All created cubemaps use [ENVIRONMENT_MAP_FORMAT].
+#[derive(Resource)]
+pub struct EnvironmentMapFilter {
+    equirectangular: RenderPipeline,
+    downsample: RenderPipeline,
+    specular: RenderPipeline,
+    irradiance: RenderPipeline,
+    equirectangular_layout: BindGroupLayout,
+    cube_layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
+impl EnvironmentMapFilter {
+    pub fn new(device: &Device) -> Self {
+        let params = BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let sampler_entry = BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        };
+        let texture_entry = |binding, view_dimension| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let equirectangular_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Equirectangular BGLayout"),
+            entries: &[
+                texture_entry(0, TextureViewDimension::D2),
+                sampler_entry,
+                params,
+            ],
+        });
+        let cube_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Environment map filter BGLayout"),
+            entries: &[
+                sampler_entry,
+                params,
+                texture_entry(3, TextureViewDimension::Cube),
+            ],
+        });
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Environment map filter shader"),
+            source: ShaderSource::Wgsl(
+                concat!(
+                    include_str!("sequence/fullscreen.wgsl"),
+                    include_str!("environment_map.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline = |layout: &BindGroupLayout, entry_point: &str| {
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Environment map filter pipeline layout"),
+                bind_group_layouts: &[Some(layout)],
+                immediate_size: 0,
+            });
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("Environment map filter pipeline"),
+                layout: Some(&layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: Default::default(),
+                    targets: &[Some(ColorTargetState {
+                        format: ENVIRONMENT_MAP_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                multiview_mask: None,
+                cache: None,
+            })
+        };
+        Self {
+            equirectangular: pipeline(&equirectangular_layout, "fs_equirectangular"),
+            downsample: pipeline(&cube_layout, "fs_downsample"),
+            specular: pipeline(&cube_layout, "fs_specular"),
+            irradiance: pipeline(&cube_layout, "fs_irradiance"),
+            equirectangular_layout,
+            cube_layout,
+            sampler: device.create_sampler(&SamplerDescriptor {
+                label: Some("Environment map filter sampler"),
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                mipmap_filter: MipmapFilterMode::Linear,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Converts an equirectangular (latitude/longitude) image to a cubemap with faces of `size`.
+    /// The center of the image is -Z, and the top is +Y. The other mip levels are generated, see [generate_mipmaps](Self::generate_mipmaps).
+    pub fn equirectangular_to_cubemap(
+        &self,
+        device: &Device,
+        command_encoder: &mut CommandEncoder,
+        equirectangular: &TextureView,
+        size: u32,
+        mip_levels: u32,
+    ) -> Cubemap {
+        let cubemap = Cubemap::new(
+            device,
+            size,
+            ENVIRONMENT_MAP_FORMAT,
+            mip_levels,
+            TextureUsages::RENDER_ATTACHMENT,
+        );
+        for face in 0..6 {
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Equirectangular bind group"),
+                layout: &self.equirectangular_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(equirectangular),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&self.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: self.params(device, face, 0.0, 0).as_entire_binding(),
+                    },
+                ],
+            });
+            filter_pass(
+                command_encoder,
+                &self.equirectangular,
+                &bind_group,
+                &cubemap.face_view(face, 0),
+            );
+        }
+        self.generate_mipmaps(device, command_encoder, &cubemap);
+        cubemap
+    }
+
+    /// Fills the mip levels after the first by downsampling.
+    /// The cubemap needs [RENDER_ATTACHMENT](TextureUsages::RENDER_ATTACHMENT) usage and [ENVIRONMENT_MAP_FORMAT].
+    pub fn generate_mipmaps(
+        &self,
+        device: &Device,
+        command_encoder: &mut CommandEncoder,
+        cubemap: &Cubemap,
+    ) {
+        for mip_level in 1..cubemap.mip_level_count() {
+            let source = cubemap.texture.create_view(&TextureViewDescriptor {
+                label: Some("Cubemap mip level"),
+                dimension: Some(TextureViewDimension::Cube),
+                base_mip_level: mip_level - 1,
+                mip_level_count: Some(1),
+                ..Default::default()
+            });
+            for face in 0..6 {
+                let bind_group = self.cube_bind_group(device, &source, face, 0.0, 0);
+                filter_pass(
+                    command_encoder,
+                    &self.downsample,
+                    &bind_group,
+                    &cubemap.face_view(face, mip_level),
+                );
+            }
+        }
+    }
+
+    /// Prefilters the source for specular image based lighting with the GGX distribution.
+    /// Mip level `i` of the result is filtered for a roughness of `i / (mip_levels - 1)`, so the first level is a copy.
+    /// Sources with mip levels alias less, `sample_count` samples are taken per texel.
+    pub fn prefilter_specular(
+        &self,
+        device: &Device,
+        command_encoder: &mut CommandEncoder,
+        source: &Cubemap,
+        size: u32,
+        mip_levels: u32,
+        sample_count: u32,
+    ) -> Cubemap {
+        let cubemap = Cubemap::new(
+            device,
+            size,
+            ENVIRONMENT_MAP_FORMAT,
+            mip_levels,
+            TextureUsages::RENDER_ATTACHMENT,
+        );
+        let levels = cubemap.mip_level_count();
+        for mip_level in 0..levels {
+            let roughness = match levels {
+                1 => 0.0,
+                _ => mip_level as f32 / (levels - 1) as f32,
+            };
+            for face in 0..6 {
+                let bind_group =
+                    self.cube_bind_group(device, &source.view, face, roughness, sample_count);
+                filter_pass(
+                    command_encoder,
+                    &self.specular,
+                    &bind_group,
+                    &cubemap.face_view(face, mip_level),
+                );
+            }
+        }
+        cubemap
+    }
+
+    /// Convolves the source with a cosine lobe, for diffuse image based lighting.
+    /// The result is the average incoming light of each normal (irradiance / PI), so it is multiplied by the albedo.
+    /// Small sizes like 32 are enough, `sample_count` samples are taken per texel.
+    pub fn irradiance(
+        &self,
+        device: &Device,
+        command_encoder: &mut CommandEncoder,
+        source: &Cubemap,
+        size: u32,
+        sample_count: u32,
+    ) -> Cubemap {
+        let cubemap = Cubemap::new(
+            device,
+            size,
+            ENVIRONMENT_MAP_FORMAT,
+            1,
+            TextureUsages::RENDER_ATTACHMENT,
+        );
+        for face in 0..6 {
+            let bind_group = self.cube_bind_group(device, &source.view, face, 1.0, sample_count);
+            filter_pass(
+                command_encoder,
+                &self.irradiance,
+                &bind_group,
+                &cubemap.face_view(face, 0),
+            );
+        }
+        cubemap
+    }
+
+    /// The `FilterParams` of a pass
+    fn params(&self, device: &Device, face: u32, roughness: f32, sample_count: u32) -> Buffer {
+        let params = [face, roughness.to_bits(), sample_count.max(1), 0];
+        device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment map filter params"),
+            contents: bytemuck::cast_slice(&params),
+            usage: BufferUsages::UNIFORM,
+        })
+    }
+
+    fn cube_bind_group(
+        &self,
+        device: &Device,
+        source: &TextureView,
+        face: u32,
+        roughness: f32,
+        sample_count: u32,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Environment map filter bind group"),
+            layout: &self.cube_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self
+                        .params(device, face, roughness, sample_count)
+                        .as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(source),
+                },
+            ],
+        })
+    }
+}
+
+/// Draws a fullscreen triangle to a face
+fn filter_pass(
+    command_encoder: &mut CommandEncoder,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    destination: &TextureView,
+) {
+    let mut pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Environment map filter pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: destination,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::BLACK),
+                store: StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+        multiview_mask: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+pub(crate) fn init_environment_map_filter(mut commands: Commands, ctx: Res<RenderContext>) {
+    commands.insert_resource(EnvironmentMapFilter::new(&ctx.device));
+}
@@ -0,0 +1,246 @@
+use crate::{camera_view_size, Camera, GlobalTransform, Mesh};
+use bevy_ecs::prelude::*;
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use modul_asset::{AssetId, Assets};
+
+/// [SystemSet] within [Synchronize](crate::Synchronize) that computes the bounds of [MeshBounds] entities and the [VisibleEntities] of cameras.
+/// It runs after [TransformPropagateSet](crate::TransformPropagateSet), systems batching visible entities should run after it.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VisibilitySet;
+
+/// An axis aligned bounding box in the local space of the entity, see [GlobalTransform]
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[require(ViewVisibility)]
+pub struct Aabb {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+}
+
+impl Aabb {
+    pub fn from_min_max(min: Vec3, max: Vec3) -> Self {
+        Self {
+            center: (min + max) / 2.0,
+            half_extents: (max - min) / 2.0,
+        }
+    }
+
+    /// The smallest box containing the points, None if there are none
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let (min, max) = points.fold((first, first), |(min, max), p| (min.min(p), max.max(p)));
+        Some(Self::from_min_max(min, max))
+    }
+
+    pub fn min(&self) -> Vec3 {
+        self.center - self.half_extents
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.center + self.half_extents
+    }
+
+    /// The box containing this box after the transform
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let center = transform.transform_point3(self.center);
+        let half_extents = transform.x_axis.xyz().abs() * self.half_extents.x
+            + transform.y_axis.xyz().abs() * self.half_extents.y
+            + transform.z_axis.xyz().abs() * self.half_extents.z;
+        Self {
+            center,
+            half_extents,
+        }
+    }
+}
+
+/// A bounding sphere in the local space of the entity, see [GlobalTransform].
+/// It is tested before the [Aabb] if an entity has both.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[require(ViewVisibility)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// The sphere containing the box
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        Self {
+            center: aabb.center,
+            radius: aabb.half_extents.length(),
+        }
+    }
+
+    /// The sphere containing this sphere after the transform, scaled by the largest axis scale
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let scale = transform
+            .x_axis
+            .xyz()
+            .length()
+            .max(transform.y_axis.xyz().length())
+            .max(transform.z_axis.xyz().length());
+        Self {
+            center: transform.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Computes the [Aabb] and [BoundingSphere] of the entity from the positions of the [Mesh] during [VisibilitySet].
+/// They are recomputed when this component or [Assets<Mesh>] changes, and removed while the mesh does not exist.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshBounds {
+    pub mesh: AssetId<Mesh>,
+    /// The shader location of the position attribute
+    pub position_location: u32,
+}
+
+impl MeshBounds {
+    /// Bounds of the positions at shader location 0
+    pub fn new(mesh: AssetId<Mesh>) -> Self {
+        Self {
+            mesh,
+            position_location: 0,
+        }
+    }
+}
+
+/// Entities with bounds and this component are always visible
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct NoFrustumCulling;
+
+/// Whether the entity was visible to any camera with [VisibleEntities] during the last [VisibilitySet]
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ViewVisibility(pub bool);
+
+/// Add to a [Camera] entity to have the entities with an [Aabb] or [BoundingSphere] inside its frustum collected during [VisibilitySet].
+/// Entities without a [GlobalTransform] are not collected.
+#[derive(Component, Clone, Debug, Default)]
+pub struct VisibleEntities(pub Vec<Entity>);
+
+impl VisibleEntities {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.0.contains(&entity)
+    }
+}
+
+/// The six planes of a view frustum, pointing inwards with `xyz` the normal and `w` the distance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// The frustum of a view projection matrix with a depth range of 0 to 1.
+    /// The far plane of infinite projections never culls.
+    pub fn from_view_projection(view_projection: &Mat4) -> Self {
+        let row = |i| view_projection.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ];
+        Self {
+            planes: planes.map(|plane| {
+                let length = plane.xyz().length();
+                // degenerate planes (like the far plane of infinite projections) contain everything
+                match length > f32::EPSILON {
+                    true => plane / length,
+                    false => Vec4::new(0.0, 0.0, 0.0, 1.0),
+                }
+            }),
+        }
+    }
+
+    /// Whether the world space sphere is at least partially inside
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    /// Whether the world space box is at least partially inside, boxes near the corners may be reported as inside
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            let radius = normal.abs().dot(aabb.half_extents);
+            normal.dot(aabb.center) + plane.w >= -radius
+        })
+    }
+}
+
+pub(crate) fn update_mesh_bounds(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    query: Query<(Entity, Ref<MeshBounds>)>,
+) {
+    let meshes_changed = meshes.is_changed();
+    for (entity, bounds) in query.iter() {
+        if !meshes_changed && !bounds.is_changed() {
+            continue;
+        }
+        let aabb = meshes
+            .get(bounds.mesh)
+            .and_then(|mesh| mesh.compute_aabb(bounds.position_location));
+        match aabb {
+            Some(aabb) => {
+                commands
+                    .entity(entity)
+                    .insert((aabb, BoundingSphere::from_aabb(&aabb)));
+            }
+            None => {
+                commands.entity(entity).remove::<(Aabb, BoundingSphere)>();
+            }
+        }
+    }
+}
+
+pub(crate) fn cull_entities(world: &mut World) {
+    let mut cameras = world
+        .query_filtered::<(Entity, &Camera, Option<&GlobalTransform>), With<VisibleEntities>>();
+    let mut frustums = Vec::new();
+    for (entity, camera, global) in cameras.iter(world) {
+        // suspended surfaces keep the last visible entities
+        let Some((width, height)) = camera_view_size(world, entity) else {
+            continue;
+        };
+        let transform = global.map_or(camera.transform, |g| g.0);
+        let view_projection =
+            camera.projection.matrix(width as f32 / height as f32) * transform.inverse();
+        frustums.push((entity, Frustum::from_view_projection(&view_projection)));
+    }
+
+    let mut entities = world.query_filtered::<(
+        Entity,
+        &GlobalTransform,
+        Option<&Aabb>,
+        Option<&BoundingSphere>,
+        Has<NoFrustumCulling>,
+        &mut ViewVisibility,
+    ), Or<(With<Aabb>, With<BoundingSphere>)>>();
+    let mut visible = vec![Vec::new(); frustums.len()];
+    for (entity, global, aabb, sphere, no_culling, mut visibility) in entities.iter_mut(world) {
+        let sphere = sphere.map(|s| s.transformed(&global.0));
+        let aabb = aabb.map(|a| a.transformed(&global.0));
+        let mut any = false;
+        for ((_, frustum), visible) in frustums.iter().zip(&mut visible) {
+            let inside = no_culling
+                || (sphere.is_none_or(|s| frustum.intersects_sphere(&s))
+                    && aabb.is_none_or(|a| frustum.intersects_aabb(&a)));
+            if inside {
+                visible.push(entity);
+                any = true;
+            }
+        }
+        visibility.set_if_neq(ViewVisibility(any));
+    }
+
+    for ((camera, _), visible) in frustums.into_iter().zip(visible) {
+        if let Some(mut entities) = world.get_mut::<VisibleEntities>(camera) {
+            entities.0 = visible;
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    ApplyOffscreenTargetsSystemSet, Draw, DrawSet, GpuProfilingPlugin, GpuTimings, TracksWindowSize,
+};
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use std::time::Duration;
+
+/// Readbacks skipped after changing the scale, as the timings lag a few frames behind and were measured at the old scale
+const SETTLE_TIMINGS: u32 = 2;
+
+/// Scales the [OffscreenRenderTargets](crate::OffscreenRenderTarget) with a [DynamicResolution] based on the GPU time of recent frames.
+/// Adds the [GpuProfilingPlugin] if it is not added yet, without its features nothing is measured and the scale stays at the max.
+pub struct DynamicResolutionPlugin;
+
+impl Plugin for DynamicResolutionPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<GpuProfilingPlugin>() {
+            app.add_plugins(GpuProfilingPlugin);
+        }
+        app.add_systems(
+            Draw,
+            update_dynamic_resolution
+                .before(ApplyOffscreenTargetsSystemSet)
+                .in_set(DrawSet::PrepareTargets),
+        );
+    }
+}
+
+/// Resizes the [OffscreenRenderTarget](crate::OffscreenRenderTarget) of the entity to the size of the window times a scale,
+/// which is lowered when the total of the [GpuTimings] is over `target_time` and raised again when there is headroom.
+/// Render the scene into the target and upsample it to the surface with a [BlitOperation](crate::BlitOperation), so the target needs
+/// [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage. The scale is applied with a [TracksWindowSize], which is inserted if missing.
+#[derive(Component, Clone, Debug)]
+pub struct DynamicResolution {
+    pub window: Entity,
+    /// The GPU time per frame to stay under
+    pub target_time: Duration,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// The scale changes in multiples of this, so the textures are not recreated every frame
+    pub step: f32,
+    /// The scale is only raised while the GPU time is below this fraction of `target_time`
+    pub headroom: f32,
+    scale: f32,
+    // readbacks left to skip after a change, see SETTLE_TIMINGS
+    settling: u32,
+}
+
+impl DynamicResolution {
+    /// Scales between 0.5 and 1 in steps of 0.05, starting at full resolution
+    pub fn new(window: Entity, target_time: Duration) -> Self {
+        Self {
+            window,
+            target_time,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            step: 0.05,
+            headroom: 0.8,
+            scale: 1.0,
+            settling: 0,
+        }
+    }
+
+    /// The current scale of the window size
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// The next scale for a frame that took `gpu_time` at the current scale
+    fn next_scale(&self, gpu_time: Duration) -> f32 {
+        let (time, target) = (gpu_time.as_secs_f32(), self.target_time.as_secs_f32());
+        if time <= 0.0 || (time <= target && time >= target * self.headroom) {
+            return self.scale;
+        }
+        // the GPU time mostly follows the amount of pixels, which is the scale squared
+        let ideal = self.scale * (target / time).sqrt();
+        // half way, so a single slow frame does not drop the resolution all the way
+        let next = (self.scale + ideal) / 2.0;
+        // at least one step, or small scales would never reach the next step
+        let steps = match time > target {
+            true => ((self.scale - next) / self.step).ceil().max(1.0),
+            false => -((next - self.scale) / self.step).floor().max(1.0),
+        };
+        // on the grid of steps, so rounding errors don't add up
+        (((self.scale / self.step).round() - steps) * self.step)
+            .clamp(self.min_scale, self.max_scale)
+    }
+}
+
+fn update_dynamic_resolution(
+    mut commands: Commands,
+    timings: Res<GpuTimings>,
+    mut targets: Query<(
+        Entity,
+        &mut DynamicResolution,
+        Option<&mut TracksWindowSize>,
+    )>,
+) {
+    // the timings only change when a new frame has been read back
+    let gpu_time = timings.is_changed().then(|| timings.total());
+    for (entity, mut resolution, tracks) in targets.iter_mut() {
+        if let Some(gpu_time) = gpu_time {
+            let scale = resolution.next_scale(gpu_time);
+            if resolution.settling > 0 {
+                resolution.settling -= 1;
+            } else if scale != resolution.scale {
+                resolution.scale = scale;
+                resolution.settling = SETTLE_TIMINGS;
+            }
+        }
+        let tracking = TracksWindowSize(resolution.window, resolution.scale);
+        match tracks {
+            Some(mut tracks) if tracks.0 != tracking.0 || tracks.1 != tracking.1 => {
+                *tracks = tracking;
+            }
+            Some(_) => {}
+            None => {
+                commands.entity(entity).insert(tracking);
+            }
+        }
+    }
+}
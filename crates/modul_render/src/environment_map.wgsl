@@ -0,0 +1,115 @@
+struct FilterParams {
+    face: u32,
+    roughness: f32,
+    sample_count: u32,
+}
+
+// only used by the equirectangular conversion
+@group(0) @binding(0)
+var equirectangular: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: FilterParams;
+// the source of the cube filters
+@group(0) @binding(3)
+var source: texture_cube<f32>;
+
+const PI: f32 = 3.14159265359;
+
+// the direction through a texel of the face, matching how cubemaps are sampled
+fn face_direction(uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch params.face {
+        case 0u: { return normalize(vec3<f32>(1.0, -st.y, -st.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -st.y, st.x)); }
+        case 2u: { return normalize(vec3<f32>(st.x, 1.0, st.y)); }
+        case 3u: { return normalize(vec3<f32>(st.x, -1.0, -st.y)); }
+        case 4u: { return normalize(vec3<f32>(st.x, -st.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-st.x, -st.y, -1.0)); }
+    }
+}
+
+// low discrepancy sample i of n
+fn hammersley(i: u32, n: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(n), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// rotates the z up direction to be around n
+fn tangent_to_world(direction: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return normalize(tangent * direction.x + bitangent * direction.y + n * direction.z);
+}
+
+// the source mip level covering the solid angle of a sample with the pdf, to alias less than always reading level 0
+fn sample_level(pdf: f32) -> f32 {
+    let size = f32(textureDimensions(source).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+    let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf + 0.0001);
+    let max_level = f32(textureNumLevels(source) - 1u);
+    return clamp(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, max_level);
+}
+
+@fragment
+fn fs_equirectangular(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(in.uv);
+    // -Z in the center, +Y at the top
+    let uv = vec2<f32>(
+        atan2(direction.x, -direction.z) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    return vec4<f32>(textureSampleLevel(equirectangular, source_sampler, uv, 0.0).rgb, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSampleLevel(source, source_sampler, face_direction(in.uv), 0.0).rgb, 1.0);
+}
+
+@fragment
+fn fs_specular(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(in.uv);
+    if params.roughness == 0.0 {
+        return vec4<f32>(textureSampleLevel(source, source_sampler, n, 0.0).rgb, 1.0);
+    }
+    let a = params.roughness * params.roughness;
+    let a2 = a * a;
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        // importance sampling the GGX distribution, with the view direction equal to the normal
+        let xi = hammersley(i, params.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a2 - 1.0) * xi.y));
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let h = tangent_to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            let d = a2 / (PI * pow(cos_theta * cos_theta * (a2 - 1.0) + 1.0, 2.0));
+            let level = sample_level(d / 4.0);
+            color += textureSampleLevel(source, source_sampler, l, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(weight, 0.0001), 1.0);
+}
+
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(in.uv);
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < params.sample_count; i++) {
+        // cosine weighted, so the average is the convolution
+        let xi = hammersley(i, params.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let l = tangent_to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+        let level = sample_level(cos_theta / PI);
+        color += textureSampleLevel(source, source_sampler, l, level).rgb;
+    }
+    return vec4<f32>(color / f32(params.sample_count), 1.0);
+}
@@ -0,0 +1,82 @@
+use bevy_ecs::prelude::*;
+use std::time::{Duration, Instant};
+
+/// CPU timings of the last completed [Redraw](modul_core::Redraw), updated by the [RenderPlugin](crate::RenderPlugin).
+/// Systems running during a frame see the timings of the frame before it, except for `frame` and `frame_time`.
+/// The parts are zero in frames that were not drawn, GPU time is measured by the [GpuProfilingPlugin](crate::GpuProfilingPlugin).
+#[derive(Resource, Clone, Debug, Default)]
+pub struct FrameStats {
+    /// The amount of [Redraws](modul_core::Redraw) so far, including the current one
+    pub frame: u64,
+    /// The time between the start of the previous frame and the current one
+    pub frame_time: Duration,
+    /// The time from the start to the end of the frame
+    pub cpu_time: Duration,
+    /// Handling the window events
+    pub events: Duration,
+    /// Running [PreSynchronize](crate::PreSynchronize) to [PostSynchronize](crate::PostSynchronize)
+    pub synchronize: Duration,
+    /// Running [PreDraw](crate::PreDraw) to [PostDraw](crate::PostDraw), including `encode` and `submit`
+    pub draw: Duration,
+    /// Encoding the sequences into command buffers
+    pub encode: Duration,
+    /// Submitting the command buffers to the queue
+    pub submit: Duration,
+    /// Presenting the surface textures
+    pub present: Duration,
+    /// The parts of the current frame, copied to the fields when it ends
+    pub(crate) current: FrameParts,
+    start: Option<Instant>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FrameParts {
+    pub(crate) events: Duration,
+    pub(crate) synchronize: Duration,
+    pub(crate) draw: Duration,
+    pub(crate) encode: Duration,
+    pub(crate) submit: Duration,
+    pub(crate) present: Duration,
+}
+
+impl FrameStats {
+    /// Frames per second based on the last frame time
+    pub fn fps(&self) -> f32 {
+        match self.frame_time.is_zero() {
+            true => 0.0,
+            false => 1.0 / self.frame_time.as_secs_f32(),
+        }
+    }
+}
+
+pub(crate) fn begin_frame_stats(mut stats: ResMut<FrameStats>) {
+    let now = Instant::now();
+    if let Some(start) = stats.start {
+        stats.frame_time = now - start;
+    }
+    stats.frame += 1;
+    stats.start = Some(now);
+    stats.current = FrameParts::default();
+}
+
+/// Adds the time spent encoding and submitting a command buffer
+pub(crate) fn record_submission(world: &mut World, encode: Duration, submit: Duration) {
+    if let Some(mut stats) = world.get_resource_mut::<FrameStats>() {
+        stats.current.encode += encode;
+        stats.current.submit += submit;
+    }
+}
+
+pub(crate) fn end_frame_stats(mut stats: ResMut<FrameStats>) {
+    let Some(start) = stats.start else {
+        return;
+    };
+    let parts = stats.current.clone();
+    stats.cpu_time = start.elapsed();
+    stats.events = parts.events;
+    stats.synchronize = parts.synchronize;
+    stats.draw = parts.draw;
+    stats.encode = parts.encode;
+    stats.submit = parts.submit;
+    stats.present = parts.present;
+}
@@ -0,0 +1,315 @@
+use crate::{
+    BindGroupProvider, CameraBindGroup, CameraUniformProvider,
+    DirectRenderPipelineResourceProvider, GenericColorTargetState, GenericDepthStencilState,
+    GenericFragmentState, GenericMultisampleState, GenericRenderPipelineDescriptor,
+    GenericVertexBufferLayout, GenericVertexState, GpuVec, Operation, OperationBuilder,
+    OperationError, PipelineLayoutComposer, RenderPipelineManager, RenderTargetSource,
+};
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec2, Vec3};
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use std::f32::consts::TAU;
+use wgpu::{
+    BlendState, BufferUsages, CommandEncoder, CompareFunction, DepthBiasState, Device,
+    PipelineLayout, PrimitiveState, PrimitiveTopology, ShaderModule, StencilState, VertexAttribute,
+    VertexFormat, VertexStepMode,
+};
+
+/// The number of lines of a gizmo circle
+const CIRCLE_SEGMENTS: u32 = 32;
+
+/// [SystemSet] within [Draw](crate::Draw) that uploads the lines of the [Gizmos] and clears them for the next frame.
+/// It runs after [ApplyOffscreenTargetsSystemSet](crate::ApplyOffscreenTargetsSystemSet) and before the sequences.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GizmoUploadSet;
+
+/// An end of a gizmo line
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct GizmoVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl GizmoVertex {
+    /// The vertex layout used by the gizmo shader, the position at location 0 and the color at 1
+    pub fn vertex_buffer_layout() -> GenericVertexBufferLayout {
+        GenericVertexBufferLayout {
+            array_stride: size_of::<Self>() as u64,
+            step_mode: VertexStepMode::Vertex,
+            attributes: vec![
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: 0,
+                    shader_location: 0,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x3.size(),
+                    shader_location: 1,
+                },
+            ],
+        }
+    }
+}
+
+/// Immediate mode debug lines in world space, drawn by [GizmoOperations](GizmoOperation).
+/// Shapes added before [Draw](crate::Draw) are drawn that frame, then they are cleared during [GizmoUploadSet].
+#[derive(Resource)]
+pub struct Gizmos {
+    vertices: Vec<GizmoVertex>,
+    /// the lines of the current frame
+    buffer: GpuVec<GizmoVertex>,
+}
+
+impl Default for Gizmos {
+    fn default() -> Self {
+        Self {
+            vertices: Vec::new(),
+            buffer: GpuVec::new(BufferUsages::VERTEX),
+        }
+    }
+}
+
+impl Gizmos {
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        self.vertices.extend([
+            GizmoVertex {
+                position: start.to_array(),
+                color,
+            },
+            GizmoVertex {
+                position: end.to_array(),
+                color,
+            },
+        ]);
+    }
+
+    /// Lines between consecutive points, closing the loop if `closed`
+    pub fn line_strip(
+        &mut self,
+        points: impl IntoIterator<Item = Vec3>,
+        closed: bool,
+        color: [f32; 4],
+    ) {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return;
+        };
+        let mut previous = first;
+        for point in points {
+            self.line(previous, point, color);
+            previous = point;
+        }
+        if closed {
+            self.line(previous, first, color);
+        }
+    }
+
+    /// A rectangle in the XY plane of the rotation
+    pub fn rect(&mut self, center: Vec3, rotation: Quat, size: Vec2, color: [f32; 4]) {
+        let half = size / 2.0;
+        let corners = [
+            Vec2::new(-half.x, -half.y),
+            Vec2::new(half.x, -half.y),
+            Vec2::new(half.x, half.y),
+            Vec2::new(-half.x, half.y),
+        ];
+        let corners = corners.map(|c| center + rotation * c.extend(0.0));
+        self.line_strip(corners, true, color);
+    }
+
+    /// A circle around the normal
+    pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: [f32; 4]) {
+        let rotation = Quat::from_rotation_arc(Vec3::Z, normal.normalize_or(Vec3::Z));
+        let points = (0..CIRCLE_SEGMENTS).map(|i| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+            center + rotation * Vec3::new(angle.cos(), angle.sin(), 0.0) * radius
+        });
+        self.line_strip(points, true, color);
+    }
+
+    /// A circle around each axis
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4]) {
+        for normal in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.circle(center, normal, radius, color);
+        }
+    }
+
+    /// The X (red), Y (green) and Z (blue) axes of the transform, `length` long before the transform
+    pub fn axes(&mut self, transform: Mat4, length: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        let axes = [
+            (Vec3::X, [1.0, 0.0, 0.0, 1.0]),
+            (Vec3::Y, [0.0, 1.0, 0.0, 1.0]),
+            (Vec3::Z, [0.0, 0.0, 1.0, 1.0]),
+        ];
+        for (axis, color) in axes {
+            self.line(origin, transform.transform_point3(axis * length), color);
+        }
+    }
+
+    /// Removes the shapes added this frame
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// The number of vertices added this frame, two per line
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// The vertices drawn this frame, as a line list
+    pub fn buffer(&self) -> &GpuVec<GizmoVertex> {
+        &self.buffer
+    }
+}
+
+/// The pipelines of [GizmoOperations](GizmoOperation), inserted during [Init](modul_core::Init).
+/// Group 0 is the [CameraUniformProvider].
+#[derive(Resource)]
+pub struct GizmoPipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+    /// Tests against the depth buffer without writing to it
+    pub depth_tested_manager: AssetId<RenderPipelineManager>,
+}
+
+/// Draws the [Gizmos] of the frame to the target, as seen by the [Camera](crate::Camera) entity.
+/// With `depth_test` lines behind the contents of the depth buffer are hidden, this does nothing for targets without a depth buffer.
+pub struct GizmoOperation {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+    pub depth_test: bool,
+}
+
+impl Operation for GizmoOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(camera) = world.get::<CameraBindGroup>(self.camera) else {
+            return Err(format!("{} has no camera bind group", self.camera).into());
+        };
+        let camera = camera.bind_group().clone();
+        let buffer = world.resource::<Gizmos>().buffer();
+        let vertices = buffer.len() as u32;
+        let Some(buffer) = buffer.buffer().cloned() else {
+            return Ok(());
+        };
+        if vertices == 0 {
+            return Ok(());
+        }
+        let gizmo_pipeline = world.resource::<GizmoPipeline>();
+        let manager = match self.depth_test {
+            true => gizmo_pipeline.depth_tested_manager,
+            false => gizmo_pipeline.manager,
+        };
+        let mut pipeline = None;
+        world.asset_scope(manager, |world, manager| {
+            pipeline = manager.get_compatible(self.render_target, world).cloned();
+        });
+        let Some(pipeline) = pipeline else {
+            return Ok(());
+        };
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &camera, &[]);
+        pass.set_vertex_buffer(0, buffer.slice(..));
+        pass.draw(0..vertices, 0..1);
+        Ok(())
+    }
+}
+
+impl OperationBuilder for GizmoOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+pub(crate) fn init_gizmo_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    let mut composer = PipelineLayoutComposer::new();
+    composer.add_bind_group(CameraUniformProvider::new(&ctx.device));
+    let layout = layouts.add(composer.compose_pipeline_layout(&ctx.device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(&ctx.device, "Gizmo shader", include_str!("gizmos.wgsl"))
+            .expect("the built-in gizmo shader has balanced directives")
+            .clone(),
+    );
+    let mut create_manager = |depth_compare| {
+        managers.add(RenderPipelineManager::new(
+            GenericRenderPipelineDescriptor {
+                resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                    layout,
+                    vertex_shader_module: shader,
+                    fragment_shader_module: shader,
+                }),
+                label: Some("Gizmo pipeline".to_string()),
+                vertex_state: GenericVertexState {
+                    entry_point: "vs_main".to_string(),
+                    buffers: vec![GizmoVertex::vertex_buffer_layout()],
+                },
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(GenericDepthStencilState {
+                    depth_write_enable: false,
+                    depth_compare,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: GenericMultisampleState {
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: Some(GenericFragmentState {
+                    entry_point: "fs_main".to_string(),
+                    targets: vec![GenericColorTargetState::new(Some(
+                        BlendState::ALPHA_BLENDING,
+                    ))],
+                }),
+            },
+        ))
+    };
+    let manager = create_manager(CompareFunction::Always);
+    let depth_tested_manager = create_manager(CompareFunction::LessEqual);
+    commands.insert_resource(GizmoPipeline {
+        manager,
+        depth_tested_manager,
+    });
+}
+
+pub(crate) fn upload_gizmos(ctx: Res<RenderContext>, mut gizmos: ResMut<Gizmos>) {
+    let gizmos = &mut *gizmos;
+    gizmos.buffer.clear();
+    gizmos.buffer.extend_from_slice(&gizmos.vertices);
+    gizmos.buffer.flush(&ctx.device, &ctx.queue);
+    gizmos.vertices.clear();
+}
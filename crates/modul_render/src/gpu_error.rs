@@ -0,0 +1,100 @@
+use bevy_ecs::prelude::*;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use wgpu::{Device, ErrorFilter, ErrorScopeGuard};
+
+/// The kind of a [GpuError], like [wgpu::ErrorFilter]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GpuErrorKind {
+    Validation,
+    OutOfMemory,
+    Internal,
+}
+
+/// A wgpu error captured while running sequences, written as a message instead of reaching the uncaptured error handler.
+/// Errors of creating bind groups, buffers and other resources are captured per operation, errors of recording
+/// passes are only reported when the command encoder is finished, so they have the label of the encoder.
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct GpuError {
+    pub kind: GpuErrorKind,
+    /// What was running, for example the operation and its sequence
+    pub label: String,
+    pub message: String,
+}
+
+impl GpuError {
+    fn new(label: String, error: wgpu::Error) -> Self {
+        let kind = match &error {
+            wgpu::Error::Validation { .. } => GpuErrorKind::Validation,
+            wgpu::Error::OutOfMemory { .. } => GpuErrorKind::OutOfMemory,
+            wgpu::Error::Internal { .. } => GpuErrorKind::Internal,
+        };
+        Self {
+            kind,
+            label,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl Error for GpuError {}
+
+impl Display for GpuError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} error in {}: {}",
+            self.kind, self.label, self.message
+        )
+    }
+}
+
+/// How [GpuError]s are handled, inserted by the [RenderPlugin](crate::RenderPlugin)
+#[derive(Resource, Clone, Debug, Default)]
+pub struct GpuErrorSettings {
+    /// Panic on the first error after writing it, so a debugger stops where it was captured (break on `rust_panic`)
+    pub break_on_error: bool,
+}
+
+/// Captures all wgpu errors until it is popped
+pub(crate) struct GpuErrorScope {
+    /// In the order they were pushed
+    guards: [ErrorScopeGuard; 3],
+}
+
+impl GpuErrorScope {
+    pub(crate) fn push(device: &Device) -> Self {
+        Self {
+            guards: [
+                device.push_error_scope(ErrorFilter::Validation),
+                device.push_error_scope(ErrorFilter::OutOfMemory),
+                device.push_error_scope(ErrorFilter::Internal),
+            ],
+        }
+    }
+
+    /// Writes the captured errors as [GpuError]s, the label is only created if there are any
+    pub(crate) fn pop(self, world: &mut World, label: impl FnOnce() -> String) {
+        let [validation, out_of_memory, internal] = self.guards;
+        let errors = [internal, out_of_memory, validation]
+            .map(|guard| pollster::block_on(guard.pop()))
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            return;
+        }
+        let label = label();
+        for error in errors {
+            let error = GpuError::new(label.clone(), error);
+            log::error!("{}", error);
+            world.write_message(error.clone());
+            if world
+                .get_resource::<GpuErrorSettings>()
+                .is_some_and(|settings| settings.break_on_error)
+            {
+                panic!("{}", error);
+            }
+        }
+    }
+}
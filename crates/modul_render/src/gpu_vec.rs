@@ -0,0 +1,246 @@
+use crate::PreDraw;
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use bytemuck::Pod;
+use modul_asset::{AssetAppExt, Assets};
+use modul_core::RenderContext;
+use std::marker::PhantomData;
+use std::ops::{Range, RangeBounds};
+use wgpu::{
+    Buffer, BufferDescriptor, BufferSlice, BufferUsages, Device, Queue, COPY_BUFFER_ALIGNMENT,
+};
+
+/// [SystemSet] within [PreDraw] that writes changed [GpuVecs](GpuVec) to the GPU
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GpuVecFlushSet;
+
+/// A [Vec] mirrored to a [Buffer], stored as an asset.
+/// Only the changed range is written on flush, and the buffer is recreated with more capacity when the data outgrows it.
+/// Flushing is done automatically in [GpuVecFlushSet] for types registered with [GpuVecPlugin].
+pub struct GpuVec<T: Pod> {
+    data: Vec<T>,
+    usage: BufferUsages,
+    buffer: Option<Buffer>,
+    /// capacity of the buffer in elements
+    capacity: usize,
+    buffer_version: u32,
+    dirty: Option<Range<usize>>,
+}
+
+impl<T: Pod> GpuVec<T> {
+    /// Creates an empty vec, [COPY_DST](BufferUsages::COPY_DST) is always added to the usage
+    pub fn new(usage: BufferUsages) -> Self {
+        Self::from_vec(usage, Vec::new())
+    }
+
+    pub fn from_vec(usage: BufferUsages, data: Vec<T>) -> Self {
+        let dirty = (!data.is_empty()).then_some(0..data.len());
+        Self {
+            data,
+            usage: usage | BufferUsages::COPY_DST,
+            buffer: None,
+            capacity: 0,
+            buffer_version: 0,
+            dirty,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Mutably gets the whole vec, marking everything as changed
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.mark_dirty(..);
+        &mut self.data
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.data.get(index)
+    }
+
+    /// Mutably gets an element, marking it as changed
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index < self.data.len() {
+            self.mark_dirty(index..index + 1);
+        }
+        self.data.get_mut(index)
+    }
+
+    /// Sets the element at the index
+    /// ## Panics
+    /// If the index is out of bounds
+    pub fn set(&mut self, index: usize, value: T) {
+        self.data[index] = value;
+        self.mark_dirty(index..index + 1);
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.data.push(value);
+        self.mark_dirty(self.data.len() - 1..);
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[T]) {
+        let start = self.data.len();
+        self.data.extend_from_slice(values);
+        self.mark_dirty(start..);
+    }
+
+    /// Removes the last element, the buffer is not written as the element is simply outside the length
+    pub fn pop(&mut self) -> Option<T> {
+        let value = self.data.pop();
+        self.clamp_dirty();
+        value
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.clamp_dirty();
+    }
+
+    /// Removes all elements, the buffer is kept so it can be refilled without reallocating
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.dirty = None;
+    }
+
+    /// Marks a range as changed, so it is written on the next flush
+    pub fn mark_dirty(&mut self, range: impl RangeBounds<usize>) {
+        let range = bounds_to_range(range, self.data.len());
+        if range.is_empty() {
+            return;
+        }
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// Returns true if there are changes that have not been flushed
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// The buffer, None if nothing has been flushed yet.
+    /// The buffer may be larger than the data, so use [slice](Self::slice) when binding it as vertex or index buffer.
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.buffer.as_ref()
+    }
+
+    /// The part of the buffer holding the data, None if there is no buffer or the vec is empty
+    pub fn slice(&self) -> Option<BufferSlice<'_>> {
+        let size = (self.data.len() * size_of::<T>()) as u64;
+        let buffer = self.buffer.as_ref()?;
+        (size > 0).then(|| buffer.slice(..size))
+    }
+
+    /// Increased every time the buffer is recreated, bind groups using the buffer must be recreated when this changes
+    pub fn buffer_version(&self) -> u32 {
+        self.buffer_version
+    }
+
+    /// Writes the changes to the buffer, creating a larger buffer if needed.
+    /// This is done automatically in [GpuVecFlushSet] for types registered with [GpuVecPlugin].
+    pub fn flush(&mut self, device: &Device, queue: &Queue) {
+        if self.data.len() > self.capacity {
+            self.capacity = self.data.len().next_power_of_two();
+            self.buffer = Some(
+                device.create_buffer(&BufferDescriptor {
+                    label: Some("gpu vec buffer"),
+                    size: ((self.capacity * size_of::<T>()) as u64)
+                        .next_multiple_of(COPY_BUFFER_ALIGNMENT),
+                    usage: self.usage,
+                    mapped_at_creation: false,
+                }),
+            );
+            self.buffer_version += 1;
+            // the new buffer has none of the old data
+            self.dirty = Some(0..self.data.len());
+        }
+        let (Some(dirty), Some(buffer)) = (self.dirty.take(), &self.buffer) else {
+            return;
+        };
+        let bytes: &[u8] = bytemuck::cast_slice(&self.data);
+        let align = COPY_BUFFER_ALIGNMENT as usize;
+        let start = (dirty.start * size_of::<T>()) / align * align;
+        let end = (dirty.end * size_of::<T>()).next_multiple_of(align);
+        if end <= bytes.len() {
+            queue.write_buffer(buffer, start as u64, &bytes[start..end]);
+        } else {
+            // the write size must be aligned, so pad the end with zeroes
+            let mut padded = bytes[start..].to_vec();
+            padded.resize(end - start, 0);
+            queue.write_buffer(buffer, start as u64, &padded);
+        }
+    }
+
+    fn clamp_dirty(&mut self) {
+        let len = self.data.len();
+        self.dirty = self
+            .dirty
+            .take()
+            .map(|d| d.start.min(len)..d.end.min(len))
+            .filter(|d| !d.is_empty());
+    }
+}
+
+fn bounds_to_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
+    use std::ops::Bound;
+    let start = match range.start_bound() {
+        Bound::Included(&s) => s,
+        Bound::Excluded(&s) => s + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&e) => e + 1,
+        Bound::Excluded(&e) => e,
+        Bound::Unbounded => len,
+    };
+    start..end.min(len)
+}
+
+/// Registers [GpuVec<T>] as an asset, and flushes them during [GpuVecFlushSet]
+pub struct GpuVecPlugin<T: Pod> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> Default for GpuVecPlugin<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Pod> GpuVecPlugin<T> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Pod + Send + Sync + 'static> Plugin for GpuVecPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.init_assets::<GpuVec<T>>();
+        app.add_systems(PreDraw, flush_gpu_vecs::<T>.in_set(GpuVecFlushSet));
+    }
+}
+
+fn flush_gpu_vecs<T: Pod + Send + Sync + 'static>(
+    mut vecs: ResMut<Assets<GpuVec<T>>>,
+    ctx: Res<RenderContext>,
+) {
+    // bypass change detection, as most frames nothing changes
+    for (_, vec) in vecs.bypass_change_detection().iter_mut() {
+        if vec.is_dirty() {
+            vec.flush(&ctx.device, &ctx.queue);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use modul_core::RenderContext;
+use wgpu::{Buffer, BufferAddress, DownlevelFlags, Features, RenderPass};
+
+/// Arguments of a non indexed indirect draw, can be stored in a [GpuVec](crate::GpuVec) or written by a compute shader.
+/// A non zero `first_instance` requires [INDIRECT_FIRST_INSTANCE](Features::INDIRECT_FIRST_INSTANCE).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct DrawIndirectArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+/// Arguments of an indexed indirect draw, see [DrawIndirectArgs]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+/// What the device supports of indirect drawing, inserted during [Init](modul_core::Init)
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct IndirectSupport {
+    /// Indirect draws are executed by the GPU, instead of being emulated or unsupported
+    pub indirect_execution: bool,
+    /// [INDIRECT_FIRST_INSTANCE](Features::INDIRECT_FIRST_INSTANCE) is enabled
+    pub first_instance: bool,
+    /// [MULTI_DRAW_INDIRECT_COUNT](Features::MULTI_DRAW_INDIRECT_COUNT) is enabled, otherwise [multi_draw_indirect_count] draws the max count
+    pub multi_draw_count: bool,
+}
+
+impl IndirectSupport {
+    pub fn new(ctx: &RenderContext) -> Self {
+        let features = ctx.device.features();
+        Self {
+            indirect_execution: ctx
+                .adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(DownlevelFlags::INDIRECT_EXECUTION),
+            first_instance: features.contains(Features::INDIRECT_FIRST_INSTANCE),
+            multi_draw_count: features.contains(Features::MULTI_DRAW_INDIRECT_COUNT),
+        }
+    }
+}
+
+pub(crate) fn init_indirect_support(mut commands: Commands, ctx: Res<RenderContext>) {
+    commands.insert_resource(IndirectSupport::new(&ctx));
+}
+
+/// Issues the draws of a buffer of [DrawIndirectArgs] or [DrawIndexedIndirectArgs], with the amount of draws read from `count_buffer` if there is one.
+/// Without [multi_draw_count](IndirectSupport::multi_draw_count) all `max_count` draws are issued, so draws past the count should have an instance count of 0.
+pub fn multi_draw_indirect_count(
+    pass: &mut RenderPass,
+    support: &IndirectSupport,
+    indexed: bool,
+    buffer: &Buffer,
+    offset: BufferAddress,
+    count_buffer: Option<(&Buffer, BufferAddress)>,
+    max_count: u32,
+) {
+    match (count_buffer.filter(|_| support.multi_draw_count), indexed) {
+        (Some((count_buffer, count_offset)), true) => pass.multi_draw_indexed_indirect_count(
+            buffer,
+            offset,
+            count_buffer,
+            count_offset,
+            max_count,
+        ),
+        (Some((count_buffer, count_offset)), false) => {
+            pass.multi_draw_indirect_count(buffer, offset, count_buffer, count_offset, max_count)
+        }
+        (None, true) => pass.multi_draw_indexed_indirect(buffer, offset, max_count),
+        (None, false) => pass.multi_draw_indirect(buffer, offset, max_count),
+    }
+}
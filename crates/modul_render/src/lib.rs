@@ -1,23 +1,105 @@
 mod render_target;
 mod sequence;
 mod pipeline_manager;
+mod compute_pipeline_manager;
 mod bind_group_composition;
+mod bind_group_cache;
+mod camera;
+mod capture;
+mod cubemap;
+mod culling;
+mod dynamic_resolution;
+mod frame_stats;
+mod gizmos;
+mod gpu_error;
+mod gpu_vec;
+mod indirect;
+mod material;
+mod memory_stats;
+mod mesh;
+mod mirror;
+mod phase;
+mod picking;
+mod pipeline_cache;
+mod ping_pong;
+mod polyline;
+mod present_stats;
+mod profiler;
+mod query;
+mod readback;
+mod sampler_cache;
+mod scene_depth;
+mod shader_composer;
+mod shader_defs;
+mod shader_error;
+mod shader_library;
+mod shadow;
+mod skybox;
+mod staging;
+mod taa;
+mod transform;
+mod uniform_allocator;
+mod viewport;
 
 use bevy_app::{App, Plugin};
 use bevy_ecs::prelude::*;
 use bevy_ecs::schedule::ScheduleLabel;
-use modul_asset::AssetAppExt;
+use modul_asset::{
+    AssetAppExt, AssetDependencies, AssetEvent, AssetId, AssetLoadSet, Assets, UntypedAssetId,
+};
 use modul_core::{
-    EventBuffer, ImportantWindow, Redraw, RenderContext, ShouldExit, SurfaceFormat,
-    UpdatingWindow, WindowComponent, WindowMap,
+    EventBuffer, ImportantWindow, Init, InitialWindowConfig, Redraw, RenderContext, ShouldExit,
+    SurfaceFormat, UpdatingWindow, WindowComponent, WindowMap,
 };
-use wgpu::{PipelineLayout, ShaderModule};
+use modul_util::HashSet;
+use std::time::Instant;
+use wgpu::{CompositeAlphaMode, PipelineLayout, ShaderModule};
 use winit::event::{Event, WindowEvent};
 
 pub use render_target::*;
 pub use pipeline_manager::*;
+pub use compute_pipeline_manager::*;
 pub use sequence::*;
 pub use bind_group_composition::*;
+pub use bind_group_cache::*;
+pub use camera::*;
+pub use capture::*;
+pub use cubemap::*;
+pub use culling::*;
+pub use dynamic_resolution::*;
+pub use frame_stats::*;
+pub use gizmos::*;
+pub use gpu_error::*;
+pub use gpu_vec::*;
+pub use indirect::*;
+pub use material::*;
+pub use memory_stats::*;
+pub use mesh::*;
+pub use mirror::*;
+pub use phase::*;
+pub use picking::*;
+pub use pipeline_cache::*;
+pub use ping_pong::*;
+pub use polyline::*;
+pub use present_stats::*;
+pub use profiler::*;
+pub use query::*;
+pub use readback::*;
+pub use sampler_cache::*;
+pub use scene_depth::*;
+pub use shader_composer::*;
+pub use shader_defs::*;
+pub use shader_error::*;
+pub use shader_library::*;
+pub use shadow::*;
+pub use skybox::*;
+pub use staging::*;
+pub use taa::*;
+pub use transform::*;
+pub use uniform_allocator::*;
+pub use viewport::*;
+
+pub use glam;
 
 /// Runs before [Synchronize] useful to pause processes that should be rendered
 #[derive(ScheduleLabel, Clone, Hash, PartialEq, Eq, Debug)]
@@ -55,6 +137,43 @@ pub struct ApplyOffscreenTargetsSystemSet;
 #[derive(SystemSet, Clone, Hash, PartialEq, Eq, Debug)]
 pub struct SequenceRunnerSet;
 
+/// Ordering [SystemSets](SystemSet) within [Redraw], configured by the [RenderPlugin] to run in this order.
+/// Plugins should order themselves with these instead of the sets of single features.
+#[derive(SystemSet, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum RedrawSet {
+    /// Loading assets ([AssetLoadSet]) and preparing meshes, materials and pipelines
+    Prepare,
+    /// Contains [RenderSystemSet], running the schedules from [PreSynchronize] to [PostDraw]
+    Render,
+    /// Presenting the surface textures and requesting the next redraws
+    Present,
+    /// Statistics of the frame, like [GpuMemorySet]
+    Finish,
+}
+
+/// Ordering [SystemSets](SystemSet) within [Synchronize], configured by the [RenderPlugin] to run in this order
+#[derive(SystemSet, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum SynchronizeSet {
+    First,
+    /// Transform propagation, culling and phase sorting
+    Main,
+    Last,
+}
+
+/// Ordering [SystemSets](SystemSet) within [Draw], configured by the [RenderPlugin] to run in this order.
+/// The surfaces are presented after [Draw], in [RedrawSet::Present].
+#[derive(SystemSet, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum DrawSet {
+    /// Contains [ApplyOffscreenTargetsSystemSet], after it the render targets have their size for this frame
+    PrepareTargets,
+    /// Uploading data used by the sequences, like cameras, gizmos, shadows and text
+    Prepare,
+    /// Contains [UniformFlushSet], writing the uniforms of the frame
+    Flush,
+    /// Contains [ComputeSequenceRunnerSet] and [SequenceRunnerSet]
+    RunSequences,
+}
+
 pub struct RenderPlugin;
 
 impl Plugin for RenderPlugin {
@@ -69,6 +188,38 @@ impl Plugin for RenderPlugin {
         app.init_assets::<ShaderModule>();
         app.init_assets::<PipelineLayout>();
         app.init_assets::<RenderPipelineManager>();
+        app.init_assets::<ComputePipelineManager>();
+        app.init_assets::<Mesh>();
+        app.init_assets::<Cubemap>();
+        app.init_assets::<Polyline>();
+        app.init_resource::<SamplerCache>();
+        app.init_resource::<BindGroupCache>();
+        app.init_resource::<PipelineTrimSettings>();
+        app.init_resource::<ShaderComposer>();
+        app.init_resource::<BloomSettings>();
+        app.init_resource::<UniformAllocator>();
+        app.init_resource::<ModelMatrices>();
+        app.init_resource::<Gizmos>();
+        app.init_resource::<RedrawingWindows>();
+        app.init_resource::<QuerySetPool>();
+        app.init_resource::<QueryResults>();
+        app.init_resource::<ComputeFence>();
+        app.init_resource::<SequenceErrors>();
+        app.init_resource::<FrameStats>();
+        app.init_resource::<GpuMemoryStats>();
+        app.init_resource::<GpuErrorSettings>();
+        app.init_resource::<Screenshots>();
+        app.init_resource::<TextureReadbacks>();
+        app.add_message::<ReadbackEvent>();
+        app.add_message::<PickRequest>();
+        app.add_message::<PickResult>();
+        app.add_message::<PipelineErrorEvent>();
+        app.add_message::<ShaderCompileError>();
+        app.add_message::<GpuError>();
+        app.add_message::<FrameDropped>();
+        app.add_plugins(BindGroupLayoutInitPlugin::<SceneDepthLayout>::new());
+        app.add_plugins(BindGroupLayoutInitPlugin::<ShadowLayout>::new());
+        app.add_plugins(BindGroupLayoutInitPlugin::<CubemapLayout>::new());
 
         app.add_systems(
             Redraw,
@@ -84,16 +235,169 @@ impl Plugin for RenderPlugin {
             Redraw,
             (present_surface_targets, request_redraws)
                 .run_if(resource_exists::<ShouldDraw>)
-                .after(RenderSystemSet),
+                .in_set(RedrawSet::Present),
         );
+        app.add_systems(Redraw, begin_frame_stats.in_set(RedrawSet::Prepare));
+        app.add_systems(Redraw, update_mirror_windows.in_set(RedrawSet::Prepare));
+        app.add_systems(Redraw, update_render_memory_stats.in_set(GpuMemorySet));
+        app.add_systems(Redraw, end_frame_stats.in_set(RedrawSet::Finish));
         app.add_systems(
             Draw,
-            apply_offscreen_targets.in_set(ApplyOffscreenTargetsSystemSet),
+            (track_window_sizes, apply_offscreen_targets)
+                .chain()
+                .in_set(ApplyOffscreenTargetsSystemSet),
+        );
+        app.add_systems(
+            Draw,
+            update_ping_pong_histories
+                .after(ApplyOffscreenTargetsSystemSet)
+                .in_set(DrawSet::PrepareTargets),
         );
         app.add_systems(Draw, run_sequences.in_set(SequenceRunnerSet));
+        app.add_systems(
+            Draw,
+            run_compute_sequences.in_set(ComputeSequenceRunnerSet),
+        );
+        app.add_systems(Draw, flush_uniforms.in_set(UniformFlushSet));
+        app.add_systems(Draw, upload_cameras.in_set(CameraUploadSet));
+        app.add_systems(Draw, upload_gizmos.in_set(GizmoUploadSet));
+        app.add_systems(Draw, prepare_shadows.in_set(ShadowPrepareSet));
+        app.add_systems(
+            Synchronize,
+            (
+                propagate_transforms.in_set(TransformPropagateSet),
+                upload_model_matrices.in_set(ModelMatrixUploadSet),
+                (update_mesh_bounds, cull_entities)
+                    .chain()
+                    .in_set(VisibilitySet),
+                sort_phases.in_set(PhaseSortSet),
+            ),
+        );
+        app.add_systems(
+            PreDraw,
+            (
+                clear_sequence_errors,
+                swap_ping_pong_targets,
+                advance_camera_jitter,
+            ),
+        );
+        app.add_systems(
+            PostDraw,
+            (
+                reset_uniforms,
+                read_query_results,
+                evict_bind_groups,
+                trim_render_pipelines,
+                (read_texture_readbacks, write_pick_results).chain(),
+            ),
+        );
+        app.add_systems(
+            Init,
+            (
+                init_staging_uploads,
+                init_blit_pipeline,
+                init_bloom_pipeline,
+                init_clear_region_pipeline,
+                init_indirect_support,
+                init_camera_provider,
+                init_gizmo_pipeline,
+                init_shadows,
+                init_environment_map_filter,
+                init_skybox_pipeline,
+                init_polyline_pipeline,
+                init_picking,
+            ),
+        );
+        app.add_systems(
+            Redraw,
+            (
+                track_pipeline_dependencies::<RenderPipelineManager>,
+                track_pipeline_dependencies::<ComputePipelineManager>,
+            )
+                .before(AssetLoadSet),
+        );
+        app.add_systems(
+            Redraw,
+            (
+                clear_modified_pipelines::<RenderPipelineManager>,
+                clear_modified_pipelines::<ComputePipelineManager>,
+                clear_pipelines_on_composer_change,
+            )
+                .after(AssetLoadSet)
+                .in_set(RedrawSet::Prepare),
+        );
+        app.add_systems(
+            Redraw,
+            collect_pick_requests
+                .after(AssetLoadSet)
+                .in_set(RedrawSet::Prepare),
+        );
+        app.add_systems(
+            Redraw,
+            (prepare_meshes, prepare_polylines).in_set(MeshPrepareSet),
+        );
+        app.configure_sets(
+            Redraw,
+            (
+                RedrawSet::Prepare,
+                RedrawSet::Render,
+                RedrawSet::Present,
+                RedrawSet::Finish,
+            )
+                .chain(),
+        );
+        app.configure_sets(
+            Synchronize,
+            (
+                SynchronizeSet::First,
+                SynchronizeSet::Main,
+                SynchronizeSet::Last,
+            )
+                .chain(),
+        );
         app.configure_sets(
             Draw,
-            SequenceRunnerSet.after(ApplyOffscreenTargetsSystemSet),
+            (
+                DrawSet::PrepareTargets,
+                DrawSet::Prepare,
+                DrawSet::Flush,
+                DrawSet::RunSequences,
+            )
+                .chain(),
+        );
+        app.configure_sets(Redraw, AssetLoadSet.in_set(RedrawSet::Prepare));
+        app.configure_sets(Redraw, RenderSystemSet.in_set(RedrawSet::Render));
+        app.configure_sets(Redraw, GpuMemorySet.in_set(RedrawSet::Finish));
+        app.configure_sets(
+            Redraw,
+            MeshPrepareSet
+                .after(AssetLoadSet)
+                .in_set(RedrawSet::Prepare),
+        );
+        app.configure_sets(
+            Synchronize,
+            (
+                TransformPropagateSet,
+                ModelMatrixUploadSet.after(TransformPropagateSet),
+                VisibilitySet.after(TransformPropagateSet),
+                PhaseSortSet.after(VisibilitySet),
+            )
+                .in_set(SynchronizeSet::Main),
+        );
+        app.configure_sets(
+            Draw,
+            ApplyOffscreenTargetsSystemSet.in_set(DrawSet::PrepareTargets),
+        );
+        app.configure_sets(
+            Draw,
+            (CameraUploadSet, GizmoUploadSet, ShadowPrepareSet).in_set(DrawSet::Prepare),
+        );
+        app.configure_sets(Draw, UniformFlushSet.in_set(DrawSet::Flush));
+        app.configure_sets(
+            Draw,
+            (ComputeSequenceRunnerSet, SequenceRunnerSet)
+                .chain()
+                .in_set(DrawSet::RunSequences),
         );
     }
 }
@@ -102,79 +406,222 @@ impl Plugin for RenderPlugin {
 #[derive(Resource)]
 pub struct ShouldDraw;
 
+/// Added to windows whose [SurfaceRenderTarget] has a zero size (e.g. the window is minimized), their surface is not configured.
+/// Suspended windows are not in the [RedrawingWindows], so their [WindowSequenceQueues](WindowSequenceQueue) are skipped.
+#[derive(Component)]
+pub struct SurfaceSuspended;
+
+/// The windows that requested the current redraw, used to run their [WindowSequenceQueues](WindowSequenceQueue)
+#[derive(Resource, Default)]
+pub struct RedrawingWindows(Vec<Entity>);
+
+impl RedrawingWindows {
+    pub fn contains(&self, window: Entity) -> bool {
+        self.0.contains(&window)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.0.iter().copied()
+    }
+}
+
 /// Sets the initial [SurfaceRenderTargetConfig] of a window entity, this will be removed when the render target is created
 #[derive(Component)]
 pub struct InitialSurfaceConfig(pub SurfaceRenderTargetConfig);
 
+/// Resizes the [OffscreenRenderTarget] of the entity to the size of the [SurfaceRenderTarget] of the window entity, multiplied by the scale.
+/// Useful for intermediate buffers, use a scale of 0.5 for half resolution.
+/// The size is updated in [ApplyOffscreenTargetsSystemSet] before the changes are applied.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct TracksWindowSize(pub Entity, pub f32);
+
+/// Implemented by the pipeline managers, so the same system can clear both
+trait PipelineCache: Send + Sync + 'static {
+    fn clear(&mut self);
+
+    fn dependencies(&self) -> Vec<UntypedAssetId>;
+}
+
+impl PipelineCache for RenderPipelineManager {
+    fn clear(&mut self) {
+        self.clear();
+    }
+
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        self.dependencies()
+    }
+}
+
+impl PipelineCache for ComputePipelineManager {
+    fn clear(&mut self) {
+        self.clear();
+    }
+
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        self.dependencies()
+    }
+}
+
+/// Registers the dependencies of added managers and removes the ones of removed managers,
+/// so the managers are cleared when a shader or layout they use is reloaded or marked as changed
+fn track_pipeline_dependencies<T: PipelineCache>(
+    mut tracked: Local<HashSet<AssetId<T>>>,
+    managers: Res<Assets<T>>,
+    mut dependencies: ResMut<AssetDependencies>,
+) {
+    tracked.retain(|id| {
+        let exists = managers.contains(id);
+        if !exists {
+            dependencies.clear_dependencies(*id);
+        }
+        exists
+    });
+    for id in managers.ids() {
+        if !tracked.insert(id) {
+            continue;
+        }
+        if let Some(manager) = managers.get(id) {
+            dependencies.add_dependencies(id, manager.dependencies());
+        }
+    }
+}
+
+fn clear_modified_pipelines<T: PipelineCache>(
+    mut events: MessageReader<AssetEvent<T>>,
+    mut managers: ResMut<Assets<T>>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified(id) | AssetEvent::DependencyModified(id) = event {
+            if let Some(manager) = managers.get_mut(*id) {
+                manager.clear();
+            }
+        }
+    }
+}
+
+type RedrawWindow<'a> = (
+    &'a WindowComponent,
+    &'a mut SurfaceRenderTarget,
+    Option<&'a mut PresentStats>,
+    Has<ImportantWindow>,
+    Has<WindowSequenceQueue>,
+);
+
+#[allow(clippy::too_many_arguments)]
 fn handle_events(
     mut commands: Commands,
     ctx: Res<RenderContext>,
     events: ResMut<EventBuffer>,
     map: Res<WindowMap>,
-    mut window_query: Query<(
-        &WindowComponent,
-        &mut SurfaceRenderTarget,
-        Has<ImportantWindow>,
-    )>,
+    mut redrawing: ResMut<RedrawingWindows>,
+    mut stats: ResMut<FrameStats>,
+    mut dropped: MessageWriter<FrameDropped>,
+    mut window_query: Query<RedrawWindow>,
 ) {
+    let start = Instant::now();
+    redrawing.0.clear();
+    let max_dimension = ctx.device.limits().max_texture_dimension_2d;
 
     for e in events.events().iter() {
         let Event::WindowEvent { window_id, event } = e else {
             continue;
         };
-        let Ok((win, mut render_target, important)) =
-            window_query.get_mut(match map.get(window_id) {
-                None => continue,
-                Some(v) => v,
-            })
+        let Some(entity) = map.get(window_id) else {
+            continue;
+        };
+        let Ok((win, mut render_target, present_stats, important, has_queue)) =
+            window_query.get_mut(entity)
         else {
             continue;
         };
         if let WindowEvent::Resized(size) = event {
-            render_target.set_size((size.width, size.height));
+            render_target.set_size((size.width, size.height), max_dimension);
+            set_suspended(&mut commands, entity, render_target.is_suspended());
         } else if let WindowEvent::RedrawRequested = event {
-            match render_target.update(&ctx.device, &win.surface) {
+            let status = render_target.update(&ctx.device, &ctx.adapter, &win.surface);
+            if let Some(mut present_stats) = present_stats {
+                present_stats.record(status);
+                if let SurfaceUpdateStatus::Skipped(reason) = status {
+                    dropped.write(FrameDropped {
+                        window: entity,
+                        reason,
+                        consecutive: present_stats.consecutive_errors,
+                    });
+                }
+            }
+            match status {
                 SurfaceUpdateStatus::Ready | SurfaceUpdateStatus::ReadySuboptimal => {}
-                SurfaceUpdateStatus::Skipped => {
+                SurfaceUpdateStatus::Skipped(_) => {
                     win.window.request_redraw();
                 }
                 SurfaceUpdateStatus::Failed => {
                     eprintln!("Fatal surface error, exiting...");
                     commands.insert_resource(ShouldExit);
                 }
+                // the window is redrawn when it is resized again
+                SurfaceUpdateStatus::Suspended => {}
             }
-            if important {
+            if !render_target.is_suspended() && !redrawing.contains(entity) {
+                redrawing.0.push(entity);
+            }
+            if important || has_queue {
                 commands.insert_resource(ShouldDraw);
             }
         }
     }
+    stats.current.events = start.elapsed();
 }
 
+type NewWindow<'a> = (
+    Entity,
+    &'a WindowComponent,
+    Option<&'a InitialSurfaceConfig>,
+    Option<&'a InitialWindowConfig>,
+);
+
 fn create_surface_targets(
     mut commands: Commands,
     ctx: Res<RenderContext>,
     format: Res<SurfaceFormat>,
-    window_query: Query<
-        (Entity, &WindowComponent, Option<&InitialSurfaceConfig>),
-        Without<SurfaceRenderTarget>,
-    >,
+    window_query: Query<NewWindow, Without<SurfaceRenderTarget>>,
 ) {
-    for (e, WindowComponent { window, surface }, cfg) in window_query.iter() {
-        let mut rt = SurfaceRenderTarget::new(cfg.map(|r| r.0.clone()).unwrap_or_default());
+    for (e, WindowComponent { window, surface }, cfg, window_cfg) in window_query.iter() {
+        let mut cfg = cfg.map(|r| r.0.clone()).unwrap_or_default();
+        let transparent = window_cfg.is_some_and(|c| c.window_attribs.transparent);
+        if transparent && cfg.alpha_mode == CompositeAlphaMode::Auto {
+            cfg.alpha_mode = CompositeAlphaMode::PreMultiplied;
+        }
+        let mut rt = SurfaceRenderTarget::new(cfg);
         rt.init(format.0, surface.get_capabilities(&ctx.adapter));
         let s = window.inner_size();
-        rt.set_size((s.width, s.height));
-        commands.entity(e).insert(rt).remove::<InitialSurfaceConfig>();
+        rt.set_size((s.width, s.height), ctx.device.limits().max_texture_dimension_2d);
+        set_suspended(&mut commands, e, rt.is_suspended());
+        commands
+            .entity(e)
+            .insert((rt, PresentStats::default()))
+            .remove::<InitialSurfaceConfig>();
     }
 }
 
+fn set_suspended(commands: &mut Commands, window: Entity, suspended: bool) {
+    match suspended {
+        true => commands.entity(window).insert(SurfaceSuspended),
+        false => commands.entity(window).remove::<SurfaceSuspended>(),
+    };
+}
+
 fn draw(world: &mut World) {
+    let start = Instant::now();
     world.run_schedule(PreSynchronize);
     world.run_schedule(Synchronize);
     world.run_schedule(PostSynchronize);
+    let synchronized = Instant::now();
     world.run_schedule(PreDraw);
     world.run_schedule(Draw);
     world.run_schedule(PostDraw);
+    let mut stats = world.resource_mut::<FrameStats>();
+    stats.current.synchronize = synchronized - start;
+    stats.current.draw = synchronized.elapsed();
 }
 
 fn apply_offscreen_targets(
@@ -182,14 +629,36 @@ fn apply_offscreen_targets(
     mut target_query: Query<&mut OffscreenRenderTarget>,
 ) {
     for mut rt in target_query.iter_mut() {
-        rt.apply_changes(&ctx.device);
+        rt.apply_changes(&ctx.device, &ctx.adapter);
     }
 }
 
-fn present_surface_targets(mut target_query: Query<&mut SurfaceRenderTarget>) {
+fn track_window_sizes(
+    mut target_query: Query<(&mut OffscreenRenderTarget, &TracksWindowSize)>,
+    window_query: Query<&SurfaceRenderTarget>,
+) {
+    for (mut rt, &TracksWindowSize(window, scale)) in target_query.iter_mut() {
+        let Ok(window_rt) = window_query.get(window) else {
+            continue;
+        };
+        let (width, height) = window_rt.size();
+        let scaled = |s: u32| ((s as f32 * scale).round() as u32).max(1);
+        let size = (scaled(width), scaled(height));
+        if rt.scheduled_size().unwrap_or_else(|| rt.size()) != size {
+            rt.resize(size);
+        }
+    }
+}
+
+fn present_surface_targets(
+    mut stats: ResMut<FrameStats>,
+    mut target_query: Query<&mut SurfaceRenderTarget>,
+) {
+    let start = Instant::now();
     for mut rt in target_query.iter_mut() {
         rt.present();
     }
+    stats.current.present = start.elapsed();
 }
 
 fn request_redraws(query: Query<&WindowComponent, With<UpdatingWindow>>) {
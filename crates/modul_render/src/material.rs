@@ -0,0 +1,496 @@
+use crate::{
+    BindGroupLayoutProvider, BindGroupProvider, CameraBindGroup, CameraUniformProvider,
+    DirectRenderPipelineResourceProvider, GenericColorTargetState, GenericDepthStencilState,
+    GenericFragmentState, GenericMultisampleState, GenericRenderPipelineDescriptor,
+    GenericVertexBufferLayout, GenericVertexState, GpuVec, GpuVecPlugin, Mesh, Operation,
+    OperationBuilder, OperationError, PipelineLayoutComposer, RedrawSet, RenderPipelineManager,
+    RenderTargetSource, ShaderDefError,
+};
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use bytemuck::Pod;
+use modul_asset::{AssetAppExt, AssetId, AssetLoadSet, AssetWorldExt, Assets};
+use modul_core::{Init, Redraw, RenderContext};
+use modul_util::HashMap;
+use std::any::type_name;
+use std::marker::PhantomData;
+use std::num::NonZero;
+use std::ops::Range;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder,
+    CompareFunction, DepthBiasState, Device, IndexFormat, PipelineLayout, PrimitiveState, Queue,
+    RenderPass, RenderPipeline, ShaderModule, ShaderStages, StencilState, VertexAttribute,
+    VertexStepMode,
+};
+
+/// [SystemSet] within [Redraw] that uploads changed [Materials](Material), it runs after [AssetLoadSet] in [RedrawSet::Prepare]
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MaterialPrepareSet;
+
+/// A uniform struct with the WGSL shader drawing it, stored as an asset and drawn through its [MaterialManager].
+///
+/// The shader declares `struct Material` matching the Rust struct (with WGSL uniform alignment), and the `vs_main` and `fs_main` entry points.
+/// `camera` ([CameraUniformProvider]) is group 0 and `material` is group 1.
+/// Mesh attributes are at their own locations, the [Instance](Material::Instance) attributes at the locations of [instance_attributes](Material::instance_attributes).
+pub trait Material: Pod + Send + Sync + 'static {
+    /// Per-instance data, passed as an instance step vertex buffer
+    type Instance: Pod + Send + Sync;
+
+    const SHADER: &'static str;
+
+    /// The attributes of [Instance](Material::Instance), the locations must not overlap the mesh attributes.
+    /// [wgpu::vertex_attr_array] can be used to create them.
+    fn instance_attributes() -> Vec<VertexAttribute>;
+
+    /// Changes the pipeline, which by default is an opaque triangle list with depth testing and writing
+    fn specialize(_descriptor: &mut GenericRenderPipelineDescriptor) {}
+}
+
+/// The bind group layout of a [Material], with the uniform at binding 0, declaring `material`
+pub struct MaterialLayout<M: Material> {
+    layout: BindGroupLayout,
+    _marker: PhantomData<M>,
+}
+
+// manual impl so M does not have to be Clone
+impl<M: Material> Clone for MaterialLayout<M> {
+    fn clone(&self) -> Self {
+        Self {
+            layout: self.layout.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M: Material> MaterialLayout<M> {
+    const LIBRARY: &'static str = "@group(#BIND_GROUP) @binding(0)
+var<uniform> material: Material;";
+
+    pub fn new(device: &Device) -> Self {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some(type_name::<M>()),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZero::new(size_of::<M>() as u64),
+                },
+                count: None,
+            }],
+        });
+        Self {
+            layout,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M: Material> BindGroupLayoutProvider for MaterialLayout<M> {
+    fn layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    fn library(&self) -> &str {
+        Self::LIBRARY
+    }
+}
+
+struct MaterialBindGroup {
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+/// Creates the bind groups of all [Materials](Material) of a type, and a pipeline per mesh vertex layout, inserted during [Init] by the [MaterialPlugin].
+/// Uniforms are uploaded during [MaterialPrepareSet] when the `Assets<M>` changed.
+#[derive(Resource)]
+pub struct MaterialManager<M: Material> {
+    layout: MaterialLayout<M>,
+    pipeline_layout: AssetId<PipelineLayout>,
+    shader: AssetId<ShaderModule>,
+    /// pipelines by the layouts of the mesh vertex buffers
+    variants: HashMap<Vec<GenericVertexBufferLayout>, AssetId<RenderPipelineManager>>,
+    bind_groups: HashMap<AssetId<M>, MaterialBindGroup>,
+}
+
+impl<M: Material> MaterialManager<M> {
+    /// Composes the pipeline layout and the shader of the material, fails if the shader has unbalanced directives
+    pub fn new(
+        device: &Device,
+        shaders: &mut Assets<ShaderModule>,
+        layouts: &mut Assets<PipelineLayout>,
+    ) -> Result<Self, ShaderDefError> {
+        let layout = MaterialLayout::<M>::new(device);
+        let mut composer = PipelineLayoutComposer::new();
+        composer
+            .add_bind_group(CameraUniformProvider::new(device))
+            .add_bind_group(layout.clone());
+        let pipeline_layout = layouts.add(composer.compose_pipeline_layout(device).clone());
+        let shader = shaders.add(
+            composer
+                .compose_shader(device, type_name::<M>(), M::SHADER)?
+                .clone(),
+        );
+        Ok(Self {
+            layout,
+            pipeline_layout,
+            shader,
+            variants: HashMap::new(),
+            bind_groups: HashMap::new(),
+        })
+    }
+
+    pub fn layout(&self) -> &MaterialLayout<M> {
+        &self.layout
+    }
+
+    /// The bind group of a material, None until it is prepared
+    pub fn bind_group(&self, material: AssetId<M>) -> Option<&BindGroup> {
+        self.bind_groups.get(&material).map(|b| &b.bind_group)
+    }
+
+    /// The uniform buffer of a material, None until it is prepared
+    pub fn buffer(&self, material: AssetId<M>) -> Option<&Buffer> {
+        self.bind_groups.get(&material).map(|b| &b.buffer)
+    }
+
+    /// The layout of the instance buffer, bound after the mesh vertex buffers
+    pub fn instance_buffer_layout() -> GenericVertexBufferLayout {
+        GenericVertexBufferLayout {
+            array_stride: size_of::<M::Instance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: M::instance_attributes(),
+        }
+    }
+
+    /// Gets the pipeline for meshes with the vertex buffer layouts (see [Mesh::vertex_buffer_layouts]), creating it on first use
+    pub fn pipeline_manager(
+        &mut self,
+        managers: &mut Assets<RenderPipelineManager>,
+        mesh_layouts: &[GenericVertexBufferLayout],
+    ) -> AssetId<RenderPipelineManager> {
+        if let Some(manager) = self.variants.get(mesh_layouts) {
+            return *manager;
+        }
+        let mut buffers = mesh_layouts.to_vec();
+        buffers.push(Self::instance_buffer_layout());
+        let mut descriptor = GenericRenderPipelineDescriptor {
+            resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                layout: self.pipeline_layout,
+                vertex_shader_module: self.shader,
+                fragment_shader_module: self.shader,
+            }),
+            label: Some(type_name::<M>().to_string()),
+            vertex_state: GenericVertexState {
+                entry_point: "vs_main".to_string(),
+                buffers,
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(GenericDepthStencilState {
+                depth_write_enable: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: GenericMultisampleState {
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(GenericFragmentState {
+                entry_point: "fs_main".to_string(),
+                targets: vec![GenericColorTargetState::new(None)],
+            }),
+        };
+        M::specialize(&mut descriptor);
+        let manager = managers.add(RenderPipelineManager::new(descriptor));
+        self.variants.insert(mesh_layouts.to_vec(), manager);
+        manager
+    }
+
+    /// Uploads the uniforms of all materials, creating bind groups for new ones and dropping the ones of removed materials
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, materials: &Assets<M>) {
+        self.bind_groups.retain(|id, _| materials.contains(id));
+        for (id, material) in materials.iter() {
+            if let Some(bind_group) = self.bind_groups.get(&id) {
+                queue.write_buffer(&bind_group.buffer, 0, bytemuck::bytes_of(&*material));
+                continue;
+            }
+            let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(type_name::<M>()),
+                contents: bytemuck::bytes_of(&*material),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            });
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some(type_name::<M>()),
+                layout: &self.layout.layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            self.bind_groups
+                .insert(id, MaterialBindGroup { buffer, bind_group });
+        }
+    }
+}
+
+/// Adds `Assets<M>` (if missing), the [MaterialManager] and the system uploading the materials.
+/// Also adds the [GpuVecPlugin] of the instance type if it is missing, for [DrawInstancedOperations](crate::DrawInstancedOperation).
+/// Add it after the [RenderPlugin](crate::RenderPlugin).
+pub struct MaterialPlugin<M: Material> {
+    _marker: PhantomData<M>,
+}
+
+impl<M: Material> Default for MaterialPlugin<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Material> MaterialPlugin<M> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M: Material> Plugin for MaterialPlugin<M> {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<Assets<M>>() {
+            app.init_assets::<M>();
+        }
+        if !app
+            .world()
+            .contains_resource::<Assets<GpuVec<M::Instance>>>()
+        {
+            app.add_plugins(GpuVecPlugin::<M::Instance>::new());
+        }
+        app.add_systems(Init, init_material_manager::<M>);
+        app.add_systems(Redraw, prepare_materials::<M>.in_set(MaterialPrepareSet));
+        app.configure_sets(
+            Redraw,
+            MaterialPrepareSet
+                .after(AssetLoadSet)
+                .in_set(RedrawSet::Prepare),
+        );
+    }
+}
+
+fn init_material_manager<M: Material>(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+) {
+    match MaterialManager::<M>::new(&ctx.device, &mut shaders, &mut layouts) {
+        Ok(manager) => commands.insert_resource(manager),
+        Err(e) => log::error!(
+            "Failed to compose the shader of {}: {}",
+            type_name::<M>(),
+            e
+        ),
+    }
+}
+
+fn prepare_materials<M: Material>(
+    ctx: Res<RenderContext>,
+    materials: Res<Assets<M>>,
+    manager: Option<ResMut<MaterialManager<M>>>,
+) {
+    let Some(mut manager) = manager else {
+        return;
+    };
+    if materials.is_changed() {
+        manager.prepare(&ctx.device, &ctx.queue, &materials);
+    }
+}
+
+/// Draws a [Mesh] with a [Material], as seen by the [Camera](crate::Camera) entity.
+/// The instances are uploaded once when the sequence is built, see [DrawInstancedOperation](crate::DrawInstancedOperation) for instances that change.
+pub struct DrawMaterialMesh<M: Material> {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+    pub mesh: AssetId<Mesh>,
+    pub material: AssetId<M>,
+    pub instances: Vec<M::Instance>,
+}
+
+struct DrawMaterialMeshOperation<M: Material> {
+    render_target: RenderTargetSource,
+    camera: Entity,
+    mesh: AssetId<Mesh>,
+    material: AssetId<M>,
+    instances: Option<Buffer>,
+    instance_count: u32,
+}
+
+impl<M: Material> Operation for DrawMaterialMeshOperation<M> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(instances) = self.instances.clone() else {
+            return Ok(());
+        };
+        draw_material_mesh(
+            world,
+            command_encoder,
+            self.render_target,
+            self.camera,
+            self.mesh,
+            self.material,
+            &instances,
+            self.instance_count,
+        )
+    }
+}
+
+impl<M: Material> OperationBuilder for DrawMaterialMesh<M> {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, device: &Device) -> impl Operation + 'static {
+        let instances = (!self.instances.is_empty()).then(|| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("Material instance buffer"),
+                contents: bytemuck::cast_slice(&self.instances),
+                usage: BufferUsages::VERTEX,
+            })
+        });
+        DrawMaterialMeshOperation::<M> {
+            render_target: self.render_target,
+            camera: self.camera,
+            mesh: self.mesh,
+            material: self.material,
+            instances,
+            instance_count: self.instances.len() as u32,
+        }
+    }
+}
+
+/// Draws the instances of a mesh with a material in one pass, shared by the material draw operations
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn draw_material_mesh<M: Material>(
+    world: &mut World,
+    command_encoder: &mut CommandEncoder,
+    render_target: RenderTargetSource,
+    camera: Entity,
+    mesh: AssetId<Mesh>,
+    material: AssetId<M>,
+    instances: &Buffer,
+    instance_count: u32,
+) -> Result<(), OperationError> {
+    if instance_count == 0 {
+        return Ok(());
+    }
+    let camera_bind_group = camera_bind_group(world, camera)?;
+    let Some(draw) = MaterialDraw::prepare(world, render_target, mesh, material)? else {
+        return Ok(());
+    };
+    let Some(mut rt) = render_target.get_mut(world) else {
+        return Ok(());
+    };
+    let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+        return Ok(());
+    };
+    pass.set_bind_group(0, &camera_bind_group, &[]);
+    draw.record(&mut pass, instances, 0..instance_count);
+    Ok(())
+}
+
+pub(crate) fn camera_bind_group(
+    world: &World,
+    camera: Entity,
+) -> Result<BindGroup, OperationError> {
+    match world.get::<CameraBindGroup>(camera) {
+        Some(camera_bind_group) => Ok(camera_bind_group.bind_group().clone()),
+        None => Err(format!("{} has no camera bind group", camera).into()),
+    }
+}
+
+/// The resources to draw a mesh with a material, the camera bind group is set by the caller
+pub(crate) struct MaterialDraw {
+    pipeline: RenderPipeline,
+    material_bind_group: BindGroup,
+    vertex_buffer: Buffer,
+    /// the slot of the instance buffer, after the mesh buffers
+    instance_slot: u32,
+    index_buffer: Option<(Buffer, IndexFormat)>,
+    count: u32,
+}
+
+impl MaterialDraw {
+    /// None if the mesh, the material or the pipeline is not ready yet
+    pub(crate) fn prepare<M: Material>(
+        world: &mut World,
+        render_target: RenderTargetSource,
+        mesh: AssetId<Mesh>,
+        material: AssetId<M>,
+    ) -> Result<Option<Self>, OperationError> {
+        let Some((mesh_layouts, vertex_buffer, index_buffer, count)) =
+            world.get_asset(mesh).and_then(|mesh| {
+                let buffers = mesh.buffers()?;
+                let count = match buffers.index_buffer {
+                    Some(_) => buffers.index_count,
+                    None => buffers.vertex_count,
+                };
+                Some((
+                    mesh.vertex_buffer_layouts(),
+                    buffers.vertex_buffer.clone(),
+                    buffers.index_buffer.clone(),
+                    count,
+                ))
+            })
+        else {
+            return Ok(None);
+        };
+        let Some(material_manager) = world.get_resource::<MaterialManager<M>>() else {
+            return Err(format!("no MaterialManager for {}", type_name::<M>()).into());
+        };
+        let Some(material_bind_group) = material_manager.bind_group(material).cloned() else {
+            return Ok(None);
+        };
+        let manager =
+            world.resource_scope(|world, mut material_manager: Mut<MaterialManager<M>>| {
+                let mut managers = world.resource_mut::<Assets<RenderPipelineManager>>();
+                material_manager.pipeline_manager(&mut managers, &mesh_layouts)
+            });
+        let mut pipeline = None;
+        world.asset_scope(manager, |world, manager| {
+            pipeline = manager.get_compatible(render_target, world).cloned();
+        });
+        Ok(pipeline.map(|pipeline| Self {
+            pipeline,
+            material_bind_group,
+            vertex_buffer,
+            instance_slot: mesh_layouts.len() as u32,
+            index_buffer,
+            count,
+        }))
+    }
+
+    /// Draws the range of the instance buffer
+    pub(crate) fn record(&self, pass: &mut RenderPass, instances: &Buffer, range: Range<u32>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(1, &self.material_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(self.instance_slot, instances.slice(..));
+        match &self.index_buffer {
+            Some((index_buffer, format)) => {
+                pass.set_index_buffer(index_buffer.slice(..), *format);
+                pass.draw_indexed(0..self.count, 0, range);
+            }
+            None => pass.draw(0..self.count, range),
+        }
+    }
+}
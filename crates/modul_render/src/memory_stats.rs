@@ -0,0 +1,132 @@
+use crate::{Cubemap, Mesh, OffscreenRenderTarget, SurfaceRenderTarget};
+use bevy_ecs::prelude::*;
+use modul_asset::Assets;
+use modul_util::HashMap;
+use wgpu::{Buffer, Texture, TextureFormat};
+
+/// [SystemSet] within [Redraw](modul_core::Redraw) that updates the [GpuMemoryStats], it runs in [RedrawSet::Finish](crate::RedrawSet::Finish).
+/// Systems setting their own categories should run in it.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GpuMemorySet;
+
+/// The approximate GPU memory of a category of [GpuMemoryStats]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GpuMemoryUsage {
+    pub bytes: u64,
+    /// The amount of textures and buffers
+    pub count: usize,
+}
+
+impl GpuMemoryUsage {
+    pub fn add_texture(&mut self, texture: &Texture) {
+        self.bytes += texture_memory(texture);
+        self.count += 1;
+    }
+
+    pub fn add_buffer(&mut self, buffer: &Buffer) {
+        self.bytes += buffer.size();
+        self.count += 1;
+    }
+}
+
+/// Approximate GPU memory of the textures and buffers owned by modul's types by category, recomputed every frame in [GpuMemorySet].
+/// The [RenderPlugin](crate::RenderPlugin) sets `render targets`, `meshes` and `cubemaps`, other crates add their own categories.
+/// Sizes are computed from the texture and buffer descriptors, so padding of the driver is not included,
+/// neither are resources created directly with the device.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct GpuMemoryStats {
+    categories: HashMap<&'static str, GpuMemoryUsage>,
+}
+
+impl GpuMemoryStats {
+    /// Zero if the category has not been set
+    pub fn get(&self, category: &str) -> GpuMemoryUsage {
+        self.categories.get(category).copied().unwrap_or_default()
+    }
+
+    /// Replaces the usage of the category
+    pub fn set(&mut self, category: &'static str, usage: GpuMemoryUsage) {
+        self.categories.insert(category, usage);
+    }
+
+    /// The categories sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, GpuMemoryUsage)> {
+        let mut categories = self
+            .categories
+            .iter()
+            .map(|(category, usage)| (*category, *usage))
+            .collect::<Vec<_>>();
+        categories.sort_by_key(|(category, _)| *category);
+        categories.into_iter()
+    }
+
+    /// The sum of all categories
+    pub fn total(&self) -> GpuMemoryUsage {
+        self.categories
+            .values()
+            .fold(GpuMemoryUsage::default(), |total, usage| GpuMemoryUsage {
+                bytes: total.bytes + usage.bytes,
+                count: total.count + usage.count,
+            })
+    }
+}
+
+/// The approximate size in bytes of the texture with all mip levels, layers and samples
+pub fn texture_memory(texture: &Texture) -> u64 {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    // the depth aspect of these formats can not be copied, so wgpu does not know their size
+    let block_size = format
+        .block_copy_size(None)
+        .or(match format {
+            TextureFormat::Depth24Plus | TextureFormat::Depth24PlusStencil8 => Some(4),
+            TextureFormat::Depth32FloatStencil8 => Some(8),
+            _ => None,
+        })
+        // multi-planar formats
+        .unwrap_or(4) as u64;
+    let size = texture.size();
+    let bytes = (0..texture.mip_level_count())
+        .map(|level| {
+            let mip = size.mip_level_size(level, texture.dimension());
+            let blocks = mip.width.div_ceil(block_width) as u64
+                * mip.height.div_ceil(block_height) as u64
+                * mip.depth_or_array_layers as u64;
+            blocks * block_size
+        })
+        .sum::<u64>();
+    bytes * texture.sample_count() as u64
+}
+
+pub(crate) fn update_render_memory_stats(
+    mut stats: ResMut<GpuMemoryStats>,
+    offscreen_targets: Query<&OffscreenRenderTarget>,
+    surface_targets: Query<&SurfaceRenderTarget>,
+    meshes: Res<Assets<Mesh>>,
+    cubemaps: Res<Assets<Cubemap>>,
+) {
+    let mut targets = GpuMemoryUsage::default();
+    offscreen_targets
+        .iter()
+        .flat_map(|rt| rt.owned_textures())
+        .chain(surface_targets.iter().flat_map(|rt| rt.owned_textures()))
+        .for_each(|texture| targets.add_texture(texture));
+    stats.set("render targets", targets);
+
+    let mut mesh_usage = GpuMemoryUsage::default();
+    for (_, mesh) in meshes.iter() {
+        if let Some(buffers) = mesh.buffers() {
+            mesh_usage.add_buffer(&buffers.vertex_buffer);
+            if let Some((index_buffer, _)) = &buffers.index_buffer {
+                mesh_usage.add_buffer(index_buffer);
+            }
+        }
+    }
+    stats.set("meshes", mesh_usage);
+
+    let mut cubemap_usage = GpuMemoryUsage::default();
+    for (_, cubemap) in cubemaps.iter() {
+        cubemap_usage.add_texture(&cubemap.texture);
+    }
+    stats.set("cubemaps", cubemap_usage);
+}
@@ -0,0 +1,359 @@
+use crate::{Aabb, GenericVertexBufferLayout};
+use bevy_ecs::prelude::*;
+use glam::Vec3;
+use log::warn;
+use modul_asset::Assets;
+use modul_core::RenderContext;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    Buffer, BufferAddress, BufferUsages, Device, IndexFormat, RenderPass, VertexAttribute,
+    VertexFormat, VertexStepMode,
+};
+
+/// The values of one vertex attribute, one per vertex
+#[derive(Clone, Debug)]
+pub enum VertexAttributeValues {
+    Float32(Vec<f32>),
+    Float32x2(Vec<[f32; 2]>),
+    Float32x3(Vec<[f32; 3]>),
+    Float32x4(Vec<[f32; 4]>),
+    Uint32(Vec<u32>),
+    Uint32x2(Vec<[u32; 2]>),
+    Uint32x3(Vec<[u32; 3]>),
+    Uint32x4(Vec<[u32; 4]>),
+    Sint32(Vec<i32>),
+    Sint32x2(Vec<[i32; 2]>),
+    Sint32x3(Vec<[i32; 3]>),
+    Sint32x4(Vec<[i32; 4]>),
+    Unorm8x4(Vec<[u8; 4]>),
+}
+
+impl VertexAttributeValues {
+    /// The amount of vertices
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Float32(v) => v.len(),
+            Self::Float32x2(v) => v.len(),
+            Self::Float32x3(v) => v.len(),
+            Self::Float32x4(v) => v.len(),
+            Self::Uint32(v) => v.len(),
+            Self::Uint32x2(v) => v.len(),
+            Self::Uint32x3(v) => v.len(),
+            Self::Uint32x4(v) => v.len(),
+            Self::Sint32(v) => v.len(),
+            Self::Sint32x2(v) => v.len(),
+            Self::Sint32x3(v) => v.len(),
+            Self::Sint32x4(v) => v.len(),
+            Self::Unorm8x4(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn format(&self) -> VertexFormat {
+        match self {
+            Self::Float32(_) => VertexFormat::Float32,
+            Self::Float32x2(_) => VertexFormat::Float32x2,
+            Self::Float32x3(_) => VertexFormat::Float32x3,
+            Self::Float32x4(_) => VertexFormat::Float32x4,
+            Self::Uint32(_) => VertexFormat::Uint32,
+            Self::Uint32x2(_) => VertexFormat::Uint32x2,
+            Self::Uint32x3(_) => VertexFormat::Uint32x3,
+            Self::Uint32x4(_) => VertexFormat::Uint32x4,
+            Self::Sint32(_) => VertexFormat::Sint32,
+            Self::Sint32x2(_) => VertexFormat::Sint32x2,
+            Self::Sint32x3(_) => VertexFormat::Sint32x3,
+            Self::Sint32x4(_) => VertexFormat::Sint32x4,
+            Self::Unorm8x4(_) => VertexFormat::Unorm8x4,
+        }
+    }
+
+    /// The raw bytes of the values
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Float32(v) => bytemuck::cast_slice(v),
+            Self::Float32x2(v) => bytemuck::cast_slice(v),
+            Self::Float32x3(v) => bytemuck::cast_slice(v),
+            Self::Float32x4(v) => bytemuck::cast_slice(v),
+            Self::Uint32(v) => bytemuck::cast_slice(v),
+            Self::Uint32x2(v) => bytemuck::cast_slice(v),
+            Self::Uint32x3(v) => bytemuck::cast_slice(v),
+            Self::Uint32x4(v) => bytemuck::cast_slice(v),
+            Self::Sint32(v) => bytemuck::cast_slice(v),
+            Self::Sint32x2(v) => bytemuck::cast_slice(v),
+            Self::Sint32x3(v) => bytemuck::cast_slice(v),
+            Self::Sint32x4(v) => bytemuck::cast_slice(v),
+            Self::Unorm8x4(v) => bytemuck::cast_slice(v),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(i) => i.len(),
+            Indices::U32(i) => i.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            Indices::U16(_) => IndexFormat::Uint16,
+            Indices::U32(_) => IndexFormat::Uint32,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Indices::U16(i) => bytemuck::cast_slice(i),
+            Indices::U32(i) => bytemuck::cast_slice(i),
+        }
+    }
+}
+
+/// Vertex data with optional indices, stored as an asset.
+/// The attributes are interleaved into one vertex buffer ordered by shader location.
+/// Buffers are created for changed meshes in [MeshPrepareSet], so after changing a mesh the old buffers are used until then.
+#[derive(Default)]
+pub struct Mesh {
+    attributes: BTreeMap<u32, VertexAttributeValues>,
+    indices: Option<Indices>,
+    buffers: Option<MeshBuffers>,
+    changed: bool,
+}
+
+/// The GPU buffers of a [Mesh]
+pub struct MeshBuffers {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Option<(Buffer, IndexFormat)>,
+    pub vertex_count: u32,
+    pub index_count: u32,
+}
+
+impl Mesh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the values of the attribute at the shader location, all attributes must have the same length
+    pub fn insert_attribute(&mut self, location: u32, values: VertexAttributeValues) {
+        self.attributes.insert(location, values);
+        self.changed = true;
+    }
+
+    /// Builder version of [insert_attribute](Self::insert_attribute)
+    pub fn with_attribute(mut self, location: u32, values: VertexAttributeValues) -> Self {
+        self.insert_attribute(location, values);
+        self
+    }
+
+    pub fn remove_attribute(&mut self, location: u32) -> Option<VertexAttributeValues> {
+        self.changed = true;
+        self.attributes.remove(&location)
+    }
+
+    pub fn attribute(&self, location: u32) -> Option<&VertexAttributeValues> {
+        self.attributes.get(&location)
+    }
+
+    /// Mutably gets an attribute, marking the mesh as changed
+    pub fn attribute_mut(&mut self, location: u32) -> Option<&mut VertexAttributeValues> {
+        self.changed = true;
+        self.attributes.get_mut(&location)
+    }
+
+    pub fn set_indices(&mut self, indices: Option<Indices>) {
+        self.indices = indices;
+        self.changed = true;
+    }
+
+    /// Builder version of [set_indices](Self::set_indices)
+    pub fn with_indices(mut self, indices: Indices) -> Self {
+        self.set_indices(Some(indices));
+        self
+    }
+
+    pub fn indices(&self) -> Option<&Indices> {
+        self.indices.as_ref()
+    }
+
+    /// The amount of vertices, 0 if there are no attributes
+    pub fn vertex_count(&self) -> usize {
+        self.attributes.values().next().map_or(0, |a| a.len())
+    }
+
+    /// The layout of the vertex buffer, can be put directly in [GenericVertexState](crate::GenericVertexState).
+    /// Meshes with the same attribute locations and formats have the same layout.
+    pub fn vertex_buffer_layouts(&self) -> Vec<GenericVertexBufferLayout> {
+        let mut offset = 0;
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(location, values)| {
+                let attribute = VertexAttribute {
+                    format: values.format(),
+                    offset,
+                    shader_location: *location,
+                };
+                offset += values.format().size();
+                attribute
+            })
+            .collect();
+        vec![GenericVertexBufferLayout {
+            array_stride: offset,
+            step_mode: VertexStepMode::Vertex,
+            attributes,
+        }]
+    }
+
+    /// The bounds of the positions at the shader location (`Float32x2` at z 0, or the xyz of `Float32x3`/`Float32x4`).
+    /// None if there is no such attribute or it is empty.
+    pub fn compute_aabb(&self, location: u32) -> Option<Aabb> {
+        match self.attributes.get(&location)? {
+            VertexAttributeValues::Float32x2(v) => {
+                Aabb::from_points(v.iter().map(|p| Vec3::new(p[0], p[1], 0.0)))
+            }
+            VertexAttributeValues::Float32x3(v) => Aabb::from_points(v.iter().map(|&p| p.into())),
+            VertexAttributeValues::Float32x4(v) => {
+                Aabb::from_points(v.iter().map(|p| Vec3::new(p[0], p[1], p[2])))
+            }
+            _ => None,
+        }
+    }
+
+    /// The GPU buffers, None if the mesh has not been prepared yet
+    pub fn buffers(&self) -> Option<&MeshBuffers> {
+        self.buffers.as_ref()
+    }
+
+    /// Creates the buffers, this is done automatically during [MeshPrepareSet] for changed meshes.
+    /// On an error the mesh is left without buffers, so it is not drawn.
+    pub fn prepare(&mut self, device: &Device) -> Result<(), MeshError> {
+        self.changed = false;
+        self.buffers = None;
+        let vertex_count = self.vertex_count();
+        if let Some((&location, values)) = self
+            .attributes
+            .iter()
+            .find(|(_, values)| values.len() != vertex_count)
+        {
+            return Err(MeshError::AttributeLength {
+                location,
+                len: values.len(),
+                vertex_count,
+            });
+        }
+        // wgpu can not bind empty buffers
+        if vertex_count == 0 || self.indices.as_ref().is_some_and(|i| i.is_empty()) {
+            return Err(MeshError::Empty);
+        }
+        let stride: BufferAddress = self.attributes.values().map(|a| a.format().size()).sum();
+        let mut data = Vec::with_capacity(stride as usize * vertex_count);
+        for i in 0..vertex_count {
+            for values in self.attributes.values() {
+                let size = values.format().size() as usize;
+                data.extend_from_slice(&values.bytes()[i * size..(i + 1) * size]);
+            }
+        }
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("mesh vertex buffer"),
+            contents: &data,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        let index_buffer = self.indices.as_ref().map(|indices| {
+            (
+                device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("mesh index buffer"),
+                    contents: indices.bytes(),
+                    usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+                }),
+                indices.format(),
+            )
+        });
+        self.buffers = Some(MeshBuffers {
+            vertex_buffer,
+            index_buffer,
+            vertex_count: vertex_count as u32,
+            index_count: self.indices.as_ref().map_or(0, |i| i.len() as u32),
+        });
+        Ok(())
+    }
+
+    /// Sets the buffers at the vertex buffer slot and draws all instances in the range, does nothing if the mesh is not prepared
+    pub fn draw(&self, pass: &mut RenderPass, slot: u32, instances: Range<u32>) {
+        let Some(buffers) = &self.buffers else {
+            return;
+        };
+        pass.set_vertex_buffer(slot, buffers.vertex_buffer.slice(..));
+        match &buffers.index_buffer {
+            Some((index_buffer, format)) => {
+                pass.set_index_buffer(index_buffer.slice(..), *format);
+                pass.draw_indexed(0..buffers.index_count, 0, instances);
+            }
+            None => pass.draw(0..buffers.vertex_count, instances),
+        }
+    }
+}
+
+/// Returned by [Mesh::prepare] for meshes that can not be drawn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshError {
+    /// The attribute at the location has a different amount of values than the first one
+    AttributeLength {
+        location: u32,
+        len: usize,
+        vertex_count: usize,
+    },
+    /// The mesh has no vertices or empty indices
+    Empty,
+}
+
+impl Error for MeshError {}
+
+impl Display for MeshError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::AttributeLength {
+                location,
+                len,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh attribute {} has {} values, but the mesh has {} vertices",
+                location, len, vertex_count
+            ),
+            MeshError::Empty => write!(f, "mesh has no vertices or indices"),
+        }
+    }
+}
+
+/// [SystemSet] within [Redraw](modul_core::Redraw) that creates the buffers of changed [Meshes](Mesh) and [Polylines](crate::Polyline), it runs after [AssetLoadSet](modul_asset::AssetLoadSet) in [RedrawSet::Prepare](crate::RedrawSet::Prepare)
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MeshPrepareSet;
+
+pub(crate) fn prepare_meshes(mut meshes: ResMut<Assets<Mesh>>, ctx: Res<RenderContext>) {
+    // bypass change detection, as most frames nothing changes
+    for (_, mesh) in meshes.bypass_change_detection().iter_mut() {
+        if mesh.changed {
+            if let Err(error) = mesh.prepare(&ctx.device) {
+                warn!("{}, it is not drawn", error);
+            }
+        }
+    }
+}
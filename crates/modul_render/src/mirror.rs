@@ -0,0 +1,64 @@
+use crate::{
+    BlitOperation, RenderTargetSource, Sequence, SequenceBuilder, SequenceQueue,
+    WindowSequenceQueue,
+};
+use bevy_ecs::entity::EntityHashMap;
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, Assets};
+use modul_core::UpdatingWindow;
+use wgpu::FilterMode;
+
+/// Mirrors the color of a render target into the window entity it is on every time the window is redrawn, scaled to fit with a [BlitOperation].
+/// Useful for a clean output window showing the same content as a control window.
+/// The source needs [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage, so it is usually an offscreen target that is blitted to both windows.
+/// This owns the [WindowSequenceQueue] of the window, which runs after the [RunningSequenceQueue](crate::RunningSequenceQueue) so the source is up to date.
+#[derive(Component, Clone, Copy, Debug)]
+#[require(UpdatingWindow)]
+pub struct MirrorWindow {
+    pub source: RenderTargetSource,
+    pub filter: FilterMode,
+}
+
+impl MirrorWindow {
+    /// Mirror with linear filtering
+    pub fn new(source: RenderTargetSource) -> Self {
+        Self {
+            source,
+            filter: FilterMode::Linear,
+        }
+    }
+}
+
+pub(crate) fn update_mirror_windows(
+    mut commands: Commands,
+    mut sequences: ResMut<Assets<Sequence>>,
+    // the sequence blitting to each window, rebuilt when the MirrorWindow changes
+    mut mirror_sequences: Local<EntityHashMap<AssetId<Sequence>>>,
+    changed: Query<(Entity, &MirrorWindow), Changed<MirrorWindow>>,
+    mut removed: RemovedComponents<MirrorWindow>,
+) {
+    for window in removed.read() {
+        let Some(sequence) = mirror_sequences.remove(&window) else {
+            continue;
+        };
+        sequences.remove(sequence);
+        if let Ok(mut window) = commands.get_entity(window) {
+            window.remove::<WindowSequenceQueue>();
+        }
+    }
+    for (window, mirror) in changed.iter() {
+        if let Some(old) = mirror_sequences.remove(&window) {
+            sequences.remove(old);
+        }
+        let mut builder = SequenceBuilder::new();
+        builder.add(BlitOperation {
+            filter: mirror.filter,
+            ..BlitOperation::new(mirror.source, RenderTargetSource::Surface(window))
+        });
+        let sequence = builder.finish(&mut sequences);
+        mirror_sequences.insert(window, sequence);
+        commands
+            .entity(window)
+            .insert(WindowSequenceQueue(SequenceQueue(vec![sequence])));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{Aabb, Camera, GlobalTransform, ViewVisibility, VisibleEntities};
+use bevy_ecs::prelude::*;
+use glam::Vec3;
+use modul_util::HashSet;
+
+/// [SystemSet] within [Synchronize](crate::Synchronize) that fills the [ViewPhases] of cameras.
+/// It runs after [VisibilitySet](crate::VisibilitySet), so culled entities can be skipped.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PhaseSortSet;
+
+/// The phase an entity is drawn in, which decides how it is sorted in the [ViewPhases] of cameras.
+/// Phases are meant to be drawn in order, with their pipelines (for example blending in [Material::specialize](crate::Material::specialize)) matching the phase.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DrawPhase {
+    /// Sorted front to back, so hidden fragments fail the depth test early
+    #[default]
+    Opaque,
+    /// Opaque with discarded fragments, sorted front to back and drawn after [Opaque](DrawPhase::Opaque) as discarding disables early depth testing
+    AlphaMask,
+    /// Blended without depth writes, sorted back to front so farther entities are blended first
+    Transparent,
+}
+
+impl DrawPhase {
+    /// All phases in the order they should be drawn
+    pub const ALL: [DrawPhase; 3] = [Self::Opaque, Self::AlphaMask, Self::Transparent];
+
+    pub fn is_back_to_front(self) -> bool {
+        self == Self::Transparent
+    }
+}
+
+/// An entity in a phase of a camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhaseItem {
+    pub entity: Entity,
+    /// The view space depth of the entity, positive in front of the camera.
+    /// It is the depth of the [Aabb] center if the entity has one, otherwise the one of its translation.
+    pub depth: f32,
+}
+
+/// Add to a [Camera] entity to have the [DrawPhase] entities with a [GlobalTransform] sorted by their depth during [PhaseSortSet].
+/// If the camera also has [VisibleEntities], entities with bounds outside its frustum are left out.
+#[derive(Component, Clone, Debug, Default)]
+pub struct ViewPhases {
+    pub opaque: Vec<PhaseItem>,
+    pub alpha_mask: Vec<PhaseItem>,
+    pub transparent: Vec<PhaseItem>,
+}
+
+impl ViewPhases {
+    /// The sorted items of a phase
+    pub fn get(&self, phase: DrawPhase) -> &[PhaseItem] {
+        match phase {
+            DrawPhase::Opaque => &self.opaque,
+            DrawPhase::AlphaMask => &self.alpha_mask,
+            DrawPhase::Transparent => &self.transparent,
+        }
+    }
+
+    fn get_mut(&mut self, phase: DrawPhase) -> &mut Vec<PhaseItem> {
+        match phase {
+            DrawPhase::Opaque => &mut self.opaque,
+            DrawPhase::AlphaMask => &mut self.alpha_mask,
+            DrawPhase::Transparent => &mut self.transparent,
+        }
+    }
+}
+
+pub(crate) fn sort_phases(world: &mut World) {
+    let mut cameras = world.query_filtered::<(
+        Entity,
+        &Camera,
+        Option<&GlobalTransform>,
+        Option<&VisibleEntities>,
+    ), With<ViewPhases>>();
+    let views: Vec<_> = cameras
+        .iter(world)
+        .map(|(entity, camera, global, visible)| {
+            let view = global.map_or(camera.transform, |g| g.0).inverse();
+            let visible = visible.map(|v| v.0.iter().copied().collect::<HashSet<_>>());
+            (entity, view, visible)
+        })
+        .collect();
+
+    let mut entities = world.query::<(
+        Entity,
+        &DrawPhase,
+        &GlobalTransform,
+        Option<&Aabb>,
+        Has<ViewVisibility>,
+    )>();
+    for (camera, view, visible) in views {
+        let mut phases = ViewPhases::default();
+        for (entity, phase, global, aabb, bounded) in entities.iter(world) {
+            // entities without bounds are never culled
+            if bounded && visible.as_ref().is_some_and(|v| !v.contains(&entity)) {
+                continue;
+            }
+            let center = aabb.map_or(Vec3::ZERO, |aabb| aabb.center);
+            let depth = -view.transform_point3(global.0.transform_point3(center)).z;
+            phases.get_mut(*phase).push(PhaseItem { entity, depth });
+        }
+        for phase in DrawPhase::ALL {
+            let items = phases.get_mut(phase);
+            match phase.is_back_to_front() {
+                true => items.sort_by(|a, b| b.depth.total_cmp(&a.depth)),
+                false => items.sort_by(|a, b| a.depth.total_cmp(&b.depth)),
+            }
+        }
+        if let Some(mut view_phases) = world.get_mut::<ViewPhases>(camera) {
+            *view_phases = phases;
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use crate::{
+    BindGroupProvider, CameraBindGroup, CameraUniformProvider,
+    DirectRenderPipelineResourceProvider, GenericColorTargetState, GenericDepthStencilState,
+    GenericFragmentState, GenericMultisampleState, GenericRenderPipelineDescriptor,
+    GenericVertexBufferLayout, GenericVertexState, GpuTransform, Mesh, ModelIndex, ModelMatrices,
+    OffscreenRenderTargetConfig, Operation, OperationBuilder, OperationError,
+    PipelineLayoutComposer, ReadbackDelivery, RenderPipelineManager, RenderTarget,
+    RenderTargetColorConfig, RenderTargetDepthStencilConfig, RenderTargetSource,
+    SurfaceRenderTarget, TextureReadbacks,
+};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use modul_util::HashMap;
+use std::sync::{Arc, Mutex};
+use wgpu::{
+    CommandEncoder, CompareFunction, DepthBiasState, Device, PipelineLayout, PrimitiveState,
+    ShaderModule, StencilState, TextureFormat, TextureUsages, VertexAttribute, VertexFormat,
+    VertexStepMode,
+};
+
+/// Draws the [Mesh] into the ID targets of [PickingOperations](PickingOperation) with the model matrix of the entity, so it can be picked
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+#[require(GpuTransform)]
+pub struct Pickable {
+    pub mesh: AssetId<Mesh>,
+    /// The shader location of the position attribute, which has to be `Float32x3`
+    pub position_location: u32,
+}
+
+impl Pickable {
+    /// A pickable mesh with the position at shader location 0
+    pub fn new(mesh: AssetId<Mesh>) -> Self {
+        Self {
+            mesh,
+            position_location: 0,
+        }
+    }
+}
+
+/// Asks for the [Pickable] entity at a pixel of a window, answered by a [PickResult] once the [PickingOperation] of the window has been read back.
+/// Requests are collected every frame and answered by the next frame that is drawn, the ones no operation answered that frame are dropped.
+#[derive(Message, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PickRequest {
+    pub window: Entity,
+    /// In pixels of the window, from the top left
+    pub pixel: (u32, u32),
+}
+
+/// The answer to a [PickRequest], usually a few frames after it was made.
+/// The entity is None if no [Pickable] entity covers the pixel, it might have been despawned since.
+#[derive(Message, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PickResult {
+    pub request: PickRequest,
+    pub entity: Option<Entity>,
+}
+
+/// The pending [PickRequests](PickRequest) and the picking pipelines, inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct Picking {
+    pending: Vec<PickRequest>,
+    /// filled by the readback callbacks
+    results: Arc<Mutex<Vec<PickResult>>>,
+    pipeline_layout: AssetId<PipelineLayout>,
+    shader: AssetId<ShaderModule>,
+    /// pipelines by the position layout of the pickable meshes
+    variants: HashMap<GenericVertexBufferLayout, AssetId<RenderPipelineManager>>,
+}
+
+impl Picking {
+    /// The requests waiting for a [PickingOperation] this frame
+    pub fn pending(&self) -> &[PickRequest] {
+        &self.pending
+    }
+
+    /// Gets the pipeline for pickable meshes with the position layout, creating it on first use
+    fn pipeline_manager(
+        &mut self,
+        managers: &mut Assets<RenderPipelineManager>,
+        position_layout: GenericVertexBufferLayout,
+    ) -> AssetId<RenderPipelineManager> {
+        if let Some(manager) = self.variants.get(&position_layout) {
+            return *manager;
+        }
+        let manager = managers.add(RenderPipelineManager::new(
+            GenericRenderPipelineDescriptor {
+                resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                    layout: self.pipeline_layout,
+                    vertex_shader_module: self.shader,
+                    fragment_shader_module: self.shader,
+                }),
+                label: Some("Picking pipeline".to_string()),
+                vertex_state: GenericVertexState {
+                    entry_point: "vs_main".to_string(),
+                    buffers: vec![
+                        position_layout.clone(),
+                        ModelMatrices::vertex_buffer_layout(1),
+                    ],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: Some(GenericDepthStencilState {
+                    depth_write_enable: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: GenericMultisampleState {
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: Some(GenericFragmentState {
+                    entry_point: "fs_main".to_string(),
+                    targets: vec![GenericColorTargetState::new(None)],
+                }),
+            },
+        ));
+        self.variants.insert(position_layout, manager);
+        manager
+    }
+}
+
+/// Renders the [Pickable] entities as seen by the [Camera](crate::Camera) entity into the ID target, clearing it first,
+/// and reads back the pixels of the [PickRequests](PickRequest) of the window.
+/// The target needs an [R32Uint](TextureFormat::R32Uint) color texture with [COPY_SRC](TextureUsages::COPY_SRC) usage, a depth buffer and no multisampling,
+/// see [target_config](Self::target_config). Pixels are scaled by the size of the target relative to the window.
+pub struct PickingOperation {
+    pub window: Entity,
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+}
+
+impl PickingOperation {
+    pub fn new(window: Entity, render_target: RenderTargetSource, camera: Entity) -> Self {
+        Self {
+            window,
+            render_target,
+            camera,
+        }
+    }
+
+    /// The config of an ID target, use [TracksWindowSize](crate::TracksWindowSize) to keep it at the size of the window
+    pub fn target_config(size: (u32, u32)) -> OffscreenRenderTargetConfig {
+        OffscreenRenderTargetConfig {
+            size,
+            color_config: Some(RenderTargetColorConfig {
+                usages: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                format_override: Some(TextureFormat::R32Uint),
+                ..Default::default()
+            }),
+            depth_stencil_config: Some(RenderTargetDepthStencilConfig {
+                format: TextureFormat::Depth32Float,
+                ..Default::default()
+            }),
+            label: Some("Picking target".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl Operation for PickingOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(camera) = world.get::<CameraBindGroup>(self.camera) else {
+            return Err(format!("{} has no camera bind group", self.camera).into());
+        };
+        let camera = camera.bind_group().clone();
+        let model_matrices = world.resource::<ModelMatrices>().buffer().cloned();
+
+        let mut pickables = world.query::<(Entity, &Pickable, &ModelIndex)>();
+        let pickables: Vec<_> = pickables
+            .iter(world)
+            .map(|(entity, pickable, index)| (entity, *pickable, index.0))
+            .collect();
+        // the ids are the model indices plus one, as 0 is the clear value
+        let mut entities = HashMap::new();
+        let mut draws = Vec::new();
+        for (entity, pickable, model_index) in pickables {
+            let Some((position_layout, vertex_buffer, index_buffer, count)) =
+                world.get_asset(pickable.mesh).and_then(|mesh| {
+                    let buffers = mesh.buffers()?;
+                    let layout = mesh.vertex_buffer_layouts().pop()?;
+                    let position = layout.attributes.iter().find(|a| {
+                        a.shader_location == pickable.position_location
+                            && a.format == VertexFormat::Float32x3
+                    })?;
+                    let position_layout = GenericVertexBufferLayout {
+                        array_stride: layout.array_stride,
+                        step_mode: VertexStepMode::Vertex,
+                        attributes: vec![VertexAttribute {
+                            shader_location: 0,
+                            ..*position
+                        }],
+                    };
+                    let count = match buffers.index_buffer {
+                        Some(_) => buffers.index_count,
+                        None => buffers.vertex_count,
+                    };
+                    Some((
+                        position_layout,
+                        buffers.vertex_buffer.clone(),
+                        buffers.index_buffer.clone(),
+                        count,
+                    ))
+                })
+            else {
+                continue;
+            };
+            let manager = world.resource_scope(|world, mut picking: Mut<Picking>| {
+                let mut managers = world.resource_mut::<Assets<RenderPipelineManager>>();
+                picking.pipeline_manager(&mut managers, position_layout)
+            });
+            let mut pipeline = None;
+            world.asset_scope(manager, |world, manager| {
+                pipeline = manager.get_compatible(self.render_target, world).cloned();
+            });
+            let Some(pipeline) = pipeline else {
+                continue;
+            };
+            entities.insert(model_index, entity);
+            draws.push((pipeline, vertex_buffer, index_buffer, count, model_index));
+        }
+
+        let window_size = world
+            .get::<SurfaceRenderTarget>(self.window)
+            .map(|window| window.size());
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(texture) = rt.texture().cloned() else {
+            return Err("the picking target has no color texture".into());
+        };
+        let layer = rt.layer();
+        rt.schedule_clear_color();
+        rt.schedule_clear_depth();
+        if let Some(mut pass) = rt.begin_ending_pass(command_encoder) {
+            pass.set_bind_group(0, &camera, &[]);
+            if let Some(model_matrices) = &model_matrices {
+                pass.set_vertex_buffer(1, model_matrices.slice(..));
+                for (pipeline, vertex_buffer, index_buffer, count, model_index) in &draws {
+                    let instance = *model_index..*model_index + 1;
+                    pass.set_pipeline(pipeline);
+                    pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    match index_buffer {
+                        Some((index_buffer, format)) => {
+                            pass.set_index_buffer(index_buffer.slice(..), *format);
+                            pass.draw_indexed(0..*count, 0, instance);
+                        }
+                        None => pass.draw(0..*count, instance),
+                    }
+                }
+            }
+        }
+
+        let mut picking = world.resource_mut::<Picking>();
+        let mut requests = Vec::new();
+        picking.pending.retain(|request| {
+            let ours = request.window == self.window;
+            if ours {
+                requests.push(*request);
+            }
+            !ours
+        });
+        let results = picking.results.clone();
+        let (width, height) = (texture.width(), texture.height());
+        let (window_width, window_height) = window_size.unwrap_or((width, height));
+        let entities = Arc::new(entities);
+        let device = world.resource::<RenderContext>().device.clone();
+        for request in requests {
+            let scale = |pixel: u32, size: u32, window_size: u32| {
+                (pixel as u64 * size as u64 / window_size.max(1) as u64) as u32
+            };
+            let x = scale(request.pixel.0, width, window_width);
+            let y = scale(request.pixel.1, height, window_height);
+            if x >= width || y >= height {
+                let result = PickResult {
+                    request,
+                    entity: None,
+                };
+                results.lock().unwrap().push(result);
+                continue;
+            }
+            let entities = entities.clone();
+            let results = results.clone();
+            let delivery = ReadbackDelivery::Callback(Box::new(move |image| {
+                let id = image
+                    .pixel(0, 0)
+                    .and_then(|bytes| Some(u32::from_ne_bytes(bytes.try_into().ok()?)));
+                let entity = id
+                    .and_then(|id| id.checked_sub(1))
+                    .and_then(|index| entities.get(&index).copied());
+                results.lock().unwrap().push(PickResult { request, entity });
+            }));
+            world.resource_mut::<TextureReadbacks>().copy_region(
+                &device,
+                command_encoder,
+                &texture,
+                layer,
+                (x, y),
+                (1, 1),
+                delivery,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl OperationBuilder for PickingOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+pub(crate) fn init_picking(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+) {
+    let mut composer = PipelineLayoutComposer::new();
+    composer.add_bind_group(CameraUniformProvider::new(&ctx.device));
+    let pipeline_layout = layouts.add(composer.compose_pipeline_layout(&ctx.device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(&ctx.device, "Picking shader", include_str!("picking.wgsl"))
+            .expect("the built-in picking shader has balanced directives")
+            .clone(),
+    );
+    commands.insert_resource(Picking {
+        pending: Vec::new(),
+        results: Arc::new(Mutex::new(Vec::new())),
+        pipeline_layout,
+        shader,
+        variants: HashMap::new(),
+    });
+}
+
+pub(crate) fn collect_pick_requests(
+    mut requests: MessageReader<PickRequest>,
+    picking: Option<ResMut<Picking>>,
+) {
+    let Some(mut picking) = picking else {
+        return;
+    };
+    picking.pending.extend(requests.read().copied());
+}
+
+/// Writes the results read back this frame and drops the requests no [PickingOperation] answered
+pub(crate) fn write_pick_results(
+    mut picking: ResMut<Picking>,
+    mut results: MessageWriter<PickResult>,
+) {
+    picking.pending.clear();
+    results.write_batch(picking.results.lock().unwrap().drain(..));
+}
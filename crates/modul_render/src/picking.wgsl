@@ -0,0 +1,26 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+}
+
+@vertex
+fn vs_main(
+    @builtin(instance_index) instance: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) model_0: vec4<f32>,
+    @location(2) model_1: vec4<f32>,
+    @location(3) model_2: vec4<f32>,
+    @location(4) model_3: vec4<f32>,
+) -> VertexOutput {
+    let model = mat4x4<f32>(model_0, model_1, model_2, model_3);
+    var out: VertexOutput;
+    out.position = camera.view_projection * model * vec4<f32>(position, 1.0);
+    // the instance is the model index, 0 is left for the cleared background
+    out.id = instance + 1u;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    return in.id;
+}
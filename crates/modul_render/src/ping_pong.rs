@@ -0,0 +1,97 @@
+use bevy_ecs::prelude::*;
+
+use crate::{OffscreenRenderTarget, OffscreenRenderTargetConfig, RenderTargetSource};
+
+/// Two [OffscreenRenderTargets](OffscreenRenderTarget) swapped every frame, one is written while the other holds the result of the last frame.
+/// Used for TAA, feedback effects and iterative simulations, operations use [read](PingPongTarget::read) and [write](PingPongTarget::write) as their sources.
+/// Both targets should have the same config, resize both (or add [TracksWindowSize](crate::TracksWindowSize) to both) when resizing.
+#[derive(Component)]
+pub struct PingPongTarget {
+    targets: [Entity; 2],
+    read: usize,
+    history_valid: bool,
+    reset_scheduled: bool,
+    // generations of the targets when the history was last checked
+    generations: Option<[u32; 2]>,
+}
+
+impl PingPongTarget {
+    /// Uses two existing [OffscreenRenderTarget] entities
+    pub fn new(a: Entity, b: Entity) -> Self {
+        Self {
+            targets: [a, b],
+            read: 0,
+            history_valid: false,
+            reset_scheduled: false,
+            generations: None,
+        }
+    }
+
+    /// Spawns two [OffscreenRenderTargets](OffscreenRenderTarget) with the config, and the [PingPongTarget] using them
+    pub fn spawn(commands: &mut Commands, config: OffscreenRenderTargetConfig) -> Entity {
+        let a = commands
+            .spawn(OffscreenRenderTarget::new(config.clone()))
+            .id();
+        let b = commands.spawn(OffscreenRenderTarget::new(config)).id();
+        commands.spawn(PingPongTarget::new(a, b)).id()
+    }
+
+    /// The source of the side written last frame, for the entity of this component
+    pub fn read(entity: Entity) -> RenderTargetSource {
+        RenderTargetSource::PingPongRead(entity)
+    }
+
+    /// The source of the side written this frame, for the entity of this component
+    pub fn write(entity: Entity) -> RenderTargetSource {
+        RenderTargetSource::PingPongWrite(entity)
+    }
+
+    pub fn read_entity(&self) -> Entity {
+        self.targets[self.read]
+    }
+
+    pub fn write_entity(&self) -> Entity {
+        self.targets[1 - self.read]
+    }
+
+    /// Both target entities
+    pub fn targets(&self) -> [Entity; 2] {
+        self.targets
+    }
+
+    /// False if the read side does not hold the last frame, on the first frame, after the textures were recreated (e.g. resizing) and after a [reset](PingPongTarget::reset).
+    /// Decided after [ApplyOffscreenTargetsSystemSet](crate::ApplyOffscreenTargetsSystemSet), so it is up to date for the sequences.
+    pub fn history_valid(&self) -> bool {
+        self.history_valid
+    }
+
+    /// Invalidates the history for the next frame, for example on camera cuts
+    pub fn reset(&mut self) {
+        self.reset_scheduled = true;
+    }
+}
+
+pub(crate) fn swap_ping_pong_targets(mut ping_pongs: Query<&mut PingPongTarget>) {
+    for mut ping_pong in ping_pongs.iter_mut() {
+        ping_pong.read = 1 - ping_pong.read;
+    }
+}
+
+/// Runs after the changes of the targets are applied, as recreated textures hold no history
+pub(crate) fn update_ping_pong_histories(
+    mut ping_pongs: Query<&mut PingPongTarget>,
+    targets: Query<&OffscreenRenderTarget>,
+) {
+    for mut ping_pong in ping_pongs.iter_mut() {
+        let [a, b] = ping_pong.targets;
+        let generations = match (targets.get(a), targets.get(b)) {
+            (Ok(a), Ok(b)) => Some([a.generation(), b.generation()]),
+            _ => None,
+        };
+        ping_pong.history_valid = generations.is_some()
+            && generations == ping_pong.generations
+            && !ping_pong.reset_scheduled;
+        ping_pong.generations = generations;
+        ping_pong.reset_scheduled = false;
+    }
+}
@@ -0,0 +1,102 @@
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use log::{error, info};
+use modul_core::{Init, RenderContext};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use wgpu::{Features, PipelineCache, PipelineCacheDescriptor};
+
+/// A [PipelineCache] loaded from and saved to a file, used by the [RenderPipelineManagers](crate::RenderPipelineManager) and [ComputePipelineManagers](crate::ComputePipelineManager) to create their pipelines.
+/// Later runs reuse the compiled shaders, cutting the time spent creating pipelines.
+/// The file is named by [pipeline_cache_key](wgpu::util::pipeline_cache_key), so caches of different adapters are kept apart.
+/// Pipeline caches are only supported on Vulkan with [PIPELINE_CACHE](Features::PIPELINE_CACHE) enabled, otherwise the cache does nothing.
+/// The cache is saved when dropped, which happens when the app exits.
+#[derive(Resource)]
+pub struct PersistentPipelineCache {
+    cache: Option<(PipelineCache, PathBuf)>,
+}
+
+impl PersistentPipelineCache {
+    /// Loads the cache of the adapter from the directory, starting with an empty cache if there is no file
+    pub fn load(context: &RenderContext, directory: impl Into<PathBuf>) -> Self {
+        let key = wgpu::util::pipeline_cache_key(&context.adapter.get_info());
+        let supported = context.device.features().contains(Features::PIPELINE_CACHE);
+        let Some(key) = key.filter(|_| supported) else {
+            return Self { cache: None };
+        };
+        let path = directory.into().join(key);
+        let data = fs::read(&path).ok();
+        if data.is_some() {
+            info!("Loaded pipeline cache {:?}", path);
+        }
+        // SAFETY: the file is only written by save with data from get_data, named by the key of the adapter
+        let cache = unsafe {
+            context
+                .device
+                .create_pipeline_cache(&PipelineCacheDescriptor {
+                    label: Some("Persistent pipeline cache"),
+                    data: data.as_deref(),
+                    // outdated data (for example after a driver update) gives an empty cache
+                    fallback: true,
+                })
+        };
+        Self {
+            cache: Some((cache, path)),
+        }
+    }
+
+    /// The cache, None if pipeline caches are not supported
+    pub fn cache(&self) -> Option<&PipelineCache> {
+        self.cache.as_ref().map(|(cache, _)| cache)
+    }
+
+    /// The file the cache is saved to, None if pipeline caches are not supported
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.cache.as_ref().map(|(_, path)| path)
+    }
+
+    /// Writes the cache to the file, done automatically when the cache is dropped.
+    /// The data is written to a temporary file first, so an interrupted save does not leave a broken cache.
+    pub fn save(&self) -> io::Result<()> {
+        let Some((cache, path)) = &self.cache else {
+            return Ok(());
+        };
+        let Some(data) = cache.get_data() else {
+            return Ok(());
+        };
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let temp_path = path.with_extension("temp");
+        fs::write(&temp_path, data)?;
+        fs::rename(temp_path, path)
+    }
+}
+
+impl Drop for PersistentPipelineCache {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            error!("Failed to save pipeline cache {:?}: {}", self.path(), e);
+        }
+    }
+}
+
+/// Inserts a [PersistentPipelineCache] loaded from the directory during [Init].
+/// [PIPELINE_CACHE](Features::PIPELINE_CACHE) has to be enabled by the graphics initializer, for example as an optional feature.
+/// Pipelines created before the cache is inserted (like in other [Init] systems) do not use it.
+pub struct PersistentPipelineCachePlugin {
+    pub directory: PathBuf,
+}
+
+impl Plugin for PersistentPipelineCachePlugin {
+    fn build(&self, app: &mut App) {
+        let directory = self.directory.clone();
+        app.add_systems(
+            Init,
+            move |mut commands: Commands, context: Res<RenderContext>| {
+                commands.insert_resource(PersistentPipelineCache::load(&context, &directory));
+            },
+        );
+    }
+}
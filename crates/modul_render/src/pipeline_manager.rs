@@ -1,26 +1,58 @@
-use crate::RenderTargetSource;
-use bevy_ecs::world::World;
-use modul_core::RenderContext;
+use crate::{
+    PersistentPipelineCache, RenderTargetSource, ShaderCompileError, ShaderDefError, ShaderDefs,
+    ShaderImportError,
+};
+use bevy_ecs::prelude::*;
+use modul_core::{profile_span, RenderContext};
 use modul_util::HashMap;
 use wgpu::{
     BlendState, BufferAddress, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
-    DepthStencilState, FragmentState, MultisampleState, PipelineLayout, PrimitiveState,
-    RenderPipeline, RenderPipelineDescriptor, ShaderModule, StencilState, TextureFormat,
-    VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
+    DepthStencilState, Device, ErrorFilter, FragmentState, MultisampleState, PipelineCache,
+    PipelineLayout, PrimitiveState, RenderPipeline, RenderPipelineDescriptor, ShaderModule,
+    StencilState, TextureFormat, VertexAttribute, VertexBufferLayout, VertexState,
+    VertexStepMode,
 };
-use modul_asset::{AssetId, AssetWorldExt};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+use bevy_tasks::{AsyncComputeTaskPool, Task, TaskPool};
+use modul_asset::{AssetId, AssetWorldExt, Assets, UntypedAssetId};
 
-/// Provides [BindGroupLayout](wgpu::BindGroupLayout) and [ShaderModules](ShaderModule) for a [RenderPipeline](RenderPipeline)
+/// Provides [BindGroupLayout](wgpu::BindGroupLayout) and [ShaderModules](ShaderModule) for a [RenderPipeline](RenderPipeline).
+/// The resources are returned by value, as wgpu handles are reference counted and cheap to clone.
 pub trait RenderPipelineResourceProvider {
     /// Should always be called before getting resources.  
     fn update(&self, world: &mut World);
 
     // no mut self, because it gets mut world and should just be a ref
-    fn get_pipeline_layout<'a>(&self, world: &'a World) -> &'a PipelineLayout;
+    fn get_pipeline_layout(&self, world: &World) -> PipelineLayout;
+
+    fn get_vertex_shader_module(&self, world: &World) -> ShaderModule;
 
-    fn get_vertex_shader_module<'a>(&self, world: &'a World) -> &'a ShaderModule;
+    fn get_fragment_shader_module(&self, world: &World) -> ShaderModule;
 
-    fn get_fragment_shader_module<'a>(&self, world: &'a World) -> &'a ShaderModule;
+    /// The vertex shader module for the [ShaderDefs] of the [PipelineParameters], by default the defs are ignored
+    fn get_specialized_vertex_shader_module(
+        &self,
+        world: &World,
+        _defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        Ok(self.get_vertex_shader_module(world))
+    }
+
+    /// The fragment shader module for the [ShaderDefs] of the [PipelineParameters], by default the defs are ignored
+    fn get_specialized_fragment_shader_module(
+        &self,
+        world: &World,
+        _defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        Ok(self.get_fragment_shader_module(world))
+    }
+
+    /// The assets the resources are created from, see [AssetDependencies](modul_asset::AssetDependencies)
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        Vec::new()
+    }
 }
 
 pub struct DirectRenderPipelineResourceProvider {
@@ -30,23 +62,33 @@ pub struct DirectRenderPipelineResourceProvider {
 }
 
 impl RenderPipelineResourceProvider for DirectRenderPipelineResourceProvider {
-    fn update(&self, world: &mut World) {}
+    fn update(&self, _world: &mut World) {}
+
+    fn get_pipeline_layout(&self, world: &World) -> PipelineLayout {
+        world.asset(self.layout).clone()
+    }
 
-    fn get_pipeline_layout<'a>(&self, world: &'a World) -> &'a PipelineLayout {
-        world.asset(self.layout)
+    fn get_vertex_shader_module(&self, world: &World) -> ShaderModule {
+        world.asset(self.vertex_shader_module).clone()
     }
 
-    fn get_vertex_shader_module<'a>(&self, world: &'a World) -> &'a ShaderModule {
-        world.asset(self.vertex_shader_module)
+    fn get_fragment_shader_module(&self, world: &World) -> ShaderModule {
+        world.asset(self.fragment_shader_module).clone()
     }
 
-    fn get_fragment_shader_module<'a>(&self, world: &'a World) -> &'a ShaderModule {
-        world.asset(self.fragment_shader_module)
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        vec![
+            self.layout.into(),
+            self.vertex_shader_module.into(),
+            self.fragment_shader_module.into(),
+        ]
     }
 }
 
 /// A stripped version of [RenderPipelineDescriptor] that removes multisample and format information.
 /// This is useful to define pipelines without knowing anything about the textures.
+/// Immediate data (push constants) is declared by the pipeline layout of the resource provider,
+/// see [PipelineLayoutComposer::add_immediates](crate::PipelineLayoutComposer::add_immediates).
 pub struct GenericRenderPipelineDescriptor {
     pub resource_provider: Box<dyn RenderPipelineResourceProvider + Send + Sync + 'static>,
     pub label: Option<String>,
@@ -64,7 +106,7 @@ pub struct GenericVertexState {
 }
 
 /// Used with [GenericVertexState]
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GenericVertexBufferLayout {
     pub array_stride: BufferAddress,
     pub step_mode: VertexStepMode,
@@ -88,36 +130,85 @@ pub struct GenericMultisampleState {
 /// Used with [GenericRenderPipelineDescriptor]
 pub struct GenericFragmentState {
     pub entry_point: String,
-    /// Blend state of the possible target
-    pub target_blend: Option<BlendState>,
-    /// Color write of the possible target
-    pub target_color_writes: ColorWrites,
+    /// The color targets by location, there must be one for every color attachment of the render target
+    pub targets: Vec<GenericColorTargetState>,
+}
+
+/// Used with [GenericFragmentState], the format is given by the [PipelineParameters]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GenericColorTargetState {
+    pub blend: Option<BlendState>,
+    pub write_mask: ColorWrites,
+}
+
+impl GenericColorTargetState {
+    /// A target writing all channels with the blend state
+    pub fn new(blend: Option<BlendState>) -> Self {
+        Self {
+            blend,
+            write_mask: ColorWrites::ALL,
+        }
+    }
 }
 
 /// Used with [GenericPipelineDescriptor] to describe a pipeline.  
-/// The parameters are invalid if both color_format and depth_stencil_format are None.  
+/// The parameters are invalid if color_formats is empty and depth_stencil_format is None.  
 #[derive(Hash, Clone, PartialEq, Eq, Debug)]
 pub struct PipelineParameters {
-    pub color_format: Option<TextureFormat>,
+    /// The formats of the color attachments by location, see [RenderTarget::color_formats](crate::RenderTarget::color_formats)
+    pub color_formats: Vec<TextureFormat>,
     pub depth_stencil_format: Option<TextureFormat>,
     pub sample_count: u32,
+    /// Passed to the [RenderPipelineResourceProvider] to specialize the shaders,
+    /// see [SpecializedRenderPipelineResourceProvider](crate::SpecializedRenderPipelineResourceProvider)
+    pub shader_defs: ShaderDefs,
 }
 
 /// Used to manage instances of a [GenericRenderPipelineDescriptor]
 pub struct RenderPipelineManager {
     desc: GenericRenderPipelineDescriptor,
-    instances: HashMap<PipelineParameters, RenderPipeline>,
+    /// the pipelines with the frame they were last used in
+    instances: HashMap<PipelineParameters, (RenderPipeline, u64)>,
+    frame: u64,
+    /// parameters the pipeline could not be created for, kept until the manager is cleared
+    failed: HashMap<PipelineParameters, PipelineError>,
+    /// pipelines being created by [request](Self::request)
+    pending: HashMap<PipelineParameters, Task<Result<RenderPipeline, PipelineError>>>,
 }
 
 impl RenderPipelineManager {
+    /// Panics if the descriptor has neither a fragment nor a depth/stencil state, see [try_new](Self::try_new)
     pub fn new(desc: GenericRenderPipelineDescriptor) -> Self {
+        Self::try_new(desc).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [new](Self::new), but returns an error instead of panicking
+    pub fn try_new(desc: GenericRenderPipelineDescriptor) -> Result<Self, PipelineError> {
         if desc.fragment.is_none() && desc.depth_stencil.is_none() {
-            panic!("must have either fragment or depth_stencil");
+            return Err(PipelineError::NoOutput);
         }
-        Self {
+        Ok(Self {
             desc,
             instances: HashMap::new(),
-        }
+            frame: 0,
+            failed: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    /// The assets the pipelines are created from, given by the [RenderPipelineResourceProvider].
+    /// The [RenderPlugin](crate::RenderPlugin) registers them in [AssetDependencies](modul_asset::AssetDependencies) for managers in `Assets<RenderPipelineManager>`, so the pipelines are rebuilt when they change.
+    pub fn dependencies(&self) -> Vec<UntypedAssetId> {
+        self.desc.resource_provider.dependencies()
+    }
+
+    /// Removes all created pipelines, they will be created again when requested.  
+    /// This is done automatically when an [AssetEvent::DependencyModified](modul_asset::AssetEvent::DependencyModified) is written for the manager.  
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.failed.clear();
+        // pipelines still being created use the old resources, they are dropped when finished
+        self.pending.clear();
     }
 
     /// Returns if the pipeline can be created for a parameter with only depth_stencil.  
@@ -130,125 +221,529 @@ impl RenderPipelineManager {
         self.desc.fragment.is_some()
     }
 
+    /// The amount of color targets of the fragment state, 0 if there is none
+    pub fn color_target_count(&self) -> usize {
+        self.desc.fragment.as_ref().map_or(0, |f| f.targets.len())
+    }
+
     /// Gets a pipeline from the internal cache, or creates and stores one given the parameters.  
     /// The returned value can be ignored if you just want to init the pipeline.  
+    /// If the pipeline was [requested](Self::request) this waits for it to be created.
+    /// Panics if the pipeline can not be created, see [try_create](Self::try_create).
     pub fn get(&mut self, world: &mut World, params: &PipelineParameters) -> &RenderPipeline {
-        if params.color_format.is_none() && params.depth_stencil_format.is_none() {
-            panic!("color_format and depth_stencil_format must not both be none");
+        if let Some(error) = self.try_create(world, params).err() {
+            panic!("failed to create pipeline {:?}: {}", self.desc.label, error);
         }
-        if !self.has_depth_stencil() && params.color_format.is_none() {
-            panic!("no color_format on pipeline that only supports color");
+        &self.instances[params].0
+    }
+
+    /// Like [get](Self::get), but returns an error instead of panicking, for example when the parameters do not fit the descriptor,
+    /// or when wgpu rejects a shader with invalid WGSL after a hot reload.
+    /// The error is also written as a [PipelineErrorEvent], and returned again without retrying until the manager is [cleared](Self::clear).
+    pub fn try_create(
+        &mut self,
+        world: &mut World,
+        params: &PipelineParameters,
+    ) -> Result<&RenderPipeline, PipelineError> {
+        if !self.instances.contains_key(params) {
+            if let Some(error) = self.failed.get(params) {
+                return Err(error.clone());
+            }
+            let result = match self.pending.remove(params) {
+                Some(pending) => bevy_tasks::block_on(pending),
+                None => self.check(params).and_then(|()| {
+                    let device = world.resource::<RenderContext>().device.clone();
+                    self.source(world, params)?.create(&device)
+                }),
+            };
+            self.store(world, params, result)?;
+        }
+        Ok(self.mark_used(params))
+    }
+
+    /// Like [get](Self::get), but the pipeline is created on the [AsyncComputeTaskPool] so the frame does not wait for the shaders to compile.
+    /// Returns [PipelineStatus::Pending] until it is created, operations can skip drawing or use a fallback until then.
+    /// The shader modules are still created immediately. Errors are handled like in [try_create](Self::try_create).
+    pub fn request(
+        &mut self,
+        world: &mut World,
+        params: &PipelineParameters,
+    ) -> PipelineStatus<'_> {
+        if let Some(pending) = self.pending.get(params) {
+            if !pending.is_finished() {
+                return PipelineStatus::Pending;
+            }
+            let result = bevy_tasks::block_on(self.pending.remove(params).unwrap());
+            let _ = self.store(world, params, result);
+        }
+        if self.instances.contains_key(params) {
+            return PipelineStatus::Ready(self.mark_used(params));
+        }
+        if !self.failed.contains_key(params) {
+            match self.check(params).and_then(|()| self.source(world, params)) {
+                Ok(source) => {
+                    let device = world.resource::<RenderContext>().device.clone();
+                    let pending =
+                        AsyncComputeTaskPool::get_or_init(TaskPool::new).spawn(async move {
+                            // stored as a failed pipeline instead of panicking again when it is taken
+                            panic::catch_unwind(AssertUnwindSafe(|| source.create(&device)))
+                                .unwrap_or(Err(PipelineError::Panicked))
+                        });
+                    self.pending.insert(params.clone(), pending);
+                    return PipelineStatus::Pending;
+                }
+                Err(error) => {
+                    let _ = self.store(world, params, Err(error));
+                }
+            }
+        }
+        PipelineStatus::Failed(&self.failed[params])
+    }
+
+    /// Like [request](Self::request) for the parameters of the [RenderTarget], see [get_compatible](Self::get_compatible).
+    /// Returns [PipelineStatus::Incompatible] instead of None.
+    pub fn request_compatible(
+        &mut self,
+        render_target: RenderTargetSource,
+        world: &mut World,
+    ) -> PipelineStatus<'_> {
+        match self.compatible_params(render_target, ShaderDefs::default(), world) {
+            Some(params) => self.request(world, &params),
+            None => PipelineStatus::Incompatible,
+        }
+    }
+
+    /// Starts creating the pipelines for all the parameters in the background, for example at startup with the formats of the targets that will be used.
+    /// They are created with [request](Self::request), so they can be requested again to check if they are ready.
+    pub fn warm_up(
+        &mut self,
+        world: &mut World,
+        params: impl IntoIterator<Item = PipelineParameters>,
+    ) {
+        for params in params {
+            self.request(world, &params);
+        }
+    }
+
+    /// If any requested pipelines are still being created
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// The error of the parameters if the pipeline could not be created, see [try_create](Self::try_create)
+    pub fn error(&self, params: &PipelineParameters) -> Option<&PipelineError> {
+        self.failed.get(params)
+    }
+
+    /// Checks if the parameters fit the descriptor
+    pub fn check(&self, params: &PipelineParameters) -> Result<(), PipelineError> {
+        if params.color_formats.is_empty() && params.depth_stencil_format.is_none() {
+            return Err(PipelineError::NoFormats);
+        }
+        if !self.has_depth_stencil() && params.color_formats.is_empty() {
+            return Err(PipelineError::MissingColorFormats);
         }
         if !self.has_color() && params.depth_stencil_format.is_none() {
-            panic!("no depth_stencil format on pipeline that only supports depth_stencil");
+            return Err(PipelineError::MissingDepthStencilFormat);
         }
-        
-        self.instances.entry(params.clone()).or_insert_with(|| {
-            self.desc.resource_provider.update(world);
+        if self.has_color()
+            && !params.color_formats.is_empty()
+            && params.color_formats.len() != self.color_target_count()
+        {
+            return Err(PipelineError::ColorTargetCount {
+                formats: params.color_formats.len(),
+                targets: self.color_target_count(),
+            });
+        }
+        Ok(())
+    }
 
-            let device = &world.resource::<RenderContext>().device;
+    /// Stores the pipeline, or the error which is also written as a [PipelineErrorEvent],
+    /// and as a [ShaderCompileError] if a shader failed to compile
+    fn store(
+        &mut self,
+        world: &mut World,
+        params: &PipelineParameters,
+        result: Result<RenderPipeline, PipelineError>,
+    ) -> Result<(), PipelineError> {
+        match result {
+            Ok(pipeline) => {
+                self.instances
+                    .insert(params.clone(), (pipeline, self.frame));
+                Ok(())
+            }
+            Err(error) => {
+                world.write_message(PipelineErrorEvent {
+                    label: self.desc.label.clone(),
+                    params: params.clone(),
+                    error: error.clone(),
+                });
+                if let PipelineError::Compile(compile_error) = &error {
+                    world.write_message(compile_error.clone());
+                }
+                self.failed.insert(params.clone(), error.clone());
+                Err(error)
+            }
+        }
+    }
 
-            let vs_module = self.desc.resource_provider.get_vertex_shader_module(world);
-            let fs_module = self
-                .desc
-                .resource_provider
-                .get_fragment_shader_module(world);
+    /// Everything needed to create the pipeline, owned so it can be moved to another thread
+    fn source(
+        &self,
+        world: &mut World,
+        params: &PipelineParameters,
+    ) -> Result<PipelineSource, PipelineError> {
+        let device = world.resource::<RenderContext>().device.clone();
+        // invalid shaders are reported when the modules are created
+        let scope = device.push_error_scope(ErrorFilter::Validation);
+        self.desc.resource_provider.update(world);
 
-            let mut targets = Vec::new();
+        let provider = &self.desc.resource_provider;
+        let defs = &params.shader_defs;
+        let modules = provider
+            .get_specialized_vertex_shader_module(world, defs)
+            .and_then(|vs| Ok((vs, provider.get_specialized_fragment_shader_module(world, defs)?)));
+        let layout = provider.get_pipeline_layout(world);
+        if let Some(error) = pollster::block_on(scope.pop()) {
+            return Err(PipelineError::Validation(error.to_string()));
+        }
+        let (vs_module, fs_module) = modules?;
 
-            let desc = RenderPipelineDescriptor {
-                label: self.desc.label.as_ref().map(String::as_str),
-                layout: Some(self.desc.resource_provider.get_pipeline_layout(world)),
-                vertex: VertexState {
-                    module: vs_module,
-                    entry_point: Some(self.desc.vertex_state.entry_point.as_str()),
-                    compilation_options: Default::default(),
-                    buffers: &self
-                        .desc
-                        .vertex_state
-                        .buffers
-                        .iter()
-                        .map(|l| VertexBufferLayout {
-                            array_stride: l.array_stride,
-                            step_mode: l.step_mode,
-                            attributes: &l.attributes,
-                        })
-                        .collect::<Box<_>>(),
-                },
-                primitive: self.desc.primitive.clone(),
-                depth_stencil: params.depth_stencil_format.and_then(|format| {
-                    self.desc
-                        .depth_stencil
-                        .as_ref()
-                        .map(|ds| DepthStencilState {
+        let fragment = self
+            .desc
+            .fragment
+            .as_ref()
+            .filter(|_| !params.color_formats.is_empty())
+            .map(|frag| {
+                let targets = frag
+                    .targets
+                    .iter()
+                    .zip(&params.color_formats)
+                    .map(|(target, &format)| {
+                        Some(ColorTargetState {
                             format,
-                            depth_write_enabled: Some(ds.depth_write_enable),
-                            depth_compare: Some(ds.depth_compare),
-                            stencil: ds.stencil.clone(),
-                            bias: ds.bias,
+                            blend: target.blend,
+                            write_mask: target.write_mask,
                         })
-                }),
-                multisample: MultisampleState {
-                    count: params.sample_count,
-                    mask: self.desc.multisample.mask,
-                    alpha_to_coverage_enabled: self.desc.multisample.alpha_to_coverage_enabled,
-                },
-                fragment: if let (Some(format), Some(frag)) =
-                    (params.color_format, self.desc.fragment.as_ref())
-                {
-                    targets.push(Some(ColorTargetState {
+                    })
+                    .collect();
+                (frag.entry_point.clone(), targets)
+            });
+
+        Ok(PipelineSource {
+            label: self.desc.label.clone(),
+            layout,
+            vs_module,
+            fs_module,
+            vertex_entry_point: self.desc.vertex_state.entry_point.clone(),
+            buffers: self.desc.vertex_state.buffers.clone(),
+            primitive: self.desc.primitive,
+            depth_stencil: params.depth_stencil_format.and_then(|format| {
+                self.desc
+                    .depth_stencil
+                    .as_ref()
+                    .map(|ds| DepthStencilState {
                         format,
-                        blend: frag.target_blend,
-                        write_mask: frag.target_color_writes,
-                    }));
-                    Some(FragmentState {
-                        module: fs_module,
-                        entry_point: Some(frag.entry_point.as_str()),
-                        compilation_options: Default::default(),
-                        targets: &targets,
+                        depth_write_enabled: Some(ds.depth_write_enable),
+                        depth_compare: Some(ds.depth_compare),
+                        stencil: ds.stencil.clone(),
+                        bias: ds.bias,
                     })
-                } else {
-                    None
-                },
-                multiview_mask: None,
-                cache: None,
-            };
-            device.create_render_pipeline(&desc)
+            }),
+            multisample: MultisampleState {
+                count: params.sample_count,
+                mask: self.desc.multisample.mask,
+                alpha_to_coverage_enabled: self.desc.multisample.alpha_to_coverage_enabled,
+            },
+            fragment,
+            cache: world
+                .get_resource::<PersistentPipelineCache>()
+                .and_then(|c| c.cache().cloned()),
         })
     }
-    
+
     /// Gets a pipeline if it exists, otherwise will return None.  
     /// Using [get](Self::get) will create the desired pipeline instead of returning an option.  
+    /// This does not count as using the pipeline for [trim](Self::trim).
     pub fn try_get(&self, params: &PipelineParameters) -> Option<&RenderPipeline> {
-        self.instances.get(params)
+        self.instances.get(params).map(|(pipeline, _)| pipeline)
+    }
+
+    /// The amount of created pipelines
+    pub fn instance_count(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Removes the pipelines that have not been gotten or requested for the amount of frames, returning how many were removed.
+    /// Managers in `Assets<RenderPipelineManager>` are trimmed automatically, see [PipelineTrimSettings].
+    pub fn trim(&mut self, unused_for_frames: u64) -> usize {
+        let frame = self.frame;
+        let count = self.instances.len();
+        self.instances
+            .retain(|_, (_, last_used)| frame - *last_used < unused_for_frames);
+        count - self.instances.len()
+    }
+
+    /// Starts a new frame for [trim](Self::trim), done during [PostDraw](crate::PostDraw) for managers in `Assets<RenderPipelineManager>`.
+    /// Managers stored elsewhere have to call this themselves to be trimmed.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    fn mark_used(&mut self, params: &PipelineParameters) -> &RenderPipeline {
+        let (pipeline, last_used) = self.instances.get_mut(params).unwrap();
+        *last_used = self.frame;
+        pipeline
     }
 
     /// Gets the pipeline for a [RenderTarget], see [Self::get] for more details.  
     /// This can also be used for initialization
+    /// Returns None if the color attachments of the target do not match the color targets of the fragment state,
+    /// or if the pipeline can not be created, which is written as a [PipelineErrorEvent] like in [try_create](Self::try_create).
     pub fn get_compatible(
         &mut self,
         render_target: RenderTargetSource,
         world: &mut World,
     ) -> Option<&RenderPipeline> {
+        self.get_specialized(render_target, ShaderDefs::default(), world)
+    }
+
+    /// Gets the pipeline for a [RenderTarget] specialized with the [ShaderDefs], like [Self::get_compatible]
+    pub fn get_specialized(
+        &mut self,
+        render_target: RenderTargetSource,
+        shader_defs: ShaderDefs,
+        world: &mut World,
+    ) -> Option<&RenderPipeline> {
+        let params = self.compatible_params(render_target, shader_defs, world)?;
+        self.try_create(world, &params).ok()
+    }
+
+    /// The parameters for the [RenderTarget], None if the pipeline can not be used with it
+    fn compatible_params(
+        &self,
+        render_target: RenderTargetSource,
+        shader_defs: ShaderDefs,
+        world: &World,
+    ) -> Option<PipelineParameters> {
         let render_target = render_target.get(world)?;
-        let color_format = render_target.texture().map(|t| t.format());
+        let color_formats = render_target.color_formats();
         let depth_stencil_format = render_target.depth_stencil().map(|t| t.format());
-        if color_format.is_none() && depth_stencil_format.is_none() {
+        if color_formats.is_empty() && depth_stencil_format.is_none() {
             return None;
         }
         if !self.has_color() && depth_stencil_format.is_none() {
             return None;
         }
-        if !self.has_depth_stencil() && color_format.is_none() {
+        if !self.has_depth_stencil() && color_formats.is_empty() {
+            return None;
+        }
+        if self.has_color()
+            && !color_formats.is_empty()
+            && color_formats.len() != self.color_target_count()
+        {
             return None;
         }
-        Some(self.get(
-            world,
-            &PipelineParameters {
-                color_format,
-                depth_stencil_format,
-                sample_count: render_target.sample_count(),
+        Some(PipelineParameters {
+            color_formats,
+            depth_stencil_format,
+            sample_count: render_target.sample_count(),
+            shader_defs,
+        })
+    }
+}
+
+/// How long unused pipelines are kept by the [RenderPipelineManagers](RenderPipelineManager) in `Assets<RenderPipelineManager>`,
+/// so variants for formats or sample counts that are no longer used (like after changing the MSAA setting) are dropped.
+#[derive(Resource, Clone, Debug)]
+pub struct PipelineTrimSettings {
+    /// Pipelines not used for this many drawn frames are removed, None keeps them until the manager is cleared
+    pub unused_for_frames: Option<u64>,
+}
+
+impl Default for PipelineTrimSettings {
+    fn default() -> Self {
+        Self {
+            unused_for_frames: Some(600),
+        }
+    }
+}
+
+pub(crate) fn trim_render_pipelines(
+    settings: Res<PipelineTrimSettings>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    for (_, manager) in managers.iter_mut() {
+        if let Some(unused_for_frames) = settings.unused_for_frames {
+            manager.trim(unused_for_frames);
+        }
+        manager.next_frame();
+    }
+}
+
+/// Returned by [RenderPipelineManager::request]
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineStatus<'a> {
+    Ready(&'a RenderPipeline),
+    /// The pipeline is being created in the background
+    Pending,
+    /// The pipeline can not be used with the render target, see [RenderPipelineManager::request_compatible]
+    Incompatible,
+    /// The pipeline could not be created, see [RenderPipelineManager::try_create]
+    Failed(&'a PipelineError),
+}
+
+impl<'a> PipelineStatus<'a> {
+    /// The pipeline if it is ready
+    pub fn ready(self) -> Option<&'a RenderPipeline> {
+        match self {
+            PipelineStatus::Ready(pipeline) => Some(pipeline),
+            _ => None,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self, PipelineStatus::Ready(_))
+    }
+}
+
+struct PipelineSource {
+    label: Option<String>,
+    layout: PipelineLayout,
+    vs_module: ShaderModule,
+    fs_module: ShaderModule,
+    vertex_entry_point: String,
+    buffers: Vec<GenericVertexBufferLayout>,
+    primitive: PrimitiveState,
+    depth_stencil: Option<DepthStencilState>,
+    multisample: MultisampleState,
+    /// The entry point and targets, None if there are no color formats
+    fragment: Option<(String, Vec<Option<ColorTargetState>>)>,
+    cache: Option<PipelineCache>,
+}
+
+impl PipelineSource {
+    fn create(&self, device: &Device) -> Result<RenderPipeline, PipelineError> {
+        profile_span!("create render pipeline", label = ?self.label);
+        let scope = device.push_error_scope(ErrorFilter::Validation);
+        let buffers = self
+            .buffers
+            .iter()
+            .map(|l| VertexBufferLayout {
+                array_stride: l.array_stride,
+                step_mode: l.step_mode,
+                attributes: &l.attributes,
+            })
+            .collect::<Box<_>>();
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: self.label.as_deref(),
+            layout: Some(&self.layout),
+            vertex: VertexState {
+                module: &self.vs_module,
+                entry_point: Some(self.vertex_entry_point.as_str()),
+                compilation_options: Default::default(),
+                buffers: &buffers,
             },
-        ))
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: self.multisample,
+            fragment: self
+                .fragment
+                .as_ref()
+                .map(|(entry_point, targets)| FragmentState {
+                    module: &self.fs_module,
+                    entry_point: Some(entry_point.as_str()),
+                    compilation_options: Default::default(),
+                    targets,
+                }),
+            multiview_mask: None,
+            cache: self.cache.as_ref(),
+        });
+        match pollster::block_on(scope.pop()) {
+            Some(error) => Err(PipelineError::Validation(error.to_string())),
+            None => Ok(pipeline),
+        }
     }
 }
+
+/// Why a [RenderPipelineManager] could not create a pipeline
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipelineError {
+    /// The descriptor has neither a fragment nor a depth/stencil state
+    NoOutput,
+    /// The parameters have neither color formats nor a depth/stencil format
+    NoFormats,
+    /// The pipeline only has a fragment state, but the parameters have no color formats
+    MissingColorFormats,
+    /// The pipeline only has a depth/stencil state, but the parameters have no depth/stencil format
+    MissingDepthStencilFormat,
+    /// The amount of color formats does not match the color targets of the fragment state
+    ColorTargetCount { formats: usize, targets: usize },
+    /// The shader source could not be specialized with the [ShaderDefs]
+    ShaderDefs(ShaderDefError),
+    /// The imports of the shader source could not be resolved by the [ShaderLibrary](crate::ShaderLibrary)
+    Import(ShaderImportError),
+    /// A shader failed to parse or validate, see [ShaderCompileError]
+    Compile(ShaderCompileError),
+    /// wgpu rejected the shader modules or the pipeline, for example because of invalid WGSL
+    Validation(String),
+    /// Creating the pipeline on the [AsyncComputeTaskPool] panicked
+    Panicked,
+}
+
+impl Error for PipelineError {}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::NoOutput => write!(f, "must have either fragment or depth_stencil"),
+            PipelineError::NoFormats => {
+                write!(f, "color_formats must not be empty when depth_stencil_format is none")
+            }
+            PipelineError::MissingColorFormats => {
+                write!(f, "no color_formats on pipeline that only supports color")
+            }
+            PipelineError::MissingDepthStencilFormat => write!(
+                f,
+                "no depth_stencil format on pipeline that only supports depth_stencil"
+            ),
+            PipelineError::ColorTargetCount { formats, targets } => write!(
+                f,
+                "{} color_formats on pipeline with {} color targets",
+                formats, targets
+            ),
+            PipelineError::ShaderDefs(error) => write!(f, "{}", error),
+            PipelineError::Import(error) => write!(f, "{}", error),
+            PipelineError::Compile(error) => write!(f, "{}", error),
+            PipelineError::Validation(error) => write!(f, "{}", error),
+            PipelineError::Panicked => write!(f, "pipeline creation panicked"),
+        }
+    }
+}
+
+impl From<ShaderDefError> for PipelineError {
+    fn from(error: ShaderDefError) -> Self {
+        PipelineError::ShaderDefs(error)
+    }
+}
+
+impl From<ShaderImportError> for PipelineError {
+    fn from(error: ShaderImportError) -> Self {
+        PipelineError::Import(error)
+    }
+}
+
+impl From<ShaderCompileError> for PipelineError {
+    fn from(error: ShaderCompileError) -> Self {
+        PipelineError::Compile(error)
+    }
+}
+
+/// Written when a [RenderPipelineManager] fails to create a pipeline, see [RenderPipelineManager::try_create]
+#[derive(Message, Clone, Debug)]
+pub struct PipelineErrorEvent {
+    /// The label of the [GenericRenderPipelineDescriptor]
+    pub label: Option<String>,
+    pub params: PipelineParameters,
+    pub error: PipelineError,
+}
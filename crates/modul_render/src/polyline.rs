@@ -0,0 +1,433 @@
+use crate::{
+    BindGroupLayoutDef, BindGroupLayoutProvider, BindGroupProvider, CachedBindGroupLayout,
+    CameraBindGroup, CameraUniformProvider, DirectRenderPipelineResourceProvider,
+    GenericColorTargetState, GenericDepthStencilState, GenericFragmentState,
+    GenericMultisampleState, GenericRenderPipelineDescriptor, GenericVertexBufferLayout,
+    GenericVertexState, Operation, OperationBuilder, OperationError, PipelineLayoutComposer,
+    RenderPipelineManager, RenderTargetSource,
+};
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferBindingType, BufferDescriptor,
+    BufferUsages, CommandEncoder, CompareFunction, DepthBiasState, Device, PipelineLayout,
+    PrimitiveState, ShaderModule, ShaderStages, StencilState, VertexAttribute, VertexFormat,
+    VertexStepMode,
+};
+
+/// The vertices drawn per segment, a quad and the triangles of the join at its end.
+/// Must match `JOIN_TRIANGLES` in the shader.
+const VERTICES_PER_SEGMENT: u32 = 6 + 3 * 8;
+
+/// A point of a [Polyline]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct PolylinePoint {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+/// How consecutive segments of a [Polyline] are connected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PolylineJoin {
+    /// The outer edges are extended until they meet, falling back to [Bevel](PolylineJoin::Bevel) past the [miter_limit](Polyline::miter_limit)
+    #[default]
+    Miter,
+    /// The outer corners are connected by a straight edge
+    Bevel,
+    /// The outer corners are connected by an arc
+    Round,
+}
+
+/// A line strip in world space with a width in pixels, stored as an asset and drawn by [PolylineOperations](PolylineOperation).
+/// The segments are expanded to screen space quads in the vertex shader, so the width does not depend on the distance or the backend.
+/// The ends of open polylines are cut off at their points.
+/// The buffer is created for changed polylines in [MeshPrepareSet](crate::MeshPrepareSet), like the buffers of a [Mesh](crate::Mesh).
+pub struct Polyline {
+    points: Vec<PolylinePoint>,
+    closed: bool,
+    /// The width in pixels
+    pub width: f32,
+    pub join: PolylineJoin,
+    /// The longest miter, relative to half the width, before a [Miter](PolylineJoin::Miter) join is beveled
+    pub miter_limit: f32,
+    buffer: Option<Buffer>,
+    segment_count: u32,
+    changed: bool,
+}
+
+impl Polyline {
+    /// A polyline through the points, connecting the last point to the first if `closed`.
+    /// It is 1 pixel wide with [Miter](PolylineJoin::Miter) joins and a miter limit of 4.
+    pub fn new(points: Vec<PolylinePoint>, closed: bool) -> Self {
+        Self {
+            points,
+            closed,
+            width: 1.0,
+            join: PolylineJoin::Miter,
+            miter_limit: 4.0,
+            buffer: None,
+            segment_count: 0,
+            changed: true,
+        }
+    }
+
+    /// A polyline through the positions with one color
+    pub fn from_positions(
+        positions: impl IntoIterator<Item = Vec3>,
+        color: [f32; 4],
+        closed: bool,
+    ) -> Self {
+        let points = positions
+            .into_iter()
+            .map(|position| PolylinePoint {
+                position: position.to_array(),
+                color,
+            })
+            .collect();
+        Self::new(points, closed)
+    }
+
+    pub fn with_width(mut self, width: f32) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn with_join(mut self, join: PolylineJoin) -> Self {
+        self.join = join;
+        self
+    }
+
+    pub fn points(&self) -> &[PolylinePoint] {
+        &self.points
+    }
+
+    pub fn set_points(&mut self, points: Vec<PolylinePoint>) {
+        self.points = points;
+        self.changed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn set_closed(&mut self, closed: bool) {
+        self.closed = closed;
+        self.changed = true;
+    }
+
+    /// The number of segments drawn, 0 until the polyline is prepared
+    pub fn segment_count(&self) -> u32 {
+        self.segment_count
+    }
+
+    /// Creates the buffer, this is done automatically during [MeshPrepareSet](crate::MeshPrepareSet) for changed polylines
+    pub fn prepare(&mut self, device: &Device) {
+        self.changed = false;
+        if self.points.len() < 2 {
+            self.buffer = None;
+            self.segment_count = 0;
+            return;
+        }
+        // every segment reads its start, its end and the point after it for the join,
+        // so the points are followed by the ones the last segment needs
+        let mut points = self.points.clone();
+        match self.closed {
+            true => points.extend_from_slice(&self.points[..2]),
+            false => points.push(*self.points.last().unwrap()),
+        }
+        self.buffer = Some(device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("polyline buffer"),
+            contents: bytemuck::cast_slice(&points),
+            usage: BufferUsages::VERTEX,
+        }));
+        self.segment_count = points.len() as u32 - 2;
+    }
+
+    /// The vertex layouts of the shader, the start, end and next point of a segment are read from the same buffer at consecutive offsets
+    pub fn vertex_buffer_layouts() -> Vec<GenericVertexBufferLayout> {
+        let stride = size_of::<PolylinePoint>() as u64;
+        let point = |location| GenericVertexBufferLayout {
+            array_stride: stride,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: 0,
+                    shader_location: location,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x3.size(),
+                    shader_location: location + 1,
+                },
+            ],
+        };
+        let mut next = point(4);
+        next.attributes.truncate(1);
+        vec![point(0), point(2), next]
+    }
+}
+
+pub(crate) fn prepare_polylines(mut polylines: ResMut<Assets<Polyline>>, ctx: Res<RenderContext>) {
+    // bypass change detection, as most frames nothing changes
+    for (_, polyline) in polylines.bypass_change_detection().iter_mut() {
+        if polyline.changed {
+            polyline.prepare(&ctx.device);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct PolylineUniform {
+    viewport_size: [f32; 2],
+    width: f32,
+    join: u32,
+    miter_limit: f32,
+    _padding: [f32; 3],
+}
+
+/// Bind group layout of the settings of a [PolylineOperation], declaring `polyline`
+pub struct PolylineLayout;
+
+impl BindGroupLayoutDef for PolylineLayout {
+    const LAYOUT: &'static BindGroupLayoutDescriptor<'static> = &BindGroupLayoutDescriptor {
+        label: Some("Polyline BGLayout"),
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    };
+    const LIBRARY: &'static str = "struct Polyline {
+    viewport_size: vec2<f32>,
+    width: f32,
+    join: u32,
+    miter_limit: f32,
+}
+@group(#BIND_GROUP) @binding(0)
+var<uniform> polyline: Polyline;";
+}
+
+/// The pipelines of [PolylineOperations](PolylineOperation), inserted during [Init](modul_core::Init).
+/// Group 0 is the [CameraUniformProvider] and group 1 the [PolylineLayout].
+#[derive(Resource)]
+pub struct PolylinePipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+    /// Tests against the depth buffer without writing to it
+    pub depth_tested_manager: AssetId<RenderPipelineManager>,
+    pub bind_group_layout: BindGroupLayout,
+}
+
+/// Draws a [Polyline] to the target, as seen by the [Camera](crate::Camera) entity, with alpha blending.
+/// With `depth_test` segments behind the contents of the depth buffer are hidden, this does nothing for targets without a depth buffer.
+/// Nothing is drawn while the polyline has less than 2 points or is not prepared.
+pub struct PolylineOperation {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+    pub polyline: AssetId<Polyline>,
+    pub depth_test: bool,
+}
+
+impl PolylineOperation {
+    pub fn new(
+        render_target: RenderTargetSource,
+        camera: Entity,
+        polyline: AssetId<Polyline>,
+        depth_test: bool,
+    ) -> Self {
+        Self {
+            render_target,
+            camera,
+            polyline,
+            depth_test,
+        }
+    }
+}
+
+impl OperationBuilder for PolylineOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, world: &World, device: &Device) -> impl Operation + 'static {
+        let uniform = device.create_buffer(&BufferDescriptor {
+            label: Some("Polyline uniform"),
+            size: size_of::<PolylineUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Polyline bind group"),
+            layout: &world.resource::<PolylinePipeline>().bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            }],
+        });
+        PreparedPolyline {
+            operation: self,
+            uniform,
+            bind_group,
+        }
+    }
+}
+
+struct PreparedPolyline {
+    operation: PolylineOperation,
+    uniform: Buffer,
+    bind_group: BindGroup,
+}
+
+impl Operation for PreparedPolyline {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let PolylineOperation {
+            render_target,
+            camera,
+            polyline,
+            depth_test,
+        } = self.operation;
+        let Some(camera) = world.get::<CameraBindGroup>(camera) else {
+            return Err(format!("{} has no camera bind group", camera).into());
+        };
+        let camera = camera.bind_group().clone();
+        let polylines = world.resource::<Assets<Polyline>>();
+        let Some((buffer, segment_count, mut uniform)) = polylines.get(polyline).and_then(|p| {
+            let uniform = PolylineUniform {
+                viewport_size: [0.0; 2],
+                width: p.width,
+                join: p.join as u32,
+                miter_limit: p.miter_limit,
+                _padding: [0.0; 3],
+            };
+            Some((p.buffer.clone()?, p.segment_count, uniform))
+        }) else {
+            return Ok(());
+        };
+        let polyline_pipeline = world.resource::<PolylinePipeline>();
+        let manager = match depth_test {
+            true => polyline_pipeline.depth_tested_manager,
+            false => polyline_pipeline.manager,
+        };
+        let mut pipeline = None;
+        world.asset_scope(manager, |world, manager| {
+            pipeline = manager.get_compatible(render_target, world).cloned();
+        });
+        let Some(pipeline) = pipeline else {
+            return Ok(());
+        };
+        let Some(rt) = render_target.get(world) else {
+            return Ok(());
+        };
+        // clip space covers the viewport, if there is one
+        let (width, height) = rt.size();
+        uniform.viewport_size = rt
+            .viewport()
+            .and_then(|v| v.clamped((width, height)))
+            .map_or([width as f32, height as f32], |v| [v.width, v.height]);
+        world.resource::<RenderContext>().queue.write_buffer(
+            &self.uniform,
+            0,
+            bytemuck::bytes_of(&uniform),
+        );
+        let Some(mut rt) = render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        let stride = size_of::<PolylinePoint>() as u64;
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &camera, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        for slot in 0..3 {
+            pass.set_vertex_buffer(slot, buffer.slice(slot as u64 * stride..));
+        }
+        pass.draw(0..VERTICES_PER_SEGMENT, 0..segment_count);
+        Ok(())
+    }
+}
+
+pub(crate) fn init_polyline_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    let polyline_layout = CachedBindGroupLayout::<PolylineLayout>::new(&ctx.device);
+    let bind_group_layout = polyline_layout.layout().clone();
+    let mut composer = PipelineLayoutComposer::new();
+    composer.add_bind_group(CameraUniformProvider::new(&ctx.device));
+    composer.add_bind_group(polyline_layout);
+    let layout = layouts.add(composer.compose_pipeline_layout(&ctx.device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(
+                &ctx.device,
+                "Polyline shader",
+                include_str!("polyline.wgsl"),
+            )
+            .expect("the built-in polyline shader has balanced directives")
+            .clone(),
+    );
+    let mut create_manager = |depth_compare| {
+        managers.add(RenderPipelineManager::new(
+            GenericRenderPipelineDescriptor {
+                resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                    layout,
+                    vertex_shader_module: shader,
+                    fragment_shader_module: shader,
+                }),
+                label: Some("Polyline pipeline".to_string()),
+                vertex_state: GenericVertexState {
+                    entry_point: "vs_main".to_string(),
+                    buffers: Polyline::vertex_buffer_layouts(),
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: Some(GenericDepthStencilState {
+                    depth_write_enable: false,
+                    depth_compare,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: GenericMultisampleState {
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: Some(GenericFragmentState {
+                    entry_point: "fs_main".to_string(),
+                    targets: vec![GenericColorTargetState::new(Some(
+                        BlendState::ALPHA_BLENDING,
+                    ))],
+                }),
+            },
+        ))
+    };
+    let manager = create_manager(CompareFunction::Always);
+    let depth_tested_manager = create_manager(CompareFunction::LessEqual);
+    commands.insert_resource(PolylinePipeline {
+        manager,
+        depth_tested_manager,
+        bind_group_layout,
+    });
+}
@@ -0,0 +1,142 @@
+// the triangles of the join at the end of every segment, unused ones are degenerate
+const JOIN_TRIANGLES: u32 = 8u;
+const JOIN_MITER: u32 = 0u;
+const JOIN_ROUND: u32 = 2u;
+// points closer to the camera plane are clipped, dividing by w there mirrors them across the screen
+const NEAR_W: f32 = 1e-4;
+// outside of the clip volume, for discarding the triangles of hidden segments
+const HIDDEN: vec4<f32> = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+fn to_screen(clip: vec4<f32>) -> vec2<f32> {
+    return clip.xy / clip.w * polyline.viewport_size * 0.5;
+}
+
+// moves a clip space position by a pixel offset
+fn offset_clip(clip: vec4<f32>, offset: vec2<f32>) -> vec4<f32> {
+    return vec4<f32>(clip.xy + offset / (polyline.viewport_size * 0.5) * clip.w, clip.zw);
+}
+
+// moves the end of a segment that is behind the near plane onto it
+fn clip_end(start: vec4<f32>, end: vec4<f32>) -> vec4<f32> {
+    if end.w >= NEAR_W {
+        return end;
+    }
+    return mix(start, end, (start.w - NEAR_W) / (start.w - end.w));
+}
+
+fn direction(start: vec2<f32>, end: vec2<f32>) -> vec2<f32> {
+    let delta = end - start;
+    let len = length(delta);
+    return select(vec2<f32>(0.0), delta / len, len > 1e-6);
+}
+
+fn left_normal(direction: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(-direction.y, direction.x);
+}
+
+// the pixel offset of a corner of a join triangle, corner 0 is the joined point
+fn join_offset(triangle: u32, corner: u32, outer: vec2<f32>, next_outer: vec2<f32>) -> vec2<f32> {
+    if corner == 0u {
+        return vec2<f32>(0.0);
+    }
+    if polyline.join == JOIN_ROUND {
+        let start_angle = atan2(outer.y, outer.x);
+        let sweep = atan2(outer.x * next_outer.y - outer.y * next_outer.x, dot(outer, next_outer));
+        let angle = start_angle + sweep * f32(triangle + corner - 1u) / f32(JOIN_TRIANGLES);
+        return vec2<f32>(cos(angle), sin(angle)) * length(outer);
+    }
+    let sum = outer + next_outer;
+    let bisector = sum / max(length(sum), 1e-6);
+    // the cosine of half the angle between the outer corners, 0 for U-turns
+    let cos_half = dot(bisector, outer) / length(outer);
+    if polyline.join == JOIN_MITER && cos_half * polyline.miter_limit > 1.0 {
+        let tip = bisector * length(outer) / cos_half;
+        if triangle == 0u {
+            return select(tip, outer, corner == 1u);
+        }
+        if triangle == 1u {
+            return select(next_outer, tip, corner == 1u);
+        }
+        return vec2<f32>(0.0);
+    }
+    if triangle == 0u {
+        return select(next_outer, outer, corner == 1u);
+    }
+    return vec2<f32>(0.0);
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+    @location(0) start: vec3<f32>,
+    @location(1) start_color: vec4<f32>,
+    @location(2) end: vec3<f32>,
+    @location(3) end_color: vec4<f32>,
+    @location(4) next: vec3<f32>,
+) -> VertexOutput {
+    let unclipped_start = camera.view_projection * vec4<f32>(start, 1.0);
+    let unclipped_end = camera.view_projection * vec4<f32>(end, 1.0);
+    var out: VertexOutput;
+    if unclipped_start.w < NEAR_W && unclipped_end.w < NEAR_W {
+        out.position = HIDDEN;
+        out.color = end_color;
+        return out;
+    }
+    let start_clip = clip_end(unclipped_end, unclipped_start);
+    let end_clip = clip_end(unclipped_start, unclipped_end);
+    let end_screen = to_screen(end_clip);
+    let segment_direction = direction(to_screen(start_clip), end_screen);
+    let half_width = polyline.width * 0.5;
+    if index < 6u {
+        // x selects the end, y the side
+        var corners = array<vec2<f32>, 6>(
+            vec2<f32>(0.0, -1.0),
+            vec2<f32>(1.0, -1.0),
+            vec2<f32>(1.0, 1.0),
+            vec2<f32>(0.0, -1.0),
+            vec2<f32>(1.0, 1.0),
+            vec2<f32>(0.0, 1.0),
+        );
+        let corner = corners[index];
+        let offset = left_normal(segment_direction) * corner.y * half_width;
+        if corner.x == 0.0 {
+            out.position = offset_clip(start_clip, offset);
+            out.color = start_color;
+        } else {
+            out.position = offset_clip(end_clip, offset);
+            out.color = end_color;
+        }
+        return out;
+    }
+    out.color = end_color;
+    // there is nothing to join behind the camera
+    if unclipped_end.w < NEAR_W {
+        out.position = HIDDEN;
+        return out;
+    }
+    let next_clip = clip_end(end_clip, camera.view_projection * vec4<f32>(next, 1.0));
+    // the last segment of an open polyline is followed by its end point, so it has no join
+    let next_direction = direction(end_screen, to_screen(next_clip));
+    var offset = vec2<f32>(0.0);
+    if any(segment_direction != vec2<f32>(0.0)) && any(next_direction != vec2<f32>(0.0)) {
+        let turn = segment_direction.x * next_direction.y - segment_direction.y * next_direction.x;
+        // the join fills the gap on the outer side of the turn
+        let side = select(1.0, -1.0, turn > 0.0);
+        let outer = left_normal(segment_direction) * side * half_width;
+        let next_outer = left_normal(next_direction) * side * half_width;
+        let join_index = index - 6u;
+        offset = join_offset(join_index / 3u, join_index % 3u, outer, next_outer);
+    }
+    out.position = offset_clip(end_clip, offset);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
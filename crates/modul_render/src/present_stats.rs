@@ -0,0 +1,50 @@
+use crate::{SurfaceSkipReason, SurfaceUpdateStatus};
+use bevy_ecs::prelude::*;
+
+/// Presentation statistics of a window, inserted with its [SurfaceRenderTarget](crate::SurfaceRenderTarget) and updated every time it redraws.
+/// Apps can lower their quality when `consecutive_errors` grows, see also [FrameDropped].
+#[derive(Component, Clone, Debug, Default)]
+pub struct PresentStats {
+    /// Redraws that acquired a surface texture
+    pub acquired: u64,
+    /// Redraws that could not acquire a surface texture, so nothing was shown
+    pub dropped: u64,
+    /// Dropped frames where acquiring the texture timed out
+    pub timeouts: u64,
+    /// The last texture was suboptimal, the surface is reconfigured on the next redraw
+    pub suboptimal: bool,
+    /// Dropped frames in a row, reset when a texture is acquired
+    pub consecutive_errors: u32,
+    /// Why the last dropped frame was dropped
+    pub last_drop: Option<SurfaceSkipReason>,
+}
+
+impl PresentStats {
+    pub(crate) fn record(&mut self, status: SurfaceUpdateStatus) {
+        match status {
+            SurfaceUpdateStatus::Ready | SurfaceUpdateStatus::ReadySuboptimal => {
+                self.acquired += 1;
+                self.suboptimal = status == SurfaceUpdateStatus::ReadySuboptimal;
+                self.consecutive_errors = 0;
+            }
+            SurfaceUpdateStatus::Skipped(reason) => {
+                self.dropped += 1;
+                if reason == SurfaceSkipReason::Timeout {
+                    self.timeouts += 1;
+                }
+                self.consecutive_errors += 1;
+                self.last_drop = Some(reason);
+            }
+            SurfaceUpdateStatus::Failed | SurfaceUpdateStatus::Suspended => {}
+        }
+    }
+}
+
+/// Written when a window redraws without being able to acquire a surface texture, so the frame is not shown
+#[derive(Message, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDropped {
+    pub window: Entity,
+    pub reason: SurfaceSkipReason,
+    /// Including this one, see [PresentStats::consecutive_errors]
+    pub consecutive: u32,
+}
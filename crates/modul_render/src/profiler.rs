@@ -0,0 +1,165 @@
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use log::warn;
+use modul_core::{Init, RenderContext};
+use modul_util::HashMap;
+use std::time::Duration;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Features, PollType, QuerySet,
+    QuerySetDescriptor, QueryType, QUERY_SIZE,
+};
+
+use crate::query::ReadbackRing;
+use crate::PostDraw;
+
+/// Max amount of timed operations per frame
+const MAX_OPERATIONS: u32 = 128;
+
+/// Measures the GPU time of every sequence operation, and writes them to [GpuTimings].
+/// Requires [TIMESTAMP_QUERY](Features::TIMESTAMP_QUERY) and [TIMESTAMP_QUERY_INSIDE_ENCODERS](Features::TIMESTAMP_QUERY_INSIDE_ENCODERS),
+/// which can be added to the optional features of the [DefaultGraphicsInitializer](modul_core::DefaultGraphicsInitializer).
+/// If the device does not support them a warning is logged and nothing is measured.
+pub struct GpuProfilingPlugin;
+
+impl Plugin for GpuProfilingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GpuTimings>();
+        app.add_systems(Init, init_gpu_profiler);
+        app.add_systems(PostDraw, read_gpu_timings);
+    }
+}
+
+/// GPU time of the sequence operations of a recent frame, usually one or two frames old.
+/// Operations are labeled by [label](crate::OperationBuilder::label), operations with the same label are added together.
+#[derive(Resource, Default)]
+pub struct GpuTimings {
+    timings: HashMap<String, Duration>,
+}
+
+impl GpuTimings {
+    pub fn get(&self, label: &str) -> Option<Duration> {
+        self.timings.get(label).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.timings.iter().map(|(l, d)| (l.as_str(), *d))
+    }
+
+    /// The time of all operations
+    pub fn total(&self) -> Duration {
+        self.timings.values().sum()
+    }
+}
+
+/// Writes the timestamps of sequence operations, inserted by [GpuProfilingPlugin] if the device supports it
+#[derive(Resource)]
+pub struct GpuProfiler {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readbacks: ReadbackRing<Vec<String>>,
+    labels: Vec<String>,
+    /// nanoseconds per timestamp tick
+    period: f32,
+}
+
+impl GpuProfiler {
+    /// Features needed by the profiler
+    pub const FEATURES: Features =
+        Features::TIMESTAMP_QUERY.union(Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+    pub fn new(device: &Device, period: f32) -> Self {
+        let size = (MAX_OPERATIONS * 2) as u64 * QUERY_SIZE as u64;
+        Self {
+            query_set: device.create_query_set(&QuerySetDescriptor {
+                label: Some("Profiler query set"),
+                ty: QueryType::Timestamp,
+                count: MAX_OPERATIONS * 2,
+            }),
+            resolve_buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("Profiler resolve buffer"),
+                size,
+                usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readbacks: ReadbackRing::new(device, size),
+            labels: Vec::new(),
+            period,
+        }
+    }
+
+    /// Writes the start timestamp of an operation, returns None if the limit for the frame is reached
+    pub(crate) fn begin(&mut self, encoder: &mut CommandEncoder, label: &str) -> Option<u32> {
+        let index = self.labels.len() as u32;
+        if index >= MAX_OPERATIONS {
+            return None;
+        }
+        self.labels.push(label.to_string());
+        encoder.write_timestamp(&self.query_set, index * 2);
+        Some(index)
+    }
+
+    pub(crate) fn end(&self, encoder: &mut CommandEncoder, index: u32) {
+        encoder.write_timestamp(&self.query_set, index * 2 + 1);
+    }
+
+    /// Copies the timestamps of the frame to a free readback buffer, if there is none the frame is not measured
+    pub(crate) fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let labels = std::mem::take(&mut self.labels);
+        if labels.is_empty() {
+            return;
+        }
+        let query_count = labels.len() as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..query_count, &self.resolve_buffer, 0);
+        self.readbacks.copy(
+            encoder,
+            &self.resolve_buffer,
+            query_count as u64 * QUERY_SIZE as u64,
+            labels,
+        );
+    }
+
+    /// Maps the readback buffers copied to this frame, must be called after submitting
+    pub(crate) fn map_copied(&mut self) {
+        self.readbacks.map_copied();
+    }
+
+    /// Reads the newest mapped frame, freeing all mapped readback buffers
+    fn read(&mut self) -> Option<HashMap<String, Duration>> {
+        let (timestamps, labels) = self.readbacks.read()?;
+        let mut timings = HashMap::<String, Duration>::new();
+        for (label, pair) in labels.into_iter().zip(timestamps.chunks(2)) {
+            let nanos = pair[1].saturating_sub(pair[0]) as f64 * self.period as f64;
+            *timings.entry(label).or_default() += Duration::from_nanos(nanos as u64);
+        }
+        Some(timings)
+    }
+}
+
+fn init_gpu_profiler(mut commands: Commands, ctx: Res<RenderContext>) {
+    if !ctx.device.features().contains(GpuProfiler::FEATURES) {
+        warn!(
+            "GPU profiling requires {:?}, GpuTimings will stay empty",
+            GpuProfiler::FEATURES
+        );
+        return;
+    }
+    commands.insert_resource(GpuProfiler::new(
+        &ctx.device,
+        ctx.queue.get_timestamp_period(),
+    ));
+}
+
+fn read_gpu_timings(
+    ctx: Res<RenderContext>,
+    profiler: Option<ResMut<GpuProfiler>>,
+    mut timings: ResMut<GpuTimings>,
+) {
+    let Some(mut profiler) = profiler else {
+        return;
+    };
+    // invokes the map callbacks of finished frames
+    let _ = ctx.device.poll(PollType::Poll);
+    if let Some(frame) = profiler.read() {
+        timings.timings = frame;
+    }
+}
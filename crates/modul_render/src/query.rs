@@ -0,0 +1,263 @@
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use modul_util::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, MapMode, PollType, QuerySet,
+    QuerySetDescriptor, QueryType, QUERY_SIZE,
+};
+
+/// Amount of frames that can be waiting to be read back
+const READBACK_COUNT: usize = 3;
+
+/// Query sets that are resolved after the sequences run, and read back into [QueryResults].
+/// Occlusion sets can be used with [set_occlusion_query_set](crate::RenderTarget::set_occlusion_query_set),
+/// pipeline statistics sets with [begin_pipeline_statistics_query](wgpu::RenderPass::begin_pipeline_statistics_query),
+/// which requires [PIPELINE_STATISTICS_QUERY](wgpu::Features::PIPELINE_STATISTICS_QUERY).
+///
+/// Every query of a set is resolved each frame, so results of queries that were not written in that frame are undefined.
+#[derive(Resource, Default)]
+pub struct QuerySetPool {
+    sets: HashMap<String, PooledQuerySet>,
+}
+
+struct PooledQuerySet {
+    query_set: QuerySet,
+    ty: QueryType,
+    count: u32,
+    /// u64 values written per query
+    values_per_query: u32,
+    resolve_buffer: Buffer,
+    readbacks: ReadbackRing<()>,
+}
+
+impl QuerySetPool {
+    /// Gets the set with the name, creating it if it does not exist or has a different type or count
+    pub fn get_or_create(
+        &mut self,
+        device: &Device,
+        name: &str,
+        ty: QueryType,
+        count: u32,
+    ) -> &QuerySet {
+        let outdated = self
+            .sets
+            .get(name)
+            .is_none_or(|set| !same_type(set.ty, ty) || set.count != count);
+        if outdated {
+            self.sets
+                .insert(name.to_string(), PooledQuerySet::new(device, ty, count));
+        }
+        &self.sets[name].query_set
+    }
+
+    pub fn get(&self, name: &str) -> Option<&QuerySet> {
+        self.sets.get(name).map(|set| &set.query_set)
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.sets.remove(name);
+    }
+
+    pub(crate) fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        for set in self.sets.values_mut() {
+            encoder.resolve_query_set(&set.query_set, 0..set.count, &set.resolve_buffer, 0);
+            set.readbacks
+                .copy(encoder, &set.resolve_buffer, set.resolve_buffer.size(), ());
+        }
+    }
+
+    pub(crate) fn map_copied(&mut self) {
+        for set in self.sets.values_mut() {
+            set.readbacks.map_copied();
+        }
+    }
+}
+
+impl PooledQuerySet {
+    fn new(device: &Device, ty: QueryType, count: u32) -> Self {
+        let values_per_query = match ty {
+            QueryType::PipelineStatistics(types) => types.bits().count_ones(),
+            _ => 1,
+        };
+        let size = (count * values_per_query) as u64 * QUERY_SIZE as u64;
+        Self {
+            query_set: device.create_query_set(&QuerySetDescriptor {
+                label: Some("Pooled query set"),
+                ty,
+                count,
+            }),
+            ty,
+            count,
+            values_per_query,
+            resolve_buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("Pooled query resolve buffer"),
+                size,
+                usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readbacks: ReadbackRing::new(device, size),
+        }
+    }
+}
+
+// QueryType does not implement PartialEq
+fn same_type(a: QueryType, b: QueryType) -> bool {
+    match (a, b) {
+        (QueryType::Occlusion, QueryType::Occlusion) => true,
+        (QueryType::Timestamp, QueryType::Timestamp) => true,
+        (QueryType::PipelineStatistics(a), QueryType::PipelineStatistics(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Results of the sets in the [QuerySetPool] from a recent frame, usually one or two frames old
+#[derive(Resource, Default)]
+pub struct QueryResults {
+    results: HashMap<String, (Vec<u64>, u32)>,
+}
+
+impl QueryResults {
+    /// All values of the set, pipeline statistics queries write one value per enabled statistic
+    pub fn get(&self, name: &str) -> Option<&[u64]> {
+        self.results.get(name).map(|(values, _)| values.as_slice())
+    }
+
+    /// The values written by a single query
+    pub fn get_query(&self, name: &str, index: u32) -> Option<&[u64]> {
+        let (values, per_query) = self.results.get(name)?;
+        let start = (index * per_query) as usize;
+        values.get(start..start + *per_query as usize)
+    }
+}
+
+pub(crate) fn read_query_results(
+    ctx: Res<RenderContext>,
+    mut pool: ResMut<QuerySetPool>,
+    mut results: ResMut<QueryResults>,
+) {
+    if pool.sets.is_empty() {
+        return;
+    }
+    // invokes the map callbacks of finished frames
+    let _ = ctx.device.poll(PollType::Poll);
+    results
+        .results
+        .retain(|name, _| pool.sets.contains_key(name));
+    for (name, set) in pool.sets.iter_mut() {
+        if let Some((values, ())) = set.readbacks.read() {
+            results
+                .results
+                .insert(name.clone(), (values, set.values_per_query));
+        }
+    }
+}
+
+/// Buffers that are copied to, mapped after submitting, and read once the mapping finishes without stalling.
+/// Each copy can carry data needed to interpret the values.
+pub(crate) struct ReadbackRing<T> {
+    readbacks: Vec<Readback<T>>,
+    frame: u64,
+}
+
+struct Readback<T> {
+    buffer: Buffer,
+    size: u64,
+    frame: u64,
+    data: Option<T>,
+    state: ReadbackState,
+}
+
+enum ReadbackState {
+    Free,
+    /// copied to in the current frame, mapped after submitting
+    Copied,
+    Mapping(Arc<AtomicBool>),
+}
+
+impl<T> ReadbackRing<T> {
+    pub(crate) fn new(device: &Device, size: u64) -> Self {
+        Self {
+            readbacks: (0..READBACK_COUNT)
+                .map(|_| Readback {
+                    buffer: device.create_buffer(&BufferDescriptor {
+                        label: Some("Query readback buffer"),
+                        size,
+                        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    size: 0,
+                    frame: 0,
+                    data: None,
+                    state: ReadbackState::Free,
+                })
+                .collect(),
+            frame: 0,
+        }
+    }
+
+    /// Copies `size` bytes of the source to a free buffer, if there is none the frame is skipped
+    pub(crate) fn copy(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        source: &Buffer,
+        size: u64,
+        data: T,
+    ) {
+        self.frame += 1;
+        let Some(readback) = self
+            .readbacks
+            .iter_mut()
+            .find(|r| matches!(r.state, ReadbackState::Free))
+        else {
+            return;
+        };
+        encoder.copy_buffer_to_buffer(source, 0, &readback.buffer, 0, size);
+        readback.size = size;
+        readback.frame = self.frame;
+        readback.data = Some(data);
+        readback.state = ReadbackState::Copied;
+    }
+
+    /// Maps the buffers copied to this frame, must be called after submitting
+    pub(crate) fn map_copied(&mut self) {
+        for readback in &mut self.readbacks {
+            if let ReadbackState::Copied = readback.state {
+                let mapped = Arc::new(AtomicBool::new(false));
+                let callback_mapped = mapped.clone();
+                readback
+                    .buffer
+                    .map_async(MapMode::Read, ..readback.size, move |res| {
+                        callback_mapped.store(res.is_ok(), Ordering::Release);
+                    });
+                readback.state = ReadbackState::Mapping(mapped);
+            }
+        }
+    }
+
+    /// Reads the newest mapped frame, freeing all mapped buffers
+    pub(crate) fn read(&mut self) -> Option<(Vec<u64>, T)> {
+        let mut newest = None;
+        for readback in &mut self.readbacks {
+            let ReadbackState::Mapping(mapped) = &readback.state else {
+                continue;
+            };
+            if !mapped.load(Ordering::Acquire) {
+                continue;
+            }
+            let values =
+                bytemuck::cast_slice(&readback.buffer.get_mapped_range(..readback.size)).to_vec();
+            readback.buffer.unmap();
+            readback.state = ReadbackState::Free;
+            let data = readback.data.take().unwrap();
+            if newest
+                .as_ref()
+                .is_none_or(|(frame, _, _)| readback.frame > *frame)
+            {
+                newest = Some((readback.frame, values, data));
+            }
+        }
+        newest.map(|(_, values, data)| (values, data))
+    }
+}
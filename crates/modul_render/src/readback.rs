@@ -0,0 +1,282 @@
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Extent3d, MapMode, Origin3d,
+    PollType, TexelCopyBufferInfo, TexelCopyBufferLayout, TexelCopyTextureInfo, Texture,
+    TextureFormat, TextureUsages, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::{resolve_for_reading, OperationError, RenderTargetSource};
+
+/// The pixels of a render target copied to the CPU, rows are tightly packed without the padding needed for the copy
+#[derive(Clone, Debug)]
+pub struct ReadbackImage {
+    pub size: (u32, u32),
+    pub format: TextureFormat,
+    pub data: Vec<u8>,
+}
+
+impl ReadbackImage {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        self.format.block_copy_size(None).unwrap_or(0)
+    }
+
+    /// The bytes of the pixel, None if it is outside the image
+    pub fn pixel(&self, x: u32, y: u32) -> Option<&[u8]> {
+        if x >= self.size.0 || y >= self.size.1 {
+            return None;
+        }
+        let bpp = self.bytes_per_pixel() as usize;
+        let start = (y as usize * self.size.0 as usize + x as usize) * bpp;
+        self.data.get(start..start + bpp)
+    }
+}
+
+/// Written when a [ReadbackOperation](crate::ReadbackOperation) has been read, usually a few frames after it ran
+#[derive(Message)]
+pub struct ReadbackEvent {
+    pub target: RenderTargetSource,
+    pub image: ReadbackImage,
+}
+
+/// Called with the image of a [Screenshots] request
+pub type ReadbackCallback = Box<dyn FnOnce(ReadbackImage) + Send + Sync>;
+
+/// Requests to read back render targets at the end of the next frame they are drawn in, the callback runs once the pixels are on the CPU.
+/// Only the [selected layer](crate::RenderTarget::layer) of layered targets is read back.
+/// The color texture needs [COPY_SRC](TextureUsages::COPY_SRC) usage, set in the [RenderTargetColorConfig](crate::RenderTargetColorConfig).
+#[derive(Resource, Default)]
+pub struct Screenshots {
+    requests: Vec<(RenderTargetSource, ReadbackCallback)>,
+}
+
+impl Screenshots {
+    pub fn take(
+        &mut self,
+        target: RenderTargetSource,
+        callback: impl FnOnce(ReadbackImage) + Send + Sync + 'static,
+    ) {
+        self.requests.push((target, Box::new(callback)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+/// How a finished readback is delivered
+pub(crate) enum ReadbackDelivery {
+    Event(RenderTargetSource),
+    Callback(ReadbackCallback),
+}
+
+/// Texture copies waiting to be mapped and read, the buffers are reused once read
+#[derive(Resource, Default)]
+pub(crate) struct TextureReadbacks {
+    pending: Vec<PendingReadback>,
+    free: Vec<Buffer>,
+}
+
+struct PendingReadback {
+    buffer: Buffer,
+    size: (u32, u32),
+    format: TextureFormat,
+    padded_bytes_per_row: u32,
+    delivery: ReadbackDelivery,
+    /// None until mapped after submitting, then one of the `MAP_` states
+    mapped: Option<Arc<AtomicU8>>,
+}
+
+const MAP_PENDING: u8 = 0;
+const MAP_OK: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+impl TextureReadbacks {
+    /// Copies the first mip level of a layer of the texture to a buffer, read once the frame is finished
+    pub(crate) fn copy(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        layer: u32,
+        delivery: ReadbackDelivery,
+    ) -> Result<(), OperationError> {
+        let size = (texture.width(), texture.height());
+        self.copy_region(device, encoder, texture, layer, (0, 0), size, delivery)
+    }
+
+    /// Like [copy](Self::copy), but only the region starting at `origin`, which must be inside the texture
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn copy_region(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        layer: u32,
+        origin: (u32, u32),
+        size: (u32, u32),
+        delivery: ReadbackDelivery,
+    ) -> Result<(), OperationError> {
+        if !texture.usage().contains(TextureUsages::COPY_SRC) {
+            return Err("the texture needs COPY_SRC usage to be read back".into());
+        }
+        let format = texture.format();
+        let Some(bytes_per_pixel) = format.block_copy_size(None) else {
+            return Err(format!("{:?} textures can not be read back", format).into());
+        };
+        let (width, height) = size;
+        if width == 0 || height == 0 {
+            return Err("an empty region can not be read back".into());
+        }
+        let padded_bytes_per_row = (width * bytes_per_pixel).div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer_size = padded_bytes_per_row as u64 * height as u64;
+        let buffer = match self.free.iter().position(|b| b.size() == buffer_size) {
+            Some(i) => self.free.swap_remove(i),
+            None => device.create_buffer(&BufferDescriptor {
+                label: Some("Texture readback buffer"),
+                size: buffer_size,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        };
+        encoder.copy_texture_to_buffer(
+            TexelCopyTextureInfo {
+                origin: Origin3d {
+                    x: origin.0,
+                    y: origin.1,
+                    z: layer,
+                },
+                ..texture.as_image_copy()
+            },
+            TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.pending.push(PendingReadback {
+            buffer,
+            size: (width, height),
+            format,
+            padded_bytes_per_row,
+            delivery,
+            mapped: None,
+        });
+        Ok(())
+    }
+
+    /// Maps the buffers copied to this frame, must be called after submitting
+    pub(crate) fn map_copied(&mut self) {
+        for readback in self.pending.iter_mut().filter(|r| r.mapped.is_none()) {
+            let mapped = Arc::new(AtomicU8::new(MAP_PENDING));
+            let callback_mapped = mapped.clone();
+            readback.buffer.map_async(MapMode::Read, .., move |res| {
+                let state = match res {
+                    Ok(()) => MAP_OK,
+                    Err(_) => MAP_FAILED,
+                };
+                callback_mapped.store(state, Ordering::Release);
+            });
+            readback.mapped = Some(mapped);
+        }
+    }
+
+    /// Removes the padding of the mapped buffers, in the order they were copied.
+    /// Readbacks whose buffer failed to map are dropped without being delivered.
+    fn read(&mut self) -> Vec<(ReadbackDelivery, ReadbackImage)> {
+        let mut finished = Vec::new();
+        let mut i = 0;
+        while i < self.pending.len() {
+            let state = self.pending[i]
+                .mapped
+                .as_ref()
+                .map_or(MAP_PENDING, |m| m.load(Ordering::Acquire));
+            if state == MAP_PENDING {
+                i += 1;
+                continue;
+            }
+            let readback = self.pending.remove(i);
+            if state == MAP_FAILED {
+                log::error!("Readback of a {:?} texture failed to map", readback.format);
+                continue;
+            }
+            let (width, height) = readback.size;
+            let row_size = (width * readback.format.block_copy_size(None).unwrap_or(0)) as usize;
+            let mut data = Vec::with_capacity(row_size * height as usize);
+            {
+                let mapped = readback.buffer.get_mapped_range(..);
+                for row in mapped.chunks(readback.padded_bytes_per_row as usize) {
+                    data.extend_from_slice(&row[..row_size]);
+                }
+            }
+            readback.buffer.unmap();
+            self.free.push(readback.buffer);
+            finished.push((
+                readback.delivery,
+                ReadbackImage {
+                    size: readback.size,
+                    format: readback.format,
+                    data,
+                },
+            ));
+        }
+        finished
+    }
+}
+
+/// Copies the targets of the [Screenshots] requests, after the sequences of the frame are encoded
+pub(crate) fn copy_screenshots(world: &mut World, command_encoder: &mut CommandEncoder) {
+    let requests = std::mem::take(&mut world.resource_mut::<Screenshots>().requests);
+    for (target, callback) in requests {
+        resolve_for_reading(target, world, command_encoder);
+        let device = world.resource::<RenderContext>().device.clone();
+        let Some((texture, layer)) = target
+            .get(world)
+            .and_then(|rt| Some((rt.texture().cloned()?, rt.layer())))
+        else {
+            log::error!("Screenshot of {:?} failed: no color texture", target);
+            continue;
+        };
+        let result = world.resource_mut::<TextureReadbacks>().copy(
+            &device,
+            command_encoder,
+            &texture,
+            layer,
+            ReadbackDelivery::Callback(callback),
+        );
+        if let Err(e) = result {
+            log::error!("Screenshot of {:?} failed: {}", target, e);
+        }
+    }
+}
+
+pub(crate) fn read_texture_readbacks(
+    ctx: Res<RenderContext>,
+    mut readbacks: ResMut<TextureReadbacks>,
+    mut events: MessageWriter<ReadbackEvent>,
+) {
+    if readbacks.pending.is_empty() {
+        return;
+    }
+    // invokes the map callbacks of finished frames
+    let _ = ctx.device.poll(PollType::Poll);
+    for (delivery, image) in readbacks.read() {
+        match delivery {
+            ReadbackDelivery::Event(target) => {
+                events.write(ReadbackEvent { target, image });
+            }
+            ReadbackDelivery::Callback(callback) => callback(image),
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use bevy_ecs::component::Component;
 use log::warn;
 use wgpu::{
-    Color, CommandEncoder, CurrentSurfaceTexture, Device, Extent3d, LoadOp, Operations,
-    PresentMode, RenderPass, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
-    RenderPassDescriptor, StoreOp, Surface, SurfaceCapabilities, SurfaceConfiguration,
-    SurfaceTexture, Texture, TextureDescriptor, TextureFormat, TextureUsages, TextureView,
-    TextureViewDescriptor,
+    Adapter, Color, CommandEncoder, CompositeAlphaMode, CurrentSurfaceTexture, Device, Extent3d,
+    Features, LoadOp, LoadOpDontCare, Operations, PresentMode, QuerySet, RenderPass,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp,
+    Surface, SurfaceCapabilities, SurfaceConfiguration, SurfaceTexture, Texture, TextureAspect,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureFormatFeatureFlags, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension,
 };
 
 /// Result of [SurfaceRenderTarget::update].
@@ -16,11 +17,29 @@ pub enum SurfaceUpdateStatus {
     /// Texture was acquired but the surface is suboptimal (e.g. mid-resize).
     /// Render this frame; the next call to `update` will reconfigure the surface.
     ReadySuboptimal,
-    /// No texture was acquired (Outdated/Lost/Timeout/Occluded). The caller
+    /// No texture was acquired for the reason. The caller
     /// should request another redraw and try again.
-    Skipped,
+    Skipped(SurfaceSkipReason),
     /// Fatal validation error from the surface. The caller should exit.
     Failed,
+    /// The surface has a zero size (e.g. the window is minimized), so it is not configured and no
+    /// texture was acquired. It is configured again once it has a size.
+    Suspended,
+}
+
+/// Why [SurfaceRenderTarget::update] could not acquire a texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SurfaceSkipReason {
+    /// The surface no longer matches the window, it was reconfigured
+    Outdated,
+    /// The surface was lost, it was reconfigured
+    Lost,
+    /// Acquiring the texture timed out
+    Timeout,
+    /// The window is not visible
+    Occluded,
+    /// The target was not initialized with a format and capabilities yet
+    Uninitialized,
 }
 // almost 1000 lines of BS
 // brace yourself
@@ -48,12 +67,92 @@ impl Default for RenderTargetDepthStencilConfig {
     }
 }
 
+impl RenderTargetDepthStencilConfig {
+    /// A [Depth32Float](TextureFormat::Depth32Float) texture that can be sampled, like a shadow map
+    pub fn depth_only() -> Self {
+        Self {
+            usages: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            format: TextureFormat::Depth32Float,
+            ..Default::default()
+        }
+    }
+
+    /// A [Stencil8](TextureFormat::Stencil8) texture, for masking
+    pub fn stencil_only() -> Self {
+        Self {
+            format: TextureFormat::Stencil8,
+            ..Default::default()
+        }
+    }
+
+    /// Adds [TEXTURE_BINDING](TextureUsages::TEXTURE_BINDING) to the usages, so the depth can be sampled through [depth_view](RenderTarget::depth_view)
+    pub fn readable(mut self) -> Self {
+        self.usages |= TextureUsages::TEXTURE_BINDING;
+        self
+    }
+
+    pub fn has_depth(&self) -> bool {
+        self.format.has_depth_aspect()
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.format.has_stencil_aspect()
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct RenderTargetMultisampleConfig {
-    /// sample count of the internal Texture
+    /// sample count of the internal Texture, clamped to the nearest count in [supported_sample_counts] when the texture is created
     pub sample_count: u32,
 }
 
+/// The sample counts that can be used to multisample and resolve textures of the format, always including 1.
+/// Without [TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES](Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) only the counts guaranteed by WebGPU are supported.
+pub fn supported_sample_counts(
+    adapter: &Adapter,
+    device: &Device,
+    format: TextureFormat,
+) -> Vec<u32> {
+    let features = match device
+        .features()
+        .contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+    {
+        true => adapter.get_texture_format_features(format),
+        false => format.guaranteed_format_features(device.features()),
+    };
+    // the targets resolve the multisampled texture, so this is needed as well
+    match features.flags.contains(TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE) {
+        true => features.flags.supported_sample_counts(),
+        false => vec![1],
+    }
+}
+
+/// Clamps the sample count to the nearest supported count, removing multisampling if that is 1
+fn clamp_multisample_config(
+    config: &mut Option<RenderTargetMultisampleConfig>,
+    supported: &[u32],
+    label: Option<&str>,
+) {
+    let Some(requested) = config.as_ref().map(|c| c.sample_count) else {
+        return;
+    };
+    if supported.contains(&requested) {
+        return;
+    }
+    let count = supported
+        .iter()
+        .copied()
+        .min_by_key(|&count| (count.abs_diff(requested), count))
+        .unwrap_or(1);
+    warn!(
+        "sample count {} is not supported by render target {:?}, using {}",
+        requested, label, count
+    );
+    *config = (count > 1).then_some(RenderTargetMultisampleConfig {
+        sample_count: count,
+    });
+}
+
 impl Default for RenderTargetMultisampleConfig {
     #[inline]
     fn default() -> Self {
@@ -69,8 +168,12 @@ pub struct RenderTargetColorConfig {
     pub clear_color: Color,
     /// The usages of the main texture, [RENDER_ATTACHMENT](TextureUsages::RENDER_ATTACHMENT) always set
     pub usages: TextureUsages,
-    /// The format of the color texture, if none [OffscreenRenderTarget] will default to Rgba8UnormSrgb, while surfaces will choose the preferred Srgb format
+    /// The format of the color texture, if none [OffscreenRenderTarget] will default to Rgba8UnormSrgb, while surfaces will use the [SurfaceFormat](modul_core::SurfaceFormat).
+    /// Surfaces fall back to the [SurfaceFormat](modul_core::SurfaceFormat) if the format is unsupported, for example an HDR format on an SDR display.
     pub format_override: Option<TextureFormat>,
+    /// Other formats views of the color texture may have, see [begin_pass_with_view](RenderTarget::begin_pass_with_view).
+    /// Surfaces only support the sRGB or linear variant of their format, and [VIEW_FORMATS](wgpu::DownlevelFlags::VIEW_FORMATS) is needed (not on GL).
+    pub view_formats: Vec<TextureFormat>,
 }
 
 impl Default for RenderTargetColorConfig {
@@ -81,6 +184,7 @@ impl Default for RenderTargetColorConfig {
             usages: TextureUsages::RENDER_ATTACHMENT,
             format_override: None,
             multisample_config: None,
+            view_formats: Vec::new(),
         }
     }
 }
@@ -105,10 +209,16 @@ pub trait RenderTarget {
     fn texture(&self) -> Option<&Texture>;
     /// The primary texture view of the RenderTarget, might be changed when the RenderTarget is resized (and possibly in other situations)
     fn texture_view(&self) -> Option<&TextureView>;
+    /// The formats of the color attachments of the passes by location, empty if there are none.
+    /// Used to create compatible pipelines, see [get_compatible](super::RenderPipelineManager::get_compatible)
+    fn color_formats(&self) -> Vec<TextureFormat>;
     /// The depth/stencil texture of the RenderTarget, might be changed when the RenderTarget is resized (and possibly in other situations)
     fn depth_stencil(&self) -> Option<&Texture>;
     /// The depth/stencil texture view of the RenderTarget, might be changed when the RenderTarget is resized (and possibly in other situations)
     fn depth_stencil_view(&self) -> Option<&TextureView>;
+    /// A view of only the depth aspect of the depth/stencil texture, for sampling it in later passes.
+    /// None unless the format has depth and the usages contain [TEXTURE_BINDING](TextureUsages::TEXTURE_BINDING)
+    fn depth_view(&self) -> Option<&TextureView>;
     /// The current color config, the scheduled config will be applied based on the implementation
     fn current_color_config(&self) -> Option<&RenderTargetColorConfig>;
     /// An immutable reference to the scheduled color config.
@@ -142,6 +252,19 @@ pub trait RenderTarget {
     fn schedule_clear_depth(&mut self);
     /// The next [RenderPass] created will clear the depth.
     fn schedule_clear_stencil(&mut self);
+    /// The next [RenderPass] created will not store the color, saving bandwidth on tiled GPUs.
+    /// On multisampled targets the multisampled texture is discarded by the next resolving pass, which still writes the resolved color.
+    fn schedule_discard_color(&mut self);
+    /// The next [RenderPass] created will not store the depth/stencil, for when it is not needed after the pass.
+    fn schedule_discard_depth_stencil(&mut self);
+    /// The next [RenderPass] created starts with undefined color contents ([LoadOp::DontCare]), unless it clears.
+    /// # Safety
+    /// All pixels must be written before they are read, blended or stored, see [LoadOpDontCare::enabled]
+    unsafe fn schedule_dont_care_color(&mut self);
+    /// Like [schedule_dont_care_color](RenderTarget::schedule_dont_care_color), but for the depth/stencil.
+    /// # Safety
+    /// See [schedule_dont_care_color](RenderTarget::schedule_dont_care_color)
+    unsafe fn schedule_dont_care_depth_stencil(&mut self);
     /// Next [RenderPass] created will be resolving, when using [Sequences](super::Sequence) this a called automatically
     fn schedule_resolve(&mut self);
     /// Begins a render pass, the pass will be resolving if [schedule_resolve](RenderTarget::schedule_resolve) was called after the last call to this method
@@ -159,6 +282,38 @@ pub trait RenderTarget {
         &mut self,
         command_encoder: &'a mut CommandEncoder,
     ) -> Option<RenderPass<'a>>;
+    /// Like [begin_ending_pass](RenderTarget::begin_ending_pass), but the color attachment is a view of the color texture created with the descriptor.
+    /// Used to reinterpret the texture with one of the [view_formats](RenderTargetColorConfig::view_formats), for example rendering linear values into an sRGB texture.
+    /// The multisampled texture is viewed with the same format, the descriptor has to select a single layer and mip level.
+    fn begin_pass_with_view<'a>(
+        &mut self,
+        command_encoder: &'a mut CommandEncoder,
+        descriptor: &TextureViewDescriptor,
+    ) -> Option<RenderPass<'a>>;
+    /// The occlusion query set of the passes created by this [RenderTarget]
+    fn occlusion_query_set(&self) -> Option<&QuerySet>;
+    /// Sets the occlusion query set used by passes created afterwards, needed for [begin_occlusion_query](RenderPass::begin_occlusion_query).
+    /// Usually a set from the [QuerySetPool](super::QuerySetPool).
+    fn set_occlusion_query_set(&mut self, query_set: Option<QuerySet>);
+    /// The label of the textures and passes of this [RenderTarget], shown in graphics debuggers and wgpu validation errors.
+    /// Set with the `label` of the config, changing it only relabels the textures when they are recreated.
+    fn label(&self) -> Option<&str>;
+    fn set_label(&mut self, label: Option<String>);
+    /// The amount of array layers (or cube faces) of the textures, 1 on a [SurfaceRenderTarget]
+    fn layer_count(&self) -> u32;
+    /// The layer passes are created on, [texture_view](RenderTarget::texture_view) and [depth_stencil_view](RenderTarget::depth_stencil_view) are views of only this layer
+    fn layer(&self) -> u32;
+    /// Selects the layer passes are created on, ignored on a [SurfaceRenderTarget] and with a warning when `layer` is out of range.
+    /// Multisampled targets share one multisampled texture for all layers, so every pass on a layer must resolve (be the finishing pass) before another layer is selected.
+    fn set_layer(&mut self, layer: u32);
+    /// The viewport set on passes right after they are created, None for the whole target
+    fn viewport(&self) -> Option<Viewport>;
+    /// Sets the viewport of passes created afterwards, it is clamped to the size of the target and nothing is drawn if nothing is left
+    fn set_viewport(&mut self, viewport: Option<Viewport>);
+    /// The scissor rect set on passes right after they are created, None for the whole target
+    fn scissor_rect(&self) -> Option<ScissorRect>;
+    /// Sets the scissor rect of passes created afterwards, it is clamped to the size of the target and nothing is drawn if nothing is left
+    fn set_scissor_rect(&mut self, scissor_rect: Option<ScissorRect>);
 }
 
 // This is where the somewhat good code ends
@@ -171,9 +326,15 @@ trait RenderTargetImpl {
     fn size(&self) -> (u32, u32);
     fn texture(&self) -> Option<&Texture>;
     fn texture_view(&self) -> Option<&TextureView>;
+    fn multisampled(&self) -> Option<&Texture>;
     fn multisampled_view(&self) -> Option<&TextureView>;
+    /// False if the color texture can not be a render attachment (1D textures)
+    fn color_renderable(&self) -> bool;
+    /// The slice of a 3D color texture passes draw to
+    fn depth_slice(&self) -> Option<u32>;
     fn depth_stencil(&self) -> Option<&Texture>;
     fn depth_stencil_view(&self) -> Option<&TextureView>;
+    fn depth_view(&self) -> Option<&TextureView>;
     fn current_color_config(&self) -> Option<&RenderTargetColorConfig>;
     fn scheduled_color_config(&self) -> Option<&RenderTargetColorConfig>;
     fn scheduled_color_config_mut(&mut self) -> Option<&mut RenderTargetColorConfig>;
@@ -191,10 +352,23 @@ trait RenderTargetImpl {
     fn scheduled_resolve(&self) -> bool;
     /// Required for making renderpasses, return color, depth, stencil
     fn clearing(&self) -> (bool, bool, bool);
+    fn pass_ops(&self) -> PassOps;
+    fn pass_ops_mut(&mut self) -> &mut PassOps;
     /// Tells the implementation that a pass was created and the next pass shouldn't clear
     fn pass_created(&mut self);
     /// called when a finishing pass is created
     fn unschedule_resolve(&mut self);
+    fn occlusion_query_set(&self) -> Option<&QuerySet>;
+    fn set_occlusion_query_set(&mut self, query_set: Option<QuerySet>);
+    fn label(&self) -> Option<&str>;
+    fn set_label(&mut self, label: Option<String>);
+    fn layer_count(&self) -> u32;
+    fn layer(&self) -> u32;
+    fn set_layer(&mut self, layer: u32);
+    fn viewport(&self) -> Option<Viewport>;
+    fn set_viewport(&mut self, viewport: Option<Viewport>);
+    fn scissor_rect(&self) -> Option<ScissorRect>;
+    fn set_scissor_rect(&mut self, scissor_rect: Option<ScissorRect>);
 }
 
 impl<T: RenderTargetImpl> RenderTarget for T {
@@ -233,6 +407,14 @@ impl<T: RenderTargetImpl> RenderTarget for T {
         self.texture_view()
     }
 
+    fn color_formats(&self) -> Vec<TextureFormat> {
+        self.texture()
+            .filter(|_| self.color_renderable())
+            .map(|t| t.format())
+            .into_iter()
+            .collect()
+    }
+
     fn depth_stencil(&self) -> Option<&Texture> {
         self.depth_stencil()
     }
@@ -241,6 +423,10 @@ impl<T: RenderTargetImpl> RenderTarget for T {
         self.depth_stencil_view()
     }
 
+    fn depth_view(&self) -> Option<&TextureView> {
+        self.depth_view()
+    }
+
     fn current_color_config(&self) -> Option<&RenderTargetColorConfig> {
         self.current_color_config()
     }
@@ -262,7 +448,7 @@ impl<T: RenderTargetImpl> RenderTarget for T {
     }
 
     fn scheduled_depth_stencil_config(&self) -> Option<&RenderTargetDepthStencilConfig> {
-        self.current_depth_stencil_config()
+        self.scheduled_depth_stencil_config()
     }
 
     fn scheduled_depth_stencil_config_mut(
@@ -276,18 +462,21 @@ impl<T: RenderTargetImpl> RenderTarget for T {
     }
 
     fn set_clear_color(&mut self, color: Color) {
-        self.scheduled_color_config_mut()
-            .map(|c| c.clear_color = color);
+        if let Some(c) = self.scheduled_color_config_mut() {
+            c.clear_color = color;
+        }
     }
 
     fn set_clear_depth(&mut self, depth: f32) {
-        self.scheduled_depth_stencil_config_mut()
-            .map(|c| c.clear_depth = depth);
+        if let Some(c) = self.scheduled_depth_stencil_config_mut() {
+            c.clear_depth = depth;
+        }
     }
 
     fn set_clear_stencil(&mut self, stencil: u32) {
-        self.scheduled_depth_stencil_config_mut()
-            .map(|c| c.clear_stencil = stencil);
+        if let Some(c) = self.scheduled_depth_stencil_config_mut() {
+            c.clear_stencil = stencil;
+        }
     }
 
     fn schedule_clear_color(&mut self) {
@@ -302,6 +491,22 @@ impl<T: RenderTargetImpl> RenderTarget for T {
         self.schedule_clear_stencil();
     }
 
+    fn schedule_discard_color(&mut self) {
+        self.pass_ops_mut().discard_color = true;
+    }
+
+    fn schedule_discard_depth_stencil(&mut self) {
+        self.pass_ops_mut().discard_depth_stencil = true;
+    }
+
+    unsafe fn schedule_dont_care_color(&mut self) {
+        self.pass_ops_mut().dont_care_color = true;
+    }
+
+    unsafe fn schedule_dont_care_depth_stencil(&mut self) {
+        self.pass_ops_mut().dont_care_depth_stencil = true;
+    }
+
     fn schedule_resolve(&mut self) {
         self.schedule_resolve();
     }
@@ -328,82 +533,311 @@ impl<T: RenderTargetImpl> RenderTarget for T {
     ) -> Option<RenderPass<'a>> {
         create_pass(self, command_encoder, false)
     }
+
+    fn begin_pass_with_view<'a>(
+        &mut self,
+        command_encoder: &'a mut CommandEncoder,
+        descriptor: &TextureViewDescriptor,
+    ) -> Option<RenderPass<'a>> {
+        let view = self.texture()?.create_view(descriptor);
+        let multisampled = self.multisampled().map(|t| {
+            t.create_view(&TextureViewDescriptor {
+                format: descriptor.format,
+                ..Default::default()
+            })
+        });
+        let res = create_pass_with_views(
+            self,
+            command_encoder,
+            self.scheduled_resolve(),
+            Some((&view, multisampled.as_ref())),
+        );
+        self.unschedule_resolve();
+        res
+    }
+
+    fn occlusion_query_set(&self) -> Option<&QuerySet> {
+        self.occlusion_query_set()
+    }
+
+    fn set_occlusion_query_set(&mut self, query_set: Option<QuerySet>) {
+        self.set_occlusion_query_set(query_set);
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.label()
+    }
+
+    fn set_label(&mut self, label: Option<String>) {
+        self.set_label(label);
+    }
+
+    fn layer_count(&self) -> u32 {
+        self.layer_count()
+    }
+
+    fn layer(&self) -> u32 {
+        self.layer()
+    }
+
+    fn set_layer(&mut self, layer: u32) {
+        self.set_layer(layer);
+    }
+
+    fn viewport(&self) -> Option<Viewport> {
+        self.viewport()
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.set_viewport(viewport);
+    }
+
+    fn scissor_rect(&self) -> Option<ScissorRect> {
+        self.scissor_rect()
+    }
+
+    fn set_scissor_rect(&mut self, scissor_rect: Option<ScissorRect>) {
+        self.set_scissor_rect(scissor_rect);
+    }
+}
+
+/// Load and store ops of the next pass, besides clearing
+#[derive(Clone, Copy, Default)]
+struct PassOps {
+    discard_color: bool,
+    discard_depth_stencil: bool,
+    dont_care_color: bool,
+    dont_care_depth_stencil: bool,
+}
+
+// clearing takes priority over not caring
+fn load_op<V>(clear: Option<V>, dont_care: bool) -> LoadOp<V> {
+    match (clear, dont_care) {
+        (Some(value), _) => LoadOp::Clear(value),
+        // SAFETY: only scheduled through the unsafe schedule_dont_care methods
+        (None, true) => LoadOp::DontCare(unsafe { LoadOpDontCare::enabled() }),
+        (None, false) => LoadOp::Load,
+    }
+}
+
+fn store_op(discard: bool) -> StoreOp {
+    match discard {
+        true => StoreOp::Discard,
+        false => StoreOp::Store,
+    }
 }
 
 fn create_pass<'a>(
     target: &mut impl RenderTargetImpl,
     command_encoder: &'a mut CommandEncoder,
     resolve: bool,
+) -> Option<RenderPass<'a>> {
+    create_pass_with_views(target, command_encoder, resolve, None)
+}
+
+/// `color_views` replaces the color view and multisampled view of the target
+fn create_pass_with_views<'a>(
+    target: &mut impl RenderTargetImpl,
+    command_encoder: &'a mut CommandEncoder,
+    resolve: bool,
+    color_views: Option<(&TextureView, Option<&TextureView>)>,
 ) -> Option<RenderPass<'a>> {
     let (clear_color, clear_depth, clear_stencil) = target.clearing();
+    let ops = target.pass_ops();
     target.pass_created();
-    if target.texture_view().is_none() && target.depth_stencil_view().is_none() {
+    // sequences resolve when the target is read, so the multisampled texture is kept until then
+    let discard_color = ops.discard_color && (target.multisampled().is_none() || resolve);
+    if ops.discard_color && !discard_color {
+        target.pass_ops_mut().discard_color = true;
+    }
+    let depth_slice = target.depth_slice();
+    let color_views = color_views
+        .or_else(|| Some((target.texture_view()?, target.multisampled_view())))
+        .filter(|_| target.color_renderable());
+    if color_views.is_none() && target.depth_stencil_view().is_none() {
         return None;
     }
-    Some(command_encoder.begin_render_pass(&RenderPassDescriptor {
-        label: None,
+    let color_attachment = color_views.map(|(view, multisample)| {
+        RenderPassColorAttachment {
+            // if multisample is available do it, else use the regular view
+            view: multisample.unwrap_or(view),
+            // set resolve target if multisampling and should resolve
+            resolve_target: Some(view).filter(|_| multisample.is_some() && resolve),
+            depth_slice,
+            ops: Operations {
+                load: load_op(
+                    clear_color.then(|| {
+                        target
+                            .current_color_config()
+                            .expect("texture but no color config")
+                            .clear_color
+                    }),
+                    ops.dont_care_color,
+                ),
+                store: store_op(discard_color),
+            },
+        }
+    });
+    // pipelines without color targets are incompatible with a single empty attachment
+    let color_attachments = match color_attachment {
+        Some(_) => std::slice::from_ref(&color_attachment),
+        None => &[],
+    };
+    let mut pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+        label: target.label(),
         multiview_mask: None,
-        color_attachments: &[target.texture_view().map(|view| {
-            let multisample = target.multisampled_view();
-            RenderPassColorAttachment {
-                // if multisample is available do it, else use the regular view
-                view: multisample.unwrap_or(view),
-                // set resolve target if multisampling and should resolve
-                resolve_target: Some(view).filter(|_| multisample.is_some() && resolve),
-                depth_slice: None,
-                ops: Operations {
-                    load: if clear_color {
-                        LoadOp::Clear(
-                            target
-                                .current_color_config()
-                                .as_ref()
-                                .expect("texture but no color config")
-                                .clear_color,
-                        )
-                    } else {
-                        LoadOp::Load
-                    },
-                    store: StoreOp::Store,
-                },
-            }
-        })],
+        color_attachments,
         // maybe fix DRY
         depth_stencil_attachment: target.depth_stencil_view().map(|view| {
+            let config = target
+                .current_depth_stencil_config()
+                .expect("texture but no depth/stencil config");
+            // depth-only and stencil-only formats must not have operations for the missing aspect
             RenderPassDepthStencilAttachment {
                 view,
-                depth_ops: Some(Operations {
-                    load: if clear_depth {
-                        LoadOp::Clear(
-                            target
-                                .current_depth_stencil_config()
-                                .as_ref()
-                                .expect("texture but no depth/stencil config")
-                                .clear_depth,
-                        )
-                    } else {
-                        LoadOp::Load
-                    },
-                    store: StoreOp::Store,
+                depth_ops: config.format.has_depth_aspect().then(|| Operations {
+                    load: load_op(
+                        clear_depth.then_some(config.clear_depth),
+                        ops.dont_care_depth_stencil,
+                    ),
+                    store: store_op(ops.discard_depth_stencil),
                 }),
-                stencil_ops: Some(Operations {
-                    load: if clear_stencil {
-                        LoadOp::Clear(
-                            target
-                                .current_depth_stencil_config()
-                                .as_ref()
-                                .expect("texture but no depth/stencil config")
-                                .clear_stencil,
-                        )
-                    } else {
-                        LoadOp::Load
-                    },
-                    store: StoreOp::Store,
+                stencil_ops: config.format.has_stencil_aspect().then(|| Operations {
+                    load: load_op(
+                        clear_stencil.then_some(config.clear_stencil),
+                        ops.dont_care_depth_stencil,
+                    ),
+                    store: store_op(ops.discard_depth_stencil),
                 }),
             }
         }),
         timestamp_writes: None,
-        occlusion_query_set: None,
-    }))
+        occlusion_query_set: target.occlusion_query_set(),
+    });
+    set_viewport_and_scissor_rect(
+        &mut pass,
+        target.viewport(),
+        target.scissor_rect(),
+        target.size(),
+    );
+    Some(pass)
+}
+
+/// Clamps the viewport and scissor rect to the size, a region with nothing left draws nothing instead of the whole target
+fn set_viewport_and_scissor_rect(
+    pass: &mut RenderPass,
+    viewport: Option<Viewport>,
+    scissor_rect: Option<ScissorRect>,
+    size: (u32, u32),
+) {
+    let viewport = viewport.map(|v| v.clamped(size));
+    let scissor_rect = scissor_rect.map(|r| r.clamped(size));
+    if matches!(viewport, Some(None)) || matches!(scissor_rect, Some(None)) {
+        // empty viewports are invalid, so everything is scissored away instead
+        pass.set_scissor_rect(0, 0, 0, 0);
+        return;
+    }
+    if let Some(Some(viewport)) = viewport {
+        pass.set_viewport(
+            viewport.x,
+            viewport.y,
+            viewport.width,
+            viewport.height,
+            viewport.min_depth,
+            viewport.max_depth,
+        );
+    }
+    if let Some(Some(rect)) = scissor_rect {
+        pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+    }
+}
+
+/// A viewport in pixels, see [RenderTarget::set_viewport]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl Viewport {
+    /// A viewport with the full depth range
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    /// Clamped to the size, None if nothing is left
+    pub(crate) fn clamped(self, size: (u32, u32)) -> Option<Self> {
+        let (w, h) = (size.0 as f32, size.1 as f32);
+        let (x, y) = (self.x.clamp(0.0, w), self.y.clamp(0.0, h));
+        let width = (self.x + self.width).min(w) - x;
+        let height = (self.y + self.height).min(h) - y;
+        (width > 0.0 && height > 0.0).then_some(Self {
+            x,
+            y,
+            width,
+            height,
+            ..self
+        })
+    }
+
+    /// Scaled to a mip level, see [OffscreenRenderTarget::begin_mip_pass]
+    fn for_mip_level(self, mip_level: u32) -> Self {
+        let scale = 0.5f32.powi(mip_level as i32);
+        Self {
+            x: self.x * scale,
+            y: self.y * scale,
+            width: self.width * scale,
+            height: self.height * scale,
+            ..self
+        }
+    }
+}
+
+/// A scissor rect in pixels, see [RenderTarget::set_scissor_rect]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    /// Clamped to the size, None if nothing is left
+    pub(crate) fn clamped(self, size: (u32, u32)) -> Option<Self> {
+        let (x, y) = (self.x.min(size.0), self.y.min(size.1));
+        let width = self.x.saturating_add(self.width).min(size.0) - x;
+        let height = self.y.saturating_add(self.height).min(size.1) - y;
+        (width > 0 && height > 0).then_some(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// Scaled to a mip level, rounded outwards, see [OffscreenRenderTarget::begin_mip_pass]
+    fn for_mip_level(self, mip_level: u32) -> Self {
+        let divisor = 1u32.checked_shl(mip_level).unwrap_or(u32::MAX);
+        let (x, y) = (self.x / divisor, self.y / divisor);
+        Self {
+            x,
+            y,
+            width: self.x.saturating_add(self.width).div_ceil(divisor) - x,
+            height: self.y.saturating_add(self.height).div_ceil(divisor) - y,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -414,6 +848,20 @@ pub struct OffscreenRenderTargetConfig {
     pub color_config: Option<RenderTargetColorConfig>,
     /// The depth/stencil config of the texture, if None the texture will not have a depth/stencil buffer
     pub depth_stencil_config: Option<RenderTargetDepthStencilConfig>,
+    /// The amount of array layers of the color and depth/stencil textures, see [set_layer](RenderTarget::set_layer)
+    pub layers: u32,
+    /// The [array views](OffscreenRenderTarget::array_view) are cube views, this requires a square size and a multiple of 6 layers.
+    /// The faces are the layers in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub cube: bool,
+    /// The amount of mip levels of the color texture, clamped to the full mip chain so `u32::MAX` can be used for all levels.
+    /// Passes are created on level 0, other levels are drawn to with [begin_mip_pass](OffscreenRenderTarget::begin_mip_pass).
+    pub mip_levels: u32,
+    /// See [RenderTarget::label]
+    pub label: Option<String>,
+    /// [D3](TextureDimension::D3) creates a 3D color texture with `layers` slices, passes draw to the selected [layer](RenderTarget::set_layer) slice.
+    /// [D1](TextureDimension::D1) textures can not be rendered to, only written by copies or compute shaders, they need a height of 1 and ignore `layers`.
+    /// Other dimensions than [D2](TextureDimension::D2) are not multisampled, have one mip level and a 2D depth/stencil texture shared by all slices.
+    pub dimension: TextureDimension,
 }
 
 impl Default for OffscreenRenderTargetConfig {
@@ -422,7 +870,65 @@ impl Default for OffscreenRenderTargetConfig {
             size: (1, 1),
             depth_stencil_config: Some(Default::default()),
             color_config: Some(Default::default()),
+            layers: 1,
+            cube: false,
+            mip_levels: 1,
+            label: None,
+            dimension: TextureDimension::D2,
+        }
+    }
+}
+
+impl OffscreenRenderTargetConfig {
+    /// A target with only a [depth_only](RenderTargetDepthStencilConfig::depth_only) texture, for rendering shadow maps
+    pub fn shadow_map(size: (u32, u32)) -> Self {
+        Self {
+            size,
+            color_config: None,
+            depth_stencil_config: Some(RenderTargetDepthStencilConfig::depth_only()),
+            ..Default::default()
+        }
+    }
+
+    /// A [shadow_map](OffscreenRenderTargetConfig::shadow_map) with 6 layers viewed as a cube, for point lights
+    pub fn cube_shadow_map(size: u32) -> Self {
+        Self {
+            layers: 6,
+            cube: true,
+            ..Self::shadow_map((size, size))
+        }
+    }
+
+    fn array_view_dimension(&self) -> TextureViewDimension {
+        match self.dimension {
+            TextureDimension::D1 => return TextureViewDimension::D1,
+            TextureDimension::D3 => return TextureViewDimension::D3,
+            TextureDimension::D2 => {}
+        }
+        if self.cube {
+            if self.layers > 0 && self.layers.is_multiple_of(6) && self.size.0 == self.size.1 {
+                return match self.layers {
+                    6 => TextureViewDimension::Cube,
+                    _ => TextureViewDimension::CubeArray,
+                };
+            }
+            warn!(
+                "cube render target {:?} needs a square size and a multiple of 6 layers, using an array view",
+                self.label
+            );
+        }
+        match self.layers {
+            1 => TextureViewDimension::D2,
+            _ => TextureViewDimension::D2Array,
+        }
+    }
+
+    fn clamped_mip_levels(&self) -> u32 {
+        if self.dimension != TextureDimension::D2 {
+            return 1;
         }
+        let full_chain = u32::BITS - self.size.0.max(self.size.1).max(1).leading_zeros();
+        self.mip_levels.clamp(1, full_chain)
     }
 }
 
@@ -434,11 +940,22 @@ pub struct OffscreenRenderTarget {
     color_texture: Option<(Texture, TextureView)>,
     multisampled_texture: Option<(Texture, TextureView)>,
     depth_stencil_texture: Option<(Texture, TextureView)>,
+    // views of the single layers and mip levels, empty if there is only one of both
+    color_layers: Vec<TextureView>,
+    depth_stencil_layers: Vec<TextureView>,
+    // depth aspect views of each layer, empty if the depth can not be sampled
+    depth_layers: Vec<TextureView>,
+    layer: u32,
+    generation: u32,
 
     resolve_scheduled: bool,
     clear_color_scheduled: bool,
     clear_depth_scheduled: bool,
     clear_stencil_scheduled: bool,
+    pass_ops: PassOps,
+    occlusion_query_set: Option<QuerySet>,
+    viewport: Option<Viewport>,
+    scissor_rect: Option<ScissorRect>,
 }
 
 impl OffscreenRenderTarget {
@@ -450,13 +967,39 @@ impl OffscreenRenderTarget {
             color_texture: None,
             multisampled_texture: None,
             depth_stencil_texture: None,
+            color_layers: Vec::new(),
+            depth_stencil_layers: Vec::new(),
+            depth_layers: Vec::new(),
+            layer: 0,
+            generation: 0,
             resolve_scheduled: false,
             clear_color_scheduled: false,
             clear_depth_scheduled: false,
             clear_stencil_scheduled: false,
+            pass_ops: PassOps::default(),
+            occlusion_query_set: None,
+            viewport: None,
+            scissor_rect: None,
         }
     }
 
+    /// The color, multisampled and depth/stencil textures that currently exist
+    pub(crate) fn owned_textures(&self) -> impl Iterator<Item = &Texture> {
+        [
+            &self.color_texture,
+            &self.multisampled_texture,
+            &self.depth_stencil_texture,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(texture, _)| texture)
+    }
+
+    /// Increased every time [apply_changes](OffscreenRenderTarget::apply_changes) recreates the textures
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
     /// The scheduled size of the [OffscreenRenderTarget], will be [None] if resizing is not scheduled
     pub fn scheduled_size(&self) -> Option<(u32, u32)> {
         self.scheduled_config.as_ref().map(|c| c.size)
@@ -467,6 +1010,122 @@ impl OffscreenRenderTarget {
         self.scheduled_config_mut().size = size;
     }
 
+    /// Schedule changing the amount of layers, see [OffscreenRenderTargetConfig::layers]
+    pub fn set_layers(&mut self, layers: u32, cube: bool) {
+        let config = self.scheduled_config_mut();
+        config.layers = layers;
+        config.cube = cube;
+    }
+
+    /// A view of all layers of the color texture, a cube view if the target is a cube
+    pub fn array_view(&self) -> Option<&TextureView> {
+        self.color_texture.as_ref().map(|(_, view)| view)
+    }
+
+    /// A view of all layers of the depth/stencil texture, a cube view if the target is a cube
+    pub fn depth_stencil_array_view(&self) -> Option<&TextureView> {
+        self.depth_stencil_texture.as_ref().map(|(_, view)| view)
+    }
+
+    /// A view of the first mip level of a single layer of the color texture
+    pub fn layer_view(&self, layer: u32) -> Option<&TextureView> {
+        self.mip_view(layer, 0)
+    }
+
+    /// The amount of mip levels of the color texture, see [OffscreenRenderTargetConfig::mip_levels]
+    pub fn mip_level_count(&self) -> u32 {
+        self.color_texture
+            .as_ref()
+            .map_or(1, |(tex, _)| tex.mip_level_count())
+    }
+
+    /// The size of a mip level of the color texture
+    pub fn mip_size(&self, mip_level: u32) -> (u32, u32) {
+        let (width, height) = <Self as RenderTarget>::size(self);
+        ((width >> mip_level).max(1), (height >> mip_level).max(1))
+    }
+
+    /// A view of a single mip level of a single layer of the color texture, on 3D targets the view of all slices
+    pub fn mip_view(&self, layer: u32, mip_level: u32) -> Option<&TextureView> {
+        let mip_levels = self.mip_level_count();
+        if mip_level >= mip_levels {
+            return None;
+        }
+        match self.color_layers.is_empty() {
+            true => self.array_view().filter(|_| layer == 0 || self.shares_views()),
+            false => self.color_layers.get((layer * mip_levels + mip_level) as usize),
+        }
+    }
+
+    /// Begins a pass drawing to a mip level of the selected [layer](RenderTarget::layer) of the color texture, for generating mipmaps or downsampling.
+    /// The pass has no depth/stencil attachment and is never multisampled, it clears if [schedule_clear_color](RenderTarget::schedule_clear_color) was called.
+    /// The [viewport](RenderTarget::viewport) and [scissor rect](RenderTarget::scissor_rect) are scaled to the mip level.
+    /// Returns None if there is no color texture or the mip level does not exist.
+    pub fn begin_mip_pass<'a>(
+        &mut self,
+        command_encoder: &'a mut CommandEncoder,
+        mip_level: u32,
+    ) -> Option<RenderPass<'a>> {
+        let view = self.mip_view(self.layer, mip_level)?;
+        let load = match self.clear_color_scheduled {
+            true => LoadOp::Clear(<Self as RenderTarget>::clear_color(self)?),
+            false => LoadOp::Load,
+        };
+        let mut pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+            label: <Self as RenderTarget>::label(self),
+            multiview_mask: None,
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                depth_slice: self.depth_slice(),
+                ops: Operations {
+                    load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let (width, height) = <Self as RenderTarget>::size(self);
+        set_viewport_and_scissor_rect(
+            &mut pass,
+            self.viewport.map(|v| v.for_mip_level(mip_level)),
+            self.scissor_rect.map(|r| r.for_mip_level(mip_level)),
+            ((width >> mip_level).max(1), (height >> mip_level).max(1)),
+        );
+        self.clear_color_scheduled = false;
+        Some(pass)
+    }
+
+    /// A view of a single layer of the depth/stencil texture
+    pub fn depth_stencil_layer_view(&self, layer: u32) -> Option<&TextureView> {
+        match self.depth_stencil_layers.is_empty() {
+            true => self
+                .depth_stencil_array_view()
+                .filter(|_| layer == 0 || self.shares_views()),
+            false => self.depth_stencil_layers.get(layer as usize),
+        }
+    }
+
+    /// A view of only the depth aspect of a single layer, see [depth_view](RenderTarget::depth_view)
+    pub fn depth_layer_view(&self, layer: u32) -> Option<&TextureView> {
+        match self.shares_views() {
+            true => self.depth_layers.first(),
+            false => self.depth_layers.get(layer as usize),
+        }
+    }
+
+    /// The dimension of the color texture, see [OffscreenRenderTargetConfig::dimension]
+    pub fn dimension(&self) -> TextureDimension {
+        self.current_or_scheduled_config().dimension
+    }
+
+    // 1D and 3D targets have one color view and one 2D depth/stencil texture for all slices
+    fn shares_views(&self) -> bool {
+        self.dimension() != TextureDimension::D2
+    }
+
     /// Remove the color texture when changes are applied
     pub fn remove_color(&mut self) {
         self.scheduled_config
@@ -482,43 +1141,98 @@ impl OffscreenRenderTarget {
     }
 
     /// Applies the scheduled changes, this might replace the textures and thereby clear them
-    pub fn apply_changes(&mut self, device: &Device) {
+    pub fn apply_changes(&mut self, device: &Device, adapter: &Adapter) {
         let changes = self.changes();
         if changes == (false, false, false) {
+            // changes that do not need new textures, like the label
+            if let Some(cfg) = self.scheduled_config.take() {
+                self.current_config = Some(cfg);
+            }
             return;
         }
         let (color_changed, multisample_changed, depth_stencil_changed) = changes;
+        self.generation = self.generation.wrapping_add(1);
         if let Some(cfg) = self.scheduled_config.take() {
             self.current_config = Some(cfg);
         }
+        let config = self.current_config.as_mut().unwrap();
+        if let Some(c) = config.color_config.as_mut() {
+            let format = c.format_override.unwrap_or(TextureFormat::Rgba8UnormSrgb);
+            let supported = match config.dimension {
+                TextureDimension::D2 => supported_sample_counts(adapter, device, format),
+                _ => vec![1],
+            };
+            let label = config.label.as_deref();
+            clamp_multisample_config(&mut c.multisample_config, &supported, label);
+        }
         let (width, height) = <Self as RenderTarget>::size(self);
+        let config = self.current_config.as_ref().unwrap();
+        let layers = match config.dimension {
+            TextureDimension::D1 => 1,
+            _ => config.layers.max(1),
+        };
+        let texture_dimension = config.dimension;
+        let mip_levels = config.clamped_mip_levels();
+        let dimension = config.array_view_dimension();
+        let label = config.label.as_deref();
+        let (color_label, multisampled_label, depth_stencil_label) = (
+            texture_label(label, "color"),
+            texture_label(label, "multisampled"),
+            texture_label(label, "depth/stencil"),
+        );
+        self.layer = self.layer.min(layers - 1);
+        let view_formats = <Self as RenderTarget>::current_color_config(self)
+            .map(|c| c.view_formats.clone())
+            .unwrap_or_default();
         let mut desc = texture_descriptor(width, height);
+        desc.view_formats = &view_formats;
         if color_changed {
             let mut mt = self.multisampled_texture.take();
             // funky map abuse
             self.color_texture = <Self as RenderTarget>::current_color_config(self).map(|c| {
                 desc.format = c.format_override.unwrap_or(TextureFormat::Rgba8UnormSrgb);
+                // multisampled textures can not have layers, so one is shared and resolved to the current layer
                 if multisample_changed {
                     mt = c.multisample_config.as_ref().map(|mc| {
+                        desc.label = multisampled_label.as_deref();
                         desc.sample_count = mc.sample_count;
                         with_view(device.create_texture(&desc))
                     });
                 }
+                desc.label = color_label.as_deref();
                 desc.sample_count = 1;
+                desc.size.depth_or_array_layers = layers;
+                desc.mip_level_count = mip_levels;
+                desc.dimension = texture_dimension;
                 // setting here because multisampled is only RENDER_ATTACHMENT
-                desc.usage = c.usages | TextureUsages::RENDER_ATTACHMENT;
-                with_view(device.create_texture(&desc))
+                desc.usage = match texture_dimension {
+                    TextureDimension::D1 => c.usages - TextureUsages::RENDER_ATTACHMENT,
+                    _ => c.usages | TextureUsages::RENDER_ATTACHMENT,
+                };
+                with_array_view(device.create_texture(&desc), dimension)
             });
+            self.color_layers = layer_views(self.color_texture.as_ref(), layers);
             self.multisampled_texture = mt;
         }
 
         if depth_stencil_changed {
+            let (layers, dimension) = match texture_dimension {
+                TextureDimension::D2 => (layers, dimension),
+                _ => (1, TextureViewDimension::D2),
+            };
+            desc.size.depth_or_array_layers = layers;
+            desc.mip_level_count = 1;
+            desc.dimension = TextureDimension::D2;
+            desc.view_formats = &[];
             self.depth_stencil_texture = <Self as RenderTarget>::current_depth_stencil_config(self)
                 .map(|c| {
+                    desc.label = depth_stencil_label.as_deref();
                     desc.usage = c.usages | TextureUsages::RENDER_ATTACHMENT;
                     desc.format = c.format;
-                    with_view(device.create_texture(&desc))
-                })
+                    with_array_view(device.create_texture(&desc), dimension)
+                });
+            self.depth_stencil_layers = layer_views(self.depth_stencil_texture.as_ref(), layers);
+            self.depth_layers = depth_views(self.depth_stencil_texture.as_ref(), layers);
         }
     }
 
@@ -531,7 +1245,11 @@ impl OffscreenRenderTarget {
         }
         let cur = self.current_config.as_ref().unwrap();
         let new = self.scheduled_config.as_ref().unwrap();
-        if cur.size != new.size {
+        if cur.size != new.size
+            || cur.layers != new.layers
+            || cur.cube != new.cube
+            || cur.mip_levels != new.mip_levels
+        {
             return (true, true, true);
         }
         (
@@ -550,17 +1268,24 @@ impl OffscreenRenderTarget {
                 .expect("no scheduled or current config")
         })
     }
+
+    fn current_or_scheduled_config(&self) -> &OffscreenRenderTargetConfig {
+        self.current_config
+            .as_ref()
+            .or(self.scheduled_config.as_ref())
+            .expect("no scheduled or current config")
+    }
 }
 
 impl RenderTargetImpl for OffscreenRenderTarget {
     fn size(&self) -> (u32, u32) {
         self.current_config
             .as_ref()
-            .unwrap_or(
+            .unwrap_or_else(|| {
                 self.scheduled_config
                     .as_ref()
-                    .expect("no scheduled or current config"),
-            )
+                    .expect("no scheduled or current config")
+            })
             .size
     }
 
@@ -569,19 +1294,35 @@ impl RenderTargetImpl for OffscreenRenderTarget {
     }
 
     fn texture_view(&self) -> Option<&TextureView> {
-        self.multisampled_texture.as_ref().map(|(_, view)| view)
+        self.layer_view(self.layer)
+    }
+
+    fn multisampled(&self) -> Option<&Texture> {
+        self.multisampled_texture.as_ref().map(|(tex, _)| tex)
     }
 
     fn multisampled_view(&self) -> Option<&TextureView> {
         self.multisampled_texture.as_ref().map(|(_, view)| view)
     }
 
+    fn color_renderable(&self) -> bool {
+        self.dimension() != TextureDimension::D1
+    }
+
+    fn depth_slice(&self) -> Option<u32> {
+        (self.dimension() == TextureDimension::D3).then_some(self.layer)
+    }
+
     fn depth_stencil(&self) -> Option<&Texture> {
         self.depth_stencil_texture.as_ref().map(|(tex, _)| tex)
     }
 
     fn depth_stencil_view(&self) -> Option<&TextureView> {
-        self.depth_stencil_texture.as_ref().map(|(_, view)| view)
+        self.depth_stencil_layer_view(self.layer)
+    }
+
+    fn depth_view(&self) -> Option<&TextureView> {
+        self.depth_layer_view(self.layer)
     }
 
     fn current_color_config(&self) -> Option<&RenderTargetColorConfig> {
@@ -654,15 +1395,90 @@ impl RenderTargetImpl for OffscreenRenderTarget {
         )
     }
 
+    fn pass_ops(&self) -> PassOps {
+        self.pass_ops
+    }
+
+    fn pass_ops_mut(&mut self) -> &mut PassOps {
+        &mut self.pass_ops
+    }
+
     fn pass_created(&mut self) {
         self.clear_color_scheduled = false;
         self.clear_depth_scheduled = false;
         self.clear_stencil_scheduled = false;
+        self.pass_ops = PassOps::default();
     }
 
     fn unschedule_resolve(&mut self) {
         self.resolve_scheduled = false;
     }
+
+    fn occlusion_query_set(&self) -> Option<&QuerySet> {
+        self.occlusion_query_set.as_ref()
+    }
+
+    fn set_occlusion_query_set(&mut self, query_set: Option<QuerySet>) {
+        self.occlusion_query_set = query_set;
+    }
+
+    fn viewport(&self) -> Option<Viewport> {
+        self.viewport
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    fn scissor_rect(&self) -> Option<ScissorRect> {
+        self.scissor_rect
+    }
+
+    fn set_scissor_rect(&mut self, scissor_rect: Option<ScissorRect>) {
+        self.scissor_rect = scissor_rect;
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.current_or_scheduled_config().label.as_deref()
+    }
+
+    fn set_label(&mut self, label: Option<String>) {
+        // not scheduled, so the textures are not recreated
+        for config in [&mut self.current_config, &mut self.scheduled_config]
+            .into_iter()
+            .flatten()
+        {
+            config.label = label.clone();
+        }
+    }
+
+    fn layer_count(&self) -> u32 {
+        <Self as RenderTarget>::texture(self)
+            .or(<Self as RenderTarget>::depth_stencil(self))
+            .map(|t| t.depth_or_array_layers())
+            .unwrap_or(1)
+    }
+
+    fn layer(&self) -> u32 {
+        self.layer
+    }
+
+    fn set_layer(&mut self, layer: u32) {
+        // scheduled changes are applied before passes are created
+        let layers = match self.scheduled_config.as_ref() {
+            Some(config) if config.dimension == TextureDimension::D1 => 1,
+            Some(config) => config.layers.max(1),
+            None => <Self as RenderTarget>::layer_count(self),
+        };
+        if layer >= layers {
+            warn!(
+                "Ignoring layer {layer} of {:?}, which only has {layers} layers",
+                <Self as RenderTarget>::label(self)
+            );
+            return;
+        }
+        self.layer = layer;
+    }
 }
 
 #[derive(Clone)]
@@ -678,6 +1494,11 @@ pub struct SurfaceRenderTargetConfig {
     /// The [PresentMode] to be used if the desired is unavailable, if the desired is unavailable and this is None/unavailable the program may crash.
     /// This is optional because some [PresentMode]s are always available
     pub backup_present_mode: Option<PresentMode>,
+    /// The [CompositeAlphaMode] of the surface, if unavailable a supported mode is used, preferring transparent modes if this is not opaque.
+    /// Windows created with [transparent](winit::window::WindowAttributes::with_transparent) attributes use [PreMultiplied](CompositeAlphaMode::PreMultiplied) instead of [Auto](CompositeAlphaMode::Auto).
+    pub alpha_mode: CompositeAlphaMode,
+    /// See [RenderTarget::label]
+    pub label: Option<String>,
 }
 
 impl Default for SurfaceRenderTargetConfig {
@@ -688,6 +1509,8 @@ impl Default for SurfaceRenderTargetConfig {
             desired_maximum_frame_latency: 2,
             present_mode: PresentMode::AutoVsync,
             backup_present_mode: None,
+            alpha_mode: CompositeAlphaMode::Auto,
+            label: None,
         }
     }
 }
@@ -703,6 +1526,7 @@ pub struct SurfaceRenderTarget {
     color_texture: Option<(SurfaceTexture, TextureView)>,
     multisampled_texture: Option<(Texture, TextureView)>,
     depth_stencil_texture: Option<(Texture, TextureView)>,
+    depth_view: Option<TextureView>,
 
     /// Set when the surface returned `Suboptimal`; triggers a reconfigure on the next `update`.
     pending_reconfigure: bool,
@@ -711,6 +1535,10 @@ pub struct SurfaceRenderTarget {
     clear_color_scheduled: bool,
     clear_depth_scheduled: bool,
     clear_stencil_scheduled: bool,
+    pass_ops: PassOps,
+    occlusion_query_set: Option<QuerySet>,
+    viewport: Option<Viewport>,
+    scissor_rect: Option<ScissorRect>,
 }
 
 impl SurfaceRenderTarget {
@@ -724,15 +1552,28 @@ impl SurfaceRenderTarget {
             color_texture: None,
             multisampled_texture: None,
             depth_stencil_texture: None,
+            depth_view: None,
             pending_reconfigure: false,
             resized: false,
             resolve_scheduled: false,
             clear_color_scheduled: false,
             clear_depth_scheduled: false,
             clear_stencil_scheduled: false,
+            pass_ops: PassOps::default(),
+            occlusion_query_set: None,
+            viewport: None,
+            scissor_rect: None,
         }
     }
 
+    /// The multisampled and depth/stencil textures, the surface textures are owned by the surface
+    pub(crate) fn owned_textures(&self) -> impl Iterator<Item = &Texture> {
+        [&self.multisampled_texture, &self.depth_stencil_texture]
+            .into_iter()
+            .flatten()
+            .map(|(texture, _)| texture)
+    }
+
     /// The desired max frame latency of the [SurfaceConfiguration]
     pub fn max_frame_latency(&self) -> u32 {
         self.current_or_scheduled_config()
@@ -764,6 +1605,16 @@ impl SurfaceRenderTarget {
         self.scheduled_config_mut().backup_present_mode = backup_present_mode;
     }
 
+    /// The requested [CompositeAlphaMode], see [SurfaceRenderTargetConfig::alpha_mode]
+    pub fn alpha_mode(&self) -> CompositeAlphaMode {
+        self.current_or_scheduled_config().alpha_mode
+    }
+
+    /// Sets the scheduled [CompositeAlphaMode] of the [SurfaceConfiguration]
+    pub fn set_alpha_mode(&mut self, alpha_mode: CompositeAlphaMode) {
+        self.scheduled_config_mut().alpha_mode = alpha_mode;
+    }
+
     /// Remove the depth/stencil texture when changes are applied
     pub fn remove_depth_stencil(&mut self) {
         self.scheduled_config
@@ -782,15 +1633,25 @@ impl SurfaceRenderTarget {
 
     /// Applies the scheduled changes, and updates [SurfaceTexture] this might replace the textures and thereby clear them.
     /// Returns a [SurfaceUpdateStatus] indicating whether the texture was acquired and whether the caller should retry.
-    pub fn update(&mut self, device: &Device, surface: &Surface) -> SurfaceUpdateStatus {
+    pub fn update(
+        &mut self,
+        device: &Device,
+        adapter: &Adapter,
+        surface: &Surface,
+    ) -> SurfaceUpdateStatus {
         // yuck, maybe rewrite in the future?
         // probably not happening
         let (Some(preferred_format), Some(caps)) =
             (&self.preferred_format, &self.surface_capabilities)
         else {
             warn!("Tried to update uninitialized SurfaceRenderTarget");
-            return SurfaceUpdateStatus::Skipped;
+            return SurfaceUpdateStatus::Skipped(SurfaceSkipReason::Uninitialized);
         };
+        // configuring a zero sized surface is invalid, the changes are applied when it has a size again
+        if self.is_suspended() {
+            self.color_texture = None;
+            return SurfaceUpdateStatus::Suspended;
+        }
         let (color_changed, multisampled_changed, depth_stencil_changed) = self.changes();
         if let Some(cfg) = self.scheduled_config.take() {
             self.current_config = Some(cfg);
@@ -798,10 +1659,17 @@ impl SurfaceRenderTarget {
         let cfg = self.current_config.as_ref().unwrap();
         let surface_cfg = SurfaceConfiguration {
             usage: cfg.color_config.usages | TextureUsages::RENDER_ATTACHMENT,
-            format: cfg
-                .color_config
-                .format_override
-                .unwrap_or_else(|| *preferred_format),
+            format: match cfg.color_config.format_override {
+                Some(format) if caps.formats.contains(&format) => format,
+                Some(format) => {
+                    warn!(
+                        "surface format {:?} not supported by {:?}, using {:?}",
+                        format, cfg.label, preferred_format
+                    );
+                    *preferred_format
+                }
+                None => *preferred_format,
+            },
             width: self.size.0,
             height: self.size.1,
             present_mode: if cfg.present_mode == PresentMode::AutoVsync
@@ -814,17 +1682,28 @@ impl SurfaceRenderTarget {
                     .expect("present mode not available, and backup not set")
             },
             desired_maximum_frame_latency: cfg.desired_maximum_frame_latency,
-            alpha_mode: Default::default(),
-            view_formats: Vec::new(),
+            alpha_mode: pick_alpha_mode(cfg.alpha_mode, &caps.alpha_modes),
+            view_formats: cfg.color_config.view_formats.clone(),
         };
+        let cfg = self.current_config.as_mut().unwrap();
+        let supported = supported_sample_counts(adapter, device, surface_cfg.format);
+        clamp_multisample_config(
+            &mut cfg.color_config.multisample_config,
+            &supported,
+            cfg.label.as_deref(),
+        );
+        let cfg = self.current_config.as_ref().unwrap();
         if color_changed || self.resized || self.pending_reconfigure {
             surface.configure(device, &surface_cfg);
             self.pending_reconfigure = false;
             if multisampled_changed || self.resized {
                 self.multisampled_texture = cfg.color_config.multisample_config.as_ref().map(|m| {
+                    let label = texture_label(cfg.label.as_deref(), "multisampled");
                     let mut desc = texture_descriptor(self.size.0, self.size.1);
+                    desc.label = label.as_deref();
                     desc.format = surface_cfg.format;
                     desc.sample_count = m.sample_count;
+                    desc.view_formats = &surface_cfg.view_formats;
                     with_view(device.create_texture(&desc))
                 });
             }
@@ -842,14 +1721,23 @@ impl SurfaceRenderTarget {
                 self.pending_reconfigure = true;
                 SurfaceUpdateStatus::ReadySuboptimal
             }
-            CurrentSurfaceTexture::Outdated | CurrentSurfaceTexture::Lost => {
+            CurrentSurfaceTexture::Outdated => {
                 self.color_texture = None;
                 surface.configure(device, &surface_cfg);
-                return SurfaceUpdateStatus::Skipped;
+                return SurfaceUpdateStatus::Skipped(SurfaceSkipReason::Outdated);
+            }
+            CurrentSurfaceTexture::Lost => {
+                self.color_texture = None;
+                surface.configure(device, &surface_cfg);
+                return SurfaceUpdateStatus::Skipped(SurfaceSkipReason::Lost);
+            }
+            CurrentSurfaceTexture::Timeout => {
+                self.color_texture = None;
+                return SurfaceUpdateStatus::Skipped(SurfaceSkipReason::Timeout);
             }
-            CurrentSurfaceTexture::Timeout | CurrentSurfaceTexture::Occluded => {
+            CurrentSurfaceTexture::Occluded => {
                 self.color_texture = None;
-                return SurfaceUpdateStatus::Skipped;
+                return SurfaceUpdateStatus::Skipped(SurfaceSkipReason::Occluded);
             }
             CurrentSurfaceTexture::Validation => {
                 self.color_texture = None;
@@ -858,16 +1746,24 @@ impl SurfaceRenderTarget {
         };
         if depth_stencil_changed || self.resized {
             self.depth_stencil_texture = cfg.depth_stencil_config.as_ref().map(|ds_cfg| {
+                let label = texture_label(cfg.label.as_deref(), "depth/stencil");
                 let mut desc = texture_descriptor(self.size.0, self.size.1);
+                desc.label = label.as_deref();
                 desc.format = ds_cfg.format;
                 desc.usage |= ds_cfg.usages;
                 with_view(device.create_texture(&desc))
-            })
+            });
+            self.depth_view = depth_views(self.depth_stencil_texture.as_ref(), 1).pop();
         }
         self.resized = false;
         status
     }
 
+    /// The surface has a zero size, e.g. because the window is minimized, see [SurfaceSuspended](crate::SurfaceSuspended)
+    pub fn is_suspended(&self) -> bool {
+        self.size.0 == 0 || self.size.1 == 0
+    }
+
     /// called at the end of rendering, this will drop the [SurfaceTexture]
     pub fn present(&mut self) {
         if let Some((t, _)) = self.color_texture.take() {
@@ -875,7 +1771,18 @@ impl SurfaceRenderTarget {
         }
     }
 
-    pub(crate) fn set_size(&mut self, size: (u32, u32)) {
+    /// Clamped to `max_dimension`, which should be [max_texture_dimension_2d](wgpu::Limits::max_texture_dimension_2d)
+    pub(crate) fn set_size(&mut self, size: (u32, u32), max_dimension: u32) {
+        let clamped = (size.0.min(max_dimension), size.1.min(max_dimension));
+        if clamped != size {
+            warn!(
+                "surface size {:?} of {:?} is too large, using {:?}",
+                size,
+                <Self as RenderTarget>::label(self),
+                clamped
+            );
+        }
+        let size = clamped;
         if size != self.size {
             self.size = size;
             self.resized = true;
@@ -894,7 +1801,8 @@ impl SurfaceRenderTarget {
         (
             cur.color_config != new.color_config
                 || cur.present_mode != new.present_mode
-                || cur.backup_present_mode != new.backup_present_mode,
+                || cur.backup_present_mode != new.backup_present_mode
+                || cur.alpha_mode != new.alpha_mode,
             cur.color_config.multisample_config != new.color_config.multisample_config,
             cur.depth_stencil_config != new.depth_stencil_config,
         )
@@ -932,10 +1840,22 @@ impl RenderTargetImpl for SurfaceRenderTarget {
         self.color_texture.as_ref().map(|(_, v)| v)
     }
 
+    fn multisampled(&self) -> Option<&Texture> {
+        self.multisampled_texture.as_ref().map(|(t, _)| t)
+    }
+
     fn multisampled_view(&self) -> Option<&TextureView> {
         self.multisampled_texture.as_ref().map(|(_, v)| v)
     }
 
+    fn color_renderable(&self) -> bool {
+        true
+    }
+
+    fn depth_slice(&self) -> Option<u32> {
+        None
+    }
+
     fn depth_stencil(&self) -> Option<&Texture> {
         self.depth_stencil_texture.as_ref().map(|(t, _)| t)
     }
@@ -944,6 +1864,10 @@ impl RenderTargetImpl for SurfaceRenderTarget {
         self.depth_stencil_texture.as_ref().map(|(_, v)| v)
     }
 
+    fn depth_view(&self) -> Option<&TextureView> {
+        self.depth_view.as_ref()
+    }
+
     fn current_color_config(&self) -> Option<&RenderTargetColorConfig> {
         self.current_config.as_ref().map(|c| &c.color_config)
     }
@@ -1010,15 +1934,76 @@ impl RenderTargetImpl for SurfaceRenderTarget {
         )
     }
 
+    fn pass_ops(&self) -> PassOps {
+        self.pass_ops
+    }
+
+    fn pass_ops_mut(&mut self) -> &mut PassOps {
+        &mut self.pass_ops
+    }
+
     fn pass_created(&mut self) {
         self.clear_color_scheduled = false;
         self.clear_depth_scheduled = false;
         self.clear_stencil_scheduled = false;
+        self.pass_ops = PassOps::default();
     }
 
     fn unschedule_resolve(&mut self) {
         self.resolve_scheduled = false;
     }
+
+    fn occlusion_query_set(&self) -> Option<&QuerySet> {
+        self.occlusion_query_set.as_ref()
+    }
+
+    fn set_occlusion_query_set(&mut self, query_set: Option<QuerySet>) {
+        self.occlusion_query_set = query_set;
+    }
+
+    fn viewport(&self) -> Option<Viewport> {
+        self.viewport
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    fn scissor_rect(&self) -> Option<ScissorRect> {
+        self.scissor_rect
+    }
+
+    fn set_scissor_rect(&mut self, scissor_rect: Option<ScissorRect>) {
+        self.scissor_rect = scissor_rect;
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.current_or_scheduled_config().label.as_deref()
+    }
+
+    fn set_label(&mut self, label: Option<String>) {
+        // not scheduled, so the textures are not recreated
+        for config in [&mut self.current_config, &mut self.scheduled_config]
+            .into_iter()
+            .flatten()
+        {
+            config.label = label.clone();
+        }
+    }
+
+    fn layer_count(&self) -> u32 {
+        1
+    }
+
+    fn layer(&self) -> u32 {
+        0
+    }
+
+    fn set_layer(&mut self, _layer: u32) {}
+}
+
+fn texture_label(label: Option<&str>, texture: &str) -> Option<String> {
+    label.map(|label| format!("{label} {texture}"))
 }
 
 fn texture_descriptor(width: u32, height: u32) -> TextureDescriptor<'static> {
@@ -1038,6 +2023,76 @@ fn texture_descriptor(width: u32, height: u32) -> TextureDescriptor<'static> {
     }
 }
 
+fn pick_alpha_mode(
+    requested: CompositeAlphaMode,
+    available: &[CompositeAlphaMode],
+) -> CompositeAlphaMode {
+    if requested == CompositeAlphaMode::Auto || available.contains(&requested) {
+        return requested;
+    }
+    let fallback = match requested {
+        CompositeAlphaMode::Opaque => None,
+        _ => available
+            .iter()
+            .copied()
+            .find(|m| *m != CompositeAlphaMode::Opaque),
+    };
+    let fallback = fallback.unwrap_or(CompositeAlphaMode::Auto);
+    warn!("alpha mode {:?} not available, using {:?}", requested, fallback);
+    fallback
+}
+
+fn with_array_view(t: Texture, dimension: TextureViewDimension) -> (Texture, TextureView) {
+    let v = t.create_view(&TextureViewDescriptor {
+        dimension: Some(dimension),
+        ..Default::default()
+    });
+    (t, v)
+}
+
+// ordered by layer, then mip level, 1D and 3D textures only have the array view
+fn layer_views(texture: Option<&(Texture, TextureView)>, layers: u32) -> Vec<TextureView> {
+    let Some((texture, _)) = texture.filter(|(t, _)| {
+        t.dimension() == TextureDimension::D2 && (layers > 1 || t.mip_level_count() > 1)
+    }) else {
+        return Vec::new();
+    };
+    let mip_levels = texture.mip_level_count();
+    (0..layers)
+        .flat_map(|layer| (0..mip_levels).map(move |mip| (layer, mip)))
+        .map(|(layer, mip)| {
+            texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+// one depth aspect view per layer, if the texture has depth and can be sampled
+fn depth_views(texture: Option<&(Texture, TextureView)>, layers: u32) -> Vec<TextureView> {
+    let Some((texture, _)) = texture.filter(|(t, _)| {
+        t.format().has_depth_aspect() && t.usage().contains(TextureUsages::TEXTURE_BINDING)
+    }) else {
+        return Vec::new();
+    };
+    (0..layers)
+        .map(|layer| {
+            texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2),
+                aspect: TextureAspect::DepthOnly,
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
 fn with_view(t: Texture) -> (Texture, TextureView) {
     // FIXME customization?
     let v = t.create_view(&TextureViewDescriptor::default());
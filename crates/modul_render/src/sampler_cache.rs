@@ -0,0 +1,78 @@
+use bevy_ecs::prelude::*;
+use modul_util::HashMap;
+use wgpu::{
+    AddressMode, CompareFunction, Device, FilterMode, MipmapFilterMode, Sampler,
+    SamplerBorderColor, SamplerDescriptor,
+};
+
+/// Deduplicates [Samplers](Sampler), identical descriptors share one sampler.
+/// Labels are not part of the key, so the label of the first descriptor is used.
+#[derive(Resource, Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerKey, Sampler>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets a sampler from the cache, or creates and stores one given the descriptor.
+    /// [Sampler] is reference counted, so the result can be cloned to keep it.
+    pub fn get(&mut self, device: &Device, desc: &SamplerDescriptor) -> &Sampler {
+        self.samplers
+            .entry(SamplerKey::new(desc))
+            .or_insert_with(|| device.create_sampler(desc))
+    }
+
+    /// Gets a sampler if it exists, otherwise will return None.
+    pub fn try_get(&self, desc: &SamplerDescriptor) -> Option<&Sampler> {
+        self.samplers.get(&SamplerKey::new(desc))
+    }
+
+    /// The amount of distinct samplers
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    /// Removes all samplers, samplers still in use elsewhere are kept alive by their handles
+    pub fn clear(&mut self) {
+        self.samplers.clear();
+    }
+}
+
+// SamplerDescriptor has floats and a label, so it can not be used as a key directly
+#[derive(Hash, PartialEq, Eq)]
+struct SamplerKey {
+    address_modes: [AddressMode; 3],
+    mag_filter: FilterMode,
+    min_filter: FilterMode,
+    mipmap_filter: MipmapFilterMode,
+    lod_clamp: [u32; 2],
+    compare: Option<CompareFunction>,
+    anisotropy_clamp: u16,
+    border_color: Option<SamplerBorderColor>,
+}
+
+impl SamplerKey {
+    fn new(desc: &SamplerDescriptor) -> Self {
+        Self {
+            address_modes: [
+                desc.address_mode_u,
+                desc.address_mode_v,
+                desc.address_mode_w,
+            ],
+            mag_filter: desc.mag_filter,
+            min_filter: desc.min_filter,
+            mipmap_filter: desc.mipmap_filter,
+            lod_clamp: [desc.lod_min_clamp.to_bits(), desc.lod_max_clamp.to_bits()],
+            compare: desc.compare,
+            anisotropy_clamp: desc.anisotropy_clamp,
+            border_color: desc.border_color,
+        }
+    }
+}
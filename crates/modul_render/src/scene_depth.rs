@@ -0,0 +1,74 @@
+use crate::{BindGroupLayoutDef, RenderTarget};
+use log::warn;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Device, Sampler, SamplerBindingType,
+    ShaderStages, TextureSampleType, TextureViewDimension,
+};
+
+/// Bind group layout for sampling the depth of a render target in later passes (SSAO, soft particles, ...).
+/// Declares `scene_depth: texture_depth_2d` and a non-filtering `scene_depth_sampler`.
+/// Use with [CachedBindGroupLayout](crate::CachedBindGroupLayout), the [RenderPlugin](crate::RenderPlugin) inserts one.
+pub struct SceneDepthLayout;
+
+impl BindGroupLayoutDef for SceneDepthLayout {
+    const LAYOUT: &'static BindGroupLayoutDescriptor<'static> = &BindGroupLayoutDescriptor {
+        label: Some("Scene depth BGLayout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT.union(ShaderStages::COMPUTE),
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT.union(ShaderStages::COMPUTE),
+                ty: BindingType::Sampler(SamplerBindingType::NonFiltering),
+                count: None,
+            },
+        ],
+    };
+    const LIBRARY: &'static str = "@group(#BIND_GROUP) @binding(0)
+var scene_depth: texture_depth_2d;
+@group(#BIND_GROUP) @binding(1)
+var scene_depth_sampler: sampler;";
+}
+
+impl SceneDepthLayout {
+    /// Creates a bind group of the [depth_view](RenderTarget::depth_view) of the target, the sampler has to be non-filtering.
+    /// Returns None if the depth of the target can not be sampled, or (with a warning) if the target is multisampled, as multisampled depth is not resolved.
+    pub fn bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        target: &dyn RenderTarget,
+        sampler: &Sampler,
+    ) -> Option<BindGroup> {
+        if target.sample_count() != 1 {
+            warn!(
+                "Can not bind the depth of {:?} as scene depth, it has {} samples",
+                target.label(),
+                target.sample_count()
+            );
+            return None;
+        }
+        Some(device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Scene depth bind group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(target.depth_view()?),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        }))
+    }
+}
@@ -1,41 +1,96 @@
 use crate::render_target::{OffscreenRenderTarget, RenderTarget, SurfaceRenderTarget};
+use crate::PingPongTarget;
+use crate::{
+    copy_screenshots, record_submission, GpuErrorScope, GpuProfiler, QuerySetPool,
+    RedrawingWindows, StagingUploads, TextureReadbacks,
+};
 use bevy_ecs::prelude::*;
 use modul_asset::{AssetId, Assets};
-use modul_core::RenderContext;
+use modul_core::{profile_span, RenderContext};
 use std::iter;
 use std::ops::{Deref, DerefMut};
+use std::time::Instant;
 use wgpu::{CommandEncoder, CommandEncoderDescriptor, Device};
 mod basic;
+mod blit;
+mod bloom;
+mod bundle;
+mod clear;
+mod compute;
+mod compute_queue;
+mod conditional;
+mod draw_phase;
+mod error;
+mod graph;
+mod indirect;
+mod instanced;
+mod post_process;
+mod readback;
+mod tonemap;
 
 pub use basic::*;
+pub use blit::*;
+pub use bloom::*;
+pub use bundle::*;
+pub use clear::*;
+pub use compute::*;
+pub use compute_queue::*;
+pub use conditional::*;
+pub use draw_phase::*;
+pub use error::*;
+pub use graph::*;
+pub use indirect::*;
+pub use instanced::*;
+pub use post_process::*;
+pub use readback::*;
+pub use tonemap::*;
 use modul_util::HashSet;
 
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum RenderTargetSource {
     Surface(Entity),
     Offscreen(Entity),
+    /// The side of the [PingPongTarget] of the entity written last frame
+    PingPongRead(Entity),
+    /// The side of the [PingPongTarget] of the entity written this frame
+    PingPongWrite(Entity),
 }
 
 impl RenderTargetSource {
-    pub fn get<'a>(&'a self, world: &'a World) -> Option<&'a dyn RenderTarget> {
+    /// Replaces the [PingPongTarget] sides with the [OffscreenRenderTarget] used this frame
+    pub fn resolve(self, world: &World) -> Option<Self> {
         match self {
+            RenderTargetSource::PingPongRead(e) => world
+                .get::<PingPongTarget>(e)
+                .map(|p| RenderTargetSource::Offscreen(p.read_entity())),
+            RenderTargetSource::PingPongWrite(e) => world
+                .get::<PingPongTarget>(e)
+                .map(|p| RenderTargetSource::Offscreen(p.write_entity())),
+            _ => Some(self),
+        }
+    }
+
+    pub fn get<'a>(&'a self, world: &'a World) -> Option<&'a dyn RenderTarget> {
+        match self.resolve(world)? {
             RenderTargetSource::Surface(e) => world
-                .get::<SurfaceRenderTarget>(*e)
+                .get::<SurfaceRenderTarget>(e)
                 .map(|t| t as &dyn RenderTarget),
             RenderTargetSource::Offscreen(e) => world
-                .get::<OffscreenRenderTarget>(*e)
+                .get::<OffscreenRenderTarget>(e)
                 .map(|t| t as &dyn RenderTarget),
+            RenderTargetSource::PingPongRead(_) | RenderTargetSource::PingPongWrite(_) => None,
         }
     }
 
     pub fn get_mut<'a>(&'a self, world: &'a mut World) -> Option<RenderTargetMut<'a>> {
-        match self {
+        match self.resolve(world)? {
             RenderTargetSource::Surface(e) => {
-                world.get_mut(*e).map(|rt| RenderTargetMut::Surface(rt))
+                world.get_mut(e).map(RenderTargetMut::Surface)
             }
             RenderTargetSource::Offscreen(e) => {
-                world.get_mut(*e).map(|rt| RenderTargetMut::Offscreen(rt))
+                world.get_mut(e).map(RenderTargetMut::Offscreen)
             }
+            RenderTargetSource::PingPongRead(_) | RenderTargetSource::PingPongWrite(_) => None,
         }
     }
 }
@@ -71,109 +126,435 @@ pub trait OperationBuilder: Send + Sync + 'static {
     /// used by the sequence to determine when to resolve rendertargets
     fn writing(&self) -> Vec<RenderTargetSource>;
     fn finish(self, world: &World, device: &Device) -> impl Operation + 'static;
+    /// Used to identify the operation in [GpuTimings](crate::GpuTimings) and as its debug group, the type name by default
+    fn label(&self) -> String {
+        short_type_name::<Self>()
+    }
 }
 
 pub trait Operation: Send + Sync {
-    fn run(&mut self, world: &mut World, command_encoder: &mut CommandEncoder);
+    /// An error skips the rest of the frame and is reported in [SequenceErrors]
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError>;
 }
 
+/// Operations that run in order, with resolves inserted automatically.
+/// Operations can be inserted, removed and replaced after the sequence has run, only the new operations are finished on the next run.
 pub struct Sequence {
-    // to not have Sequence publicly be an enum
-    inner: InnerSequence,
+    entries: Vec<SequenceEntry>,
+    /// The operations with their labels, rebuilt on the next run when None
+    plan: Option<Vec<(SequenceOperation, String)>>,
+    label: Option<String>,
 }
 
 impl Sequence {
-    fn run(&mut self, command_encoder: &mut CommandEncoder, world: &mut World) {
-        if let InnerSequence::UnInitialized(builders) = &mut self.inner {
-            let device = &world.resource::<RenderContext>().device;
-            let mut operations = Vec::new();
-            let mut needs_resolving = HashSet::<RenderTargetSource>::new();
-            for builder in builders {
-                for reading in builder.reading() {
-                    if needs_resolving.contains(&reading) {
-                        needs_resolving.remove(&reading);
-                        operations.push(SequenceOperation::ResolveNext(reading));
+    /// The amount of operations
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The index of the first operation with the [label](OperationBuilder::label)
+    pub fn position(&self, label: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.label == label)
+    }
+
+    /// Inserts an operation at the index, shifting the operations after it
+    /// ## Panics
+    /// If the index is larger than [len](Self::len)
+    pub fn insert(&mut self, index: usize, operation_builder: impl OperationBuilder) {
+        self.insert_entry(
+            index,
+            Box::new(DynOperationBuilderImpl(Some(Box::new(operation_builder)))),
+        );
+    }
+
+    /// Compute version of [insert](Self::insert)
+    pub fn insert_compute(
+        &mut self,
+        index: usize,
+        operation_builder: impl ComputeOperationBuilder,
+    ) {
+        self.insert_entry(
+            index,
+            Box::new(DynComputeOperationBuilderImpl(Some(Box::new(
+                operation_builder,
+            )))),
+        );
+    }
+
+    /// Inserts a nested sequence, see [add_sequence](SequenceBuilder::add_sequence)
+    pub fn insert_sequence(&mut self, index: usize, sequence: AssetId<Sequence>) {
+        self.entries
+            .insert(index, SequenceEntry::sequence(sequence));
+        self.plan = None;
+    }
+
+    /// Adds an operation at the end
+    pub fn push(&mut self, operation_builder: impl OperationBuilder) {
+        self.insert(self.len(), operation_builder);
+    }
+
+    /// Compute version of [push](Self::push)
+    pub fn push_compute(&mut self, operation_builder: impl ComputeOperationBuilder) {
+        self.insert_compute(self.len(), operation_builder);
+    }
+
+    /// Removes the operation at the index
+    /// ## Panics
+    /// If the index is out of bounds
+    pub fn remove(&mut self, index: usize) {
+        self.entries.remove(index);
+        self.plan = None;
+    }
+
+    /// Replaces the operation at the index
+    /// ## Panics
+    /// If the index is out of bounds
+    pub fn replace(&mut self, index: usize, operation_builder: impl OperationBuilder) {
+        self.remove(index);
+        self.insert(index, operation_builder);
+    }
+
+    /// Compute version of [replace](Self::replace)
+    pub fn replace_compute(
+        &mut self,
+        index: usize,
+        operation_builder: impl ComputeOperationBuilder,
+    ) {
+        self.remove(index);
+        self.insert_compute(index, operation_builder);
+    }
+
+    fn insert_entry(&mut self, index: usize, builder: Box<dyn DynOperationBuilder>) {
+        self.entries.insert(index, SequenceEntry::new(builder));
+        self.plan = None;
+    }
+
+    /// Finishes new operations and finds where to resolve, using the targets declared when the operations were added
+    fn prepare(&mut self, world: &World, assets: &Assets<Sequence>) {
+        let device = &world.resource::<RenderContext>().device;
+        for entry in &mut self.entries {
+            match &mut entry.operation {
+                EntryOperation::Builder(builder) => {
+                    entry.operation = EntryOperation::Finished(builder.finish(world, device));
+                }
+                // the sub-sequence can change, so its targets are checked every run
+                EntryOperation::Sequence(id) => {
+                    let (reading, writing) = sequence_targets(*id, assets, &mut Vec::new());
+                    if reading != entry.reading || writing != entry.writing {
+                        entry.reading = reading;
+                        entry.writing = writing;
+                        self.plan = None;
                     }
                 }
-                for writing in builder.writing() {
-                    needs_resolving.insert(writing);
+                EntryOperation::Finished(_) => {}
+            }
+        }
+        if self.plan.is_some() {
+            return;
+        }
+        let mut plan = Vec::new();
+        let mut needs_resolving = HashSet::<RenderTargetSource>::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            for reading in &entry.reading {
+                if needs_resolving.remove(reading) {
+                    // compute passes can not resolve, so an empty pass has to do it first.
+                    // sub-sequences might start with a compute pass
+                    let sequence = matches!(entry.operation, EntryOperation::Sequence(_));
+                    plan.push(if entry.compute || sequence {
+                        (SequenceOperation::Resolve(*reading), "resolve".to_string())
+                    } else {
+                        (SequenceOperation::ResolveNext(*reading), String::new())
+                    });
                 }
-                operations.push(SequenceOperation::Run(builder.finish(&world, device)));
             }
-            for resolve in needs_resolving {
-                operations.push(SequenceOperation::ResolveNext(resolve));
+            needs_resolving.extend(entry.writing.iter().copied());
+            match plan.last_mut() {
+                Some((SequenceOperation::Compute(batch), batch_label)) if entry.compute => {
+                    batch.push(i);
+                    *batch_label += " + ";
+                    *batch_label += &entry.label;
+                }
+                _ if entry.compute => {
+                    plan.push((SequenceOperation::Compute(vec![i]), entry.label.clone()))
+                }
+                _ => plan.push((SequenceOperation::Run(i), entry.label.clone())),
             }
-            self.inner = InnerSequence::Ready(operations);
-        }
-        // should always be true, not using match as this will run after the other if let
-        if let InnerSequence::Ready(ops) = &mut self.inner {
-            for op in ops.iter_mut() {
-                match op {
-                    SequenceOperation::ResolveNext(target) => {
-                        target.get_mut(world).map(|mut rt| rt.schedule_resolve());
-                    }
-                    SequenceOperation::Run(op) => {
-                        op.run(world, command_encoder);
+        }
+        for resolve in needs_resolving {
+            plan.push((SequenceOperation::ResolveNext(resolve), String::new()));
+        }
+        self.plan = Some(plan);
+    }
+
+    /// Stops at the first error, `id` is the id of this sequence used in the error
+    fn run(
+        &mut self,
+        command_encoder: &mut CommandEncoder,
+        world: &mut World,
+        assets: &mut Assets<Sequence>,
+        id: AssetId<Sequence>,
+    ) -> Result<(), SequenceError> {
+        profile_span!("sequence", label = ?self.label, id = ?id);
+        // the builders create their resources when finished
+        let scope = GpuErrorScope::push(&world.resource::<RenderContext>().device);
+        self.prepare(world, assets);
+        scope.pop(world, || format!("finishing the operations of sequence {:?}", id));
+        if let Some(label) = &self.label {
+            command_encoder.push_debug_group(label);
+        }
+        let result = self.run_plan(command_encoder, world, assets, id);
+        if self.label.is_some() {
+            command_encoder.pop_debug_group();
+        }
+        result
+    }
+
+    fn run_plan(
+        &mut self,
+        command_encoder: &mut CommandEncoder,
+        world: &mut World,
+        assets: &mut Assets<Sequence>,
+        id: AssetId<Sequence>,
+    ) -> Result<(), SequenceError> {
+        let Self { entries, plan, .. } = self;
+        let device = world.resource::<RenderContext>().device.clone();
+        for (op, label) in plan.iter().flatten() {
+            // resolves are only scheduled here, so there is nothing to time
+            if let SequenceOperation::ResolveNext(target) = op {
+                if let Some(mut rt) = target.get_mut(world) {
+                    rt.schedule_resolve();
+                }
+                continue;
+            }
+            profile_span!("operation", label = %label);
+            command_encoder.push_debug_group(label);
+            let scope = GpuErrorScope::push(&device);
+            let timestamp = world
+                .get_resource_mut::<GpuProfiler>()
+                .and_then(|mut profiler| profiler.begin(command_encoder, label));
+            let result = match op {
+                SequenceOperation::ResolveNext(_) => unreachable!(),
+                SequenceOperation::Resolve(target) => {
+                    if let Some(mut rt) = target.get_mut(world) {
+                        rt.schedule_resolve();
+                        rt.begin_ending_pass(command_encoder);
                     }
+                    Ok(())
+                }
+                SequenceOperation::Run(i) => match &mut entries[*i].operation {
+                    EntryOperation::Finished(FinishedOperation::Render(op)) => op
+                        .run(world, command_encoder)
+                        .map_err(|error| SequenceError::Operation {
+                            sequence: id,
+                            label: label.clone(),
+                            error,
+                        }),
+                    // the sequence is taken out while running, so sequences containing themselves are skipped
+                    EntryOperation::Sequence(nested) => match assets.take(*nested) {
+                        Some(mut sequence) => {
+                            let result = sequence.run(command_encoder, world, assets, *nested);
+                            // only fails if the slot was filled while running, which keeps the newer sequence
+                            let _ = assets.replace_in_place(*nested, sequence);
+                            result
+                        }
+                        None => Ok(()),
+                    },
+                    _ => Ok(()),
+                },
+                SequenceOperation::Compute(batch) => {
+                    let mut compute_pass = begin_compute_pass(command_encoder);
+                    batch.iter().try_for_each(|i| {
+                        let entry = &mut entries[*i];
+                        match &mut entry.operation {
+                            EntryOperation::Finished(FinishedOperation::Compute(op)) => op
+                                .run(world, &mut compute_pass)
+                                .map_err(|error| SequenceError::Operation {
+                                    sequence: id,
+                                    label: entry.label.clone(),
+                                    error,
+                                }),
+                            _ => Ok(()),
+                        }
+                    })
                 }
+            };
+            if let Some(index) = timestamp {
+                world.resource::<GpuProfiler>().end(command_encoder, index);
             }
+            command_encoder.pop_debug_group();
+            scope.pop(world, || format!("operation {} of sequence {:?}", label, id));
+            result?;
         }
+        Ok(())
     }
 }
 
 pub struct SequenceBuilder {
-    operation_builders: Vec<Box<dyn DynOperationBuilder>>,
+    entries: Vec<SequenceEntry>,
+    label: Option<String>,
+}
+
+impl Default for SequenceBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SequenceBuilder {
     pub fn new() -> SequenceBuilder {
         SequenceBuilder {
-            operation_builders: vec![],
+            entries: vec![],
+            label: None,
         }
     }
 
+    /// Sets the debug group the operations of the sequence are recorded in, shown in graphics debuggers
+    pub fn label(&mut self, label: impl Into<String>) -> &mut Self {
+        self.label = Some(label.into());
+        self
+    }
+
     pub fn add(&mut self, operation_builder: impl OperationBuilder) -> &mut Self {
-        self.operation_builders
-            .push(Box::new(DynOperationBuilderImpl(Some(Box::new(
-                operation_builder,
+        self.entries
+            .push(SequenceEntry::new(Box::new(DynOperationBuilderImpl(Some(
+                Box::new(operation_builder),
             )))));
         self
     }
 
+    /// Runs another sequence as an operation, it is resolved as if it was an operation reading and writing all targets of the nested sequence.
+    /// A sequence can be nested in multiple sequences, but nesting a sequence in itself does nothing.
+    pub fn add_sequence(&mut self, sequence: AssetId<Sequence>) -> &mut Self {
+        self.entries.push(SequenceEntry::sequence(sequence));
+        self
+    }
+
+    /// Adds an operation that is skipped while the condition is false, without having to rebuild the sequence.
+    /// Render targets it writes are still resolved as if it ran.
+    pub fn add_if(
+        &mut self,
+        operation_builder: impl OperationBuilder,
+        condition: impl Fn(&World) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add(ConditionalOperationBuilder {
+            builder: operation_builder,
+            condition: Box::new(condition),
+        })
+    }
+
+    /// Compute version of [add_if](Self::add_if)
+    pub fn add_compute_if(
+        &mut self,
+        operation_builder: impl ComputeOperationBuilder,
+        condition: impl Fn(&World) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_compute(ConditionalOperationBuilder {
+            builder: operation_builder,
+            condition: Box::new(condition),
+        })
+    }
+
+    /// Adds a compute operation, compute operations added after each other run in the same compute pass
+    pub fn add_compute(&mut self, operation_builder: impl ComputeOperationBuilder) -> &mut Self {
+        self.entries.push(SequenceEntry::new(Box::new(
+            DynComputeOperationBuilderImpl(Some(Box::new(operation_builder))),
+        )));
+        self
+    }
+
     pub fn finish(self, assets: &mut Assets<Sequence>) -> AssetId<Sequence> {
         assets.add(Sequence {
-            inner: InnerSequence::UnInitialized(self.operation_builders),
+            entries: self.entries,
+            plan: None,
+            label: self.label,
         })
     }
 }
 
-pub enum SequenceOperation {
-    Run(Box<dyn Operation>),
+/// A step of a [Sequence], referring to its entries by index
+enum SequenceOperation {
+    Run(usize),
+    /// Runs in a single compute pass
+    Compute(Vec<usize>),
     ResolveNext(RenderTargetSource),
+    /// Resolves using an empty pass
+    Resolve(RenderTargetSource),
 }
 
 pub struct SequenceQueue(pub Vec<AssetId<Sequence>>);
 
 impl From<Vec<SequenceQueue>> for SequenceQueue {
     fn from(value: Vec<SequenceQueue>) -> Self {
-        Self(value.into_iter().map(|seq| seq.0).flatten().collect())
+        Self(value.into_iter().flat_map(|seq| seq.0).collect())
     }
 }
 
 #[derive(Resource)]
 pub struct RunningSequenceQueue(pub SequenceQueue);
 
+/// Sequences that only run when the window entity it is on requested the redraw, after the [RunningSequenceQueue].
+/// Redraws of windows with this component also cause drawing like an [ImportantWindow](modul_core::ImportantWindow).
+#[derive(Component)]
+pub struct WindowSequenceQueue(pub SequenceQueue);
+
 // to get around dyn not being able to consume self
 // maybe there is a better way to do this
 trait DynOperationBuilder: Send + Sync + 'static {
+    fn is_compute(&self) -> bool;
+    fn label(&self) -> String;
     fn reading(&self) -> Vec<RenderTargetSource>;
     fn writing(&self) -> Vec<RenderTargetSource>;
-    fn finish(&mut self, world: &World, device: &Device) -> Box<dyn Operation>;
+    fn finish(&mut self, world: &World, device: &Device) -> FinishedOperation;
+}
+
+enum FinishedOperation {
+    Render(Box<dyn Operation>),
+    Compute(Box<dyn ComputeOperation>),
 }
 
 struct DynOperationBuilderImpl<T: OperationBuilder>(Option<Box<T>>);
 
 impl<T: OperationBuilder> DynOperationBuilder for DynOperationBuilderImpl<T> {
+    fn is_compute(&self) -> bool {
+        false
+    }
+
+    fn label(&self) -> String {
+        OperationBuilder::label(self.0.as_ref().unwrap().as_ref())
+    }
+
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        self.0.as_ref().unwrap().reading()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        self.0.as_ref().unwrap().writing()
+    }
+
+    fn finish(&mut self, world: &World, device: &Device) -> FinishedOperation {
+        FinishedOperation::Render(Box::new(self.0.take().unwrap().finish(world, device)))
+    }
+}
+
+struct DynComputeOperationBuilderImpl<T: ComputeOperationBuilder>(Option<Box<T>>);
+
+impl<T: ComputeOperationBuilder> DynOperationBuilder for DynComputeOperationBuilderImpl<T> {
+    fn is_compute(&self) -> bool {
+        true
+    }
+
+    fn label(&self) -> String {
+        ComputeOperationBuilder::label(self.0.as_ref().unwrap().as_ref())
+    }
+
     fn reading(&self) -> Vec<RenderTargetSource> {
         self.0.as_ref().unwrap().reading()
     }
@@ -182,38 +563,169 @@ impl<T: OperationBuilder> DynOperationBuilder for DynOperationBuilderImpl<T> {
         self.0.as_ref().unwrap().writing()
     }
 
-    fn finish(&mut self, world: &World, device: &Device) -> Box<dyn Operation> {
-        Box::new(self.0.take().unwrap().finish(world, device))
+    fn finish(&mut self, world: &World, device: &Device) -> FinishedOperation {
+        FinishedOperation::Compute(Box::new(self.0.take().unwrap().finish(world, device)))
     }
 }
-enum InnerSequence {
-    Ready(Vec<SequenceOperation>),
-    UnInitialized(Vec<Box<dyn DynOperationBuilder>>),
+
+/// The name of a type without its module path and generics
+pub(crate) fn short_type_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name).to_string()
+}
+
+/// An operation of a [Sequence] with the render targets it declared when added
+struct SequenceEntry {
+    reading: Vec<RenderTargetSource>,
+    writing: Vec<RenderTargetSource>,
+    label: String,
+    compute: bool,
+    operation: EntryOperation,
+}
+
+enum EntryOperation {
+    /// Finished on the next run
+    Builder(Box<dyn DynOperationBuilder>),
+    Finished(FinishedOperation),
+    /// A nested sequence, its targets are those of all its operations
+    Sequence(AssetId<Sequence>),
+}
+
+impl SequenceEntry {
+    fn new(builder: Box<dyn DynOperationBuilder>) -> Self {
+        Self {
+            reading: builder.reading(),
+            writing: builder.writing(),
+            label: builder.label(),
+            compute: builder.is_compute(),
+            operation: EntryOperation::Builder(builder),
+        }
+    }
+
+    fn sequence(id: AssetId<Sequence>) -> Self {
+        Self {
+            reading: Vec::new(),
+            writing: Vec::new(),
+            label: "Sequence".to_string(),
+            compute: false,
+            operation: EntryOperation::Sequence(id),
+        }
+    }
+}
+
+/// All targets read and written by a sequence and the sequences nested in it
+fn sequence_targets(
+    id: AssetId<Sequence>,
+    assets: &Assets<Sequence>,
+    visited: &mut Vec<AssetId<Sequence>>,
+) -> (Vec<RenderTargetSource>, Vec<RenderTargetSource>) {
+    let mut reading = Vec::new();
+    let mut writing = Vec::new();
+    if visited.contains(&id) {
+        return (reading, writing);
+    }
+    visited.push(id);
+    let Some(sequence) = assets.get(id) else {
+        return (reading, writing);
+    };
+    for entry in &sequence.entries {
+        let (entry_reading, entry_writing) = match entry.operation {
+            EntryOperation::Sequence(nested) => sequence_targets(nested, assets, visited),
+            _ => (entry.reading.clone(), entry.writing.clone()),
+        };
+        for target in entry_reading {
+            if !reading.contains(&target) {
+                reading.push(target);
+            }
+        }
+        for target in entry_writing {
+            if !writing.contains(&target) {
+                writing.push(target);
+            }
+        }
+    }
+    (reading, writing)
+}
+
+/// Stops at the first error, so the rest of the frame is skipped
+pub(crate) fn encode_sequences(
+    queue: &[AssetId<Sequence>],
+    command_encoder: &mut CommandEncoder,
+    world: &mut World,
+    sequence_assets: &mut Assets<Sequence>,
+) -> Result<(), SequenceError> {
+    for &asset_id in queue {
+        // taken out, so it can run the sequences nested in it
+        let mut sequence = sequence_assets
+            .take(asset_id)
+            .ok_or(SequenceError::MissingSequence(asset_id))?;
+        let result = sequence.run(command_encoder, world, sequence_assets, asset_id);
+        // only fails if the slot was filled while running, which keeps the newer sequence
+        let _ = sequence_assets.replace_in_place(asset_id, sequence);
+        result?;
+    }
+    Ok(())
 }
 
 pub(crate) fn run_sequences(world: &mut World) {
-    world.resource_scope(|world, mut sequence_assets: Mut<Assets<Sequence>>| {
-        if !world.contains_resource::<RunningSequenceQueue>() {
-            return;
+    // uploads are submitted first, so the sequences see them
+    let uploads = world
+        .get_resource_mut::<StagingUploads>()
+        .and_then(|mut s| s.finish());
+    let mut queue = world
+        .get_resource::<RunningSequenceQueue>()
+        .map(|q| q.0 .0.clone())
+        .unwrap_or_default();
+    for window in world.resource::<RedrawingWindows>().iter() {
+        if let Some(window_queue) = world.get::<WindowSequenceQueue>(window) {
+            queue.extend(window_queue.0 .0.iter().copied());
         }
-        world.resource_scope(|world, sequence_queue: Mut<RunningSequenceQueue>| {
+    }
+    if queue.is_empty() {
+        if uploads.is_some() {
+            world.resource::<RenderContext>().queue.submit(uploads);
+        }
+    } else {
+        world.resource_scope(|world, mut sequence_assets: Mut<Assets<Sequence>>| {
             // FIXME maybe use multiple command encoders and run in parallel??
+            let start = Instant::now();
             let mut command_encoder = world
                 .resource::<RenderContext>()
                 .device
                 .create_command_encoder(&CommandEncoderDescriptor {
                     label: Some("Sequence runner encoder"),
                 });
-            for asset_id in &sequence_queue.0 .0 {
-                sequence_assets
-                    .get_mut(*asset_id)
-                    .expect("sequence was added to queue, but does not exist")
-                    .run(&mut command_encoder, world)
+            // errors of recording passes are reported when the encoder is finished
+            let scope = GpuErrorScope::push(&world.resource::<RenderContext>().device);
+            if let Err(error) =
+                encode_sequences(&queue, &mut command_encoder, world, &mut sequence_assets)
+            {
+                world.resource_mut::<SequenceErrors>().push(error);
+            }
+            copy_screenshots(world, &mut command_encoder);
+            world
+                .resource_mut::<QuerySetPool>()
+                .resolve(&mut command_encoder);
+            if let Some(mut profiler) = world.get_resource_mut::<GpuProfiler>() {
+                profiler.resolve(&mut command_encoder);
             }
+            let command_buffer = command_encoder.finish();
+            let encoded = Instant::now();
             world
                 .resource::<RenderContext>()
                 .queue
-                .submit(iter::once(command_encoder.finish()));
+                .submit(uploads.into_iter().chain(iter::once(command_buffer)));
+            record_submission(world, encoded - start, encoded.elapsed());
+            scope.pop(world, || "Sequence runner encoder".to_string());
+            world.resource_mut::<QuerySetPool>().map_copied();
+            world.resource_mut::<TextureReadbacks>().map_copied();
+            if let Some(mut profiler) = world.get_resource_mut::<GpuProfiler>() {
+                profiler.map_copied();
+            }
         });
-    });
+    }
+    if let Some(mut staging) = world.get_resource_mut::<StagingUploads>() {
+        staging.recall();
+    }
 }
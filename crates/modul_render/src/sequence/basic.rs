@@ -1,14 +1,23 @@
 use bevy_ecs::prelude::*;
 
-use crate::{Operation, OperationBuilder, RenderTargetSource};
+use crate::{
+    Operation, OperationBuilder, OperationError, RenderTargetSource, ScissorRect, Viewport,
+};
 
 pub struct ClearNext {
     pub render_target: RenderTargetSource,
 }
 
 impl Operation for ClearNext {
-    fn run(&mut self, world: &mut World, _command_encoder: &mut wgpu::CommandEncoder) {
-        self.render_target.get_mut(world).map(|mut rt| rt.schedule_clear_color());
+    fn run(
+        &mut self,
+        world: &mut World,
+        _command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), OperationError> {
+        if let Some(mut rt) = self.render_target.get_mut(world) {
+            rt.schedule_clear_color();
+        }
+        Ok(())
     }
 }
 
@@ -27,13 +36,138 @@ impl OperationBuilder for ClearNext {
     }
 }
 
+/// The next pass on the target does not store the selected attachments, see [schedule_discard_color](crate::RenderTarget::schedule_discard_color)
+pub struct DiscardNext {
+    pub render_target: RenderTargetSource,
+    pub color: bool,
+    pub depth_stencil: bool,
+}
+
+impl Operation for DiscardNext {
+    fn run(
+        &mut self,
+        world: &mut World,
+        _command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), OperationError> {
+        if let Some(mut rt) = self.render_target.get_mut(world) {
+            if self.color {
+                rt.schedule_discard_color();
+            }
+            if self.depth_stencil {
+                rt.schedule_discard_depth_stencil();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OperationBuilder for DiscardNext {
+    // like ClearNext, only affects the next pass
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn finish(self, _world: &World, _device: &wgpu::Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+/// Selects the layer of a layered [OffscreenRenderTarget](crate::OffscreenRenderTarget) the next passes are created on
+pub struct SetLayer {
+    pub render_target: RenderTargetSource,
+    pub layer: u32,
+}
+
+impl Operation for SetLayer {
+    fn run(
+        &mut self,
+        world: &mut World,
+        _command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        if self.layer >= rt.layer_count() {
+            return Err(format!(
+                "layer {} is out of range, the target {:?} has {} layers",
+                self.layer,
+                rt.label(),
+                rt.layer_count()
+            )
+            .into());
+        }
+        rt.set_layer(self.layer);
+        Ok(())
+    }
+}
+
+impl OperationBuilder for SetLayer {
+    // like ClearNext, only affects the passes created later
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn finish(self, _world: &World, _device: &wgpu::Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+/// Sets the viewport and scissor rect of the next passes on a target, None uses the whole target
+pub struct SetViewport {
+    pub render_target: RenderTargetSource,
+    pub viewport: Option<Viewport>,
+    pub scissor_rect: Option<ScissorRect>,
+}
+
+impl Operation for SetViewport {
+    fn run(
+        &mut self,
+        world: &mut World,
+        _command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), OperationError> {
+        if let Some(mut rt) = self.render_target.get_mut(world) {
+            rt.set_viewport(self.viewport);
+            rt.set_scissor_rect(self.scissor_rect);
+        }
+        Ok(())
+    }
+}
+
+impl OperationBuilder for SetViewport {
+    // like ClearNext, only affects the passes created later
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn finish(self, _world: &World, _device: &wgpu::Device) -> impl Operation + 'static {
+        self
+    }
+}
+
 pub struct EmptyPass {
     pub render_target: RenderTargetSource,
 }
 
 impl Operation for EmptyPass {
-    fn run(&mut self, world: &mut World, command_encoder: &mut wgpu::CommandEncoder) {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), OperationError> {
         self.render_target.get_mut(world).map(|mut rt| rt.begin_ending_pass(command_encoder));
+        Ok(())
     }
 }
 
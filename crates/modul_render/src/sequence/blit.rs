@@ -0,0 +1,262 @@
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::{RenderContext, SurfaceColorSpace};
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, CommandEncoder,
+    CompareFunction, DepthBiasState, Device, FilterMode, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureSampleType, TextureViewDimension,
+};
+
+use crate::{
+    BindGroupCache, BoundResource, DirectRenderPipelineResourceProvider, GenericColorTargetState,
+    GenericDepthStencilState, GenericFragmentState, GenericMultisampleState,
+    GenericRenderPipelineDescriptor, GenericVertexState, Operation, OperationBuilder,
+    OperationError, RenderPipelineManager, RenderTargetSource, SamplerCache,
+};
+
+/// WGSL for a vertex shader drawing a fullscreen triangle with 3 vertices.
+/// The entry point is `vs_main`, and it outputs `VertexOutput` with `@location(0) uv: vec2<f32>`, going from 0 to 1 across the screen.
+pub const FULLSCREEN_TRIANGLE_WGSL: &str = include_str!("fullscreen.wgsl");
+
+/// WGSL for `fn srgb_encode(c: vec3<f32>) -> vec3<f32>`, encoding linear colors to sRGB
+pub const SRGB_ENCODE_WGSL: &str = include_str!("srgb_encode.wgsl");
+
+/// The shared pipeline of [BlitOperations](BlitOperation), inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct BlitPipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+    /// Like `manager`, but encodes the colors to sRGB
+    pub srgb_encode_manager: AssetId<RenderPipelineManager>,
+    pub bind_group_layout: BindGroupLayout,
+}
+
+/// Draws the color texture of one render target over another, scaling it to fit.
+/// The source must have [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage and a filterable format, so surfaces usually can not be the source.
+/// Multisampled sources are resolved first, and the pipeline is created for the format and sample count of the destination.
+pub struct BlitOperation {
+    pub source: RenderTargetSource,
+    pub destination: RenderTargetSource,
+    pub filter: FilterMode,
+    /// Encode the colors to sRGB, if None they are encoded when the destination is a surface without an sRGB format (see [IsSrgbSurface](modul_core::IsSrgbSurface))
+    pub encode_srgb: Option<bool>,
+}
+
+impl BlitOperation {
+    /// Blit with linear filtering and automatic sRGB encoding
+    pub fn new(source: RenderTargetSource, destination: RenderTargetSource) -> Self {
+        Self {
+            source,
+            destination,
+            filter: FilterMode::Linear,
+            encode_srgb: None,
+        }
+    }
+
+    fn encodes_srgb(&self, world: &World) -> bool {
+        self.encode_srgb.unwrap_or_else(|| {
+            let RenderTargetSource::Surface(_) = self.destination else {
+                return false;
+            };
+            self.destination
+                .get(world)
+                .and_then(|rt| rt.texture())
+                .is_some_and(|t| {
+                    SurfaceColorSpace::of(t.format()) == SurfaceColorSpace::SrgbNonlinear
+                })
+        })
+    }
+}
+
+impl Operation for BlitOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        resolve_for_reading(self.source, world, command_encoder);
+        let Some(view) = self
+            .source
+            .get(world)
+            .and_then(|rt| rt.texture_view().cloned())
+        else {
+            return Ok(());
+        };
+        let device = world.resource::<RenderContext>().device.clone();
+        let sampler = world
+            .resource_mut::<SamplerCache>()
+            .get(
+                &device,
+                &SamplerDescriptor {
+                    label: Some("Blit sampler"),
+                    mag_filter: self.filter,
+                    min_filter: self.filter,
+                    ..Default::default()
+                },
+            )
+            .clone();
+        let encode = self.encodes_srgb(world);
+        let blit = world.resource::<BlitPipeline>();
+        let manager = match encode {
+            true => blit.srgb_encode_manager,
+            false => blit.manager,
+        };
+        let layout = blit.bind_group_layout.clone();
+        // cached by the view, so it is only created again when the source texture is replaced
+        let bind_group = world
+            .resource_mut::<BindGroupCache>()
+            .get(
+                &device,
+                &layout,
+                &[
+                    (0, BoundResource::TextureView(view)),
+                    (1, BoundResource::Sampler(sampler)),
+                ],
+            )
+            .clone();
+        world.asset_scope(manager, |world, manager| {
+            let Some(pipeline) = manager.get_compatible(self.destination, world) else {
+                return;
+            };
+            let Some(mut rt) = self.destination.get_mut(world) else {
+                return;
+            };
+            let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+                return;
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        });
+        Ok(())
+    }
+}
+
+impl OperationBuilder for BlitOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        vec![self.source]
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.destination]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+// the sequence only schedules the resolve, so a pass is needed to do it before reading
+pub(crate) fn resolve_for_reading(
+    source: RenderTargetSource,
+    world: &mut World,
+    command_encoder: &mut CommandEncoder,
+) {
+    if let Some(mut rt) = source.get_mut(world) {
+        if rt.sample_count() > 1 {
+            rt.begin_ending_pass(command_encoder);
+        }
+    }
+}
+
+pub(crate) fn init_blit_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    let bind_group_layout = ctx
+        .device
+        .create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Blit BGLayout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+    let shader = shaders.add(
+        ctx.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Blit shader"),
+            source: ShaderSource::Wgsl(
+                concat!(
+                    include_str!("fullscreen.wgsl"),
+                    include_str!("srgb_encode.wgsl"),
+                    include_str!("blit.wgsl")
+                )
+                .into(),
+            ),
+        }),
+    );
+    let layout = layouts.add(
+        ctx.device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Blit pipeline layout"),
+                bind_group_layouts: &[Some(&bind_group_layout)],
+                immediate_size: 0,
+            }),
+    );
+    let mut blit_manager = |entry_point: &str| {
+        managers.add(RenderPipelineManager::new(blit_pipeline_descriptor(
+            layout,
+            shader,
+            entry_point,
+        )))
+    };
+    let manager = blit_manager("fs_main");
+    let srgb_encode_manager = blit_manager("fs_srgb_encode");
+    commands.insert_resource(BlitPipeline {
+        manager,
+        srgb_encode_manager,
+        bind_group_layout,
+    });
+}
+
+fn blit_pipeline_descriptor(
+    layout: AssetId<PipelineLayout>,
+    shader: AssetId<ShaderModule>,
+    entry_point: &str,
+) -> GenericRenderPipelineDescriptor {
+    GenericRenderPipelineDescriptor {
+        resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+            layout,
+            vertex_shader_module: shader,
+            fragment_shader_module: shader,
+        }),
+        label: Some("Blit pipeline".to_string()),
+        vertex_state: GenericVertexState {
+            entry_point: "vs_main".to_string(),
+            buffers: vec![],
+        },
+        primitive: PrimitiveState::default(),
+        // ignoring depth, but still needed for destinations with a depth buffer
+        depth_stencil: Some(GenericDepthStencilState {
+            depth_write_enable: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: GenericMultisampleState {
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        fragment: Some(GenericFragmentState {
+            entry_point: entry_point.to_string(),
+            targets: vec![GenericColorTargetState::new(None)],
+        }),
+    }
+}
@@ -0,0 +1,510 @@
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent, BlendFactor,
+    BlendOperation, BlendState, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState,
+    ColorWrites, CommandEncoder, CompareFunction, DepthBiasState, Device, Extent3d, FilterMode,
+    FragmentState, LoadOp, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModule,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, StoreOp, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use super::blit::resolve_for_reading;
+use crate::{
+    DirectRenderPipelineResourceProvider, GenericColorTargetState, GenericDepthStencilState,
+    GenericFragmentState, GenericMultisampleState, GenericRenderPipelineDescriptor,
+    GenericVertexState, Operation, OperationBuilder, OperationError, RenderPipelineManager,
+    RenderTargetSource, SamplerCache,
+};
+
+/// The format of the bloom mip chain
+const BLOOM_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// The parameters of every [BloomOperation], read each time it runs
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+    /// How much of the glow is added to the source
+    pub intensity: f32,
+    /// Only the parts of the source brighter than this glow
+    pub threshold: f32,
+    /// The width of the smooth transition around the threshold
+    pub knee: f32,
+    /// The max amount of times the source is halved, more levels make a wider glow
+    pub max_mip_levels: u32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.3,
+            threshold: 1.0,
+            knee: 0.5,
+            max_mip_levels: 6,
+        }
+    }
+}
+
+/// The shared pipelines of [BloomOperations](BloomOperation), inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct BloomPipeline {
+    /// Writes the part of the source above the threshold to the first level
+    pub prefilter: RenderPipeline,
+    pub downsample: RenderPipeline,
+    /// Adds a level to the next larger one
+    pub upsample: RenderPipeline,
+    /// Draws the source with the glow added to the destination
+    pub composite_manager: AssetId<RenderPipelineManager>,
+    /// `input`, `input_sampler` and `params`
+    pub bind_group_layout: BindGroupLayout,
+    /// Like `bind_group_layout`, with the glow as `bloom`
+    pub composite_bind_group_layout: BindGroupLayout,
+}
+
+/// Adds a glow around the parts of an HDR source brighter than the [BloomSettings] threshold, drawing the result to the destination.
+/// The bright parts are downsampled through a chain of half resolution levels and upsampled back with filtering, so one operation does the whole effect.
+/// The chain is an `Rgba16Float` texture owned by the operation and recreated when the source size changes.
+/// Like with [BlitOperation](crate::BlitOperation), the source needs [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage and a filterable format.
+pub struct BloomOperation {
+    pub source: RenderTargetSource,
+    pub destination: RenderTargetSource,
+}
+
+impl BloomOperation {
+    pub fn new(source: RenderTargetSource, destination: RenderTargetSource) -> Self {
+        Self {
+            source,
+            destination,
+        }
+    }
+}
+
+impl OperationBuilder for BloomOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        vec![self.source]
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.destination]
+    }
+
+    fn finish(self, _world: &World, device: &Device) -> impl Operation + 'static {
+        PreparedBloom {
+            source: self.source,
+            destination: self.destination,
+            params: device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("Bloom params"),
+                contents: &[0; 16],
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }),
+            chain: None,
+        }
+    }
+}
+
+/// The mip chain of a [BloomOperation]
+struct BloomChain {
+    /// the size of the source it was created for
+    source_size: (u32, u32),
+    _texture: Texture,
+    /// one view per mip level
+    views: Vec<TextureView>,
+    /// bind groups reading each level
+    bind_groups: Vec<BindGroup>,
+}
+
+struct PreparedBloom {
+    source: RenderTargetSource,
+    destination: RenderTargetSource,
+    params: Buffer,
+    chain: Option<BloomChain>,
+}
+
+impl PreparedBloom {
+    /// Recreates the chain if the source size or level count changed
+    fn update_chain(
+        &mut self,
+        device: &Device,
+        bloom: &BloomPipeline,
+        sampler: &Sampler,
+        source_size: (u32, u32),
+        max_mip_levels: u32,
+    ) {
+        let (width, height) = ((source_size.0 / 2).max(1), (source_size.1 / 2).max(1));
+        let full_chain = u32::BITS - width.min(height).leading_zeros();
+        let mip_levels = max_mip_levels.clamp(1, full_chain);
+        let outdated = self.chain.as_ref().is_none_or(|chain| {
+            chain.source_size != source_size || chain.views.len() != mip_levels as usize
+        });
+        if outdated {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: Some("Bloom chain"),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: mip_levels,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: BLOOM_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let views: Vec<_> = (0..mip_levels)
+                .map(|mip_level| {
+                    texture.create_view(&TextureViewDescriptor {
+                        label: Some("Bloom chain level"),
+                        base_mip_level: mip_level,
+                        mip_level_count: Some(1),
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            let bind_groups = views
+                .iter()
+                .map(|view| {
+                    bind_group(
+                        device,
+                        &bloom.bind_group_layout,
+                        view,
+                        sampler,
+                        &self.params,
+                    )
+                })
+                .collect();
+            self.chain = Some(BloomChain {
+                source_size,
+                _texture: texture,
+                views,
+                bind_groups,
+            });
+        }
+    }
+}
+
+fn bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    input: &TextureView,
+    sampler: &Sampler,
+    params: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Bloom bind group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(input),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: params.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// Draws a fullscreen triangle to a level of the chain
+fn chain_pass(
+    command_encoder: &mut CommandEncoder,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    destination: &TextureView,
+    load: LoadOp<Color>,
+) {
+    let mut pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Bloom pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: destination,
+            depth_slice: None,
+            resolve_target: None,
+            ops: Operations {
+                load,
+                store: StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+        multiview_mask: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+impl Operation for PreparedBloom {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        resolve_for_reading(self.source, world, command_encoder);
+        let Some((view, source_size)) = self
+            .source
+            .get(world)
+            .and_then(|rt| Some((rt.texture_view()?.clone(), rt.size())))
+        else {
+            return Ok(());
+        };
+        let settings = *world.resource::<BloomSettings>();
+        let ctx = world.resource::<RenderContext>();
+        let device = ctx.device.clone();
+        ctx.queue.write_buffer(
+            &self.params,
+            0,
+            bytemuck::cast_slice(&[settings.threshold, settings.knee, settings.intensity, 0.0]),
+        );
+        let sampler = world
+            .resource_mut::<SamplerCache>()
+            .get(
+                &device,
+                &SamplerDescriptor {
+                    label: Some("Bloom sampler"),
+                    mag_filter: FilterMode::Linear,
+                    min_filter: FilterMode::Linear,
+                    ..Default::default()
+                },
+            )
+            .clone();
+        let bloom = world.resource::<BloomPipeline>();
+        let source_bind_group = bind_group(
+            &device,
+            &bloom.bind_group_layout,
+            &view,
+            &sampler,
+            &self.params,
+        );
+        self.update_chain(
+            &device,
+            bloom,
+            &sampler,
+            source_size,
+            settings.max_mip_levels,
+        );
+        let chain = self.chain.as_ref().unwrap();
+        chain_pass(
+            command_encoder,
+            &bloom.prefilter,
+            &source_bind_group,
+            &chain.views[0],
+            LoadOp::Clear(Color::BLACK),
+        );
+        for level in 1..chain.views.len() {
+            chain_pass(
+                command_encoder,
+                &bloom.downsample,
+                &chain.bind_groups[level - 1],
+                &chain.views[level],
+                LoadOp::Clear(Color::BLACK),
+            );
+        }
+        for level in (1..chain.views.len()).rev() {
+            chain_pass(
+                command_encoder,
+                &bloom.upsample,
+                &chain.bind_groups[level],
+                &chain.views[level - 1],
+                LoadOp::Load,
+            );
+        }
+        let composite_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Bloom composite bind group"),
+            layout: &bloom.composite_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self.params.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&chain.views[0]),
+                },
+            ],
+        });
+        let manager = bloom.composite_manager;
+        world.asset_scope(manager, |world, manager| {
+            let Some(pipeline) = manager.get_compatible(self.destination, world) else {
+                return;
+            };
+            let Some(mut rt) = self.destination.get_mut(world) else {
+                return;
+            };
+            let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+                return;
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &composite_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        });
+        Ok(())
+    }
+}
+
+fn create_bind_group_layout(device: &Device, label: &str, composite: bool) -> BindGroupLayout {
+    let texture = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+    let mut entries = vec![
+        texture(0),
+        BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+    if composite {
+        entries.push(texture(3));
+    }
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &entries,
+    })
+}
+
+pub(crate) fn init_bloom_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    let device = &ctx.device;
+    let bind_group_layout = create_bind_group_layout(device, "Bloom BGLayout", false);
+    let composite_bind_group_layout =
+        create_bind_group_layout(device, "Bloom composite BGLayout", true);
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("Bloom shader"),
+        source: ShaderSource::Wgsl(
+            concat!(include_str!("fullscreen.wgsl"), include_str!("bloom.wgsl")).into(),
+        ),
+    });
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Bloom pipeline layout"),
+        bind_group_layouts: &[Some(&bind_group_layout)],
+        immediate_size: 0,
+    });
+    let chain_pipeline = |entry_point: &str, blend: Option<BlendState>| {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Bloom pipeline"),
+            layout: Some(&layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                targets: &[Some(ColorTargetState {
+                    format: BLOOM_FORMAT,
+                    blend,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview_mask: None,
+            cache: None,
+        })
+    };
+    let additive = BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    };
+    let prefilter = chain_pipeline("fs_prefilter", None);
+    let downsample = chain_pipeline("fs_downsample", None);
+    let upsample = chain_pipeline(
+        "fs_upsample",
+        Some(BlendState {
+            color: additive,
+            alpha: additive,
+        }),
+    );
+
+    let composite_layout = layouts.add(device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Bloom composite pipeline layout"),
+        bind_group_layouts: &[Some(&composite_bind_group_layout)],
+        immediate_size: 0,
+    }));
+    let shader = shaders.add(shader);
+    let composite_manager = managers.add(RenderPipelineManager::new(
+        GenericRenderPipelineDescriptor {
+            resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                layout: composite_layout,
+                vertex_shader_module: shader,
+                fragment_shader_module: shader,
+            }),
+            label: Some("Bloom composite pipeline".to_string()),
+            vertex_state: GenericVertexState {
+                entry_point: "vs_main".to_string(),
+                buffers: vec![],
+            },
+            primitive: PrimitiveState::default(),
+            // ignoring depth, but still needed for destinations with a depth buffer
+            depth_stencil: Some(GenericDepthStencilState {
+                depth_write_enable: false,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: GenericMultisampleState {
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(GenericFragmentState {
+                entry_point: "fs_composite".to_string(),
+                targets: vec![GenericColorTargetState::new(None)],
+            }),
+        },
+    ));
+    commands.insert_resource(BloomPipeline {
+        prefilter,
+        downsample,
+        upsample,
+        composite_manager,
+        bind_group_layout,
+        composite_bind_group_layout,
+    });
+}
@@ -0,0 +1,76 @@
+struct BloomParams {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+}
+
+@group(0) @binding(0)
+var input: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: BloomParams;
+// only used by the composite
+@group(0) @binding(3)
+var bloom: texture_2d<f32>;
+
+fn sample_offset(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec3<f32> {
+    return textureSample(input, input_sampler, uv + texel * vec2<f32>(x, y)).rgb;
+}
+
+// 13 tap filter halving the resolution, from "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(input));
+    let a = sample_offset(uv, texel, -2.0, 2.0);
+    let b = sample_offset(uv, texel, 0.0, 2.0);
+    let c = sample_offset(uv, texel, 2.0, 2.0);
+    let d = sample_offset(uv, texel, -2.0, 0.0);
+    let e = sample_offset(uv, texel, 0.0, 0.0);
+    let f = sample_offset(uv, texel, 2.0, 0.0);
+    let g = sample_offset(uv, texel, -2.0, -2.0);
+    let h = sample_offset(uv, texel, 0.0, -2.0);
+    let i = sample_offset(uv, texel, 2.0, -2.0);
+    let j = sample_offset(uv, texel, -1.0, 1.0);
+    let k = sample_offset(uv, texel, 1.0, 1.0);
+    let l = sample_offset(uv, texel, -1.0, -1.0);
+    let m = sample_offset(uv, texel, 1.0, -1.0);
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// keeps the part of the color above the threshold, with a smooth transition of `knee`
+fn threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+    soft = soft * soft / (4.0 * params.knee + 0.0001);
+    let contribution = max(soft, brightness - params.threshold) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(threshold(max(downsample(in.uv), vec3<f32>(0.0))), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter, added to the next larger level
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(input));
+    let sum = sample_offset(in.uv, texel, 0.0, 0.0) * 4.0
+        + (sample_offset(in.uv, texel, 0.0, 1.0) + sample_offset(in.uv, texel, -1.0, 0.0)
+            + sample_offset(in.uv, texel, 1.0, 0.0) + sample_offset(in.uv, texel, 0.0, -1.0)) * 2.0
+        + sample_offset(in.uv, texel, -1.0, 1.0) + sample_offset(in.uv, texel, 1.0, 1.0)
+        + sample_offset(in.uv, texel, -1.0, -1.0) + sample_offset(in.uv, texel, 1.0, -1.0);
+    return vec4<f32>(sum / 16.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(input, input_sampler, in.uv);
+    let glow = textureSample(bloom, input_sampler, in.uv).rgb;
+    return vec4<f32>(color.rgb + glow * params.intensity, color.a);
+}
@@ -0,0 +1,143 @@
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use std::iter;
+use wgpu::{
+    CommandEncoder, Device, RenderBundle, RenderBundleDepthStencil, RenderBundleDescriptor,
+    RenderBundleEncoder, RenderBundleEncoderDescriptor,
+};
+
+use crate::{
+    Operation, OperationBuilder, OperationError, PipelineParameters, RenderTargetSource, ShaderDefs,
+};
+
+/// Records draws into a [RenderBundle] for a [RenderBundleOperation]
+pub trait RenderBundleRecorder: Send + Sync + 'static {
+    /// Called before recording, used to create the pipelines for the parameters of the render target
+    fn prepare(&mut self, world: &mut World, params: &PipelineParameters);
+    /// Records the draws, resources used must live as long as the recorder or the world
+    fn record<'a>(
+        &'a self,
+        world: &'a World,
+        params: &PipelineParameters,
+        encoder: &mut RenderBundleEncoder<'a>,
+    );
+    /// If true the bundle is recorded again before it is executed, by default it is only recorded when the render target format changes
+    fn changed(&self, _world: &World) -> bool {
+        false
+    }
+}
+
+/// Executes a [RenderBundle] on a render target, recording it once instead of encoding the draws every frame.
+/// The bundle is recorded again when the format or sample count of the target changes, or when [changed](RenderBundleRecorder::changed) returns true.
+pub struct RenderBundleOperation<R> {
+    pub render_target: RenderTargetSource,
+    pub recorder: R,
+}
+
+impl<R: RenderBundleRecorder> RenderBundleOperation<R> {
+    pub fn new(render_target: RenderTargetSource, recorder: R) -> Self {
+        Self {
+            render_target,
+            recorder,
+        }
+    }
+
+    fn params(&self, world: &World) -> Option<PipelineParameters> {
+        let rt = self.render_target.get(world)?;
+        let color_formats = rt.color_formats();
+        let depth_stencil_format = rt.depth_stencil().map(|t| t.format());
+        if color_formats.is_empty() && depth_stencil_format.is_none() {
+            return None;
+        }
+        Some(PipelineParameters {
+            color_formats,
+            depth_stencil_format,
+            sample_count: rt.sample_count(),
+            shader_defs: ShaderDefs::default(),
+        })
+    }
+}
+
+impl<R: RenderBundleRecorder> OperationBuilder for RenderBundleOperation<R> {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        PreparedRenderBundle {
+            operation: self,
+            bundle: None,
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("RenderBundleOperation({})", super::short_type_name::<R>())
+    }
+}
+
+struct PreparedRenderBundle<R> {
+    operation: RenderBundleOperation<R>,
+    /// The bundle with the parameters it was recorded for
+    bundle: Option<(PipelineParameters, RenderBundle)>,
+}
+
+impl<R: RenderBundleRecorder> PreparedRenderBundle<R> {
+    fn record(&mut self, world: &mut World, params: PipelineParameters) {
+        let recorder = &mut self.operation.recorder;
+        recorder.prepare(world, &params);
+        let device = world.resource::<RenderContext>().device.clone();
+        let color_formats: Vec<_> = params.color_formats.iter().copied().map(Some).collect();
+        let mut encoder = device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
+            label: Some("Render bundle encoder"),
+            color_formats: &color_formats,
+            // render target passes always write depth and stencil
+            depth_stencil: params
+                .depth_stencil_format
+                .map(|format| RenderBundleDepthStencil {
+                    format,
+                    depth_read_only: false,
+                    stencil_read_only: false,
+                }),
+            sample_count: params.sample_count,
+            multiview: None,
+        });
+        recorder.record(world, &params, &mut encoder);
+        let bundle = encoder.finish(&RenderBundleDescriptor {
+            label: Some("Render bundle"),
+        });
+        self.bundle = Some((params, bundle));
+    }
+}
+
+impl<R: RenderBundleRecorder> Operation for PreparedRenderBundle<R> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(params) = self.operation.params(world) else {
+            return Ok(());
+        };
+        let outdated = match &self.bundle {
+            Some((recorded, _)) => *recorded != params || self.operation.recorder.changed(world),
+            None => true,
+        };
+        if outdated {
+            self.record(world, params);
+        }
+        let Some(mut rt) = self.operation.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        if let Some((_, bundle)) = &self.bundle {
+            pass.execute_bundles(iter::once(bundle));
+        }
+        Ok(())
+    }
+}
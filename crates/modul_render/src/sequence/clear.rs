@@ -0,0 +1,155 @@
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use wgpu::{
+    BlendComponent, BlendFactor, BlendOperation, BlendState, Color, CommandEncoder,
+    CompareFunction, DepthBiasState, Device, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState,
+};
+
+use crate::{
+    DirectRenderPipelineResourceProvider, GenericColorTargetState, GenericDepthStencilState,
+    GenericFragmentState, GenericMultisampleState, GenericRenderPipelineDescriptor,
+    GenericVertexState, Operation, OperationBuilder, OperationError, RenderPipelineManager,
+    RenderTargetSource, ScissorRect,
+};
+
+/// The shared pipelines of [ClearRegion] and [BeginViewport](crate::BeginViewport) operations, inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct ClearRegionPipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+    /// Also writes the depth of the fullscreen triangle, which is set with the depth range of the viewport
+    pub color_depth_manager: AssetId<RenderPipelineManager>,
+    /// Writes the depth and keeps the color
+    pub depth_manager: AssetId<RenderPipelineManager>,
+}
+
+/// Clears only a region of the color texture of a target, by drawing a scissored quad.
+/// A regular clear always clears the whole texture, this is useful for damage-region rendering where that would waste bandwidth.
+/// The color is drawn using the blend constant, so the format has to be blendable. Depth/stencil are not changed.
+pub struct ClearRegion {
+    pub render_target: RenderTargetSource,
+    /// The region in pixels, clamped to the target
+    pub region: ScissorRect,
+    pub color: Color,
+}
+
+impl Operation for ClearRegion {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let manager = world.resource::<ClearRegionPipeline>().manager;
+        world.asset_scope(manager, |world, manager| {
+            let Some(pipeline) = manager.get_compatible(self.render_target, world) else {
+                return;
+            };
+            let Some(mut rt) = self.render_target.get_mut(world) else {
+                return;
+            };
+            let size = rt.size();
+            let Some(region) = self.region.clamped(size) else {
+                return;
+            };
+            let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+                return;
+            };
+            // overriding the viewport and scissor rect of the target, the triangle has to cover the region
+            pass.set_viewport(0.0, 0.0, size.0 as f32, size.1 as f32, 0.0, 1.0);
+            pass.set_scissor_rect(region.x, region.y, region.width, region.height);
+            pass.set_pipeline(pipeline);
+            pass.set_blend_constant(self.color);
+            pass.draw(0..3, 0..1);
+        });
+        Ok(())
+    }
+}
+
+impl OperationBuilder for ClearRegion {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+pub(crate) fn init_clear_region_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    let shader = shaders.add(ctx.device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("Clear region shader"),
+        source: ShaderSource::Wgsl(
+            concat!(include_str!("fullscreen.wgsl"), include_str!("clear.wgsl")).into(),
+        ),
+    }));
+    let layout = layouts.add(
+        ctx.device
+            .create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Clear region pipeline layout"),
+                bind_group_layouts: &[],
+                immediate_size: 0,
+            }),
+    );
+    // the output is 1, so this results in the blend constant
+    let constant = BlendComponent {
+        src_factor: BlendFactor::Constant,
+        dst_factor: BlendFactor::Zero,
+        operation: BlendOperation::Add,
+    };
+    let keep = BlendComponent {
+        src_factor: BlendFactor::Zero,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    };
+    let mut add_manager = |label: &str, blend: BlendComponent, depth_write_enable: bool| {
+        managers.add(RenderPipelineManager::new(
+            GenericRenderPipelineDescriptor {
+                resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                    layout,
+                    vertex_shader_module: shader,
+                    fragment_shader_module: shader,
+                }),
+                label: Some(label.to_string()),
+                vertex_state: GenericVertexState {
+                    entry_point: "vs_main".to_string(),
+                    buffers: vec![],
+                },
+                primitive: PrimitiveState::default(),
+                // still needed for targets with a depth buffer when depth is ignored
+                depth_stencil: Some(GenericDepthStencilState {
+                    depth_write_enable,
+                    depth_compare: CompareFunction::Always,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: GenericMultisampleState {
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: Some(GenericFragmentState {
+                    entry_point: "fs_main".to_string(),
+                    targets: vec![GenericColorTargetState::new(Some(BlendState {
+                        color: blend,
+                        alpha: blend,
+                    }))],
+                }),
+            },
+        ))
+    };
+    commands.insert_resource(ClearRegionPipeline {
+        manager: add_manager("Clear region pipeline", constant, false),
+        color_depth_manager: add_manager("Clear region depth pipeline", constant, true),
+        depth_manager: add_manager("Clear region depth only pipeline", keep, true),
+    });
+}
@@ -0,0 +1,5 @@
+// the color comes from the blend constant, so no bind groups are needed
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
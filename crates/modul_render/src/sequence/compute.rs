@@ -0,0 +1,37 @@
+use bevy_ecs::prelude::*;
+use wgpu::{CommandEncoder, ComputePass, ComputePassDescriptor, Device};
+
+use crate::{OperationError, RenderTargetSource};
+
+/// Like [OperationBuilder](crate::OperationBuilder), but for operations that dispatch compute work.
+/// Added to a sequence with [add_compute](crate::SequenceBuilder::add_compute).
+pub trait ComputeOperationBuilder: Send + Sync + 'static {
+    /// Multisampled render targets read here are resolved before the operation runs.
+    /// Buffers and other textures do not need to be declared, as wgpu inserts barriers between dispatches itself.
+    fn reading(&self) -> Vec<RenderTargetSource>;
+    /// Used by the sequence to determine when to resolve rendertargets
+    fn writing(&self) -> Vec<RenderTargetSource>;
+    fn finish(self, world: &World, device: &Device) -> impl ComputeOperation + 'static;
+    /// Used to identify the operation in [GpuTimings](crate::GpuTimings) and as its debug group, the type name by default
+    fn label(&self) -> String {
+        super::short_type_name::<Self>()
+    }
+}
+
+/// Compute operations next to each other in a sequence share one [ComputePass]
+pub trait ComputeOperation: Send + Sync {
+    /// An error skips the rest of the frame, see [Operation::run](crate::Operation::run)
+    fn run(
+        &mut self,
+        world: &mut World,
+        compute_pass: &mut ComputePass,
+    ) -> Result<(), OperationError>;
+}
+
+/// Begins the compute pass used by sequences, can be used by [Operations](crate::Operation) that mix compute and render passes
+pub fn begin_compute_pass(command_encoder: &mut CommandEncoder) -> ComputePass<'_> {
+    command_encoder.begin_compute_pass(&ComputePassDescriptor {
+        label: Some("Sequence compute pass"),
+        timestamp_writes: None,
+    })
+}
@@ -0,0 +1,120 @@
+use bevy_ecs::prelude::*;
+use modul_asset::Assets;
+use modul_core::RenderContext;
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use wgpu::{CommandEncoderDescriptor, Device, PollType, SubmissionIndex};
+
+use super::encode_sequences;
+use crate::{
+    record_submission, GpuErrorScope, Sequence, SequenceErrors, SequenceQueue, StagingUploads,
+    TextureReadbacks,
+};
+
+/// [SystemSet] within [Draw](crate::Draw) that submits the [ComputeSequenceQueue], it runs before [SequenceRunnerSet](crate::SequenceRunnerSet)
+#[derive(SystemSet, Clone, Hash, PartialEq, Eq, Debug)]
+pub struct ComputeSequenceRunnerSet;
+
+/// Sequences submitted on their own before the [RunningSequenceQueue](crate::RunningSequenceQueue), meant for compute only sequences.
+/// wgpu only exposes one queue, so the work is ordered before the render sequences on the GPU,
+/// but the CPU can track it separately with the [ComputeFence].
+#[derive(Resource)]
+pub struct ComputeSequenceQueue(pub SequenceQueue);
+
+impl SequenceQueue {
+    /// Makes the queue a [ComputeSequenceQueue], to be submitted separately
+    pub fn compute(self) -> ComputeSequenceQueue {
+        ComputeSequenceQueue(self)
+    }
+}
+
+/// Tracks the last submission of the [ComputeSequenceQueue]
+#[derive(Resource)]
+pub struct ComputeFence {
+    submission: Option<SubmissionIndex>,
+    done: Arc<AtomicBool>,
+}
+
+impl Default for ComputeFence {
+    fn default() -> Self {
+        Self {
+            submission: None,
+            done: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl ComputeFence {
+    /// True once the GPU finished the last submission, this is updated when the device is polled
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the last submission is finished
+    pub fn wait(&self, device: &Device) {
+        if let Some(submission) = &self.submission {
+            let _ = device.poll(PollType::Wait {
+                submission_index: Some(submission.clone()),
+                timeout: None,
+            });
+        }
+    }
+
+    pub fn submission(&self) -> Option<&SubmissionIndex> {
+        self.submission.as_ref()
+    }
+}
+
+pub(crate) fn run_compute_sequences(world: &mut World) {
+    let Some(queue) = world
+        .get_resource::<ComputeSequenceQueue>()
+        .map(|q| q.0 .0.clone())
+    else {
+        return;
+    };
+    if queue.is_empty() {
+        return;
+    }
+    // uploads are submitted first, so the sequences see them
+    let uploads = world
+        .get_resource_mut::<StagingUploads>()
+        .and_then(|mut s| s.finish());
+    let start = Instant::now();
+    let (command_buffer, scope) =
+        world.resource_scope(|world, mut sequence_assets: Mut<Assets<Sequence>>| {
+            let mut command_encoder = world
+                .resource::<RenderContext>()
+                .device
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Compute sequence encoder"),
+                });
+            // errors of recording passes are reported when the encoder is finished
+            let scope = GpuErrorScope::push(&world.resource::<RenderContext>().device);
+            if let Err(error) =
+                encode_sequences(&queue, &mut command_encoder, world, &mut sequence_assets)
+            {
+                world.resource_mut::<SequenceErrors>().push(error);
+            }
+            (command_encoder.finish(), scope)
+        });
+    let encoded = Instant::now();
+    let ctx = world.resource::<RenderContext>();
+    let submission = ctx
+        .queue
+        .submit(uploads.into_iter().chain(iter::once(command_buffer)));
+    record_submission(world, encoded - start, encoded.elapsed());
+    scope.pop(world, || "Compute sequence encoder".to_string());
+    let ctx = world.resource::<RenderContext>();
+    let done = Arc::new(AtomicBool::new(false));
+    let callback_done = done.clone();
+    ctx.queue.on_submitted_work_done(move || {
+        callback_done.store(true, Ordering::Release);
+    });
+    world.insert_resource(ComputeFence {
+        submission: Some(submission),
+        done,
+    });
+    world.resource_mut::<TextureReadbacks>().map_copied();
+}
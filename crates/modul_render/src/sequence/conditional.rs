@@ -0,0 +1,96 @@
+use bevy_ecs::prelude::*;
+use wgpu::{CommandEncoder, ComputePass, Device};
+
+use crate::{
+    ComputeOperation, ComputeOperationBuilder, Operation, OperationBuilder, OperationError,
+    RenderTargetSource,
+};
+
+/// Decides if a conditional operation runs, checked every time the sequence runs
+pub type SequenceCondition = Box<dyn Fn(&World) -> bool + Send + Sync>;
+
+/// Condition for [add_if](crate::SequenceBuilder::add_if) that is true while the resource exists
+pub fn has_resource<R: Resource>() -> impl Fn(&World) -> bool + Send + Sync + 'static {
+    |world: &World| world.contains_resource::<R>()
+}
+
+/// Wraps an [OperationBuilder] so the operation only runs while the condition is true.
+/// Usually created with [add_if](crate::SequenceBuilder::add_if).
+pub struct ConditionalOperationBuilder<B> {
+    pub builder: B,
+    pub condition: SequenceCondition,
+}
+
+// reading and writing are kept even when skipped, so the resolves of the sequence do not change at runtime
+impl<B: OperationBuilder> OperationBuilder for ConditionalOperationBuilder<B> {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        self.builder.reading()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        self.builder.writing()
+    }
+
+    fn label(&self) -> String {
+        self.builder.label()
+    }
+
+    fn finish(self, world: &World, device: &Device) -> impl Operation + 'static {
+        ConditionalOperation {
+            operation: self.builder.finish(world, device),
+            condition: self.condition,
+        }
+    }
+}
+
+impl<B: ComputeOperationBuilder> ComputeOperationBuilder for ConditionalOperationBuilder<B> {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        self.builder.reading()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        self.builder.writing()
+    }
+
+    fn label(&self) -> String {
+        self.builder.label()
+    }
+
+    fn finish(self, world: &World, device: &Device) -> impl ComputeOperation + 'static {
+        ConditionalOperation {
+            operation: self.builder.finish(world, device),
+            condition: self.condition,
+        }
+    }
+}
+
+struct ConditionalOperation<O> {
+    operation: O,
+    condition: SequenceCondition,
+}
+
+impl<O: Operation> Operation for ConditionalOperation<O> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        if (self.condition)(world) {
+            self.operation.run(world, command_encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl<O: ComputeOperation> ComputeOperation for ConditionalOperation<O> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        compute_pass: &mut ComputePass,
+    ) -> Result<(), OperationError> {
+        if (self.condition)(world) {
+            self.operation.run(world, compute_pass)?;
+        }
+        Ok(())
+    }
+}
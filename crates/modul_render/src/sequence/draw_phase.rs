@@ -0,0 +1,109 @@
+use bevy_ecs::prelude::*;
+use modul_asset::AssetId;
+use modul_core::RenderContext;
+use std::ops::Range;
+use wgpu::{BufferUsages, CommandEncoder, Device};
+
+use crate::{
+    camera_bind_group, DrawPhase, GpuVec, Material, MaterialDraw, Mesh, Operation,
+    OperationBuilder, OperationError, RenderTargetSource, ViewPhases,
+};
+
+/// An entity drawn by the [DrawPhaseOperations](DrawPhaseOperation) of the material type, in the [DrawPhase] of the entity
+#[derive(Component, Clone, Copy, Debug)]
+pub struct MaterialMesh<M: Material> {
+    pub mesh: AssetId<Mesh>,
+    pub material: AssetId<M>,
+    /// The instance data of the entity, like its model matrix
+    pub instance: M::Instance,
+}
+
+/// Draws the [MaterialMesh] entities of a phase in the order of the [ViewPhases] of the [Camera](crate::Camera) entity, in one pass.
+/// Consecutive entities with the same mesh and material are drawn as one instanced draw, the instances are uploaded every run.
+/// Entities without a [MaterialMesh] of this material type are skipped, so one operation per material type and phase is needed.
+pub struct DrawPhaseOperation<M: Material> {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+    pub phase: DrawPhase,
+    instances: GpuVec<M::Instance>,
+}
+
+impl<M: Material> DrawPhaseOperation<M> {
+    pub fn new(render_target: RenderTargetSource, camera: Entity, phase: DrawPhase) -> Self {
+        Self {
+            render_target,
+            camera,
+            phase,
+            instances: GpuVec::new(BufferUsages::VERTEX),
+        }
+    }
+}
+
+impl<M: Material> Operation for DrawPhaseOperation<M> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let camera_bind_group = camera_bind_group(world, self.camera)?;
+        let Some(phases) = world.get::<ViewPhases>(self.camera) else {
+            return Err(format!("{} has no ViewPhases", self.camera).into());
+        };
+        let mut batches: Vec<(AssetId<Mesh>, AssetId<M>, Range<u32>)> = Vec::new();
+        self.instances.clear();
+        for item in phases.get(self.phase) {
+            let Some(material_mesh) = world.get::<MaterialMesh<M>>(item.entity) else {
+                continue;
+            };
+            let index = self.instances.len() as u32;
+            self.instances.push(material_mesh.instance);
+            match batches.last_mut() {
+                Some((mesh, material, range))
+                    if *mesh == material_mesh.mesh && *material == material_mesh.material =>
+                {
+                    range.end += 1;
+                }
+                _ => batches.push((material_mesh.mesh, material_mesh.material, index..index + 1)),
+            }
+        }
+        if batches.is_empty() {
+            return Ok(());
+        }
+        let ctx = world.resource::<RenderContext>();
+        self.instances.flush(&ctx.device, &ctx.queue);
+        let Some(instances) = self.instances.buffer().cloned() else {
+            return Ok(());
+        };
+        let mut draws = Vec::with_capacity(batches.len());
+        for (mesh, material, range) in batches {
+            if let Some(draw) = MaterialDraw::prepare(world, self.render_target, mesh, material)? {
+                draws.push((draw, range));
+            }
+        }
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_bind_group(0, &camera_bind_group, &[]);
+        for (draw, range) in draws {
+            draw.record(&mut pass, &instances, range);
+        }
+        Ok(())
+    }
+}
+
+impl<M: Material> OperationBuilder for DrawPhaseOperation<M> {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
@@ -0,0 +1,71 @@
+use bevy_ecs::prelude::*;
+use modul_asset::AssetId;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use crate::Sequence;
+
+/// Returned by [Operation::run](crate::Operation::run) and [ComputeOperation::run](crate::ComputeOperation::run), any error can be converted with `?`
+pub type OperationError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum SequenceError {
+    /// A sequence in a queue does not exist
+    MissingSequence(AssetId<Sequence>),
+    /// An operation of the sequence failed
+    Operation {
+        sequence: AssetId<Sequence>,
+        /// The [label](crate::OperationBuilder::label) of the operation
+        label: String,
+        error: OperationError,
+    },
+}
+
+impl Error for SequenceError {}
+
+impl Display for SequenceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::MissingSequence(id) => {
+                write!(
+                    f,
+                    "Sequence {:?} was added to a queue, but does not exist",
+                    id
+                )
+            }
+            SequenceError::Operation {
+                sequence,
+                label,
+                error,
+            } => write!(
+                f,
+                "Operation {} of sequence {:?} failed: {}",
+                label, sequence, error
+            ),
+        }
+    }
+}
+
+/// Errors of the sequences run in the last frame, when a sequence fails the rest of the frame is skipped.
+/// The commands recorded before the error are still submitted.
+#[derive(Resource, Default)]
+pub struct SequenceErrors(Vec<SequenceError>);
+
+impl SequenceErrors {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SequenceError> {
+        self.0.iter()
+    }
+
+    pub(crate) fn push(&mut self, error: SequenceError) {
+        log::error!("{}", error);
+        self.0.push(error);
+    }
+}
+
+pub(crate) fn clear_sequence_errors(mut errors: ResMut<SequenceErrors>) {
+    errors.0.clear();
+}
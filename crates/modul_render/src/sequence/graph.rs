@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use modul_asset::{AssetId, Assets};
+use modul_util::{HashMap, HashSet};
+
+use super::{
+    DynComputeOperationBuilderImpl, DynOperationBuilder, DynOperationBuilderImpl, SequenceEntry,
+};
+use crate::{ComputeOperationBuilder, OperationBuilder, Sequence, SequenceBuilder};
+
+#[derive(Debug)]
+pub enum RenderGraphError {
+    /// The nodes depend on each other, contains their indices in the order they were added
+    Cycle(Vec<usize>),
+    /// A resource was marked as output, but no node writes it
+    MissingOutput(String),
+    /// No resource was marked with [mark_output](RenderGraphBuilder::mark_output), so every node would be culled
+    NoOutputs,
+}
+
+impl Error for RenderGraphError {}
+
+impl Display for RenderGraphError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderGraphError::Cycle(nodes) => {
+                write!(f, "Render graph cycle between nodes {:?}", nodes)
+            }
+            RenderGraphError::MissingOutput(name) => {
+                write!(f, "Render graph output {} is not written by any node", name)
+            }
+            RenderGraphError::NoOutputs => write!(f, "Render graph has no outputs"),
+        }
+    }
+}
+
+struct GraphNode {
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    builder: Box<dyn DynOperationBuilder>,
+}
+
+/// Builds a [Sequence] from operations that declare named inputs and outputs, instead of relying on the order they are added in.
+/// Names are only used for ordering, so they can refer to any texture, buffer or render target.
+///
+/// Nodes reading a resource run after every node writing it, and nodes writing the same resource run in the order they were added.
+/// Nodes that do not contribute to a resource marked with [mark_output](Self::mark_output) are culled.
+/// Resolves are inserted by the [Sequence] as usual, based on the [RenderTargetSources](crate::RenderTargetSource) the operations read and write.
+#[derive(Default)]
+pub struct RenderGraphBuilder {
+    nodes: Vec<GraphNode>,
+    outputs: Vec<String>,
+}
+
+impl RenderGraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a render operation reading the `inputs` and writing the `outputs`
+    pub fn add(
+        &mut self,
+        inputs: &[&str],
+        outputs: &[&str],
+        operation_builder: impl OperationBuilder,
+    ) -> &mut Self {
+        self.add_node(
+            inputs,
+            outputs,
+            Box::new(DynOperationBuilderImpl(Some(Box::new(operation_builder)))),
+        )
+    }
+
+    /// Adds a compute operation reading the `inputs` and writing the `outputs`
+    pub fn add_compute(
+        &mut self,
+        inputs: &[&str],
+        outputs: &[&str],
+        operation_builder: impl ComputeOperationBuilder,
+    ) -> &mut Self {
+        self.add_node(
+            inputs,
+            outputs,
+            Box::new(DynComputeOperationBuilderImpl(Some(Box::new(
+                operation_builder,
+            )))),
+        )
+    }
+
+    /// Marks a resource as a result of the graph, nodes are only kept if they contribute to an output
+    pub fn mark_output(&mut self, name: &str) -> &mut Self {
+        self.outputs.push(name.to_string());
+        self
+    }
+
+    /// Sorts and culls the nodes, returning a [SequenceBuilder] with the remaining operations
+    pub fn build(self) -> Result<SequenceBuilder, RenderGraphError> {
+        let kept = self.kept_nodes()?;
+        let order = self.sort(&kept)?;
+        let mut builders = self
+            .nodes
+            .into_iter()
+            .map(|n| Some(n.builder))
+            .collect::<Vec<_>>();
+        Ok(SequenceBuilder {
+            entries: order
+                .into_iter()
+                .map(|i| SequenceEntry::new(builders[i].take().unwrap()))
+                .collect(),
+            label: None,
+        })
+    }
+
+    /// [build](Self::build)s the graph and adds the sequence
+    pub fn finish(
+        self,
+        assets: &mut Assets<Sequence>,
+    ) -> Result<AssetId<Sequence>, RenderGraphError> {
+        Ok(self.build()?.finish(assets))
+    }
+
+    fn add_node(
+        &mut self,
+        inputs: &[&str],
+        outputs: &[&str],
+        builder: Box<dyn DynOperationBuilder>,
+    ) -> &mut Self {
+        self.nodes.push(GraphNode {
+            inputs: inputs.iter().map(|s| s.to_string()).collect(),
+            outputs: outputs.iter().map(|s| s.to_string()).collect(),
+            builder,
+        });
+        self
+    }
+
+    fn kept_nodes(&self) -> Result<Vec<bool>, RenderGraphError> {
+        if self.outputs.is_empty() {
+            return Err(RenderGraphError::NoOutputs);
+        }
+        for output in &self.outputs {
+            if !self.nodes.iter().any(|n| n.outputs.contains(output)) {
+                return Err(RenderGraphError::MissingOutput(output.clone()));
+            }
+        }
+        let mut needed = self
+            .outputs
+            .iter()
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        let mut kept = vec![false; self.nodes.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if !kept[i] && node.outputs.iter().any(|o| needed.contains(o.as_str())) {
+                    kept[i] = true;
+                    needed.extend(node.inputs.iter().map(String::as_str));
+                    changed = true;
+                }
+            }
+        }
+        Ok(kept)
+    }
+
+    fn sort(&self, kept: &[bool]) -> Result<Vec<usize>, RenderGraphError> {
+        let mut writers = HashMap::<&str, Vec<usize>>::new();
+        for (i, node) in self.nodes.iter().enumerate().filter(|(i, _)| kept[*i]) {
+            for output in &node.outputs {
+                writers.entry(output).or_default().push(i);
+            }
+        }
+        let mut dependents = vec![Vec::new(); self.nodes.len()];
+        let mut dependency_count = vec![0; self.nodes.len()];
+        let mut add_edge = |from: usize, to: usize| {
+            dependents[from].push(to);
+            dependency_count[to] += 1;
+        };
+        for writers in writers.values() {
+            for pair in writers.windows(2) {
+                add_edge(pair[0], pair[1]);
+            }
+        }
+        for (i, node) in self.nodes.iter().enumerate().filter(|(i, _)| kept[*i]) {
+            for input in &node.inputs {
+                // nodes writing what they read are ordered with the other writers
+                if node.outputs.contains(input) {
+                    continue;
+                }
+                for &writer in writers.get(input.as_str()).into_iter().flatten() {
+                    add_edge(writer, i);
+                }
+            }
+        }
+
+        // the lowest index is picked first, so independent nodes keep the order they were added in
+        let mut ready = (0..self.nodes.len())
+            .filter(|&i| kept[i] && dependency_count[i] == 0)
+            .collect::<BTreeSet<_>>();
+        let mut order = Vec::new();
+        while let Some(i) = ready.pop_first() {
+            order.push(i);
+            for &dependent in &dependents[i] {
+                dependency_count[dependent] -= 1;
+                if dependency_count[dependent] == 0 {
+                    ready.insert(dependent);
+                }
+            }
+        }
+        if order.len() < kept.iter().filter(|k| **k).count() {
+            return Err(RenderGraphError::Cycle(
+                (0..self.nodes.len())
+                    .filter(|&i| kept[i] && dependency_count[i] > 0)
+                    .collect(),
+            ));
+        }
+        Ok(order)
+    }
+}
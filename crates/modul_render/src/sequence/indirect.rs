@@ -0,0 +1,143 @@
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt};
+use wgpu::{BindGroup, Buffer, BufferAddress, CommandEncoder, Device};
+
+use crate::{
+    multi_draw_indirect_count, DrawIndexedIndirectArgs, DrawIndirectArgs, GpuVec, IndirectSupport,
+    Mesh, Operation, OperationBuilder, OperationError, RenderPipelineManager, RenderTargetSource,
+};
+
+/// Where an [IndirectDrawOperation] reads its draw arguments from
+pub enum IndirectArgs {
+    /// A buffer with `count` tightly packed args, [DrawIndexedIndirectArgs] if the mesh has indices, otherwise [DrawIndirectArgs]
+    Buffer {
+        buffer: Buffer,
+        offset: BufferAddress,
+        count: u32,
+    },
+    /// Draws every element of the vec, the mesh must not have indices
+    Draw(AssetId<GpuVec<DrawIndirectArgs>>),
+    /// Draws every element of the vec, the mesh must have indices
+    DrawIndexed(AssetId<GpuVec<DrawIndexedIndirectArgs>>),
+}
+
+/// Draws a [Mesh] with arguments from a buffer, usually written by a compute operation for GPU driven culling.
+/// The args buffer needs [INDIRECT](wgpu::BufferUsages::INDIRECT) usage, and [STORAGE](wgpu::BufferUsages::STORAGE) to be written by compute shaders.
+pub struct IndirectDrawOperation {
+    pub render_target: RenderTargetSource,
+    pub pipeline: AssetId<RenderPipelineManager>,
+    pub mesh: AssetId<Mesh>,
+    /// Set in order starting at group 0
+    pub bind_groups: Vec<BindGroup>,
+    pub args: IndirectArgs,
+    /// Buffer and offset of a u32 with the amount of draws, the args count is the max.
+    /// See [multi_draw_indirect_count] for when it is not supported.
+    pub count_buffer: Option<(Buffer, BufferAddress)>,
+}
+
+impl IndirectArgs {
+    fn get(&self, world: &World) -> Option<(Buffer, BufferAddress, u32)> {
+        match self {
+            IndirectArgs::Buffer {
+                buffer,
+                offset,
+                count,
+            } => Some((buffer.clone(), *offset, *count)),
+            IndirectArgs::Draw(id) => {
+                let vec = world.get_asset(*id)?;
+                Some((vec.buffer()?.clone(), 0, vec.len() as u32))
+            }
+            IndirectArgs::DrawIndexed(id) => {
+                let vec = world.get_asset(*id)?;
+                Some((vec.buffer()?.clone(), 0, vec.len() as u32))
+            }
+        }
+    }
+
+    /// If the args are indexed, None for buffers as their layout is not known
+    fn indexed(&self) -> Option<bool> {
+        match self {
+            IndirectArgs::Buffer { .. } => None,
+            IndirectArgs::Draw(_) => Some(false),
+            IndirectArgs::DrawIndexed(_) => Some(true),
+        }
+    }
+}
+
+impl Operation for IndirectDrawOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some((args_buffer, args_offset, count)) = self.args.get(world) else {
+            return Ok(());
+        };
+        if count == 0 {
+            return Ok(());
+        }
+        let Some(buffers) = world.get_asset(self.mesh).and_then(|mesh| {
+            mesh.buffers()
+                .map(|b| (b.vertex_buffer.clone(), b.index_buffer.clone()))
+        }) else {
+            return Ok(());
+        };
+        // the stride of the args depends on it, so a mismatch would read garbage
+        if let Some(indexed) = self.args.indexed() {
+            if indexed != buffers.1.is_some() {
+                return Err(match indexed {
+                    true => "DrawIndexed args need a mesh with indices",
+                    false => "Draw args need a mesh without indices",
+                }
+                .into());
+            }
+        }
+        let support = *world.resource::<IndirectSupport>();
+        let mut pipeline = None;
+        world.asset_scope(self.pipeline, |world, manager| {
+            pipeline = manager.get_compatible(self.render_target, world).cloned();
+        });
+        let Some(pipeline) = pipeline else {
+            return Ok(());
+        };
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_pipeline(&pipeline);
+        for (i, bind_group) in self.bind_groups.iter().enumerate() {
+            pass.set_bind_group(i as u32, bind_group, &[]);
+        }
+        let (vertex_buffer, index_buffer) = buffers;
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        if let Some((index_buffer, format)) = &index_buffer {
+            pass.set_index_buffer(index_buffer.slice(..), *format);
+        }
+        multi_draw_indirect_count(
+            &mut pass,
+            &support,
+            index_buffer.is_some(),
+            &args_buffer,
+            args_offset,
+            self.count_buffer.as_ref().map(|(b, offset)| (b, *offset)),
+            count,
+        );
+        Ok(())
+    }
+}
+
+impl OperationBuilder for IndirectDrawOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
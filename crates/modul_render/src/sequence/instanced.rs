@@ -0,0 +1,58 @@
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt};
+use wgpu::{CommandEncoder, Device};
+
+use crate::{
+    draw_material_mesh, GpuVec, Material, Mesh, Operation, OperationBuilder, OperationError,
+    RenderTargetSource,
+};
+
+/// Draws a [Mesh] with a [Material] once per element of a [GpuVec], as seen by the [Camera](crate::Camera) entity.
+/// The vec is bound as instance buffer after the mesh (see [MaterialManager](crate::MaterialManager)), so it needs [VERTEX](wgpu::BufferUsages::VERTEX) usage.
+/// Changes to the vec are uploaded during [GpuVecFlushSet](crate::GpuVecFlushSet) in [PreDraw](crate::PreDraw), which the [MaterialPlugin](crate::MaterialPlugin) registers for the instance type.
+pub struct DrawInstancedOperation<M: Material> {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+    pub mesh: AssetId<Mesh>,
+    pub material: AssetId<M>,
+    pub instances: AssetId<GpuVec<M::Instance>>,
+}
+
+impl<M: Material> Operation for DrawInstancedOperation<M> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some((buffer, count)) = world
+            .get_asset(self.instances)
+            .and_then(|vec| Some((vec.buffer()?.clone(), vec.len() as u32)))
+        else {
+            return Ok(());
+        };
+        draw_material_mesh(
+            world,
+            command_encoder,
+            self.render_target,
+            self.camera,
+            self.mesh,
+            self.material,
+            &buffer,
+            count,
+        )
+    }
+}
+
+impl<M: Material> OperationBuilder for DrawInstancedOperation<M> {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
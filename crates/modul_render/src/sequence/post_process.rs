@@ -0,0 +1,281 @@
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, UntypedAssetId};
+use modul_core::RenderContext;
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, CommandEncoder,
+    CompareFunction, DepthBiasState, Device, FilterMode, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureSampleType, TextureViewDimension,
+};
+
+use super::blit::resolve_for_reading;
+use crate::{
+    GenericColorTargetState, GenericDepthStencilState, GenericFragmentState,
+    GenericMultisampleState, GenericRenderPipelineDescriptor, GenericVertexState, Operation,
+    OperationBuilder, OperationError, RenderPipelineManager, RenderPipelineResourceProvider,
+    RenderTargetSource, SamplerCache, FULLSCREEN_TRIANGLE_WGSL,
+};
+
+/// The fragment shader of a [PostProcessOperation].
+/// The inputs are bound in group 0 as `input_0`, `input_1`, ... (`texture_2d<f32>`), followed by `input_sampler`.
+pub enum PostProcessShader {
+    /// WGSL defining `fn post_process(uv: vec2<f32>) -> vec4<f32>`, the bindings are declared automatically
+    Snippet(String),
+    /// A shader with a `fs_main` entry point taking `@location(0) uv: vec2<f32>`, it must declare the bindings itself.
+    /// The pipelines are rebuilt when the asset is modified.
+    Asset(AssetId<ShaderModule>),
+}
+
+/// Draws a fullscreen triangle to the destination with a fragment shader reading the input render targets.
+/// Pipelines are cached per destination format and sample count, and multisampled inputs are resolved first.
+/// Like with [BlitOperation](crate::BlitOperation), the inputs need [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage and filterable formats.
+pub struct PostProcessOperation {
+    pub shader: PostProcessShader,
+    pub inputs: Vec<RenderTargetSource>,
+    pub destination: RenderTargetSource,
+    pub filter: FilterMode,
+    /// Blend state used for the destination, None replaces the content
+    pub blend: Option<BlendState>,
+}
+
+impl PostProcessOperation {
+    /// Creates the operation with a [Snippet](PostProcessShader::Snippet), linear filtering and no blending
+    pub fn new(
+        snippet: impl Into<String>,
+        inputs: Vec<RenderTargetSource>,
+        destination: RenderTargetSource,
+    ) -> Self {
+        Self {
+            shader: PostProcessShader::Snippet(snippet.into()),
+            inputs,
+            destination,
+            filter: FilterMode::Linear,
+            blend: None,
+        }
+    }
+}
+
+impl OperationBuilder for PostProcessOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        self.inputs.clone()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.destination]
+    }
+
+    fn finish(self, _world: &World, device: &Device) -> impl Operation + 'static {
+        let input_count = self.inputs.len() as u32;
+        let mut entries = (0..input_count)
+            .map(|binding| BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            })
+            .collect::<Vec<_>>();
+        entries.push(BindGroupLayoutEntry {
+            binding: input_count,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Post process BGLayout"),
+            entries: &entries,
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Post process pipeline layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+        let (vertex, fragment) = match self.shader {
+            PostProcessShader::Snippet(snippet) => {
+                let mut source = FULLSCREEN_TRIANGLE_WGSL.to_string();
+                for i in 0..input_count {
+                    source +=
+                        &format!("@group(0) @binding({i})\nvar input_{i}: texture_2d<f32>;\n");
+                }
+                source +=
+                    &format!("@group(0) @binding({input_count})\nvar input_sampler: sampler;\n");
+                source += &snippet;
+                source += "\n@fragment\nfn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {\n    return post_process(in.uv);\n}\n";
+                let module = device.create_shader_module(ShaderModuleDescriptor {
+                    label: Some("Post process shader"),
+                    source: ShaderSource::Wgsl(source.into()),
+                });
+                (module.clone(), FragmentSource::Module(module))
+            }
+            PostProcessShader::Asset(id) => {
+                let module = device.create_shader_module(ShaderModuleDescriptor {
+                    label: Some("Fullscreen triangle shader"),
+                    source: ShaderSource::Wgsl(FULLSCREEN_TRIANGLE_WGSL.into()),
+                });
+                (module, FragmentSource::Asset(id))
+            }
+        };
+        let manager = RenderPipelineManager::new(GenericRenderPipelineDescriptor {
+            resource_provider: Box::new(PostProcessResourceProvider {
+                layout,
+                vertex,
+                fragment,
+            }),
+            label: Some("Post process pipeline".to_string()),
+            vertex_state: GenericVertexState {
+                entry_point: "vs_main".to_string(),
+                buffers: vec![],
+            },
+            primitive: PrimitiveState::default(),
+            // ignoring depth, but still needed for destinations with a depth buffer
+            depth_stencil: Some(GenericDepthStencilState {
+                depth_write_enable: false,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: GenericMultisampleState {
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(GenericFragmentState {
+                entry_point: "fs_main".to_string(),
+                targets: vec![GenericColorTargetState::new(self.blend)],
+            }),
+        });
+        PreparedPostProcess {
+            inputs: self.inputs,
+            destination: self.destination,
+            filter: self.filter,
+            bind_group_layout,
+            manager_id: None,
+            pending_manager: Some(manager),
+        }
+    }
+}
+
+enum FragmentSource {
+    Module(ShaderModule),
+    Asset(AssetId<ShaderModule>),
+}
+
+struct PostProcessResourceProvider {
+    layout: PipelineLayout,
+    vertex: ShaderModule,
+    fragment: FragmentSource,
+}
+
+impl RenderPipelineResourceProvider for PostProcessResourceProvider {
+    fn update(&self, _world: &mut World) {}
+
+    fn get_pipeline_layout(&self, _world: &World) -> PipelineLayout {
+        self.layout.clone()
+    }
+
+    fn get_vertex_shader_module(&self, _world: &World) -> ShaderModule {
+        self.vertex.clone()
+    }
+
+    fn get_fragment_shader_module(&self, world: &World) -> ShaderModule {
+        match &self.fragment {
+            FragmentSource::Module(module) => module.clone(),
+            FragmentSource::Asset(id) => world.asset(*id).clone(),
+        }
+    }
+
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        match self.fragment {
+            FragmentSource::Module(_) => Vec::new(),
+            FragmentSource::Asset(id) => vec![id.into()],
+        }
+    }
+}
+
+struct PreparedPostProcess {
+    inputs: Vec<RenderTargetSource>,
+    destination: RenderTargetSource,
+    filter: FilterMode,
+    bind_group_layout: BindGroupLayout,
+    manager_id: Option<AssetId<RenderPipelineManager>>,
+    /// The manager is added as an asset on the first run, as finish can not modify the world
+    pending_manager: Option<RenderPipelineManager>,
+}
+
+impl PreparedPostProcess {
+    fn manager(&mut self, world: &mut World) -> AssetId<RenderPipelineManager> {
+        if let Some(manager) = self.pending_manager.take() {
+            self.manager_id = Some(world.add_asset(manager));
+        }
+        self.manager_id.unwrap()
+    }
+}
+
+impl Operation for PreparedPostProcess {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        for input in &self.inputs {
+            resolve_for_reading(*input, world, command_encoder);
+        }
+        let Some(views) = self
+            .inputs
+            .iter()
+            .map(|input| input.get(world).and_then(|rt| rt.texture_view().cloned()))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(());
+        };
+        let device = world.resource::<RenderContext>().device.clone();
+        let sampler = world
+            .resource_mut::<SamplerCache>()
+            .get(
+                &device,
+                &SamplerDescriptor {
+                    label: Some("Post process sampler"),
+                    mag_filter: self.filter,
+                    min_filter: self.filter,
+                    ..Default::default()
+                },
+            )
+            .clone();
+        let mut entries = views
+            .iter()
+            .enumerate()
+            .map(|(i, view)| BindGroupEntry {
+                binding: i as u32,
+                resource: BindingResource::TextureView(view),
+            })
+            .collect::<Vec<_>>();
+        entries.push(BindGroupEntry {
+            binding: views.len() as u32,
+            resource: BindingResource::Sampler(&sampler),
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Post process bind group"),
+            layout: &self.bind_group_layout,
+            entries: &entries,
+        });
+        let manager = self.manager(world);
+        world.asset_scope(manager, |world, manager| {
+            let Some(pipeline) = manager.get_compatible(self.destination, world) else {
+                return;
+            };
+            let Some(mut rt) = self.destination.get_mut(world) else {
+                return;
+            };
+            let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+                return;
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use wgpu::{CommandEncoder, Device};
+
+use super::blit::resolve_for_reading;
+use crate::{
+    Operation, OperationBuilder, OperationError, ReadbackDelivery, RenderTargetSource,
+    TextureReadbacks,
+};
+
+/// Copies the color texture of a render target to the CPU every time it runs, delivered as a [ReadbackEvent](crate::ReadbackEvent) a few frames later.
+/// Use [add_if](crate::SequenceBuilder::add_if) to only read back some frames, or [Screenshots](crate::Screenshots) for a single image.
+/// Only the [selected layer](crate::RenderTarget::layer) of layered targets is copied.
+/// The texture needs [COPY_SRC](wgpu::TextureUsages::COPY_SRC) usage, otherwise the operation fails.
+pub struct ReadbackOperation {
+    pub render_target: RenderTargetSource,
+}
+
+impl Operation for ReadbackOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        resolve_for_reading(self.render_target, world, command_encoder);
+        let Some((texture, layer)) = self
+            .render_target
+            .get(world)
+            .and_then(|rt| Some((rt.texture().cloned()?, rt.layer())))
+        else {
+            return Ok(());
+        };
+        let device = world.resource::<RenderContext>().device.clone();
+        world.resource_mut::<TextureReadbacks>().copy(
+            &device,
+            command_encoder,
+            &texture,
+            layer,
+            ReadbackDelivery::Event(self.render_target),
+        )
+    }
+}
+
+impl OperationBuilder for ReadbackOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
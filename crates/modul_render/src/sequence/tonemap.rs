@@ -0,0 +1,92 @@
+use bevy_ecs::prelude::*;
+use log::warn;
+use modul_core::SurfaceColorSpace;
+use wgpu::Device;
+
+use crate::{
+    Operation, OperationBuilder, PostProcessOperation, RenderTargetSource, SRGB_ENCODE_WGSL,
+};
+
+/// The curve used by a [TonemapOperation] to map HDR values to 0..1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Clamps the values, only exposure is applied
+    None,
+    Reinhard,
+    /// The fitted ACES filmic curve by Krzysztof Narkowicz
+    Aces,
+}
+
+/// Draws an HDR source (usually an `Rgba16Float` [OffscreenRenderTarget](crate::OffscreenRenderTarget)) to the destination, applying exposure and a tonemapping curve.
+/// The output is encoded for the [SurfaceColorSpace] of the destination, [ExtendedLinear](SurfaceColorSpace::ExtendedLinear) destinations only get the exposure, as they can display the HDR values.
+/// The settings are baked into the shader, so the sequence has to be rebuilt to change them.
+pub struct TonemapOperation {
+    pub source: RenderTargetSource,
+    pub destination: RenderTargetSource,
+    pub tonemapper: Tonemapper,
+    /// Multiplies the source before tonemapping, 1 is used if it is not finite
+    pub exposure: f32,
+    /// Usually the [color_space](modul_core::SurfaceFormat::color_space) of the [SurfaceFormat](modul_core::SurfaceFormat)
+    pub output: SurfaceColorSpace,
+}
+
+impl TonemapOperation {
+    /// Creates the operation with [Aces](Tonemapper::Aces), an exposure of 1 and [Srgb](SurfaceColorSpace::Srgb) output
+    pub fn new(source: RenderTargetSource, destination: RenderTargetSource) -> Self {
+        Self {
+            source,
+            destination,
+            tonemapper: Tonemapper::Aces,
+            exposure: 1.0,
+            output: SurfaceColorSpace::Srgb,
+        }
+    }
+
+    fn snippet(&self) -> String {
+        let curve = match (self.output, self.tonemapper) {
+            (SurfaceColorSpace::ExtendedLinear, _) => "c",
+            (_, Tonemapper::None) => "clamp(c, vec3<f32>(0.0), vec3<f32>(1.0))",
+            (_, Tonemapper::Reinhard) => "c / (c + vec3<f32>(1.0))",
+            (_, Tonemapper::Aces) => {
+                "clamp((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0))"
+            }
+        };
+        let encode = match self.output {
+            SurfaceColorSpace::SrgbNonlinear => "srgb_encode(mapped)",
+            _ => "mapped",
+        };
+        // formatted into the shader, where inf and NaN are not valid
+        let exposure = match self.exposure.is_finite() {
+            true => self.exposure,
+            false => {
+                warn!("Tonemap exposure {} is not finite, using 1", self.exposure);
+                1.0
+            }
+        };
+        format!(
+            "{SRGB_ENCODE_WGSL}
+fn post_process(uv: vec2<f32>) -> vec4<f32> {{
+    let color = textureSample(input_0, input_sampler, uv);
+    let c = max(color.rgb * {exposure:?}, vec3<f32>(0.0));
+    let mapped = {curve};
+    return vec4<f32>({encode}, color.a);
+}}
+",
+        )
+    }
+}
+
+impl OperationBuilder for TonemapOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        vec![self.source]
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.destination]
+    }
+
+    fn finish(self, world: &World, device: &Device) -> impl Operation + 'static {
+        PostProcessOperation::new(self.snippet(), vec![self.source], self.destination)
+            .finish(world, device)
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{RenderPipelineManager, ShaderCompileError, ShaderDefs};
+use bevy_ecs::prelude::*;
+use modul_asset::Assets;
+use wgpu::naga::valid::Capabilities;
+
+/// Settings shared by the shader resource providers, inserted by the [RenderPlugin](crate::RenderPlugin).
+/// Used by [SpecializedRenderPipelineResourceProvider](crate::SpecializedRenderPipelineResourceProvider) and [LibraryRenderPipelineResourceProvider](crate::LibraryRenderPipelineResourceProvider)
+/// when they create modules, [PipelineLayoutComposer::compose_shader](crate::PipelineLayoutComposer::compose_shader) does not use it.
+/// Changing the settings clears all [RenderPipelineManagers](RenderPipelineManager) in the assets, so the pipelines are created again with them.
+#[derive(Resource)]
+pub struct ShaderComposer {
+    shader_defs: ShaderDefs,
+    validate: bool,
+    capabilities: Capabilities,
+    revision: u64,
+}
+
+impl Default for ShaderComposer {
+    fn default() -> Self {
+        Self {
+            shader_defs: ShaderDefs::new(),
+            validate: true,
+            capabilities: Capabilities::all(),
+            revision: 0,
+        }
+    }
+}
+
+impl ShaderComposer {
+    /// The defs enabled in every shader, for example `MSAA_SAMPLES_4`
+    pub fn shader_defs(&self) -> &ShaderDefs {
+        &self.shader_defs
+    }
+
+    pub fn set_shader_defs(&mut self, shader_defs: ShaderDefs) {
+        if self.shader_defs != shader_defs {
+            self.shader_defs = shader_defs;
+            self.revision += 1;
+        }
+    }
+
+    pub fn insert_def(&mut self, def: impl Into<String>) {
+        let def = def.into();
+        if !self.shader_defs.contains(&def) {
+            self.shader_defs.insert(def);
+            self.revision += 1;
+        }
+    }
+
+    pub fn remove_def(&mut self, def: &str) {
+        if self.shader_defs.contains(def) {
+            self.shader_defs.remove(def);
+            self.revision += 1;
+        }
+    }
+
+    /// If shaders are checked with naga before creating their modules, true by default.
+    /// Without it invalid shaders are still reported by wgpu, as [PipelineError::Validation](crate::PipelineError::Validation) without a location.
+    pub fn validates(&self) -> bool {
+        self.validate
+    }
+
+    pub fn set_validation(&mut self, validate: bool) {
+        if self.validate != validate {
+            self.validate = validate;
+            self.revision += 1;
+        }
+    }
+
+    /// The capabilities shaders may use when checked, all by default
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        if self.capabilities != capabilities {
+            self.capabilities = capabilities;
+            self.revision += 1;
+        }
+    }
+
+    /// Increased every time a setting changes, used to know when created modules are outdated
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The defs of the parameters and the provider, with the global defs
+    pub fn combine_defs(&self, defs: &ShaderDefs, provider_defs: &ShaderDefs) -> ShaderDefs {
+        let mut all_defs = defs.clone();
+        all_defs.extend(provider_defs.iter());
+        all_defs.extend(self.shader_defs.iter());
+        all_defs
+    }
+
+    /// Checks the source with naga if validation is enabled
+    pub fn check(&self, label: Option<&str>, source: &str) -> Result<(), ShaderCompileError> {
+        match self.validate {
+            true => {
+                ShaderCompileError::check_wgsl_with_capabilities(label, source, self.capabilities)
+            }
+            false => Ok(()),
+        }
+    }
+}
+
+/// Clears all render pipeline managers when the [ShaderComposer] changes
+pub(crate) fn clear_pipelines_on_composer_change(
+    mut revision: Local<u64>,
+    composer: Res<ShaderComposer>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    if composer.revision() == *revision {
+        return;
+    }
+    *revision = composer.revision();
+    for (_, manager) in managers.iter_mut() {
+        manager.clear();
+    }
+}
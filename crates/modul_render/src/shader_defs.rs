@@ -0,0 +1,232 @@
+use crate::{PipelineError, RenderPipelineResourceProvider, ShaderComposer};
+use bevy_ecs::world::World;
+use modul_asset::{AssetId, AssetWorldExt, UntypedAssetId};
+use modul_core::{profile_span, RenderContext};
+use modul_util::HashMap;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use wgpu::{PipelineLayout, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+/// A set of defined names, used to specialize a shader with `#ifdef` blocks (see [preprocess](Self::preprocess)).
+/// Part of the [PipelineParameters](crate::PipelineParameters), so a [RenderPipelineManager](crate::RenderPipelineManager) creates a pipeline per set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefs(BTreeSet<String>);
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the def, can be chained like `ShaderDefs::new().with("VERTEX_COLORS")`
+    pub fn with(mut self, def: impl Into<String>) -> Self {
+        self.insert(def);
+        self
+    }
+
+    pub fn insert(&mut self, def: impl Into<String>) {
+        self.0.insert(def.into());
+    }
+
+    pub fn remove(&mut self, def: &str) {
+        self.0.remove(def);
+    }
+
+    pub fn contains(&self, def: &str) -> bool {
+        self.0.contains(def)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// Keeps the lines of the source whose `#ifdef NAME` / `#ifndef NAME` blocks are enabled by the defs, and removes the directives.
+    /// Blocks can be nested and have an `#else`, and end with `#endif`.
+    /// Removed lines are kept empty, so line numbers in shader errors still match the source.
+    pub fn preprocess(&self, source: &str) -> Result<String, ShaderDefError> {
+        // for every open block, if its lines are kept and if it has reached #else
+        let mut blocks: Vec<(bool, bool)> = Vec::new();
+        let mut output = String::with_capacity(source.len());
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some(directive @ ("#ifdef" | "#ifndef")) => {
+                    let Some(name) = words.next() else {
+                        return Err(ShaderDefError::MissingName { line: line_number });
+                    };
+                    blocks.push((self.contains(name) == (directive == "#ifdef"), false));
+                }
+                Some("#else") => match blocks.last_mut() {
+                    Some((keep, reached_else)) if !*reached_else => {
+                        *keep = !*keep;
+                        *reached_else = true;
+                    }
+                    _ => return Err(ShaderDefError::UnexpectedElse { line: line_number }),
+                },
+                Some("#endif") => {
+                    if blocks.pop().is_none() {
+                        return Err(ShaderDefError::UnexpectedEndif { line: line_number });
+                    }
+                }
+                _ => {
+                    if blocks.iter().all(|(keep, _)| *keep) {
+                        output.push_str(line);
+                    }
+                }
+            }
+            output.push('\n');
+        }
+        match blocks.is_empty() {
+            true => Ok(output),
+            false => Err(ShaderDefError::MissingEndif),
+        }
+    }
+}
+
+impl<S: Into<String>> Extend<S> for ShaderDefs {
+    fn extend<T: IntoIterator<Item = S>>(&mut self, iter: T) {
+        self.0.extend(iter.into_iter().map(Into::into));
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for ShaderDefs {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        Self(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Returned by [ShaderDefs::preprocess] for unbalanced directives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderDefError {
+    /// An `#ifdef` or `#ifndef` without a name
+    MissingName { line: usize },
+    /// An `#else` outside a block, or a second one in a block
+    UnexpectedElse { line: usize },
+    /// An `#endif` without a block to end
+    UnexpectedEndif { line: usize },
+    /// A block is not ended before the end of the source
+    MissingEndif,
+}
+
+impl Error for ShaderDefError {}
+
+impl Display for ShaderDefError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderDefError::MissingName { line } => {
+                write!(f, "#ifdef or #ifndef without a name on line {}", line)
+            }
+            ShaderDefError::UnexpectedElse { line } => {
+                write!(f, "#else without a block on line {}", line)
+            }
+            ShaderDefError::UnexpectedEndif { line } => {
+                write!(f, "#endif without a block on line {}", line)
+            }
+            ShaderDefError::MissingEndif => write!(f, "#ifdef or #ifndef without an #endif"),
+        }
+    }
+}
+
+/// A [RenderPipelineResourceProvider] creating a shader module per [ShaderDefs] from WGSL source, preprocessed with [ShaderDefs::preprocess].
+/// Use [compose_shader_source](crate::PipelineLayoutComposer::compose_shader_source) to include the bind group libraries.
+/// The vertex buffers of the [GenericRenderPipelineDescriptor](crate::GenericRenderPipelineDescriptor) are shared, so they must have every attribute used by any of the defs.
+pub struct SpecializedRenderPipelineResourceProvider {
+    pub layout: AssetId<PipelineLayout>,
+    /// Contains both the vertex and fragment entry points
+    pub source: String,
+    pub label: Option<String>,
+    /// Enabled in every module together with the defs of the parameters and the global defs of the
+    /// [ShaderComposer], usually the [shader_defs](crate::PipelineLayoutComposer::shader_defs) of the composer.
+    /// Set before modules are created.
+    pub shader_defs: ShaderDefs,
+    /// The modules by the defs of the parameters, with the revision of the [ShaderComposer] they were created with
+    modules: Mutex<(u64, HashMap<ShaderDefs, ShaderModule>)>,
+}
+
+impl SpecializedRenderPipelineResourceProvider {
+    pub fn new(layout: AssetId<PipelineLayout>, source: impl Into<String>) -> Self {
+        Self {
+            layout,
+            source: source.into(),
+            label: None,
+            shader_defs: ShaderDefs::new(),
+            modules: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    /// Gets the module for the defs, creating it if needed.
+    /// Returns an error if the directives of the source are unbalanced, or if the shader does not compile.
+    pub fn get_module(
+        &self,
+        world: &World,
+        defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        let composer = world.resource::<ShaderComposer>();
+        let mut modules = self.modules.lock().unwrap();
+        if modules.0 != composer.revision() {
+            *modules = (composer.revision(), HashMap::new());
+        }
+        if let Some(module) = modules.1.get(defs) {
+            return Ok(module.clone());
+        }
+        profile_span!("compile shader", label = ?self.label);
+        let all_defs = composer.combine_defs(defs, &self.shader_defs);
+        let source = all_defs.preprocess(&self.source)?;
+        composer.check(self.label.as_deref(), &source)?;
+        let device = &world.resource::<RenderContext>().device;
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: self.label.as_deref(),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        modules.1.insert(defs.clone(), module.clone());
+        Ok(module)
+    }
+
+    /// The module without defs, panics like shader modules with invalid WGSL if the directives are unbalanced or it does not compile
+    fn default_module(&self, world: &World) -> ShaderModule {
+        self.get_module(world, &ShaderDefs::default())
+            .unwrap_or_else(|e| panic!("failed to create shader {:?}: {}", self.label, e))
+    }
+}
+
+impl RenderPipelineResourceProvider for SpecializedRenderPipelineResourceProvider {
+    fn update(&self, _world: &mut World) {}
+
+    fn get_pipeline_layout(&self, world: &World) -> PipelineLayout {
+        world.asset(self.layout).clone()
+    }
+
+    fn get_vertex_shader_module(&self, world: &World) -> ShaderModule {
+        self.default_module(world)
+    }
+
+    fn get_fragment_shader_module(&self, world: &World) -> ShaderModule {
+        self.default_module(world)
+    }
+
+    fn get_specialized_vertex_shader_module(
+        &self,
+        world: &World,
+        defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        self.get_module(world, defs)
+    }
+
+    fn get_specialized_fragment_shader_module(
+        &self,
+        world: &World,
+        defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        self.get_module(world, defs)
+    }
+
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        vec![self.layout.into()]
+    }
+}
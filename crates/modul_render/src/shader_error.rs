@@ -0,0 +1,84 @@
+use bevy_ecs::prelude::*;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use wgpu::naga;
+use wgpu::naga::valid::{Capabilities, ValidationFlags, Validator};
+
+/// A WGSL shader that failed to parse or validate, with where the error is.
+/// Returned in [PipelineError::Compile](crate::PipelineError::Compile), and written as a message when a [RenderPipelineManager](crate::RenderPipelineManager) fails to create a pipeline because of it.
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct ShaderCompileError {
+    /// The label of the shader
+    pub label: Option<String>,
+    /// The import path of the [WgslModule](crate::WgslModule) the error is in, None if it is in the main source
+    pub module: Option<String>,
+    pub message: String,
+    /// The 1-based line and column of the error in its module, if the error has a location
+    pub location: Option<(u32, u32)>,
+    /// The message with the lines of the composed source it refers to, underlined
+    pub rendered: String,
+}
+
+impl ShaderCompileError {
+    /// Parses and validates the source with naga, returning the first error.
+    /// Device limits and features are not checked, wgpu still reports those when creating the module.
+    pub fn check_wgsl(label: Option<&str>, source: &str) -> Result<(), ShaderCompileError> {
+        Self::check_wgsl_with_capabilities(label, source, Capabilities::all())
+    }
+
+    /// Like [check_wgsl](Self::check_wgsl), but only allows the capabilities, see [ShaderComposer](crate::ShaderComposer)
+    pub fn check_wgsl_with_capabilities(
+        label: Option<&str>,
+        source: &str,
+        capabilities: Capabilities,
+    ) -> Result<(), ShaderCompileError> {
+        Self::checked_module(label, source, capabilities).map(|_| ())
+    }
+
+    /// Parses and validates the source, returning the naga module
+    pub(crate) fn checked_module(
+        label: Option<&str>,
+        source: &str,
+        capabilities: Capabilities,
+    ) -> Result<naga::Module, ShaderCompileError> {
+        let path = label.unwrap_or("wgsl");
+        let error = match naga::front::wgsl::parse_str(source) {
+            Ok(module) => {
+                let mut validator = Validator::new(ValidationFlags::all(), capabilities);
+                match validator.validate(&module) {
+                    Ok(_) => return Ok(module),
+                    Err(e) => Self {
+                        label: label.map(str::to_owned),
+                        module: None,
+                        message: e.as_inner().to_string(),
+                        location: e.location(source).map(|l| (l.line_number, l.line_position)),
+                        rendered: e.emit_to_string_with_path(source, path),
+                    },
+                }
+            }
+            Err(e) => Self {
+                label: label.map(str::to_owned),
+                module: None,
+                message: e.message().to_owned(),
+                location: e.location(source).map(|l| (l.line_number, l.line_position)),
+                rendered: e.emit_to_string_with_path(source, path),
+            },
+        };
+        Err(error)
+    }
+}
+
+impl Error for ShaderCompileError {}
+
+impl Display for ShaderCompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label.as_deref().unwrap_or("shader"))?;
+        if let Some(module) = &self.module {
+            write!(f, " ({})", module)?;
+        }
+        if let Some((line, column)) = self.location {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
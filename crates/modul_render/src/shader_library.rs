@@ -0,0 +1,455 @@
+use crate::{PipelineError, RedrawSet, RenderPipelineResourceProvider, ShaderComposer, ShaderDefs};
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use log::warn;
+use modul_asset::{
+    AssetAppExt, AssetEvent, AssetId, AssetLoadError, AssetLoadSet, AssetLoader, AssetServer,
+    AssetWorldExt, Assets, LoadState, UntypedAssetId,
+};
+use modul_core::{profile_span, Redraw, RenderContext};
+use modul_util::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use wgpu::{PipelineLayout, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+/// WGSL source that other shaders can import through the [ShaderLibrary].
+/// A `#define_import_path name` line names the module, shaders include it with an `#import name` line.
+pub struct WgslModule {
+    pub import_path: Option<String>,
+    pub source: String,
+}
+
+impl WgslModule {
+    /// Reads the import path from the `#define_import_path` line of the source, if there is one
+    pub fn new(source: impl Into<String>) -> Self {
+        let source = source.into();
+        let import_path = source.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("#define_import_path") => words.next().map(str::to_owned),
+                _ => None,
+            }
+        });
+        Self {
+            import_path,
+            source,
+        }
+    }
+}
+
+/// [AssetLoader] for wgsl files, registered by the [ShaderLibraryPlugin]
+pub struct WgslModuleLoader;
+
+impl AssetLoader for WgslModuleLoader {
+    type Asset = WgslModule;
+
+    fn extensions(&self) -> &[&str] {
+        &["wgsl"]
+    }
+
+    fn load(&self, bytes: Vec<u8>, _path: &Path) -> Result<WgslModule, AssetLoadError> {
+        let source = String::from_utf8(bytes).map_err(|e| AssetLoadError::LoaderError(e.into()))?;
+        Ok(WgslModule::new(source))
+    }
+}
+
+/// The [WgslModules](WgslModule) shaders can import, by their import path.
+/// Modules loaded by the [AssetServer] are added when they finish loading, and replaced when they are reloaded.
+#[derive(Resource, Default)]
+pub struct ShaderLibrary {
+    modules: HashMap<String, AssetId<WgslModule>>,
+    loading: Vec<AssetId<WgslModule>>,
+    revision: u64,
+}
+
+impl ShaderLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every wgsl file in the directory (relative to the [root](AssetServer::root) of the server) and its subdirectories.
+    /// Returns the amount of files, the modules are added once they are loaded, see [is_loaded](Self::is_loaded).
+    pub fn load_directory(
+        &mut self,
+        server: &mut AssetServer,
+        modules: &Assets<WgslModule>,
+        directory: impl AsRef<Path>,
+    ) -> io::Result<usize> {
+        let mut directories = vec![directory.as_ref().to_path_buf()];
+        let mut count = 0;
+        while let Some(directory) = directories.pop() {
+            for entry in fs::read_dir(server.root().join(&directory))? {
+                let entry = entry?;
+                let path = directory.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    directories.push(path);
+                } else if path.extension().is_some_and(|ext| ext == "wgsl") {
+                    self.loading.push(server.load(modules, path));
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Adds a module that is not loaded from a file, replacing the module with the same import path
+    pub fn add(
+        &mut self,
+        modules: &mut Assets<WgslModule>,
+        module: WgslModule,
+    ) -> AssetId<WgslModule> {
+        let import_path = module.import_path.clone();
+        let id = modules.add(module);
+        self.register(id, import_path);
+        id
+    }
+
+    /// Returns true if all files of [load_directory](Self::load_directory) finished loading (or failed)
+    pub fn is_loaded(&self, server: &AssetServer) -> bool {
+        self.loading
+            .iter()
+            .all(|id| !matches!(server.load_state(*id), LoadState::Loading))
+    }
+
+    /// The module with the import path
+    pub fn get(&self, import_path: &str) -> Option<AssetId<WgslModule>> {
+        self.modules.get(import_path).copied()
+    }
+
+    /// Increased every time a module is added or replaced, used to know when composed shaders are outdated
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Replaces the `#import` lines of the source with the sources of the modules, and empties `#define_import_path` lines.
+    /// Modules can import other modules, every module is only included once.
+    /// Lines of the result can be traced back to their module with [ResolvedShader::origin].
+    pub fn resolve(
+        &self,
+        modules: &Assets<WgslModule>,
+        source: &str,
+    ) -> Result<ResolvedShader, ShaderImportError> {
+        let mut resolved = ResolvedShader {
+            source: String::with_capacity(source.len()),
+            imports: Vec::new(),
+            segments: Vec::new(),
+            lines: 0,
+        };
+        self.resolve_into(modules, source, None, &mut resolved, &mut Vec::new())?;
+        Ok(resolved)
+    }
+
+    fn resolve_into(
+        &self,
+        modules: &Assets<WgslModule>,
+        source: &str,
+        import_path: Option<&str>,
+        resolved: &mut ResolvedShader,
+        importing: &mut Vec<String>,
+    ) -> Result<(), ShaderImportError> {
+        resolved.start_segment(import_path, 1);
+        for (index, line) in source.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("#import") => {
+                    let Some(path) = words.next() else {
+                        return Err(ShaderImportError::MissingPath);
+                    };
+                    if importing.iter().any(|p| p == path) {
+                        return Err(ShaderImportError::Cycle(path.to_owned()));
+                    }
+                    let Some((id, module)) =
+                        self.get(path).and_then(|id| Some((id, modules.get(id)?)))
+                    else {
+                        return Err(ShaderImportError::UnknownImport(path.to_owned()));
+                    };
+                    if resolved.imports.contains(&id) {
+                        resolved.push_line("");
+                        continue;
+                    }
+                    importing.push(path.to_owned());
+                    self.resolve_into(modules, &module.source, Some(path), resolved, importing)?;
+                    importing.pop();
+                    resolved.imports.push(id);
+                    // the import line is replaced by the module, the source continues after it
+                    resolved.start_segment(import_path, index as u32 + 2);
+                }
+                // kept empty so line numbers of the module still match
+                Some("#define_import_path") => resolved.push_line(""),
+                _ => resolved.push_line(line),
+            }
+        }
+        Ok(())
+    }
+
+    fn register(&mut self, id: AssetId<WgslModule>, import_path: Option<String>) {
+        // the import path may have changed when reloading
+        self.modules.retain(|_, module_id| *module_id != id);
+        if let Some(path) = import_path {
+            self.modules.insert(path, id);
+        }
+        self.revision += 1;
+    }
+}
+
+/// Source with its imports resolved by [ShaderLibrary::resolve]
+pub struct ResolvedShader {
+    pub source: String,
+    /// The modules included in the source, directly or through other modules
+    pub imports: Vec<AssetId<WgslModule>>,
+    /// The first line of every part of the source, with the import path of its module and its line there
+    segments: Vec<(u32, Option<String>, u32)>,
+    lines: u32,
+}
+
+impl ResolvedShader {
+    /// The import path of the module a 1-based line of the source comes from (None for the main source),
+    /// and the line in that module
+    pub fn origin(&self, line: u32) -> (Option<&str>, u32) {
+        let index = line.saturating_sub(1);
+        match self
+            .segments
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= index)
+        {
+            Some((start, path, first)) => (path.as_deref(), first + index - start),
+            None => (None, line),
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.lines += 1;
+    }
+
+    fn start_segment(&mut self, import_path: Option<&str>, line: u32) {
+        self.segments
+            .push((self.lines, import_path.map(str::to_owned), line));
+    }
+}
+
+/// Returned by [ShaderLibrary::resolve] when an import can not be resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderImportError {
+    /// An `#import` without a path
+    MissingPath,
+    /// No module with the import path is in the library, it may still be loading
+    UnknownImport(String),
+    /// The module imports itself, directly or through other modules
+    Cycle(String),
+}
+
+impl Error for ShaderImportError {}
+
+impl Display for ShaderImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderImportError::MissingPath => write!(f, "#import without a path"),
+            ShaderImportError::UnknownImport(path) => write!(f, "no shader module {}", path),
+            ShaderImportError::Cycle(path) => write!(f, "shader module {} imports itself", path),
+        }
+    }
+}
+
+/// Adds the [ShaderLibrary] and loads the wgsl files of the directory into it.
+/// [AssetServerPlugin](modul_asset::AssetServerPlugin) must be added first,
+/// with [watch_for_changes](modul_asset::AssetServerPlugin::watch_for_changes) modules are reloaded when their files change.
+pub struct ShaderLibraryPlugin {
+    /// Relative to the root of the [AssetServer]
+    pub directory: PathBuf,
+}
+
+impl Plugin for ShaderLibraryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_assets::<WgslModule>();
+        app.register_asset_loader(WgslModuleLoader);
+        let mut library = ShaderLibrary::new();
+        app.world_mut()
+            .resource_scope(|world, mut server: Mut<AssetServer>| {
+                let modules = world.resource::<Assets<WgslModule>>();
+                if let Err(e) = library.load_directory(&mut server, modules, &self.directory) {
+                    warn!("Failed to load shader library {:?}: {}", self.directory, e);
+                }
+            });
+        app.insert_resource(library);
+        app.add_systems(
+            Redraw,
+            register_library_modules
+                .after(AssetLoadSet)
+                .in_set(RedrawSet::Prepare),
+        );
+    }
+}
+
+fn register_library_modules(
+    mut events: MessageReader<AssetEvent<WgslModule>>,
+    modules: Res<Assets<WgslModule>>,
+    mut library: ResMut<ShaderLibrary>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Loaded(id) | AssetEvent::Modified(id) = event {
+            if let Some(module) = modules.get(*id) {
+                library.register(*id, module.import_path.clone());
+            }
+        }
+    }
+}
+
+/// Shader modules by the defs and if they are for the fragment source
+type StageModules = HashMap<(bool, ShaderDefs), ShaderModule>;
+
+/// A [RenderPipelineResourceProvider] creating shader modules from WGSL source with `#import`s resolved by the [ShaderLibrary],
+/// specialized per [ShaderDefs] like [SpecializedRenderPipelineResourceProvider](crate::SpecializedRenderPipelineResourceProvider).
+/// The modules are created again when the library changes, and pipeline managers using the provider are cleared when an import is reloaded.
+/// Imports added to a module after the provider is created are not tracked.
+pub struct LibraryRenderPipelineResourceProvider {
+    pub layout: AssetId<PipelineLayout>,
+    pub label: Option<String>,
+    /// Enabled in every module together with the defs of the parameters and the global defs, see
+    /// [SpecializedRenderPipelineResourceProvider::shader_defs](crate::SpecializedRenderPipelineResourceProvider::shader_defs)
+    pub shader_defs: ShaderDefs,
+    vertex_source: String,
+    /// None if the fragment entry point is in the vertex source
+    fragment_source: Option<String>,
+    imports: Vec<AssetId<WgslModule>>,
+    /// The modules with the revisions of the library and the [ShaderComposer](crate::ShaderComposer) they were created with
+    modules: Mutex<((u64, u64), StageModules)>,
+}
+
+impl LibraryRenderPipelineResourceProvider {
+    /// The source contains both the vertex and fragment entry points,
+    /// for example from [PipelineLayoutComposer::compose_shader_source](crate::PipelineLayoutComposer::compose_shader_source).
+    /// Fails if the imports can not be resolved yet.
+    pub fn new(
+        layout: AssetId<PipelineLayout>,
+        source: impl Into<String>,
+        library: &ShaderLibrary,
+        modules: &Assets<WgslModule>,
+    ) -> Result<Self, ShaderImportError> {
+        let source = source.into();
+        let imports = library.resolve(modules, &source)?.imports;
+        Ok(Self {
+            layout,
+            label: None,
+            shader_defs: ShaderDefs::new(),
+            vertex_source: source,
+            fragment_source: None,
+            imports,
+            modules: Mutex::new(((library.revision(), 0), HashMap::new())),
+        })
+    }
+
+    /// Like [new](Self::new), with the entry points in separate sources,
+    /// so multiple pipelines can share a vertex shader with different fragment shaders
+    pub fn new_split(
+        layout: AssetId<PipelineLayout>,
+        vertex_source: impl Into<String>,
+        fragment_source: impl Into<String>,
+        library: &ShaderLibrary,
+        modules: &Assets<WgslModule>,
+    ) -> Result<Self, ShaderImportError> {
+        let fragment_source = fragment_source.into();
+        let mut provider = Self::new(layout, vertex_source, library, modules)?;
+        for id in library.resolve(modules, &fragment_source)?.imports {
+            if !provider.imports.contains(&id) {
+                provider.imports.push(id);
+            }
+        }
+        provider.fragment_source = Some(fragment_source);
+        Ok(provider)
+    }
+
+    /// Gets the module for the stage and defs, creating it if needed or if the library changed
+    pub fn get_module(
+        &self,
+        world: &World,
+        fragment: bool,
+        defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        let library = world.resource::<ShaderLibrary>();
+        let composer = world.resource::<ShaderComposer>();
+        let revisions = (library.revision(), composer.revision());
+        let (source, fragment) = match (&self.fragment_source, fragment) {
+            (Some(source), true) => (source, true),
+            _ => (&self.vertex_source, false),
+        };
+        let mut modules = self.modules.lock().unwrap();
+        if modules.0 != revisions {
+            *modules = (revisions, HashMap::new());
+        }
+        if let Some(module) = modules.1.get(&(fragment, defs.clone())) {
+            return Ok(module.clone());
+        }
+        profile_span!("compile shader", label = ?self.label);
+        let resolved = library.resolve(world.resource::<Assets<WgslModule>>(), source)?;
+        let all_defs = composer.combine_defs(defs, &self.shader_defs);
+        let source = all_defs.preprocess(&resolved.source)?;
+        composer
+            .check(self.label.as_deref(), &source)
+            .map_err(|mut error| {
+                // defs keep line numbers, so the location can be traced back to the module
+                if let Some((line, column)) = error.location {
+                    let (module, line) = resolved.origin(line);
+                    error.module = module.map(str::to_owned);
+                    error.location = Some((line, column));
+                }
+                error
+            })?;
+        let device = &world.resource::<RenderContext>().device;
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: self.label.as_deref(),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        modules.1.insert((fragment, defs.clone()), module.clone());
+        Ok(module)
+    }
+
+    /// The module without defs, panics like shader modules with invalid WGSL if the imports can not be resolved or it does not compile
+    fn default_module(&self, world: &World, fragment: bool) -> ShaderModule {
+        self.get_module(world, fragment, &ShaderDefs::default())
+            .unwrap_or_else(|e| panic!("failed to compose shader {:?}: {}", self.label, e))
+    }
+}
+
+impl RenderPipelineResourceProvider for LibraryRenderPipelineResourceProvider {
+    fn update(&self, _world: &mut World) {}
+
+    fn get_pipeline_layout(&self, world: &World) -> PipelineLayout {
+        world.asset(self.layout).clone()
+    }
+
+    fn get_vertex_shader_module(&self, world: &World) -> ShaderModule {
+        self.default_module(world, false)
+    }
+
+    fn get_fragment_shader_module(&self, world: &World) -> ShaderModule {
+        self.default_module(world, true)
+    }
+
+    fn get_specialized_vertex_shader_module(
+        &self,
+        world: &World,
+        defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        self.get_module(world, false, defs)
+    }
+
+    fn get_specialized_fragment_shader_module(
+        &self,
+        world: &World,
+        defs: &ShaderDefs,
+    ) -> Result<ShaderModule, PipelineError> {
+        self.get_module(world, true, defs)
+    }
+
+    fn dependencies(&self) -> Vec<UntypedAssetId> {
+        let imports = self.imports.iter().map(|&id| id.into());
+        std::iter::once(self.layout.into()).chain(imports).collect()
+    }
+}
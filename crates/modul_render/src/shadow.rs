@@ -0,0 +1,655 @@
+use crate::{
+    camera_view_size, Aabb, BindGroupLayoutDef, BindGroupLayoutProvider, CachedBindGroupLayout,
+    Camera, DirectRenderPipelineResourceProvider, DynamicUniformLayoutProvider, Frustum,
+    GenericDepthStencilState, GenericMultisampleState, GenericRenderPipelineDescriptor,
+    GenericVertexBufferLayout, GenericVertexState, GlobalTransform, GpuTransform, Mesh, ModelIndex,
+    ModelMatrices, OffscreenRenderTarget, Operation, OperationBuilder, OperationError,
+    PipelineLayoutComposer, Projection, RenderPipelineManager, RenderTarget, RenderTargetSource,
+    UniformAllocation, UniformAllocator,
+};
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use log::warn;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use modul_util::HashMap;
+use std::num::NonZero;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
+    BufferDescriptor, BufferUsages, CommandEncoder, CompareFunction, DepthBiasState, Device,
+    FilterMode, PipelineLayout, PrimitiveState, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModule, ShaderStages, StencilState, Texture, TextureAspect, TextureSampleType,
+    TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexAttribute, VertexFormat,
+    VertexStepMode,
+};
+
+/// The max amount of shadow views (cascades and spot lights) of all lights together
+pub const MAX_SHADOW_VIEWS: usize = 16;
+
+/// [SystemSet] within [Draw](crate::Draw) that computes the shadow views of the lights and updates the [Shadows].
+/// It runs after [ApplyOffscreenTargetsSystemSet](crate::ApplyOffscreenTargetsSystemSet) and before [UniformFlushSet](crate::UniformFlushSet).
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShadowPrepareSet;
+
+/// Marks the [OffscreenRenderTarget] the shadows are rendered to, shadow view `i` is rendered to layer `i`.
+/// It should have a depth-only format with [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage, see [shadow_map](crate::OffscreenRenderTargetConfig::shadow_map).
+/// Only the first entity with this is used.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct ShadowMap;
+
+/// A light infinitely far away shining along `direction`, its shadow is split into cascades fitted to the view of a [Camera].
+/// If the entity has a [GlobalTransform] its -Z axis is used as direction.
+#[derive(Component, Clone, Debug)]
+#[require(ShadowViewRange)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    /// The camera entity the cascades cover
+    pub camera: Entity,
+    /// The far distance of each cascade from the camera, increasing. The first cascade starts at the near plane of the camera.
+    pub cascade_splits: Vec<f32>,
+    /// How far towards the light casters outside the camera view still cast shadows into it
+    pub caster_distance: f32,
+}
+
+impl DirectionalLight {
+    /// Three cascades ending at 10, 30 and 100 units
+    pub fn new(direction: Vec3, camera: Entity) -> Self {
+        Self {
+            direction,
+            camera,
+            cascade_splits: vec![10.0, 30.0, 100.0],
+            caster_distance: 100.0,
+        }
+    }
+}
+
+/// A light cone with its tip at `position`, with one shadow view.
+/// If the entity has a [GlobalTransform] its translation and -Z axis are used as position and direction.
+#[derive(Component, Clone, Copy, Debug)]
+#[require(ShadowViewRange)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    /// The angle between the direction and the edge of the cone, in radians
+    pub angle: f32,
+    /// The far plane of the shadow view, the near plane is at 1% of it
+    pub range: f32,
+}
+
+impl SpotLight {
+    pub fn new(position: Vec3, direction: Vec3, angle: f32, range: f32) -> Self {
+        Self {
+            position,
+            direction,
+            angle,
+            range,
+        }
+    }
+}
+
+/// The shadow views of a light in the [ShadowLayout] `shadows.views` array, set during [ShadowPrepareSet].
+/// Lights that did not fit into the [ShadowMap] or [MAX_SHADOW_VIEWS] get a `count` of 0.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShadowViewRange {
+    pub first: u32,
+    pub count: u32,
+}
+
+/// Draws the [Mesh] into the shadow views with the model matrix of the entity, see [ShadowPassOperation].
+/// If the entity has an [Aabb], it is skipped for views it is not in.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+#[require(GpuTransform)]
+pub struct ShadowCaster {
+    pub mesh: AssetId<Mesh>,
+    /// The shader location of the position attribute, which has to be `Float32x3`
+    pub position_location: u32,
+}
+
+impl ShadowCaster {
+    /// A caster with the position at shader location 0
+    pub fn new(mesh: AssetId<Mesh>) -> Self {
+        Self {
+            mesh,
+            position_location: 0,
+        }
+    }
+}
+
+/// A shadow view as seen by shaders, declared as the `ShadowView` struct by the [ShadowLayout]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct ShadowView {
+    /// World to shadow map clip space
+    pub view_projection: Mat4,
+    /// The far distance of the cascade from the camera, the range of spot lights
+    pub split_far: f32,
+    _padding: [f32; 3],
+}
+
+impl ShadowView {
+    pub fn new(view_projection: Mat4, split_far: f32) -> Self {
+        Self {
+            view_projection,
+            split_far,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Bind group layout for sampling the [ShadowMap] in materials, the bind group is [Shadows::bind_group].
+/// Declares `shadow_map: texture_depth_2d_array`, the comparison sampler `shadow_sampler` and `shadows: Shadows` with the [ShadowViews](ShadowView).
+/// `shadow_factor(view, world_position)` returns 1 where lit and 0 in shadow, `cascade_shadow_factor(first, count, view_depth, world_position)` selects the cascade of a [DirectionalLight].
+/// The [RenderPlugin](crate::RenderPlugin) inserts a [CachedBindGroupLayout] of it.
+pub struct ShadowLayout;
+
+impl BindGroupLayoutDef for ShadowLayout {
+    const LAYOUT: &'static BindGroupLayoutDescriptor<'static> = &BindGroupLayoutDescriptor {
+        label: Some("Shadow BGLayout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZero::new(SHADOW_UNIFORM_SIZE),
+                },
+                count: None,
+            },
+        ],
+    };
+    const LIBRARY: &'static str = include_str!("shadow_library.wgsl");
+}
+
+/// `views` followed by `count` and padding
+const SHADOW_UNIFORM_SIZE: u64 = (MAX_SHADOW_VIEWS * size_of::<ShadowView>() + 16) as u64;
+
+/// The shadow views of the frame and the resources to render and sample them, inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct Shadows {
+    map: Option<Entity>,
+    views: Vec<ShadowView>,
+    uniform: Buffer,
+    sampler: Sampler,
+    /// the texture the bind group was created for
+    bound_texture: Option<Texture>,
+    bind_group: Option<BindGroup>,
+    view_layout: DynamicUniformLayoutProvider,
+    view_allocations: Vec<UniformAllocation>,
+    pipeline_layout: AssetId<PipelineLayout>,
+    shader: AssetId<ShaderModule>,
+    /// pipelines by the position layout of the caster meshes
+    variants: HashMap<GenericVertexBufferLayout, AssetId<RenderPipelineManager>>,
+}
+
+impl Shadows {
+    /// The [ShadowMap] entity used this frame
+    pub fn map(&self) -> Option<Entity> {
+        self.map
+    }
+
+    /// The shadow views of this frame, in the order of their layers
+    pub fn views(&self) -> &[ShadowView] {
+        &self.views
+    }
+
+    /// The bind group of the [ShadowLayout], None until there is a [ShadowMap] with a sampleable depth texture
+    pub fn bind_group(&self) -> Option<&BindGroup> {
+        self.bind_group.as_ref()
+    }
+
+    /// Gets the pipeline for caster meshes with the position layout, creating it on first use
+    fn pipeline_manager(
+        &mut self,
+        managers: &mut Assets<RenderPipelineManager>,
+        position_layout: GenericVertexBufferLayout,
+    ) -> AssetId<RenderPipelineManager> {
+        if let Some(manager) = self.variants.get(&position_layout) {
+            return *manager;
+        }
+        let manager = managers.add(RenderPipelineManager::new(
+            GenericRenderPipelineDescriptor {
+                resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                    layout: self.pipeline_layout,
+                    vertex_shader_module: self.shader,
+                    fragment_shader_module: self.shader,
+                }),
+                label: Some("Shadow pipeline".to_string()),
+                vertex_state: GenericVertexState {
+                    entry_point: "vs_main".to_string(),
+                    buffers: vec![
+                        position_layout.clone(),
+                        ModelMatrices::vertex_buffer_layout(1),
+                    ],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: Some(GenericDepthStencilState {
+                    depth_write_enable: true,
+                    depth_compare: CompareFunction::LessEqual,
+                    stencil: StencilState::default(),
+                    // against shadow acne
+                    bias: DepthBiasState {
+                        constant: 2,
+                        slope_scale: 2.0,
+                        clamp: 0.0,
+                    },
+                }),
+                multisample: GenericMultisampleState {
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: None,
+            },
+        ));
+        self.variants.insert(position_layout, manager);
+        manager
+    }
+}
+
+/// Renders the [ShadowCasters](ShadowCaster) into every shadow view of the [ShadowMap] entity, clearing each layer first.
+/// Fails if the entity is not the [ShadowMap] used this frame.
+pub struct ShadowPassOperation {
+    pub shadow_map: Entity,
+}
+
+impl Operation for ShadowPassOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(shadows) = world.get_resource::<Shadows>() else {
+            return Err("no Shadows resource".into());
+        };
+        if shadows.map != Some(self.shadow_map) {
+            return Err(format!("{} is not the shadow map", self.shadow_map).into());
+        }
+        let views = shadows.views.clone();
+        let allocations = shadows.view_allocations.clone();
+        if views.is_empty() {
+            return Ok(());
+        }
+        let Some(model_matrices) = world.resource::<ModelMatrices>().buffer().cloned() else {
+            return Ok(());
+        };
+        let render_target = RenderTargetSource::Offscreen(self.shadow_map);
+
+        let mut casters =
+            world.query::<(&ShadowCaster, &ModelIndex, &GlobalTransform, Option<&Aabb>)>();
+        let casters: Vec<_> = casters
+            .iter(world)
+            .map(|(caster, index, global, aabb)| {
+                (*caster, index.0, aabb.map(|a| a.transformed(&global.0)))
+            })
+            .collect();
+        let mut draws = Vec::new();
+        for (caster, model_index, aabb) in casters {
+            let Some((position_layout, vertex_buffer, index_buffer, count)) =
+                world.get_asset(caster.mesh).and_then(|mesh| {
+                    let buffers = mesh.buffers()?;
+                    let layout = mesh.vertex_buffer_layouts().pop()?;
+                    let position = layout.attributes.iter().find(|a| {
+                        a.shader_location == caster.position_location
+                            && a.format == VertexFormat::Float32x3
+                    })?;
+                    let position_layout = GenericVertexBufferLayout {
+                        array_stride: layout.array_stride,
+                        step_mode: VertexStepMode::Vertex,
+                        attributes: vec![VertexAttribute {
+                            shader_location: 0,
+                            ..*position
+                        }],
+                    };
+                    let count = match buffers.index_buffer {
+                        Some(_) => buffers.index_count,
+                        None => buffers.vertex_count,
+                    };
+                    Some((
+                        position_layout,
+                        buffers.vertex_buffer.clone(),
+                        buffers.index_buffer.clone(),
+                        count,
+                    ))
+                })
+            else {
+                continue;
+            };
+            let manager = world.resource_scope(|world, mut shadows: Mut<Shadows>| {
+                let mut managers = world.resource_mut::<Assets<RenderPipelineManager>>();
+                shadows.pipeline_manager(&mut managers, position_layout)
+            });
+            let mut pipeline = None;
+            world.asset_scope(manager, |world, manager| {
+                pipeline = manager.get_compatible(render_target, world).cloned();
+            });
+            let Some(pipeline) = pipeline else {
+                continue;
+            };
+            draws.push((
+                pipeline,
+                vertex_buffer,
+                index_buffer,
+                count,
+                model_index,
+                aabb,
+            ));
+        }
+
+        let view_bind_groups: Vec<_> = world.resource_scope(|world, shadows: Mut<Shadows>| {
+            let device = world.resource::<RenderContext>().device.clone();
+            let mut allocator = world.resource_mut::<UniformAllocator>();
+            allocations
+                .iter()
+                .map(|a| {
+                    allocator
+                        .bind_group(&device, &shadows.view_layout, *a)
+                        .clone()
+                })
+                .collect()
+        });
+
+        let Some(mut rt) = render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let previous_layer = rt.layer();
+        for (i, view) in views.iter().enumerate() {
+            rt.set_layer(i as u32);
+            rt.schedule_clear_depth();
+            let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+                continue;
+            };
+            pass.set_bind_group(0, &view_bind_groups[i], &[allocations[i].offset()]);
+            pass.set_vertex_buffer(1, model_matrices.slice(..));
+            let frustum = Frustum::from_view_projection(&view.view_projection);
+            for (pipeline, vertex_buffer, index_buffer, count, model_index, aabb) in &draws {
+                if aabb.is_some_and(|aabb| !frustum.intersects_aabb(&aabb)) {
+                    continue;
+                }
+                let instance = *model_index..*model_index + 1;
+                pass.set_pipeline(pipeline);
+                pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                match index_buffer {
+                    Some((index_buffer, format)) => {
+                        pass.set_index_buffer(index_buffer.slice(..), *format);
+                        pass.draw_indexed(0..*count, 0, instance);
+                    }
+                    None => pass.draw(0..*count, instance),
+                }
+            }
+        }
+        rt.set_layer(previous_layer);
+        Ok(())
+    }
+}
+
+impl OperationBuilder for ShadowPassOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![RenderTargetSource::Offscreen(self.shadow_map)]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+pub(crate) fn init_shadows(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+) {
+    let device = &ctx.device;
+    let view_layout =
+        DynamicUniformLayoutProvider::from_raw(device, "shadow_view", "mat4x4<f32>", 64);
+    let mut composer = PipelineLayoutComposer::new();
+    composer.add_bind_group(view_layout.clone());
+    let pipeline_layout = layouts.add(composer.compose_pipeline_layout(device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(device, "Shadow shader", include_str!("shadow.wgsl"))
+            .expect("the built-in shadow shader has balanced directives")
+            .clone(),
+    );
+    let uniform = device.create_buffer(&BufferDescriptor {
+        label: Some("Shadow uniform"),
+        size: SHADOW_UNIFORM_SIZE,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let sampler = device.create_sampler(&SamplerDescriptor {
+        label: Some("Shadow sampler"),
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        compare: Some(CompareFunction::LessEqual),
+        ..Default::default()
+    });
+    commands.insert_resource(Shadows {
+        map: None,
+        views: Vec::new(),
+        uniform,
+        sampler,
+        bound_texture: None,
+        bind_group: None,
+        view_layout,
+        view_allocations: Vec::new(),
+        pipeline_layout,
+        shader,
+        variants: HashMap::new(),
+    });
+}
+
+/// A rotation to look along the direction, with +Y up unless it is parallel to it
+fn look_to(eye: Vec3, direction: Vec3) -> Mat4 {
+    let direction = direction.normalize_or(Vec3::NEG_Z);
+    let up = match direction.cross(Vec3::Y).length_squared() < 1e-6 {
+        true => Vec3::Z,
+        false => Vec3::Y,
+    };
+    Mat4::look_to_rh(eye, direction, up)
+}
+
+fn cascade_views(
+    world: &World,
+    light: &DirectionalLight,
+    direction: Vec3,
+    map_size: u32,
+) -> Vec<ShadowView> {
+    let Some(camera) = world.get::<Camera>(light.camera) else {
+        return Vec::new();
+    };
+    let Some((width, height)) = camera_view_size(world, light.camera) else {
+        return Vec::new();
+    };
+    let aspect_ratio = width as f32 / height as f32;
+    let camera_transform = world
+        .get::<GlobalTransform>(light.camera)
+        .map_or(camera.transform, |g| g.0);
+    let camera_view = camera_transform.inverse();
+    let camera_near = match camera.projection {
+        Projection::Perspective { near, .. } | Projection::Orthographic { near, .. } => near,
+    };
+    let rotation = look_to(Vec3::ZERO, direction);
+    let mut near = camera_near;
+    let mut views = Vec::new();
+    for &far in &light.cascade_splits {
+        let projection = match camera.projection {
+            Projection::Perspective { fov_y, .. } => Projection::Perspective { fov_y, near, far },
+            Projection::Orthographic { height, .. } => {
+                Projection::Orthographic { height, near, far }
+            }
+        };
+        let inverse = (projection.matrix(aspect_ratio) * camera_view).inverse();
+        let corners: Vec<Vec3> = [-1.0, 1.0]
+            .into_iter()
+            .flat_map(|x| [-1.0, 1.0].map(|y| (x, y)))
+            .flat_map(|(x, y)| [0.0, 1.0].map(|z| inverse.project_point3(Vec3::new(x, y, z))))
+            .collect();
+        let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|c| c.distance(center))
+            .fold(0.0, f32::max);
+        // rounded so the size does not change while the camera rotates
+        let radius = (radius * 16.0).ceil() / 16.0;
+        // snapped to texels in light space, so shadow edges do not shimmer when the camera moves
+        let texel = 2.0 * radius / map_size.max(1) as f32;
+        let light_center = rotation.transform_point3(center);
+        let snapped = (light_center / texel).floor() * texel;
+        let center = rotation
+            .inverse()
+            .transform_point3(snapped.truncate().extend(light_center.z));
+        let eye = center - direction.normalize_or(Vec3::NEG_Z) * (radius + light.caster_distance);
+        let view = look_to(eye, direction);
+        let projection = Mat4::orthographic_rh(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            2.0 * radius + light.caster_distance,
+        );
+        views.push(ShadowView::new(projection * view, far));
+        near = far;
+    }
+    views
+}
+
+pub(crate) fn prepare_shadows(world: &mut World) {
+    let mut maps = world.query_filtered::<(Entity, &OffscreenRenderTarget), With<ShadowMap>>();
+    let map = maps.iter(world).next().map(|(entity, rt)| {
+        (
+            entity,
+            rt.size().0,
+            rt.layer_count(),
+            rt.depth_stencil().cloned(),
+        )
+    });
+    let max_views = map
+        .as_ref()
+        .map_or(0, |m| (m.2 as usize).min(MAX_SHADOW_VIEWS));
+
+    let mut views = Vec::new();
+    let mut ranges = Vec::new();
+    let mut directional = world.query::<(Entity, &DirectionalLight, Option<&GlobalTransform>)>();
+    for (entity, light, global) in directional.iter(world) {
+        let direction = global.map_or(light.direction, |g| -g.0.z_axis.truncate());
+        let cascades = cascade_views(world, light, direction, map.as_ref().map_or(1, |m| m.1));
+        let first = views.len();
+        if first + cascades.len() <= max_views {
+            views.extend(cascades);
+        }
+        ranges.push((entity, first, views.len() - first));
+    }
+    let mut spot = world.query::<(Entity, &SpotLight, Option<&GlobalTransform>)>();
+    for (entity, light, global) in spot.iter(world) {
+        let (position, direction) = global.map_or((light.position, light.direction), |g| {
+            (g.translation(), -g.0.z_axis.truncate())
+        });
+        let first = views.len();
+        if first < max_views {
+            let projection =
+                Mat4::perspective_rh(2.0 * light.angle, 1.0, light.range * 0.01, light.range);
+            views.push(ShadowView::new(
+                projection * look_to(position, direction),
+                light.range,
+            ));
+        }
+        ranges.push((entity, first, views.len() - first));
+    }
+    for (entity, first, count) in ranges {
+        let range = ShadowViewRange {
+            first: first as u32,
+            count: count as u32,
+        };
+        if let Some(mut current) = world.get_mut::<ShadowViewRange>(entity) {
+            current.set_if_neq(range);
+        }
+    }
+
+    world.resource_scope(|world, mut shadows: Mut<Shadows>| {
+        let ctx = world.resource::<RenderContext>();
+        let mut uniform = vec![0u8; SHADOW_UNIFORM_SIZE as usize];
+        let views_size = views.len() * size_of::<ShadowView>();
+        uniform[..views_size].copy_from_slice(bytemuck::cast_slice(&views));
+        let count_offset = MAX_SHADOW_VIEWS * size_of::<ShadowView>();
+        uniform[count_offset..count_offset + 4]
+            .copy_from_slice(&(views.len() as u32).to_ne_bytes());
+        ctx.queue.write_buffer(&shadows.uniform, 0, &uniform);
+
+        let texture = map.as_ref().and_then(|m| m.3.clone());
+        if texture != shadows.bound_texture {
+            // only checked when the texture changes, so this warns once
+            let bindable = texture.as_ref().filter(|texture| {
+                let bindable = texture.usage().contains(TextureUsages::TEXTURE_BINDING)
+                    && texture.sample_count() == 1;
+                if !bindable {
+                    warn!(
+                        "The shadow map needs TEXTURE_BINDING usage and one sample to be sampled"
+                    );
+                }
+                bindable
+            });
+            shadows.bind_group = bindable.map(|texture| {
+                let view = texture.create_view(&TextureViewDescriptor {
+                    label: Some("Shadow map array view"),
+                    dimension: Some(TextureViewDimension::D2Array),
+                    aspect: TextureAspect::DepthOnly,
+                    ..Default::default()
+                });
+                let layout = world.resource::<CachedBindGroupLayout<ShadowLayout>>();
+                ctx.device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Shadow bind group"),
+                    layout: layout.layout(),
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(&view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Sampler(&shadows.sampler),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: shadows.uniform.as_entire_binding(),
+                        },
+                    ],
+                })
+            });
+            shadows.bound_texture = texture;
+        }
+
+        let device = ctx.device.clone();
+        let mut allocator = world.resource_mut::<UniformAllocator>();
+        shadows.view_allocations = views
+            .iter()
+            .map(|view| allocator.push_bytes(&device, bytemuck::bytes_of(&view.view_projection)))
+            .collect();
+        shadows.map = map.as_ref().map(|m| m.0);
+        shadows.views = views;
+    });
+}
@@ -0,0 +1,11 @@
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @location(1) model_0: vec4<f32>,
+    @location(2) model_1: vec4<f32>,
+    @location(3) model_2: vec4<f32>,
+    @location(4) model_3: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(model_0, model_1, model_2, model_3);
+    return shadow_view * model * vec4<f32>(position, 1.0);
+}
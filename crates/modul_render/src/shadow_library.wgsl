@@ -0,0 +1,40 @@
+struct ShadowView {
+    view_projection: mat4x4<f32>,
+    split_far: f32,
+}
+
+struct Shadows {
+    views: array<ShadowView, 16>,
+    count: u32,
+}
+
+@group(#BIND_GROUP) @binding(0)
+var shadow_map: texture_depth_2d_array;
+@group(#BIND_GROUP) @binding(1)
+var shadow_sampler: sampler_comparison;
+@group(#BIND_GROUP) @binding(2)
+var<uniform> shadows: Shadows;
+
+// 1 where the position is lit in the shadow view, 0 in shadow, positions outside the view are lit
+fn shadow_factor(view: u32, world_position: vec3<f32>) -> f32 {
+    if view >= shadows.count {
+        return 1.0;
+    }
+    let clip = shadows.views[view].view_projection * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z < 0.0 || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    return textureSampleCompareLevel(shadow_map, shadow_sampler, uv, view, ndc.z);
+}
+
+// the shadow factor of the first cascade reaching view_depth (the distance along the camera's forward axis)
+fn cascade_shadow_factor(first: u32, count: u32, view_depth: f32, world_position: vec3<f32>) -> f32 {
+    for (var i = 0u; i < count; i++) {
+        if view_depth <= shadows.views[first + i].split_far {
+            return shadow_factor(first + i, world_position);
+        }
+    }
+    return 1.0;
+}
@@ -0,0 +1,193 @@
+use crate::{
+    BindGroupLayoutProvider, BindGroupProvider, CachedBindGroupLayout, CameraBindGroup,
+    CameraUniformProvider, Cubemap, CubemapLayout, DirectRenderPipelineResourceProvider,
+    GenericColorTargetState, GenericDepthStencilState, GenericFragmentState,
+    GenericMultisampleState, GenericRenderPipelineDescriptor, GenericVertexState, Operation,
+    OperationBuilder, OperationError, PipelineLayoutComposer, RenderPipelineManager,
+    RenderTargetSource, SamplerCache,
+};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use wgpu::{
+    BindGroup, BindGroupLayout, CommandEncoder, CompareFunction, DepthBiasState, Device,
+    FilterMode, MipmapFilterMode, PipelineLayout, PrimitiveState, SamplerDescriptor, ShaderModule,
+    StencilState, Texture,
+};
+
+/// The shared pipeline of [SkyboxOperations](SkyboxOperation), inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct SkyboxPipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+    /// The [CubemapLayout]
+    pub bind_group_layout: BindGroupLayout,
+}
+
+/// Draws a [Cubemap] as the background of the target, as seen by the [Camera](crate::Camera) entity.
+/// The skybox is drawn on the far plane and only where the depth buffer is still cleared, so it can be drawn before or after opaque geometry.
+/// Drawing it after saves shading the covered pixels. Without a depth buffer it covers the whole target.
+/// Nothing is drawn while the cubemap asset is empty, for example while it is loading.
+pub struct SkyboxOperation {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+    pub cubemap: AssetId<Cubemap>,
+}
+
+impl SkyboxOperation {
+    pub fn new(
+        render_target: RenderTargetSource,
+        camera: Entity,
+        cubemap: AssetId<Cubemap>,
+    ) -> Self {
+        Self {
+            render_target,
+            camera,
+            cubemap,
+        }
+    }
+}
+
+impl OperationBuilder for SkyboxOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        PreparedSkybox {
+            operation: self,
+            bound_texture: None,
+            bind_group: None,
+        }
+    }
+}
+
+struct PreparedSkybox {
+    operation: SkyboxOperation,
+    /// the texture the bind group was created for
+    bound_texture: Option<Texture>,
+    bind_group: Option<BindGroup>,
+}
+
+impl Operation for PreparedSkybox {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let SkyboxOperation {
+            render_target,
+            camera,
+            cubemap,
+        } = self.operation;
+        let Some(camera) = world.get::<CameraBindGroup>(camera) else {
+            return Err(format!("{} has no camera bind group", camera).into());
+        };
+        let camera = camera.bind_group().clone();
+        let Some(texture) = world
+            .resource::<Assets<Cubemap>>()
+            .get(cubemap)
+            .map(|c| c.texture.clone())
+        else {
+            return Ok(());
+        };
+        // the asset may have been replaced, for example when reloaded
+        if self.bound_texture.as_ref() != Some(&texture) {
+            let device = world.resource::<RenderContext>().device.clone();
+            let sampler = world
+                .resource_mut::<SamplerCache>()
+                .get(
+                    &device,
+                    &SamplerDescriptor {
+                        label: Some("Skybox sampler"),
+                        mag_filter: FilterMode::Linear,
+                        min_filter: FilterMode::Linear,
+                        mipmap_filter: MipmapFilterMode::Linear,
+                        ..Default::default()
+                    },
+                )
+                .clone();
+            let layout = &world.resource::<SkyboxPipeline>().bind_group_layout;
+            let cubemap = world.resource::<Assets<Cubemap>>().get(cubemap).unwrap();
+            self.bind_group = Some(cubemap.create_bind_group(&device, layout, &sampler));
+            self.bound_texture = Some(texture);
+        }
+        let manager = world.resource::<SkyboxPipeline>().manager;
+        let mut pipeline = None;
+        world.asset_scope(manager, |world, manager| {
+            pipeline = manager.get_compatible(render_target, world).cloned();
+        });
+        let (Some(pipeline), Some(bind_group)) = (pipeline, &self.bind_group) else {
+            return Ok(());
+        };
+        let Some(mut rt) = render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &camera, &[]);
+        pass.set_bind_group(1, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+pub(crate) fn init_skybox_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    let cubemap_layout = CachedBindGroupLayout::<CubemapLayout>::new(&ctx.device);
+    let bind_group_layout = cubemap_layout.layout().clone();
+    let mut composer = PipelineLayoutComposer::new();
+    composer.add_bind_group(CameraUniformProvider::new(&ctx.device));
+    composer.add_bind_group(cubemap_layout);
+    let layout = layouts.add(composer.compose_pipeline_layout(&ctx.device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(&ctx.device, "Skybox shader", include_str!("skybox.wgsl"))
+            .expect("the built-in skybox shader has balanced directives")
+            .clone(),
+    );
+    let manager = managers.add(RenderPipelineManager::new(
+        GenericRenderPipelineDescriptor {
+            resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                layout,
+                vertex_shader_module: shader,
+                fragment_shader_module: shader,
+            }),
+            label: Some("Skybox pipeline".to_string()),
+            vertex_state: GenericVertexState {
+                entry_point: "vs_main".to_string(),
+                buffers: vec![],
+            },
+            primitive: PrimitiveState::default(),
+            // only where nothing was drawn yet
+            depth_stencil: Some(GenericDepthStencilState {
+                depth_write_enable: false,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: GenericMultisampleState {
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(GenericFragmentState {
+                entry_point: "fs_main".to_string(),
+                targets: vec![GenericColorTargetState::new(None)],
+            }),
+        },
+    ));
+    commands.insert_resource(SkyboxPipeline {
+        manager,
+        bind_group_layout,
+    });
+}
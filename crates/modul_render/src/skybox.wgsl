@@ -0,0 +1,24 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// a fullscreen triangle on the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    out.position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the direction from the near to the far plane, in homogeneous coordinates so infinite projections work
+    let near = camera.inverse_view_projection * vec4<f32>(in.ndc, 0.0, 1.0);
+    let far = camera.inverse_view_projection * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = far.xyz * near.w - near.xyz * far.w;
+    return vec4<f32>(textureSample(environment_map, environment_sampler, direction).rgb, 1.0);
+}
@@ -0,0 +1,114 @@
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use std::num::NonZero;
+use wgpu::util::StagingBelt;
+use wgpu::{
+    Buffer, BufferAddress, CommandBuffer, CommandEncoder, CommandEncoderDescriptor, Device,
+    Extent3d, TexelCopyBufferInfo, TexelCopyBufferLayout, TexelCopyTextureInfo,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+/// Size of the staging buffers, uploads larger than this get their own buffer
+const CHUNK_SIZE: BufferAddress = 1 << 20;
+
+/// Uploads buffer and texture data through reused staging buffers instead of creating new ones for each write.
+/// The uploads are submitted before the sequences in [SequenceRunnerSet](crate::SequenceRunnerSet), so writes from [Synchronize](crate::Synchronize) and [PreDraw](crate::PreDraw) are visible to them.
+/// This is inserted during [Init](modul_core::Init).
+#[derive(Resource)]
+pub struct StagingUploads {
+    belt: StagingBelt,
+    encoder: Option<CommandEncoder>,
+    device: Device,
+}
+
+impl StagingUploads {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            belt: StagingBelt::new(device.clone(), CHUNK_SIZE),
+            encoder: None,
+            device: device.clone(),
+        }
+    }
+
+    /// Writes data to a buffer at the offset
+    /// ## Panics
+    /// If the data is empty, or the offset or data length are not multiples of [COPY_BUFFER_ALIGNMENT](wgpu::COPY_BUFFER_ALIGNMENT)
+    pub fn write_buffer(&mut self, target: &Buffer, offset: BufferAddress, data: &[u8]) {
+        let size = NonZero::new(data.len() as u64).expect("can not write empty data");
+        let encoder = Self::get_encoder(&mut self.encoder, &self.device);
+        self.belt
+            .write_buffer(encoder, target, offset, size)
+            .copy_from_slice(data);
+    }
+
+    /// Writes tightly packed texel data to a texture, the rows are padded as needed for the copy.
+    /// `bytes_per_row` is the size of one row of blocks in `data`.
+    pub fn write_texture(
+        &mut self,
+        target: TexelCopyTextureInfo,
+        data: &[u8],
+        bytes_per_row: u32,
+        size: Extent3d,
+    ) {
+        let padded_bytes_per_row = bytes_per_row.next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let rows = data.len() / bytes_per_row as usize;
+        let mut padded = vec![0u8; rows * padded_bytes_per_row as usize];
+        for (src, dst) in data
+            .chunks(bytes_per_row as usize)
+            .zip(padded.chunks_mut(padded_bytes_per_row as usize))
+        {
+            dst[..src.len()].copy_from_slice(src);
+        }
+        let rows_per_image = rows as u32 / size.depth_or_array_layers.max(1);
+        let slice = self.belt.allocate(
+            NonZero::new(padded.len() as u64).expect("can not write empty data"),
+            NonZero::new(COPY_BYTES_PER_ROW_ALIGNMENT as u64).unwrap(),
+        );
+        slice.get_mapped_range_mut().copy_from_slice(&padded);
+        let source = TexelCopyBufferInfo {
+            buffer: slice.buffer(),
+            layout: TexelCopyBufferLayout {
+                offset: slice.offset(),
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(rows_per_image),
+            },
+        };
+        Self::get_encoder(&mut self.encoder, &self.device)
+            .copy_buffer_to_texture(source, target, size);
+    }
+
+    /// The encoder uploads are recorded in, it is submitted before the sequences.
+    /// Useful for copies that should happen together with the uploads.
+    pub fn encoder(&mut self) -> &mut CommandEncoder {
+        Self::get_encoder(&mut self.encoder, &self.device)
+    }
+
+    /// Closes the staging buffers and returns the recorded uploads, if there are any.
+    /// [recall](Self::recall) must be called after submitting them.
+    /// This is done automatically by the sequence runner.
+    pub fn finish(&mut self) -> Option<CommandBuffer> {
+        self.belt.finish();
+        self.encoder.take().map(|e| e.finish())
+    }
+
+    /// Makes the staging buffers of submitted uploads reusable once the GPU is done with them
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+
+    // not a method, so the belt can be borrowed at the same time
+    fn get_encoder<'a>(
+        encoder: &'a mut Option<CommandEncoder>,
+        device: &Device,
+    ) -> &'a mut CommandEncoder {
+        encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Staging upload encoder"),
+            })
+        })
+    }
+}
+
+pub(crate) fn init_staging_uploads(mut commands: Commands, ctx: Res<RenderContext>) {
+    commands.insert_resource(StagingUploads::new(&ctx.device));
+}
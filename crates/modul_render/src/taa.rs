@@ -0,0 +1,192 @@
+use bevy_ecs::prelude::*;
+use glam::Vec2;
+use wgpu::{CommandEncoder, Device};
+
+use crate::{
+    Operation, OperationBuilder, OperationError, PingPongTarget, PostProcessOperation,
+    RenderTargetSource,
+};
+
+/// Offsets the projection of the [Camera](crate::Camera) on the same entity by a different subpixel amount every frame, for a [TaaOperation].
+/// The offsets follow the Halton (2, 3) sequence, starting over after `length` frames.
+/// The offset of the current frame is in the [CameraUniform](crate::CameraUniform) as `jitter`.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CameraJitter {
+    pub length: u32,
+    index: u32,
+}
+
+impl CameraJitter {
+    pub fn new(length: u32) -> Self {
+        Self { length, index: 0 }
+    }
+
+    /// The offset of the current frame in pixels, between -0.5 and 0.5
+    pub fn offset(&self) -> Vec2 {
+        // starting at 1, as the sequence starts at 0 for every base
+        let index = self.index % self.length.max(1) + 1;
+        Vec2::new(halton(index, 2), halton(index, 3)) - Vec2::splat(0.5)
+    }
+}
+
+impl Default for CameraJitter {
+    /// 8 frames
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+pub(crate) fn advance_camera_jitter(mut jitters: Query<&mut CameraJitter>) {
+    for mut jitter in jitters.iter_mut() {
+        jitter.index = jitter.index.wrapping_add(1);
+    }
+}
+
+/// Temporal anti-aliasing, blends a frame rendered with a [CameraJitter] into the history of a [PingPongTarget], an alternative to MSAA for deferred pipelines.
+/// The history is reprojected with the velocity buffer and clamped to the colors around each pixel of the current frame, so it does not ghost.
+/// The result is written to the [write](PingPongTarget::write) side of the history, blit it to the surface afterwards.
+/// While the history is not [valid](PingPongTarget::history_valid) the current frame is copied instead.
+/// The inputs need [TEXTURE_BINDING](wgpu::TextureUsages::TEXTURE_BINDING) usage, and the settings are baked into the shader.
+pub struct TaaOperation {
+    /// The jittered frame
+    pub color: RenderTargetSource,
+    /// The motion of each pixel since the last frame in UV (`current - previous`), the same size as `color`, usually `Rg16Float`
+    pub velocity: RenderTargetSource,
+    /// The entity of the [PingPongTarget], with the same size as `color`
+    pub history: Entity,
+    /// How much of the current frame is blended in, lower values are smoother but take longer to converge.
+    /// Clamped to 0..1, NaN uses 0.1.
+    pub current_weight: f32,
+}
+
+impl TaaOperation {
+    /// Blends in 10% of the current frame
+    pub fn new(color: RenderTargetSource, velocity: RenderTargetSource, history: Entity) -> Self {
+        Self {
+            color,
+            velocity,
+            history,
+            current_weight: 0.1,
+        }
+    }
+
+    fn snippet(&self) -> String {
+        format!(
+            "{YCOCG_WGSL}
+fn post_process(uv: vec2<f32>) -> vec4<f32> {{
+    let size = vec2<i32>(textureDimensions(input_0));
+    let pixel = clamp(vec2<i32>(uv * vec2<f32>(size)), vec2<i32>(0), size - 1);
+    let current = textureLoad(input_0, pixel, 0);
+    // the history is clamped to the colors around the pixel, which rejects disoccluded and changed parts
+    var low = vec3<f32>(1e30);
+    var high = vec3<f32>(-1e30);
+    for (var y = -1; y <= 1; y++) {{
+        for (var x = -1; x <= 1; x++) {{
+            let neighbor = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let color = rgb_to_ycocg(textureLoad(input_0, neighbor, 0).rgb);
+            low = min(low, color);
+            high = max(high, color);
+        }}
+    }}
+    let velocity_size = vec2<i32>(textureDimensions(input_1));
+    let velocity_pixel = clamp(vec2<i32>(uv * vec2<f32>(velocity_size)), vec2<i32>(0), velocity_size - 1);
+    let previous_uv = uv - textureLoad(input_1, velocity_pixel, 0).xy;
+    let history = textureSampleLevel(input_2, input_sampler, previous_uv, 0.0).rgb;
+    let clamped = ycocg_to_rgb(clamp(rgb_to_ycocg(history), low, high));
+    // there is no history for parts that were outside of the last frame
+    let outside = any(previous_uv < vec2<f32>(0.0)) || any(previous_uv > vec2<f32>(1.0));
+    let weight = select({weight:?}, 1.0, outside);
+    return vec4<f32>(mix(clamped, current.rgb, weight), current.a);
+}}
+",
+            // NaN is not valid in the shader
+            weight = match self.current_weight.is_nan() {
+                true => 0.1,
+                false => self.current_weight.clamp(0.0, 1.0),
+            },
+        )
+    }
+}
+
+const YCOCG_WGSL: &str = "fn rgb_to_ycocg(c: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+        0.5 * c.r - 0.5 * c.b,
+        -0.25 * c.r + 0.5 * c.g - 0.25 * c.b,
+    );
+}
+
+fn ycocg_to_rgb(c: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(c.x + c.y - c.z, c.x + c.z, c.x - c.y - c.z);
+}";
+
+const COPY_WGSL: &str = "fn post_process(uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(input_0));
+    return textureLoad(input_0, clamp(vec2<i32>(uv * vec2<f32>(size)), vec2<i32>(0), size - 1), 0);
+}";
+
+impl OperationBuilder for TaaOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        vec![
+            self.color,
+            self.velocity,
+            PingPongTarget::read(self.history),
+        ]
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![PingPongTarget::write(self.history)]
+    }
+
+    fn finish(self, world: &World, device: &Device) -> impl Operation + 'static {
+        let destination = PingPongTarget::write(self.history);
+        let resolve = PostProcessOperation::new(
+            self.snippet(),
+            vec![
+                self.color,
+                self.velocity,
+                PingPongTarget::read(self.history),
+            ],
+            destination,
+        );
+        let copy = PostProcessOperation::new(COPY_WGSL, vec![self.color], destination);
+        PreparedTaa {
+            history: self.history,
+            resolve: resolve.finish(world, device),
+            copy: copy.finish(world, device),
+        }
+    }
+}
+
+struct PreparedTaa<R, C> {
+    history: Entity,
+    resolve: R,
+    copy: C,
+}
+
+impl<R: Operation, C: Operation> Operation for PreparedTaa<R, C> {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let history_valid = world
+            .get::<PingPongTarget>(self.history)
+            .is_some_and(|history| history.history_valid());
+        match history_valid {
+            true => self.resolve.run(world, command_encoder),
+            false => self.copy.run(world, command_encoder),
+        }
+    }
+}
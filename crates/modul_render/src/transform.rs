@@ -0,0 +1,195 @@
+use crate::GenericVertexBufferLayout;
+use crate::GpuVec;
+use bevy_ecs::prelude::*;
+use glam::{Mat4, Quat, Vec3};
+use modul_core::RenderContext;
+use wgpu::{BufferSlice, BufferUsages, VertexAttribute, VertexFormat, VertexStepMode};
+
+/// [SystemSet] within [Synchronize](crate::Synchronize) that computes the [GlobalTransform] of every entity with a [Transform]
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransformPropagateSet;
+
+/// [SystemSet] within [Synchronize](crate::Synchronize) that writes the [ModelMatrices] to the GPU, it runs after [TransformPropagateSet]
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModelMatrixUploadSet;
+
+/// The transform of an entity relative to its parent ([ChildOf]), or to the world if it has no parent with a transform
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[require(GlobalTransform)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self::IDENTITY.with_translation(translation)
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self::IDENTITY.with_rotation(rotation)
+    }
+
+    pub fn from_scale(scale: Vec3) -> Self {
+        Self::IDENTITY.with_scale(scale)
+    }
+
+    pub fn with_translation(mut self, translation: Vec3) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Scales, then rotates, then translates
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+/// The world transform of an entity, computed from its [Transform] and the ones of its parents during [TransformPropagateSet].
+/// Changes made after that are only visible the next frame.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct GlobalTransform(pub Mat4);
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self(Mat4::IDENTITY)
+    }
+}
+
+impl GlobalTransform {
+    pub fn translation(&self) -> Vec3 {
+        self.0.w_axis.truncate()
+    }
+}
+
+/// Marks an entity to have its [GlobalTransform] uploaded to the [ModelMatrices]
+#[derive(Component, Clone, Copy, Debug, Default)]
+#[require(Transform, ModelIndex)]
+pub struct GpuTransform;
+
+/// The index of the model matrix of a [GpuTransform] entity in the [ModelMatrices], set every frame during [ModelMatrixUploadSet].
+/// Indices change when entities are added or removed, so they should be read every frame.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModelIndex(pub u32);
+
+/// The model matrices of all [GpuTransform] entities, packed into one buffer every frame.
+/// The buffer can be used as instance vertex buffer (see [vertex_buffer_layout](Self::vertex_buffer_layout)) or as storage buffer,
+/// use the [ModelIndex] of an entity as instance index or as index in the storage array.
+#[derive(Resource)]
+pub struct ModelMatrices {
+    matrices: GpuVec<Mat4>,
+}
+
+impl Default for ModelMatrices {
+    fn default() -> Self {
+        Self {
+            matrices: GpuVec::new(BufferUsages::VERTEX | BufferUsages::STORAGE),
+        }
+    }
+}
+
+impl ModelMatrices {
+    pub fn len(&self) -> usize {
+        self.matrices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matrices.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[Mat4] {
+        self.matrices.as_slice()
+    }
+
+    /// See [GpuVec::buffer]
+    pub fn buffer(&self) -> Option<&wgpu::Buffer> {
+        self.matrices.buffer()
+    }
+
+    /// See [GpuVec::slice]
+    pub fn slice(&self) -> Option<BufferSlice<'_>> {
+        self.matrices.slice()
+    }
+
+    /// Increased every time the buffer is recreated, storage bind groups must be recreated when this changes
+    pub fn buffer_version(&self) -> u32 {
+        self.matrices.buffer_version()
+    }
+
+    /// An instance step layout passing the matrix as 4 column vectors, at `first_location` and the 3 following locations.
+    /// In WGSL the matrix is `mat4x4<f32>(c0, c1, c2, c3)`.
+    pub fn vertex_buffer_layout(first_location: u32) -> GenericVertexBufferLayout {
+        GenericVertexBufferLayout {
+            array_stride: size_of::<Mat4>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..4)
+                .map(|column| VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: column as u64 * VertexFormat::Float32x4.size(),
+                    shader_location: first_location + column,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub(crate) fn propagate_transforms(
+    entities: Query<(Entity, Option<&ChildOf>), With<Transform>>,
+    mut transforms: Query<(&Transform, &mut GlobalTransform, Option<&Children>)>,
+) {
+    let mut stack = Vec::new();
+    for (root, child_of) in entities.iter() {
+        // children are reached from their root
+        if child_of.is_some_and(|c| transforms.contains(c.parent())) {
+            continue;
+        }
+        stack.push((root, Mat4::IDENTITY));
+        while let Some((entity, parent)) = stack.pop() {
+            let Ok((transform, mut global, children)) = transforms.get_mut(entity) else {
+                continue;
+            };
+            let matrix = parent * transform.matrix();
+            global.set_if_neq(GlobalTransform(matrix));
+            if let Some(children) = children {
+                stack.extend(children.iter().map(|child| (child, matrix)));
+            }
+        }
+    }
+}
+
+pub(crate) fn upload_model_matrices(
+    ctx: Res<RenderContext>,
+    mut model_matrices: ResMut<ModelMatrices>,
+    mut query: Query<(&GlobalTransform, &mut ModelIndex), With<GpuTransform>>,
+) {
+    let matrices = &mut model_matrices.matrices;
+    matrices.clear();
+    for (global, mut index) in query.iter_mut() {
+        index.set_if_neq(ModelIndex(matrices.len() as u32));
+        matrices.push(global.0);
+    }
+    matrices.flush(&ctx.device, &ctx.queue);
+}
@@ -0,0 +1,224 @@
+use crate::{BindGroupLayoutProvider, UniformType};
+use bevy_ecs::prelude::*;
+use modul_core::RenderContext;
+use modul_util::HashMap;
+use std::num::NonZero;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBinding, BufferBindingType,
+    BufferDescriptor, BufferUsages, Device, Queue, ShaderStages,
+};
+
+/// [SystemSet] within [Draw](crate::Draw) that writes the allocations of the [UniformAllocator] to the GPU, it runs before [SequenceRunnerSet](crate::SequenceRunnerSet)
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UniformFlushSet;
+
+/// Where a uniform was put by the [UniformAllocator], only valid for the frame it was allocated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniformAllocation {
+    block: usize,
+    offset: u32,
+    size: u64,
+}
+
+impl UniformAllocation {
+    /// The dynamic offset to pass to [set_bind_group](wgpu::RenderPass::set_bind_group)
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+}
+
+/// Sub-allocates small uniforms from large buffers, so draws don't need their own buffers and bind groups.
+/// Allocations are bound using a [DynamicUniformLayoutProvider] and a dynamic offset.
+/// The allocator is reset at the end of every frame, and written to the GPU during [UniformFlushSet].
+/// Allocations made after that (for example while running sequences) need a manual [flush](Self::flush).
+#[derive(Resource, Default)]
+pub struct UniformAllocator {
+    blocks: Vec<UniformBlock>,
+    /// 0 until the first block is created
+    alignment: u64,
+    block_size: u64,
+}
+
+struct UniformBlock {
+    buffer: Buffer,
+    data: Vec<u8>,
+    /// how much of data has been written to the buffer
+    flushed: usize,
+    bind_groups: HashMap<(BindGroupLayout, u64), BindGroup>,
+}
+
+impl UniformAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates a uniform and writes the value to it
+    pub fn push<Ty: UniformType>(
+        &mut self,
+        device: &Device,
+        value: Ty::Resource,
+    ) -> UniformAllocation {
+        let mut bytes = vec![0u8; Ty::wgsl_uniform_type().byte_size()];
+        Ty::set_bytes(value, &mut bytes);
+        self.push_bytes(device, &bytes)
+    }
+
+    /// Allocates a uniform with the bytes as content
+    /// ## Panics
+    /// If the bytes are larger than the max uniform binding size of the device (or 64KiB)
+    pub fn push_bytes(&mut self, device: &Device, bytes: &[u8]) -> UniformAllocation {
+        if self.alignment == 0 {
+            let limits = device.limits();
+            self.alignment = limits.min_uniform_buffer_offset_alignment as u64;
+            self.block_size = limits.max_uniform_buffer_binding_size.min(1 << 16);
+        }
+        let size = bytes.len() as u64;
+        if size > self.block_size {
+            panic!("uniform of {} bytes is larger than the block size", size);
+        }
+        let alignment = self.alignment as usize;
+        let block_size = self.block_size as usize;
+        let fits = |block: &UniformBlock| {
+            block.data.len().next_multiple_of(alignment) + bytes.len() <= block_size
+        };
+        let block = match self.blocks.iter().position(fits) {
+            Some(block) => block,
+            None => {
+                self.blocks.push(UniformBlock {
+                    buffer: device.create_buffer(&BufferDescriptor {
+                        label: Some("uniform allocator block"),
+                        size: self.block_size,
+                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    data: Vec::with_capacity(self.block_size as usize),
+                    flushed: 0,
+                    bind_groups: HashMap::new(),
+                });
+                self.blocks.len() - 1
+            }
+        };
+        let data = &mut self.blocks[block].data;
+        let offset = data.len().next_multiple_of(alignment);
+        data.resize(offset, 0);
+        data.extend_from_slice(bytes);
+        UniformAllocation {
+            block,
+            offset: offset as u32,
+            size,
+        }
+    }
+
+    /// The bind group to use for an allocation, together with [offset](UniformAllocation::offset).
+    /// Bind groups are cached, so allocations of the same size in the same block share one.
+    pub fn bind_group(
+        &mut self,
+        device: &Device,
+        provider: &DynamicUniformLayoutProvider,
+        allocation: UniformAllocation,
+    ) -> &BindGroup {
+        let block = &mut self.blocks[allocation.block];
+        let buffer = &block.buffer;
+        block
+            .bind_groups
+            .entry((provider.layout.clone(), allocation.size))
+            .or_insert_with(|| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("uniform allocator bind group"),
+                    layout: &provider.layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer,
+                            offset: 0,
+                            size: NonZero::new(allocation.size),
+                        }),
+                    }],
+                })
+            })
+    }
+
+    /// Writes allocations made since the last flush to the GPU
+    pub fn flush(&mut self, queue: &Queue) {
+        for block in &mut self.blocks {
+            if block.flushed < block.data.len() {
+                queue.write_buffer(
+                    &block.buffer,
+                    block.flushed as u64,
+                    &block.data[block.flushed..],
+                );
+                block.flushed = block.data.len();
+            }
+        }
+    }
+
+    /// Frees all allocations, buffers and bind groups are kept for the next frame
+    pub fn reset(&mut self) {
+        for block in &mut self.blocks {
+            block.data.clear();
+            block.flushed = 0;
+        }
+    }
+}
+
+/// A bind group layout with a single uniform binding using a dynamic offset, for use with the [UniformAllocator]
+#[derive(Clone)]
+pub struct DynamicUniformLayoutProvider {
+    layout: BindGroupLayout,
+    library: String,
+}
+
+impl DynamicUniformLayoutProvider {
+    /// Creates the layout for a uniform of the given type, named `name` in the shader
+    pub fn new<Ty: UniformType>(device: &Device, name: &str) -> Self {
+        let uniform_type = Ty::wgsl_uniform_type();
+        Self::from_raw(
+            device,
+            name,
+            uniform_type.wgsl_type_name(),
+            uniform_type.byte_size() as u64,
+        )
+    }
+
+    /// Like [new](Self::new), but for any WGSL type, for example a struct declared in a snippet
+    pub fn from_raw(device: &Device, name: &str, wgsl_type_name: &str, size: u64) -> Self {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Dynamic uniform BGLayout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZero::new(size),
+                },
+                count: None,
+            }],
+        });
+        Self {
+            layout,
+            library: format!(
+                "@group(#BIND_GROUP) @binding(0)\nvar<uniform> {name}: {wgsl_type_name};"
+            ),
+        }
+    }
+}
+
+impl BindGroupLayoutProvider for DynamicUniformLayoutProvider {
+    fn layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    fn library(&self) -> &str {
+        &self.library
+    }
+}
+
+pub(crate) fn flush_uniforms(mut allocator: ResMut<UniformAllocator>, ctx: Res<RenderContext>) {
+    allocator.flush(&ctx.queue);
+}
+
+pub(crate) fn reset_uniforms(mut allocator: ResMut<UniformAllocator>) {
+    allocator.reset();
+}
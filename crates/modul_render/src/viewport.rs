@@ -0,0 +1,167 @@
+use crate::{
+    Camera, ClearRegionPipeline, Operation, OperationBuilder, OperationError, RenderTargetSource,
+    ScissorRect, Viewport,
+};
+use bevy_ecs::prelude::*;
+use modul_asset::AssetWorldExt;
+use wgpu::{Color, CommandEncoder, Device};
+
+/// Restricts a [Camera] to a rect of its render target, so several cameras can share one target for split-screen or editor layouts.
+/// The rect is given as fractions of the target size with the origin at the top left, so it follows resizes.
+/// The aspect ratio of the camera is the one of the rect, its operations should run after a [BeginViewport].
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct CameraViewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// What [BeginViewport] clears in the rect
+    pub clear: ViewportClear,
+}
+
+impl CameraViewport {
+    /// A viewport that is not cleared
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            clear: ViewportClear::default(),
+        }
+    }
+
+    pub fn with_clear(mut self, clear: ViewportClear) -> Self {
+        self.clear = clear;
+        self
+    }
+
+    /// The rect in pixels on a target of the size, the edges are rounded so neighbouring viewports don't overlap
+    pub fn rect(&self, target_size: (u32, u32)) -> ScissorRect {
+        let edge =
+            |fraction: f32, size: u32| (fraction.clamp(0.0, 1.0) * size as f32).round() as u32;
+        let (x, y) = (edge(self.x, target_size.0), edge(self.y, target_size.1));
+        let right = edge(self.x + self.width, target_size.0).max(x);
+        let bottom = edge(self.y + self.height, target_size.1).max(y);
+        ScissorRect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// What [BeginViewport] clears, regular clears always clear the whole target so this draws into the rect like [ClearRegion](crate::ClearRegion)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ViewportClear {
+    /// Drawn as the blend constant, so the format has to be blendable
+    pub color: Option<Color>,
+    /// Sets the depth to 1, stencil is not changed
+    pub depth: bool,
+}
+
+/// The size in pixels the [Camera] entity renders at, the size of its [CameraViewport] if it has one.
+/// None while the target does not exist or the size is zero.
+pub fn camera_view_size(world: &World, camera: Entity) -> Option<(u32, u32)> {
+    let target_size = world
+        .get::<Camera>(camera)?
+        .render_target
+        .get(world)?
+        .size();
+    let size = match world.get::<CameraViewport>(camera) {
+        Some(viewport) => {
+            let rect = viewport.rect(target_size);
+            (rect.width, rect.height)
+        }
+        None => target_size,
+    };
+    (size.0 != 0 && size.1 != 0).then_some(size)
+}
+
+/// Sets the viewport and scissor rect of the target to the [CameraViewport] of the camera, and clears the rect as configured.
+/// Cameras without a [CameraViewport] use the whole target.
+/// Passes created afterwards stay in the rect until the next [BeginViewport] or a [SetViewport](crate::SetViewport) with None.
+pub struct BeginViewport {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+}
+
+impl BeginViewport {
+    pub fn new(render_target: RenderTargetSource, camera: Entity) -> Self {
+        Self {
+            render_target,
+            camera,
+        }
+    }
+}
+
+impl Operation for BeginViewport {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let viewport = world.get::<CameraViewport>(self.camera).copied();
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let size = rt.size();
+        let rect = viewport.map(|viewport| viewport.rect(size));
+        rt.set_viewport(rect.map(|rect| {
+            Viewport::new(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+            )
+        }));
+        rt.set_scissor_rect(rect);
+        let (Some(viewport), Some(rect)) = (viewport, rect) else {
+            return Ok(());
+        };
+        let pipelines = world.resource::<ClearRegionPipeline>();
+        let manager = match (viewport.clear.color, viewport.clear.depth) {
+            (None, false) => return Ok(()),
+            (Some(_), false) => pipelines.manager,
+            (Some(_), true) => pipelines.color_depth_manager,
+            (None, true) => pipelines.depth_manager,
+        };
+        world.asset_scope(manager, |world, manager| {
+            let Some(pipeline) = manager.get_compatible(self.render_target, world) else {
+                return;
+            };
+            let Some(mut rt) = self.render_target.get_mut(world) else {
+                return;
+            };
+            let Some(rect) = rect.clamped(size) else {
+                return;
+            };
+            let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+                return;
+            };
+            // the depth range puts the triangle at the far plane
+            pass.set_viewport(0.0, 0.0, size.0 as f32, size.1 as f32, 1.0, 1.0);
+            pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            pass.set_pipeline(pipeline);
+            pass.set_blend_constant(viewport.clear.color.unwrap_or(Color::BLACK));
+            pass.draw(0..3, 0..1);
+        });
+        Ok(())
+    }
+}
+
+impl OperationBuilder for BeginViewport {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    // writes when the viewport is cleared
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{Sprite, SpriteTextureLayout};
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use modul_asset::{AssetEvent, AssetId, Assets};
+use modul_core::RenderContext;
+use modul_render::glam::{Mat4, Vec2, Vec3};
+use modul_render::{
+    BindGroupLayoutProvider, CachedBindGroupLayout, GenericVertexBufferLayout, GlobalTransform,
+    GpuVec, SamplerCache,
+};
+use modul_texture::ImageAsset;
+use modul_util::HashMap;
+use std::ops::Range;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferUsages,
+    SamplerDescriptor, VertexAttribute, VertexFormat, VertexStepMode,
+};
+
+/// The per-sprite data in the instance buffer of the [SpriteBatches]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct SpriteInstance {
+    /// The transform of the unit quad centered on the origin, including the size
+    pub model: Mat4,
+    /// Min and max uv
+    pub uv_rect: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl SpriteInstance {
+    /// The instance step layout used by the sprite shader, at locations 0 to 5
+    pub fn vertex_buffer_layout() -> GenericVertexBufferLayout {
+        let vec4 = VertexFormat::Float32x4;
+        GenericVertexBufferLayout {
+            array_stride: size_of::<Self>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..6)
+                .map(|location| VertexAttribute {
+                    format: vec4,
+                    offset: location as u64 * vec4.size(),
+                    shader_location: location,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Instances drawn with the same image
+pub struct SpriteBatch {
+    pub image: AssetId<ImageAsset>,
+    pub instances: Range<u32>,
+}
+
+/// All [Sprites](Sprite) with a loaded image, sorted by z and packed into one instance buffer during [SpriteBatchSet](crate::SpriteBatchSet).
+/// Consecutive sprites with the same image form a [SpriteBatch], which is drawn with one draw call.
+#[derive(Resource)]
+pub struct SpriteBatches {
+    instances: GpuVec<SpriteInstance>,
+    batches: Vec<SpriteBatch>,
+    /// bind groups of the images used in the last frame
+    bind_groups: HashMap<AssetId<ImageAsset>, BindGroup>,
+}
+
+impl Default for SpriteBatches {
+    fn default() -> Self {
+        Self {
+            instances: GpuVec::new(BufferUsages::VERTEX),
+            batches: Vec::new(),
+            bind_groups: HashMap::new(),
+        }
+    }
+}
+
+impl SpriteBatches {
+    pub fn instances(&self) -> &GpuVec<SpriteInstance> {
+        &self.instances
+    }
+
+    pub fn batches(&self) -> &[SpriteBatch] {
+        &self.batches
+    }
+
+    /// The bind group of the image of a batch, using [SpriteTextureLayout]
+    pub fn bind_group(&self, image: AssetId<ImageAsset>) -> Option<&BindGroup> {
+        self.bind_groups.get(&image)
+    }
+}
+
+pub(crate) fn prepare_sprites(
+    ctx: Res<RenderContext>,
+    images: Res<Assets<ImageAsset>>,
+    layout: Res<CachedBindGroupLayout<SpriteTextureLayout>>,
+    mut samplers: ResMut<SamplerCache>,
+    mut sprite_batches: ResMut<SpriteBatches>,
+    mut image_events: MessageReader<AssetEvent<ImageAsset>>,
+    sprites: Query<(&Sprite, &GlobalTransform)>,
+) {
+    let sprite_batches = &mut *sprite_batches;
+    // reloaded images have a new texture
+    for event in image_events.read() {
+        if let AssetEvent::Modified(id) = event {
+            sprite_batches.bind_groups.remove(id);
+        }
+    }
+    // images are numbered in the order they are found, so sprites with the same z can be grouped
+    let mut image_order = HashMap::new();
+    let mut sorted = Vec::new();
+    for (sprite, global) in sprites.iter() {
+        let Some(image) = images.get(sprite.image) else {
+            continue;
+        };
+        let order = image_order.len();
+        let order = *image_order.entry(sprite.image).or_insert(order);
+        let image_size = Vec2::new(image.width as f32, image.height as f32);
+        let (min, max) = match sprite.rect {
+            Some(r) => (
+                Vec2::new(r.x as f32, r.y as f32),
+                Vec2::new((r.x + r.width) as f32, (r.y + r.height) as f32),
+            ),
+            None => (Vec2::ZERO, image_size),
+        };
+        let size = sprite.size.unwrap_or(max - min);
+        let instance = SpriteInstance {
+            model: global.0 * Mat4::from_scale(Vec3::new(size.x, size.y, 1.0)),
+            uv_rect: [
+                min.x / image_size.x,
+                min.y / image_size.y,
+                max.x / image_size.x,
+                max.y / image_size.y,
+            ],
+            color: sprite.color,
+        };
+        sorted.push((sprite.z, order, sprite.image, instance));
+    }
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    let instances = &mut sprite_batches.instances;
+    let batches = &mut sprite_batches.batches;
+    instances.clear();
+    batches.clear();
+    for (_, _, image, instance) in sorted {
+        let index = instances.len() as u32;
+        match batches.last_mut() {
+            Some(batch) if batch.image == image => batch.instances.end = index + 1,
+            _ => batches.push(SpriteBatch {
+                image,
+                instances: index..index + 1,
+            }),
+        }
+        instances.push(instance);
+    }
+    instances.flush(&ctx.device, &ctx.queue);
+
+    // dropping the bind groups of images that are not used anymore
+    let bind_groups = &mut sprite_batches.bind_groups;
+    bind_groups.retain(|image, _| image_order.contains_key(image));
+    for batch in batches.iter() {
+        if bind_groups.contains_key(&batch.image) {
+            continue;
+        }
+        let Some(image) = images.get(batch.image) else {
+            continue;
+        };
+        let sampler = match &image.sampler {
+            Some(sampler) => sampler.clone(),
+            None => samplers
+                .get(&ctx.device, &SamplerDescriptor::default())
+                .clone(),
+        };
+        let bind_group = ctx.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Sprite bind group"),
+            layout: layout.layout(),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&image.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        bind_groups.insert(batch.image, bind_group);
+    }
+}
@@ -0,0 +1,78 @@
+mod batch;
+mod operation;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetAppExt, AssetId, Assets};
+use modul_core::Init;
+use modul_render::glam::Vec2;
+use modul_render::{
+    BindGroupLayoutInitPlugin, Synchronize, SynchronizeSet, Transform, TransformPropagateSet,
+};
+use modul_texture::ImageAsset;
+
+pub use batch::*;
+pub use operation::*;
+
+/// [SystemSet] within [Synchronize] that sorts and batches the [Sprites](Sprite), it runs after [TransformPropagateSet]
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpriteBatchSet;
+
+/// Batches [Sprites](Sprite) every frame, and creates the pipeline used by [SpriteOperations](SpriteOperation).
+/// Add it after [RenderPlugin](modul_render::RenderPlugin), and after [ImageAssetPlugin](modul_texture::ImageAssetPlugin) if images are loaded from files.
+pub struct SpritePlugin;
+
+impl Plugin for SpritePlugin {
+    fn build(&self, app: &mut App) {
+        // images can also be created without the ImageAssetPlugin
+        if !app.world().contains_resource::<Assets<ImageAsset>>() {
+            app.init_assets::<ImageAsset>();
+        }
+        app.init_resource::<SpriteBatches>();
+        app.add_plugins(BindGroupLayoutInitPlugin::<SpriteTextureLayout>::new());
+        app.add_systems(Init, init_sprite_pipeline);
+        app.add_systems(Synchronize, prepare_sprites.in_set(SpriteBatchSet));
+        app.configure_sets(
+            Synchronize,
+            SpriteBatchSet
+                .after(TransformPropagateSet)
+                .in_set(SynchronizeSet::Main),
+        );
+    }
+}
+
+/// A textured quad centered on the [GlobalTransform](modul_render::GlobalTransform) of the entity, drawn by a [SpriteOperation]
+#[derive(Component, Clone, Debug)]
+#[require(Transform)]
+pub struct Sprite {
+    pub image: AssetId<ImageAsset>,
+    /// Multiplied with the color of the image
+    pub color: [f32; 4],
+    /// The region of the image in pixels, for texture atlases. None for the whole image
+    pub rect: Option<SpriteRect>,
+    /// The size before the transform, None for the size of the rect (or image) in pixels
+    pub size: Option<Vec2>,
+    /// Sprites are drawn from low to high z, sprites with the same z are grouped by image
+    pub z: f32,
+}
+
+impl Sprite {
+    pub fn new(image: AssetId<ImageAsset>) -> Self {
+        Self {
+            image,
+            color: [1.0; 4],
+            rect: None,
+            size: None,
+            z: 0.0,
+        }
+    }
+}
+
+/// A region of an image in pixels, starting at the top left
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
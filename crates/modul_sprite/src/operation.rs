@@ -0,0 +1,185 @@
+use crate::{SpriteBatches, SpriteInstance};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use modul_render::{
+    BindGroupLayoutDef, BindGroupProvider, CachedBindGroupLayout, CameraBindGroup,
+    CameraUniformProvider, DirectRenderPipelineResourceProvider, GenericColorTargetState,
+    GenericDepthStencilState, GenericFragmentState, GenericMultisampleState,
+    GenericRenderPipelineDescriptor, GenericVertexState, Operation, OperationBuilder,
+    OperationError, PipelineLayoutComposer, RenderPipelineManager, RenderTargetSource,
+};
+use wgpu::{
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, CommandEncoder,
+    CompareFunction, DepthBiasState, Device, PipelineLayout, PrimitiveState, PrimitiveTopology,
+    SamplerBindingType, ShaderModule, ShaderStages, StencilState, TextureSampleType,
+    TextureViewDimension,
+};
+
+/// The bind group layout of the image of a sprite batch, declaring `sprite_texture` and `sprite_sampler`
+pub struct SpriteTextureLayout;
+
+impl BindGroupLayoutDef for SpriteTextureLayout {
+    const LAYOUT: &'static BindGroupLayoutDescriptor<'static> = &BindGroupLayoutDescriptor {
+        label: Some("Sprite texture BGLayout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    };
+    const LIBRARY: &'static str = "@group(#BIND_GROUP) @binding(0)
+var sprite_texture: texture_2d<f32>;
+@group(#BIND_GROUP) @binding(1)
+var sprite_sampler: sampler;";
+}
+
+/// The pipeline of [SpriteOperations](SpriteOperation), inserted during [Init](modul_core::Init).
+/// Group 0 is the [CameraUniformProvider], group 1 the [SpriteTextureLayout].
+#[derive(Resource)]
+pub struct SpritePipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+}
+
+/// Draws all [SpriteBatches] to the target, as seen by the [Camera](modul_render::Camera) entity.
+/// Sprites are alpha blended in order and do not write depth.
+pub struct SpriteOperation {
+    pub render_target: RenderTargetSource,
+    pub camera: Entity,
+}
+
+impl Operation for SpriteOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(camera) = world.get::<CameraBindGroup>(self.camera) else {
+            return Err(format!("{} has no camera bind group", self.camera).into());
+        };
+        let camera = camera.bind_group().clone();
+        let sprite_batches = world.resource::<SpriteBatches>();
+        let Some(instances) = sprite_batches.instances().buffer().cloned() else {
+            return Ok(());
+        };
+        let batches = sprite_batches
+            .batches()
+            .iter()
+            .filter_map(|batch| {
+                let bind_group = sprite_batches.bind_group(batch.image)?;
+                Some((bind_group.clone(), batch.instances.clone()))
+            })
+            .collect::<Vec<_>>();
+        if batches.is_empty() {
+            return Ok(());
+        }
+        let manager = world.resource::<SpritePipeline>().manager;
+        let mut pipeline = None;
+        world.asset_scope(manager, |world, manager| {
+            pipeline = manager.get_compatible(self.render_target, world).cloned();
+        });
+        let Some(pipeline) = pipeline else {
+            return Ok(());
+        };
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &camera, &[]);
+        pass.set_vertex_buffer(0, instances.slice(..));
+        for (bind_group, instances) in batches {
+            pass.set_bind_group(1, &bind_group, &[]);
+            pass.draw(0..4, instances);
+        }
+        Ok(())
+    }
+}
+
+impl OperationBuilder for SpriteOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+pub(crate) fn init_sprite_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+) {
+    // identical layouts are compatible, so this does not have to wait for the provider of the RenderPlugin
+    let mut composer = PipelineLayoutComposer::new();
+    composer
+        .add_bind_group(CameraUniformProvider::new(&ctx.device))
+        .add_bind_group(CachedBindGroupLayout::<SpriteTextureLayout>::new(
+            &ctx.device,
+        ));
+    let layout = layouts.add(composer.compose_pipeline_layout(&ctx.device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(&ctx.device, "Sprite shader", include_str!("sprite.wgsl"))
+            .expect("the built-in sprite shader has balanced directives")
+            .clone(),
+    );
+    let manager = managers.add(RenderPipelineManager::new(
+        GenericRenderPipelineDescriptor {
+            resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+                layout,
+                vertex_shader_module: shader,
+                fragment_shader_module: shader,
+            }),
+            label: Some("Sprite pipeline".to_string()),
+            vertex_state: GenericVertexState {
+                entry_point: "vs_main".to_string(),
+                buffers: vec![SpriteInstance::vertex_buffer_layout()],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            // ignoring depth, but still needed for targets with a depth buffer
+            depth_stencil: Some(GenericDepthStencilState {
+                depth_write_enable: false,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: GenericMultisampleState {
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(GenericFragmentState {
+                entry_point: "fs_main".to_string(),
+                targets: vec![GenericColorTargetState::new(Some(
+                    BlendState::ALPHA_BLENDING,
+                ))],
+            }),
+        },
+    ));
+    commands.insert_resource(SpritePipeline { manager });
+}
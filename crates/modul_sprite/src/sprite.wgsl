@@ -0,0 +1,31 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+// a triangle strip of 4 vertices
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+    @location(0) model_0: vec4<f32>,
+    @location(1) model_1: vec4<f32>,
+    @location(2) model_2: vec4<f32>,
+    @location(3) model_3: vec4<f32>,
+    @location(4) uv_rect: vec4<f32>,
+    @location(5) color: vec4<f32>,
+) -> VertexOutput {
+    let corner = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+    let model = mat4x4<f32>(model_0, model_1, model_2, model_3);
+    var out: VertexOutput;
+    out.position = camera.view_projection * model * vec4<f32>(corner - 0.5, 0.0, 1.0);
+    // images start at the top
+    out.uv = mix(uv_rect.xy, uv_rect.zw, vec2<f32>(corner.x, 1.0 - corner.y));
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(sprite_texture, sprite_sampler, in.uv) * in.color;
+}
@@ -0,0 +1,376 @@
+use bevy_app::SubApp;
+use bevy_ecs::prelude::*;
+use image::RgbaImage;
+use modul_asset::AssetId;
+use modul_core::{headless_app, HeadlessGraphicsInitializer, RenderContext};
+use modul_render::{
+    OffscreenRenderTarget, OffscreenRenderTargetConfig, ReadbackImage, RenderPlugin,
+    RenderTargetSource, RunningSequenceQueue, Screenshots, Sequence, SequenceErrors, SequenceQueue,
+    ShouldDraw,
+};
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use wgpu::{PollType, TextureFormat, TextureUsages};
+
+/// Set to update the reference images instead of comparing against them
+pub const UPDATE_REFERENCES_VAR: &str = "MODUL_UPDATE_REFERENCES";
+
+/// Updates after the last frame while waiting for the readback, before giving up
+const MAX_READBACK_UPDATES: u32 = 100;
+
+/// Runs [Sequences](Sequence) without a window and reads back the result, for rendering regression tests
+pub struct TestRenderer {
+    app: SubApp,
+}
+
+impl TestRenderer {
+    /// Creates a headless app with the [RenderPlugin], `setup` is run before [Init](modul_core::Init) like in [run_app](modul_core::run_app).
+    /// Returns None if there is no adapter, so tests can be skipped on machines without a GPU.
+    pub fn new(setup: impl FnOnce(&mut SubApp)) -> Option<Self> {
+        Self::with_initializer(HeadlessGraphicsInitializer::default(), setup)
+    }
+
+    pub fn with_initializer(
+        initializer: HeadlessGraphicsInitializer,
+        setup: impl FnOnce(&mut SubApp),
+    ) -> Option<Self> {
+        let app = headless_app(initializer, |app| {
+            app.add_plugins(RenderPlugin);
+            setup(app);
+        })?;
+        Some(Self { app })
+    }
+
+    pub fn world(&self) -> &World {
+        self.app.world()
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        self.app.world_mut()
+    }
+
+    /// Spawns an [OffscreenRenderTarget], with [COPY_SRC](TextureUsages::COPY_SRC) added to the color usages so it can be read back
+    pub fn spawn_target(&mut self, mut config: OffscreenRenderTargetConfig) -> RenderTargetSource {
+        if let Some(color) = &mut config.color_config {
+            color.usages |= TextureUsages::COPY_SRC;
+        }
+        let entity = self
+            .world_mut()
+            .spawn(OffscreenRenderTarget::new(config))
+            .id();
+        RenderTargetSource::Offscreen(entity)
+    }
+
+    /// Runs [Redraw](modul_core::Redraw) with the sequence as the [RunningSequenceQueue] for `frames` frames, and reads back the target after the last.
+    /// ## Panics
+    /// If `frames` is 0, if a sequence fails, or if the target can not be read back
+    pub fn render(
+        &mut self,
+        sequence: AssetId<Sequence>,
+        target: RenderTargetSource,
+        frames: u32,
+    ) -> ReadbackImage {
+        assert!(frames > 0, "at least one frame has to be rendered");
+        let result = Arc::new(Mutex::new(None));
+        let world = self.world_mut();
+        world.insert_resource(ShouldDraw);
+        world.insert_resource(RunningSequenceQueue(SequenceQueue(vec![sequence])));
+        for frame in 0..frames {
+            if frame == frames - 1 {
+                let result = result.clone();
+                self.world_mut()
+                    .resource_mut::<Screenshots>()
+                    .take(target, move |image| {
+                        *result.lock().unwrap() = Some(image);
+                    });
+            }
+            self.app.update();
+            self.check_errors();
+        }
+        // later frames do not draw anything, they only poll the readback
+        self.world_mut().remove_resource::<RunningSequenceQueue>();
+        for _ in 0..MAX_READBACK_UPDATES {
+            if let Some(image) = result.lock().unwrap().take() {
+                return image;
+            }
+            let _ = self
+                .world()
+                .resource::<RenderContext>()
+                .device
+                .poll(PollType::wait_indefinitely());
+            self.app.update();
+        }
+        panic!("the render target was not read back, it needs a color texture");
+    }
+
+    fn check_errors(&self) {
+        let errors = self.world().resource::<SequenceErrors>();
+        if !errors.is_empty() {
+            let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+            panic!("sequence failed: {}", messages.join(", "));
+        }
+    }
+}
+
+/// How much a rendered image may differ from its reference
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// The largest difference of a channel for pixels to count as equal
+    pub channel: u8,
+    /// The fraction of pixels allowed to differ by more than `channel`
+    pub pixels: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            pixels: 0.0,
+        }
+    }
+}
+
+impl Tolerance {
+    pub fn exact() -> Self {
+        Self {
+            channel: 0,
+            pixels: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageMismatch {
+    /// The images have different sizes
+    Size {
+        actual: (u32, u32),
+        reference: (u32, u32),
+    },
+    /// More pixels differ than the [Tolerance] allows
+    Pixels {
+        differing: usize,
+        total: usize,
+        /// The largest difference of any channel
+        max_difference: u8,
+    },
+}
+
+impl std::error::Error for ImageMismatch {}
+
+impl Display for ImageMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageMismatch::Size { actual, reference } => write!(
+                f,
+                "Image size {:?} does not match reference size {:?}",
+                actual, reference
+            ),
+            ImageMismatch::Pixels {
+                differing,
+                total,
+                max_difference,
+            } => write!(
+                f,
+                "{} of {} pixels differ from the reference, by up to {}",
+                differing, total, max_difference
+            ),
+        }
+    }
+}
+
+/// Converts an 8 bit RGBA or BGRA readback to an [RgbaImage], None for other formats
+pub fn to_rgba_image(image: &ReadbackImage) -> Option<RgbaImage> {
+    let mut data = image.data.clone();
+    match image.format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {}
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+            for pixel in data.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        _ => return None,
+    }
+    RgbaImage::from_raw(image.size.0, image.size.1, data)
+}
+
+/// Compares two images with a tolerance
+pub fn compare(
+    actual: &RgbaImage,
+    reference: &RgbaImage,
+    tolerance: Tolerance,
+) -> Result<(), ImageMismatch> {
+    if actual.dimensions() != reference.dimensions() {
+        return Err(ImageMismatch::Size {
+            actual: actual.dimensions(),
+            reference: reference.dimensions(),
+        });
+    }
+    let mut differing = 0;
+    let mut max_difference = 0;
+    for (a, r) in actual.pixels().zip(reference.pixels()) {
+        let difference =
+            a.0.iter()
+                .zip(r.0.iter())
+                .map(|(a, r)| a.abs_diff(*r))
+                .max()
+                .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > tolerance.channel {
+            differing += 1;
+        }
+    }
+    let total = (actual.width() * actual.height()) as usize;
+    if differing as f32 > tolerance.pixels * total as f32 {
+        return Err(ImageMismatch::Pixels {
+            differing,
+            total,
+            max_difference,
+        });
+    }
+    Ok(())
+}
+
+/// Compares the image with the PNG reference at the path.
+/// If [UPDATE_REFERENCES_VAR] is set, the image is written as the new reference instead.
+/// ## Panics
+/// If the images differ more than the tolerance allows, the image is then written next to the reference with `.actual.png` as extension.
+/// Also panics if the reference does not exist (so a wrong path or a missing file fails), if the format can not be converted with [to_rgba_image], or if the files can not be read or written.
+pub fn assert_matches_reference(
+    image: &ReadbackImage,
+    reference: impl AsRef<Path>,
+    tolerance: Tolerance,
+) {
+    let reference = reference.as_ref();
+    let actual = to_rgba_image(image)
+        .unwrap_or_else(|| panic!("{:?} can not be compared to a reference", image.format));
+    if env::var_os(UPDATE_REFERENCES_VAR).is_some() {
+        if let Some(parent) = reference.parent() {
+            std::fs::create_dir_all(parent).expect("failed to create reference directory");
+        }
+        actual.save(reference).expect("failed to write reference");
+        return;
+    }
+    if !reference.exists() {
+        let actual_path = actual_path(reference);
+        actual.save(&actual_path).expect("failed to write image");
+        panic!(
+            "{} does not exist, the image was written to {}, run with {} set to create the reference",
+            reference.display(),
+            actual_path.display(),
+            UPDATE_REFERENCES_VAR
+        );
+    }
+    let expected = image::open(reference)
+        .expect("failed to read reference")
+        .to_rgba8();
+    if let Err(mismatch) = compare(&actual, &expected, tolerance) {
+        let actual_path = actual_path(reference);
+        actual.save(&actual_path).expect("failed to write image");
+        panic!(
+            "{} does not match: {}, the image was written to {}",
+            reference.display(),
+            mismatch,
+            actual_path.display()
+        );
+    }
+}
+
+fn actual_path(reference: &Path) -> PathBuf {
+    reference.with_extension("actual.png")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image(width: u32, height: u32, pixel: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba(pixel))
+    }
+
+    #[test]
+    fn equal_images_match() {
+        let a = image(4, 4, [10, 20, 30, 255]);
+        assert!(compare(&a, &a.clone(), Tolerance::exact()).is_ok());
+    }
+
+    #[test]
+    fn different_sizes_do_not_match() {
+        let result = compare(
+            &image(4, 4, [0; 4]),
+            &image(4, 2, [0; 4]),
+            Tolerance::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(ImageMismatch::Size {
+                actual: (4, 4),
+                reference: (4, 2)
+            })
+        ));
+    }
+
+    #[test]
+    fn channel_tolerance() {
+        let reference = image(2, 2, [100, 100, 100, 255]);
+        let actual = image(2, 2, [102, 98, 100, 255]);
+        assert!(compare(&actual, &reference, Tolerance::default()).is_ok());
+        let result = compare(&actual, &reference, Tolerance::exact());
+        assert!(matches!(
+            result,
+            Err(ImageMismatch::Pixels {
+                differing: 4,
+                total: 4,
+                max_difference: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn pixel_tolerance() {
+        let reference = image(2, 2, [0, 0, 0, 255]);
+        let mut actual = reference.clone();
+        actual.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let quarter = Tolerance {
+            channel: 0,
+            pixels: 0.25,
+        };
+        assert!(compare(&actual, &reference, quarter).is_ok());
+        let less = Tolerance {
+            channel: 0,
+            pixels: 0.2,
+        };
+        assert!(compare(&actual, &reference, less).is_err());
+    }
+
+    #[test]
+    fn bgra_is_swizzled() {
+        let readback = ReadbackImage {
+            size: (1, 1),
+            format: TextureFormat::Bgra8Unorm,
+            data: vec![1, 2, 3, 4],
+        };
+        let converted = to_rgba_image(&readback).unwrap();
+        assert_eq!(converted.get_pixel(0, 0), &Rgba([3, 2, 1, 4]));
+        let float = ReadbackImage {
+            format: TextureFormat::Rgba16Float,
+            ..readback
+        };
+        assert!(to_rgba_image(&float).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not exist")]
+    fn missing_reference_panics() {
+        let readback = ReadbackImage {
+            size: (1, 1),
+            format: TextureFormat::Rgba8Unorm,
+            data: vec![0, 0, 0, 255],
+        };
+        let reference = env::temp_dir().join("modul_test_missing_reference.png");
+        let _ = std::fs::remove_file(&reference);
+        assert_matches_reference(&readback, reference, Tolerance::default());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{Font, TextAtlasLayout};
+use ab_glyph::{Font as _, GlyphId, OutlinedGlyph, Rect};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, Assets};
+use modul_render::{
+    BindGroupLayoutProvider, CachedBindGroupLayout, GpuMemoryStats, GpuMemoryUsage,
+};
+use modul_util::HashMap;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Device, Extent3d, FilterMode,
+    Origin3d, Queue, Sampler, SamplerDescriptor, TexelCopyBufferLayout, TexelCopyTextureInfo,
+    Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor,
+};
+
+/// Pixels left empty around every glyph, so linear filtering does not bleed
+const PADDING: u32 = 1;
+
+/// A rasterized glyph: where it is in the atlas, and where it is drawn relative to the caret
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasGlyph {
+    /// Min and max uv in the atlas
+    pub uv_rect: [f32; 4],
+    /// Bounds in pixels relative to the caret on the baseline
+    pub bounds: Rect,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: AssetId<Font>,
+    glyph: GlyphId,
+    /// pixel size in 1/16 pixels
+    size: u32,
+}
+
+/// Coverage masks of rasterized glyphs of any font and size, packed into an [R8Unorm](TextureFormat::R8Unorm) texture in rows.
+/// When the atlas is full it is cleared and grown (up to the max texture size), and the glyphs of the current frame are added again.
+pub struct GlyphAtlas {
+    size: u32,
+    glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+    /// x of the current row
+    cursor: u32,
+    row_y: u32,
+    row_height: u32,
+    gpu: Option<AtlasTexture>,
+}
+
+struct AtlasTexture {
+    texture: Texture,
+    view: TextureView,
+    bind_group: BindGroup,
+}
+
+/// Returned by [GlyphAtlas::glyph] when a glyph does not fit in the atlas
+#[derive(Debug)]
+pub struct AtlasFull;
+
+impl GlyphAtlas {
+    /// Creates an empty atlas with a square texture of the size, the texture is created on first use
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            glyphs: HashMap::new(),
+            cursor: 0,
+            row_y: 0,
+            row_height: 0,
+            gpu: None,
+        }
+    }
+
+    /// The width and height of the texture
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    pub fn texture(&self) -> Option<&Texture> {
+        self.gpu.as_ref().map(|gpu| &gpu.texture)
+    }
+
+    pub fn view(&self) -> Option<&TextureView> {
+        self.gpu.as_ref().map(|gpu| &gpu.view)
+    }
+
+    /// The bind group using [TextAtlasLayout], None until the texture is created
+    pub fn bind_group(&self) -> Option<&BindGroup> {
+        self.gpu.as_ref().map(|gpu| &gpu.bind_group)
+    }
+
+    /// Removes all glyphs, and resizes the texture (it is recreated on the next use)
+    pub fn clear(&mut self, size: u32) {
+        self.glyphs.clear();
+        self.cursor = 0;
+        self.row_y = 0;
+        self.row_height = 0;
+        if size != self.size {
+            self.size = size;
+            self.gpu = None;
+        }
+    }
+
+    /// Gets a glyph, rasterizing and uploading it if it is not in the atlas.
+    /// Returns None for glyphs without an outline (like spaces).
+    #[allow(clippy::too_many_arguments)]
+    pub fn glyph(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &CachedBindGroupLayout<TextAtlasLayout>,
+        sampler: &Sampler,
+        font_id: AssetId<Font>,
+        font: &Font,
+        glyph: GlyphId,
+        px_size: f32,
+    ) -> Result<Option<AtlasGlyph>, AtlasFull> {
+        let key = GlyphKey {
+            font: font_id,
+            glyph,
+            size: (px_size * 16.0).round() as u32,
+        };
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Ok(*glyph);
+        }
+        let outlined = font
+            .font()
+            .outline_glyph(glyph.with_scale(key.size as f32 / 16.0));
+        let atlas_glyph = match outlined {
+            Some(outlined) => Some(self.insert(device, queue, layout, sampler, &outlined)?),
+            None => None,
+        };
+        self.glyphs.insert(key, atlas_glyph);
+        Ok(atlas_glyph)
+    }
+
+    fn insert(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &CachedBindGroupLayout<TextAtlasLayout>,
+        sampler: &Sampler,
+        outlined: &OutlinedGlyph,
+    ) -> Result<AtlasGlyph, AtlasFull> {
+        let bounds = outlined.px_bounds();
+        let width = bounds.width() as u32;
+        let height = bounds.height() as u32;
+        if self.cursor + width + PADDING > self.size {
+            self.row_y += self.row_height;
+            self.cursor = 0;
+            self.row_height = 0;
+        }
+        if self.cursor + width + PADDING > self.size || self.row_y + height + PADDING > self.size {
+            return Err(AtlasFull);
+        }
+        let (x, y) = (self.cursor + PADDING, self.row_y + PADDING);
+        self.cursor += width + PADDING;
+        self.row_height = self.row_height.max(height + PADDING);
+
+        let mut coverage = vec![0u8; (width * height) as usize];
+        outlined.draw(|gx, gy, c| {
+            if gx < width && gy < height {
+                coverage[(gy * width + gx) as usize] = (c * 255.0).round() as u8;
+            }
+        });
+        let size = self.size;
+        let gpu = self
+            .gpu
+            .get_or_insert_with(|| AtlasTexture::new(device, layout, sampler, size));
+        if width > 0 && height > 0 {
+            queue.write_texture(
+                TexelCopyTextureInfo {
+                    texture: &gpu.texture,
+                    mip_level: 0,
+                    origin: Origin3d { x, y, z: 0 },
+                    aspect: TextureAspect::All,
+                },
+                &coverage,
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        let size = self.size as f32;
+        Ok(AtlasGlyph {
+            uv_rect: [
+                x as f32 / size,
+                y as f32 / size,
+                (x + width) as f32 / size,
+                (y + height) as f32 / size,
+            ],
+            bounds,
+        })
+    }
+}
+
+impl AtlasTexture {
+    fn new(
+        device: &Device,
+        layout: &CachedBindGroupLayout<TextAtlasLayout>,
+        sampler: &Sampler,
+        size: u32,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Glyph atlas"),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Glyph atlas bind group"),
+            layout: layout.layout(),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        Self {
+            texture,
+            view,
+            bind_group,
+        }
+    }
+}
+
+/// The sampler of glyph atlases
+pub(crate) fn atlas_sampler_descriptor() -> SamplerDescriptor<'static> {
+    SamplerDescriptor {
+        label: Some("Glyph atlas sampler"),
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..Default::default()
+    }
+}
+
+pub(crate) fn update_atlas_memory_stats(
+    mut stats: ResMut<GpuMemoryStats>,
+    atlases: Res<Assets<GlyphAtlas>>,
+) {
+    let mut usage = GpuMemoryUsage::default();
+    for (_, atlas) in atlases.iter() {
+        if let Some(texture) = atlas.texture() {
+            usage.add_texture(texture);
+        }
+    }
+    stats.set("glyph atlases", usage);
+}
@@ -0,0 +1,233 @@
+use crate::atlas::atlas_sampler_descriptor;
+use crate::{AtlasFull, Font, GlyphAtlas, Text, TextAtlasLayout, TextPipeline, TextScaleFactor};
+use bevy_ecs::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use modul_asset::Assets;
+use modul_core::{RenderContext, WindowComponent};
+use modul_render::glam::Vec2;
+use modul_render::{
+    CachedBindGroupLayout, GenericVertexBufferLayout, GpuVec, RenderTargetSource, SamplerCache,
+};
+use modul_util::HashMap;
+use std::ops::Range;
+use wgpu::{BufferUsages, Sampler, VertexAttribute, VertexFormat, VertexStepMode};
+
+/// The per-glyph data in the instance buffer of the [TextBatches]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct TextInstance {
+    /// Left, top, right and bottom in normalized device coordinates
+    pub rect: [f32; 4],
+    /// Min and max uv in the [GlyphAtlas]
+    pub uv_rect: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl TextInstance {
+    /// The instance step layout used by the text shader, at locations 0 to 2
+    pub fn vertex_buffer_layout() -> GenericVertexBufferLayout {
+        let vec4 = VertexFormat::Float32x4;
+        GenericVertexBufferLayout {
+            array_stride: size_of::<Self>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..3)
+                .map(|location| VertexAttribute {
+                    format: vec4,
+                    offset: location as u64 * vec4.size(),
+                    shader_location: location,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The glyphs of all [Texts](Text), laid out and packed into one instance buffer during [TextPrepareSet](crate::TextPrepareSet).
+/// The glyphs of each render target are consecutive, so a [TextOperation](crate::TextOperation) draws them with one draw call.
+#[derive(Resource)]
+pub struct TextBatches {
+    instances: GpuVec<TextInstance>,
+    /// keyed by resolved targets
+    targets: HashMap<RenderTargetSource, Range<u32>>,
+}
+
+impl Default for TextBatches {
+    fn default() -> Self {
+        Self {
+            instances: GpuVec::new(BufferUsages::VERTEX),
+            targets: HashMap::new(),
+        }
+    }
+}
+
+impl TextBatches {
+    pub fn instances(&self) -> &GpuVec<TextInstance> {
+        &self.instances
+    }
+
+    /// The instances drawn to a [resolved](RenderTargetSource::resolve) target
+    pub fn instances_of(&self, target: RenderTargetSource) -> Option<Range<u32>> {
+        self.targets.get(&target).cloned()
+    }
+}
+
+/// The scale factor of a render target: the one of the window for surfaces, otherwise the [TextScaleFactor] of the target entity or 1
+pub(crate) fn scale_factor(world: &World, target: RenderTargetSource) -> f32 {
+    let entity = match target {
+        RenderTargetSource::Surface(entity) => {
+            if let Some(window) = world.get::<WindowComponent>(entity) {
+                return window.window.scale_factor() as f32;
+            }
+            entity
+        }
+        RenderTargetSource::Offscreen(entity)
+        | RenderTargetSource::PingPongRead(entity)
+        | RenderTargetSource::PingPongWrite(entity) => entity,
+    };
+    world.get::<TextScaleFactor>(entity).map_or(1.0, |s| s.0)
+}
+
+pub(crate) fn prepare_text(world: &mut World) {
+    let sampler = world.resource_scope(|world, mut samplers: Mut<SamplerCache>| {
+        let device = &world.resource::<RenderContext>().device;
+        samplers.get(device, &atlas_sampler_descriptor()).clone()
+    });
+    world.resource_scope(|world, mut text_batches: Mut<TextBatches>| {
+        let atlas_id = world.resource::<TextPipeline>().atlas;
+        let atlases = world.resource::<Assets<GlyphAtlas>>();
+        let Some(mut atlas) = atlases.write(atlas_id) else {
+            return;
+        };
+        let ctx = world.resource::<RenderContext>();
+        // the atlas is only grown once per frame, if it does not fit the glyphs after that the missing glyphs are skipped
+        if !layout_texts(world, &mut text_batches, &mut atlas, &sampler, true) {
+            let max_size = ctx.device.limits().max_texture_dimension_2d;
+            let size = (atlas.size() * 2).min(max_size);
+            atlas.clear(size);
+            layout_texts(world, &mut text_batches, &mut atlas, &sampler, false);
+        }
+        text_batches.instances.flush(&ctx.device, &ctx.queue);
+    });
+}
+
+/// Lays out [Texts](Text) into instances for one render target
+pub(crate) struct GlyphWriter<'a> {
+    pub(crate) ctx: &'a RenderContext,
+    pub(crate) layout: &'a CachedBindGroupLayout<TextAtlasLayout>,
+    pub(crate) sampler: &'a Sampler,
+    pub(crate) atlas: &'a mut GlyphAtlas,
+    /// In physical pixels
+    pub(crate) target_size: Vec2,
+    pub(crate) scale: f32,
+    /// Return at the first glyph that does not fit in the atlas, instead of skipping it
+    pub(crate) stop_when_full: bool,
+}
+
+impl GlyphWriter<'_> {
+    /// Pushes the glyphs of the text, see `stop_when_full`
+    pub(crate) fn write(
+        &mut self,
+        instances: &mut GpuVec<TextInstance>,
+        font: &Font,
+        text: &Text,
+    ) -> Result<(), AtlasFull> {
+        let px_size = text.size * self.scale;
+        let max_width = text.max_width.map(|w| w * self.scale);
+        // snapping to pixels, so glyphs are not blurred
+        let origin = (text.position * self.scale).round();
+        for glyph in font.layout(&text.value, px_size, max_width).glyphs {
+            let atlas_glyph = self.atlas.glyph(
+                &self.ctx.device,
+                &self.ctx.queue,
+                self.layout,
+                self.sampler,
+                text.font,
+                font,
+                glyph.id,
+                px_size,
+            );
+            let atlas_glyph = match atlas_glyph {
+                Ok(Some(atlas_glyph)) => atlas_glyph,
+                Ok(None) => continue,
+                Err(e) if self.stop_when_full => return Err(e),
+                Err(_) => continue,
+            };
+            let caret = origin + glyph.position.round();
+            let min = caret + Vec2::new(atlas_glyph.bounds.min.x, atlas_glyph.bounds.min.y);
+            let max = caret + Vec2::new(atlas_glyph.bounds.max.x, atlas_glyph.bounds.max.y);
+            instances.push(TextInstance {
+                rect: self.ndc_rect(min, max),
+                uv_rect: atlas_glyph.uv_rect,
+                color: text.color,
+            });
+        }
+        Ok(())
+    }
+
+    /// Left, top, right and bottom in normalized device coordinates, of a rect in physical pixels
+    pub(crate) fn ndc_rect(&self, min: Vec2, max: Vec2) -> [f32; 4] {
+        let to_ndc = |p: Vec2| {
+            Vec2::new(
+                p.x / self.target_size.x * 2.0 - 1.0,
+                1.0 - p.y / self.target_size.y * 2.0,
+            )
+        };
+        let (min, max) = (to_ndc(min), to_ndc(max));
+        [min.x, min.y, max.x, max.y]
+    }
+}
+
+/// Lays out all texts, returns false if a glyph did not fit in the atlas and `stop_when_full` is set
+fn layout_texts(
+    world: &World,
+    text_batches: &mut TextBatches,
+    atlas: &mut GlyphAtlas,
+    sampler: &Sampler,
+    stop_when_full: bool,
+) -> bool {
+    let ctx = world.resource::<RenderContext>();
+    let fonts = world.resource::<Assets<Font>>();
+    let layout = world.resource::<CachedBindGroupLayout<TextAtlasLayout>>();
+    // grouping texts by target
+    let mut texts = HashMap::<RenderTargetSource, Vec<&Text>>::new();
+    // the query fails if no text was ever spawned
+    let mut query = world.try_query::<&Text>();
+    for text in query.iter_mut().flat_map(|query| query.iter(world)) {
+        if let Some(target) = text.render_target.resolve(world) {
+            texts.entry(target).or_default().push(text);
+        }
+    }
+    let instances = &mut text_batches.instances;
+    instances.clear();
+    text_batches.targets.clear();
+    for (target, texts) in texts {
+        let Some((width, height)) = target.get(world).map(|rt| rt.size()) else {
+            continue;
+        };
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let mut writer = GlyphWriter {
+            ctx,
+            layout,
+            sampler,
+            atlas: &mut *atlas,
+            target_size: Vec2::new(width as f32, height as f32),
+            scale: scale_factor(world, target),
+            stop_when_full,
+        };
+        let start = instances.len() as u32;
+        for text in texts {
+            let Some(font) = fonts.get(text.font) else {
+                continue;
+            };
+            if writer.write(instances, &font, text).is_err() {
+                return false;
+            }
+        }
+        let end = instances.len() as u32;
+        if end > start {
+            text_batches.targets.insert(target, start..end);
+        }
+    }
+    true
+}
@@ -0,0 +1,66 @@
+use crate::{Font, Text};
+use bevy_ecs::prelude::*;
+use modul_asset::AssetId;
+use modul_render::glam::Vec2;
+use modul_render::{RenderTargetSource, ShaderCompileError, ShaderLibrary};
+
+/// Shows the last [ShaderCompileError] as [Text] on a render target, so broken shaders are visible while hot reloading.
+/// Insert it to enable the overlay, the text is drawn by a [TextOperation](crate::TextOperation) on the target.
+/// The error is hidden again when the [ShaderLibrary] changes, it reappears if the shader still does not compile.
+#[derive(Resource, Clone, Debug)]
+pub struct ShaderErrorOverlay {
+    pub font: AssetId<Font>,
+    pub size: f32,
+    pub color: [f32; 4],
+    pub render_target: RenderTargetSource,
+}
+
+impl ShaderErrorOverlay {
+    pub fn new(font: AssetId<Font>, render_target: RenderTargetSource) -> Self {
+        Self {
+            font,
+            size: 16.0,
+            color: [1.0, 0.3, 0.3, 1.0],
+            render_target,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct OverlayState {
+    entity: Option<Entity>,
+    revision: u64,
+}
+
+pub(crate) fn update_shader_error_overlay(
+    mut commands: Commands,
+    mut state: Local<OverlayState>,
+    mut errors: MessageReader<ShaderCompileError>,
+    overlay: Res<ShaderErrorOverlay>,
+    library: Option<Res<ShaderLibrary>>,
+) {
+    let revision = library.map_or(0, |library| library.revision());
+    if revision != state.revision {
+        state.revision = revision;
+        if let Some(entity) = state.entity.take() {
+            commands.entity(entity).despawn();
+        }
+    }
+    let Some(error) = errors.read().last() else {
+        return;
+    };
+    let mut text = Text::new(
+        format!("{}\n{}", error, error.rendered),
+        overlay.font,
+        overlay.size,
+        overlay.render_target,
+    );
+    text.color = overlay.color;
+    text.position = Vec2::splat(overlay.size * 0.5);
+    match state.entity {
+        Some(entity) => {
+            commands.entity(entity).insert(text);
+        }
+        None => state.entity = Some(commands.spawn(text).id()),
+    }
+}
@@ -0,0 +1,159 @@
+use ab_glyph::{Font as _, FontArc, GlyphId, InvalidFont, PxScale, ScaleFont};
+use modul_asset::{AssetLoadError, AssetLoader};
+use modul_render::glam::Vec2;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum FontError {
+    /// The bytes are not a valid TrueType or OpenType font
+    InvalidFont(InvalidFont),
+}
+
+impl Error for FontError {}
+
+impl Display for FontError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::InvalidFont(e) => write!(f, "Font load error: {}", e),
+        }
+    }
+}
+
+impl From<InvalidFont> for FontError {
+    fn from(value: InvalidFont) -> Self {
+        Self::InvalidFont(value)
+    }
+}
+
+impl From<FontError> for AssetLoadError {
+    fn from(value: FontError) -> Self {
+        AssetLoadError::LoaderError(Box::new(value))
+    }
+}
+
+/// A TrueType or OpenType font, cheap to clone
+#[derive(Clone, Debug)]
+pub struct Font {
+    font: FontArc,
+}
+
+impl Font {
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, FontError> {
+        Ok(Self {
+            font: FontArc::try_from_vec(bytes)?,
+        })
+    }
+
+    pub fn from_static(bytes: &'static [u8]) -> Result<Self, FontError> {
+        Ok(Self {
+            font: FontArc::try_from_slice(bytes)?,
+        })
+    }
+
+    pub fn font(&self) -> &FontArc {
+        &self.font
+    }
+
+    /// The height of a line (including the line gap) at the size in pixels
+    pub fn line_height(&self, px_size: f32) -> f32 {
+        let scaled = self.font.as_scaled(PxScale::from(px_size));
+        scaled.height() + scaled.line_gap()
+    }
+
+    /// Positions the glyphs of the text at the size in pixels.
+    /// Lines are broken at `\n`, and at spaces (or within words that do not fit a line) when they would get wider than `max_width`.
+    /// Characters are mapped to glyphs one by one and kerned, there is no complex shaping (ligatures, right-to-left or combining scripts).
+    pub fn layout(&self, text: &str, px_size: f32, max_width: Option<f32>) -> TextLayout {
+        let scaled = self.font.as_scaled(PxScale::from(px_size));
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut glyphs: Vec<LayoutGlyph> = Vec::new();
+        let mut caret = Vec2::new(0.0, scaled.ascent());
+        let mut width = 0.0f32;
+        let mut line_start = 0;
+        // the first glyph after the last space in the line, the caret x there, and the line width before the space
+        let mut break_point: Option<(usize, f32, f32)> = None;
+        let mut previous = None;
+        for c in text.chars() {
+            if c == '\n' {
+                width = width.max(caret.x);
+                caret = Vec2::new(0.0, caret.y + line_height);
+                line_start = glyphs.len();
+                break_point = None;
+                previous = None;
+                continue;
+            }
+            if c.is_control() {
+                continue;
+            }
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret.x += scaled.kern(previous, id);
+            }
+            let advance = scaled.h_advance(id);
+            let overflows = max_width.is_some_and(|max| caret.x + advance > max);
+            if overflows && !c.is_whitespace() {
+                if let Some((index, x, line_width)) = break_point.take() {
+                    // moving the current word to the next line
+                    width = width.max(line_width);
+                    caret.y += line_height;
+                    caret.x -= x;
+                    for glyph in &mut glyphs[index..] {
+                        glyph.position = Vec2::new(glyph.position.x - x, caret.y);
+                    }
+                    line_start = index;
+                } else if glyphs.len() > line_start {
+                    // the word is wider than a line
+                    width = width.max(caret.x);
+                    caret = Vec2::new(0.0, caret.y + line_height);
+                    line_start = glyphs.len();
+                }
+            }
+            if c.is_whitespace() {
+                break_point = Some((glyphs.len() + 1, caret.x + advance, caret.x));
+            }
+            glyphs.push(LayoutGlyph {
+                id,
+                position: caret,
+            });
+            caret.x += advance;
+            previous = Some(id);
+        }
+        TextLayout {
+            glyphs,
+            size: Vec2::new(width.max(caret.x), caret.y - scaled.descent()),
+        }
+    }
+}
+
+/// A glyph positioned by [Font::layout]
+#[derive(Clone, Copy, Debug)]
+pub struct LayoutGlyph {
+    pub id: GlyphId,
+    /// The caret on the baseline in pixels, relative to the top left of the text
+    pub position: Vec2,
+}
+
+/// The result of [Font::layout]
+#[derive(Clone, Debug, Default)]
+pub struct TextLayout {
+    pub glyphs: Vec<LayoutGlyph>,
+    /// The width of the widest line and the height of all lines in pixels
+    pub size: Vec2,
+}
+
+/// [AssetLoader] for ttf and otf files, registered by the [TextPlugin](crate::TextPlugin) when there is an [AssetServer](modul_asset::AssetServer)
+pub struct FontLoader;
+
+impl AssetLoader for FontLoader {
+    type Asset = Font;
+
+    fn extensions(&self) -> &[&str] {
+        &["ttf", "otf"]
+    }
+
+    fn load(&self, bytes: Vec<u8>, _path: &Path) -> Result<Font, AssetLoadError> {
+        Ok(Font::from_bytes(bytes)?)
+    }
+}
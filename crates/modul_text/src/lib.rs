@@ -0,0 +1,94 @@
+mod atlas;
+mod batch;
+mod error_overlay;
+mod font;
+mod operation;
+mod stats_overlay;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetAppExt, AssetId, AssetServer};
+use modul_core::{Init, Redraw};
+use modul_render::glam::Vec2;
+use modul_render::{BindGroupLayoutInitPlugin, Draw, DrawSet, GpuMemorySet, RenderTargetSource};
+
+pub use atlas::*;
+pub use batch::*;
+pub use error_overlay::*;
+pub use font::*;
+pub use operation::*;
+pub use stats_overlay::*;
+
+/// [SystemSet] within [Draw] that lays out the [Texts](Text) and rasterizes new glyphs into the [GlyphAtlas].
+/// It runs after [ApplyOffscreenTargetsSystemSet] (so the target sizes are up to date) and before the sequences.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextPrepareSet;
+
+/// Lays out [Texts](Text) every frame, and creates the pipeline and [GlyphAtlas] used by [TextOperations](TextOperation).
+/// Add it after [RenderPlugin](modul_render::RenderPlugin), and after [AssetServerPlugin](modul_asset::AssetServerPlugin) to load fonts from files.
+pub struct TextPlugin;
+
+impl Plugin for TextPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_assets::<Font>();
+        app.init_assets::<GlyphAtlas>();
+        if app.world().contains_resource::<AssetServer>() {
+            app.register_asset_loader(FontLoader);
+        }
+        app.init_resource::<TextBatches>();
+        app.add_plugins(BindGroupLayoutInitPlugin::<TextAtlasLayout>::new());
+        app.add_systems(Init, init_text_pipeline);
+        app.add_systems(
+            Draw,
+            (
+                update_shader_error_overlay.run_if(resource_exists::<ShaderErrorOverlay>),
+                record_frame_times.run_if(resource_exists::<StatsOverlay>),
+                prepare_text,
+            )
+                .chain()
+                .in_set(TextPrepareSet),
+        );
+        app.add_systems(Redraw, update_atlas_memory_stats.in_set(GpuMemorySet));
+        app.configure_sets(Draw, TextPrepareSet.in_set(DrawSet::Prepare));
+    }
+}
+
+/// Text drawn to a render target by a [TextOperation].
+/// Sizes and positions are in logical pixels, they are multiplied by the scale factor of the target (see [TextScaleFactor]).
+#[derive(Component, Clone, Debug)]
+pub struct Text {
+    pub value: String,
+    pub font: AssetId<Font>,
+    /// The height of a line without the line gap
+    pub size: f32,
+    pub color: [f32; 4],
+    /// The top left corner of the text, relative to the top left of the target
+    pub position: Vec2,
+    /// Lines are wrapped when they get wider than this, see [Font::layout]
+    pub max_width: Option<f32>,
+    pub render_target: RenderTargetSource,
+}
+
+impl Text {
+    pub fn new(
+        value: impl Into<String>,
+        font: AssetId<Font>,
+        size: f32,
+        render_target: RenderTargetSource,
+    ) -> Self {
+        Self {
+            value: value.into(),
+            font,
+            size,
+            color: [1.0; 4],
+            position: Vec2::ZERO,
+            max_width: None,
+            render_target,
+        }
+    }
+}
+
+/// The number of physical pixels per logical pixel of [Texts](Text) drawn to the render target of the entity.
+/// Surfaces use the scale factor of their window, other targets use this component or 1 if it is missing.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct TextScaleFactor(pub f32);
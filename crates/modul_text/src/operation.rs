@@ -0,0 +1,203 @@
+use crate::{GlyphAtlas, TextBatches, TextInstance};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use modul_render::{
+    BindGroupLayoutDef, CachedBindGroupLayout, DirectRenderPipelineResourceProvider,
+    GenericColorTargetState, GenericDepthStencilState, GenericFragmentState,
+    GenericMultisampleState, GenericRenderPipelineDescriptor, GenericVertexState, Operation,
+    OperationBuilder, OperationError, PipelineLayoutComposer, RenderPipelineManager,
+    RenderTargetSource,
+};
+use wgpu::{
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, CommandEncoder,
+    CompareFunction, DepthBiasState, Device, PipelineLayout, PrimitiveState, PrimitiveTopology,
+    SamplerBindingType, ShaderModule, ShaderStages, StencilState, TextureSampleType,
+    TextureViewDimension,
+};
+
+/// The bind group layout of a [GlyphAtlas], declaring `atlas_texture` and `atlas_sampler`
+pub struct TextAtlasLayout;
+
+impl BindGroupLayoutDef for TextAtlasLayout {
+    const LAYOUT: &'static BindGroupLayoutDescriptor<'static> = &BindGroupLayoutDescriptor {
+        label: Some("Text atlas BGLayout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    };
+    const LIBRARY: &'static str = "@group(#BIND_GROUP) @binding(0)
+var atlas_texture: texture_2d<f32>;
+@group(#BIND_GROUP) @binding(1)
+var atlas_sampler: sampler;";
+}
+
+/// The pipeline of [TextOperations](TextOperation) and the [GlyphAtlas] all text is rasterized into, inserted during [Init](modul_core::Init)
+#[derive(Resource)]
+pub struct TextPipeline {
+    pub manager: AssetId<RenderPipelineManager>,
+    /// Draws [TextInstances](TextInstance) as filled rects in their color, ignoring the atlas
+    pub solid_manager: AssetId<RenderPipelineManager>,
+    pub atlas: AssetId<GlyphAtlas>,
+}
+
+/// Draws all [Texts](crate::Text) of the target, alpha blended on top of its contents
+pub struct TextOperation {
+    pub render_target: RenderTargetSource,
+}
+
+impl Operation for TextOperation {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(target) = self.render_target.resolve(world) else {
+            return Ok(());
+        };
+        let text_batches = world.resource::<TextBatches>();
+        let Some(range) = text_batches.instances_of(target) else {
+            return Ok(());
+        };
+        let Some(instances) = text_batches.instances().buffer().cloned() else {
+            return Ok(());
+        };
+        let text_pipeline = world.resource::<TextPipeline>();
+        let manager = text_pipeline.manager;
+        let Some(atlas) = world
+            .resource::<Assets<GlyphAtlas>>()
+            .get(text_pipeline.atlas)
+        else {
+            return Ok(());
+        };
+        let Some(bind_group) = atlas.bind_group().cloned() else {
+            return Ok(());
+        };
+        drop(atlas);
+        let mut pipeline = None;
+        world.asset_scope(manager, |world, manager| {
+            pipeline = manager.get_compatible(self.render_target, world).cloned();
+        });
+        let Some(pipeline) = pipeline else {
+            return Ok(());
+        };
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.set_vertex_buffer(0, instances.slice(..));
+        pass.draw(0..4, range);
+        Ok(())
+    }
+}
+
+impl OperationBuilder for TextOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        self
+    }
+}
+
+pub(crate) fn init_text_pipeline(
+    mut commands: Commands,
+    ctx: Res<RenderContext>,
+    mut shaders: ResMut<Assets<ShaderModule>>,
+    mut layouts: ResMut<Assets<PipelineLayout>>,
+    mut managers: ResMut<Assets<RenderPipelineManager>>,
+    mut atlases: ResMut<Assets<GlyphAtlas>>,
+) {
+    let mut composer = PipelineLayoutComposer::new();
+    composer.add_bind_group(CachedBindGroupLayout::<TextAtlasLayout>::new(&ctx.device));
+    let layout = layouts.add(composer.compose_pipeline_layout(&ctx.device).clone());
+    let shader = shaders.add(
+        composer
+            .compose_shader(&ctx.device, "Text shader", include_str!("text.wgsl"))
+            .expect("the built-in text shader has balanced directives")
+            .clone(),
+    );
+    let manager = managers.add(RenderPipelineManager::new(text_pipeline_descriptor(
+        layout,
+        shader,
+        "Text pipeline",
+        "fs_main",
+    )));
+    let solid_manager = managers.add(RenderPipelineManager::new(text_pipeline_descriptor(
+        layout,
+        shader,
+        "Text solid pipeline",
+        "fs_solid",
+    )));
+    let atlas = atlases.add(GlyphAtlas::new(512));
+    commands.insert_resource(TextPipeline {
+        manager,
+        solid_manager,
+        atlas,
+    });
+}
+
+fn text_pipeline_descriptor(
+    layout: AssetId<PipelineLayout>,
+    shader: AssetId<ShaderModule>,
+    label: &str,
+    fragment_entry_point: &str,
+) -> GenericRenderPipelineDescriptor {
+    GenericRenderPipelineDescriptor {
+        resource_provider: Box::new(DirectRenderPipelineResourceProvider {
+            layout,
+            vertex_shader_module: shader,
+            fragment_shader_module: shader,
+        }),
+        label: Some(label.to_string()),
+        vertex_state: GenericVertexState {
+            entry_point: "vs_main".to_string(),
+            buffers: vec![TextInstance::vertex_buffer_layout()],
+        },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        // ignoring depth, but still needed for targets with a depth buffer
+        depth_stencil: Some(GenericDepthStencilState {
+            depth_write_enable: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: GenericMultisampleState {
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        fragment: Some(GenericFragmentState {
+            entry_point: fragment_entry_point.to_string(),
+            targets: vec![GenericColorTargetState::new(Some(
+                BlendState::ALPHA_BLENDING,
+            ))],
+        }),
+    }
+}
@@ -0,0 +1,281 @@
+use crate::atlas::atlas_sampler_descriptor;
+use crate::batch::{scale_factor, GlyphWriter};
+use crate::{Font, GlyphAtlas, Text, TextAtlasLayout, TextInstance, TextPipeline};
+use bevy_ecs::prelude::*;
+use modul_asset::{AssetId, AssetWorldExt, Assets};
+use modul_core::RenderContext;
+use modul_render::glam::Vec2;
+use modul_render::{
+    CachedBindGroupLayout, FrameStats, GpuMemoryStats, GpuTimings, GpuVec, Operation,
+    OperationBuilder, OperationError, RenderPipelineManager, RenderTargetSource, SamplerCache,
+};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+use wgpu::{BufferUsages, CommandEncoder, Device};
+
+/// The amount of frames in the frame time graph
+const HISTORY: usize = 120;
+/// The frame time at the top of the graph, in milliseconds
+const GRAPH_MAX_MS: f32 = 50.0;
+/// In logical pixels
+const GRAPH_HEIGHT: f32 = 40.0;
+const BAR_WIDTH: f32 = 2.0;
+const PADDING: f32 = 6.0;
+
+/// Settings of the [StatsOverlayOperation], insert it to record the frame times and draw the overlay.
+/// Set `enabled` to hide the overlay without removing the operation.
+#[derive(Resource, Clone, Debug)]
+pub struct StatsOverlay {
+    pub enabled: bool,
+    pub font: AssetId<Font>,
+    pub size: f32,
+    pub color: [f32; 4],
+    /// The color of the rect behind the text and graph
+    pub background: [f32; 4],
+    /// The top left corner in logical pixels
+    pub position: Vec2,
+    frame_times: VecDeque<Duration>,
+    last_frame: u64,
+}
+
+impl StatsOverlay {
+    pub fn new(font: AssetId<Font>) -> Self {
+        Self {
+            enabled: true,
+            font,
+            size: 14.0,
+            color: [1.0; 4],
+            background: [0.0, 0.0, 0.0, 0.6],
+            position: Vec2::splat(8.0),
+            frame_times: VecDeque::with_capacity(HISTORY),
+            last_frame: 0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// The frame times shown in the graph, oldest first
+    pub fn frame_times(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.frame_times.iter().copied()
+    }
+}
+
+/// Draws the [FrameStats] (fps and CPU timings), a graph of the recent frame times, the [GpuTimings]
+/// of the [GpuProfilingPlugin](modul_render::GpuProfilingPlugin), the amount of cached render pipelines
+/// and the total of the [GpuMemoryStats] on top of the target, when the [StatsOverlay] resource exists and is enabled.
+pub struct StatsOverlayOperation {
+    pub render_target: RenderTargetSource,
+}
+
+impl OperationBuilder for StatsOverlayOperation {
+    fn reading(&self) -> Vec<RenderTargetSource> {
+        Vec::new()
+    }
+
+    fn writing(&self) -> Vec<RenderTargetSource> {
+        vec![self.render_target]
+    }
+
+    fn finish(self, _world: &World, _device: &Device) -> impl Operation + 'static {
+        PreparedStatsOverlay {
+            render_target: self.render_target,
+            instances: GpuVec::new(BufferUsages::VERTEX),
+        }
+    }
+}
+
+struct PreparedStatsOverlay {
+    render_target: RenderTargetSource,
+    /// The background and graph bars, followed by the glyphs
+    instances: GpuVec<TextInstance>,
+}
+
+impl Operation for PreparedStatsOverlay {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
+        let Some(overlay) = world
+            .get_resource::<StatsOverlay>()
+            .filter(|overlay| overlay.enabled)
+            .cloned()
+        else {
+            return Ok(());
+        };
+        let Some(target) = self.render_target.resolve(world) else {
+            return Ok(());
+        };
+        let Some((width, height)) = target.get(world).map(|rt| rt.size()) else {
+            return Ok(());
+        };
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let sampler = world.resource_scope(|world, mut samplers: Mut<SamplerCache>| {
+            let device = &world.resource::<RenderContext>().device;
+            samplers.get(device, &atlas_sampler_descriptor()).clone()
+        });
+        let text_pipeline = world.resource::<TextPipeline>();
+        let (manager, solid_manager) = (text_pipeline.manager, text_pipeline.solid_manager);
+        let solid_count = {
+            let ctx = world.resource::<RenderContext>();
+            let fonts = world.resource::<Assets<Font>>();
+            let Some(font) = fonts.get(overlay.font) else {
+                return Ok(());
+            };
+            let atlases = world.resource::<Assets<GlyphAtlas>>();
+            let Some(mut atlas) = atlases.write(text_pipeline.atlas) else {
+                return Ok(());
+            };
+            let mut writer = GlyphWriter {
+                ctx,
+                layout: world.resource::<CachedBindGroupLayout<TextAtlasLayout>>(),
+                sampler: &sampler,
+                atlas: &mut atlas,
+                target_size: Vec2::new(width as f32, height as f32),
+                scale: scale_factor(world, target),
+                stop_when_full: false,
+            };
+            let mut text = Text::new(
+                overlay_text(world),
+                overlay.font,
+                overlay.size,
+                self.render_target,
+            );
+            text.color = overlay.color;
+            text.position = overlay.position + PADDING;
+            let text_size = font.layout(&text.value, overlay.size, None).size;
+
+            self.instances.clear();
+            let graph_width = HISTORY as f32 * BAR_WIDTH;
+            let size = Vec2::new(
+                text_size.x.max(graph_width),
+                text_size.y + PADDING + GRAPH_HEIGHT,
+            ) + PADDING * 2.0;
+            let scale = writer.scale;
+            let rect = |min: Vec2, max: Vec2| writer.ndc_rect(min * scale, max * scale);
+            self.instances.push(TextInstance {
+                rect: rect(overlay.position, overlay.position + size),
+                uv_rect: [0.0; 4],
+                color: overlay.background,
+            });
+            // the newest frame is on the right
+            let graph_bottom = overlay.position + size - PADDING;
+            let first = HISTORY - overlay.frame_times.len();
+            for (i, frame_time) in overlay.frame_times().enumerate() {
+                let ms = frame_time.as_secs_f32() * 1000.0;
+                let bar_height = (ms / GRAPH_MAX_MS).min(1.0) * GRAPH_HEIGHT;
+                let x = overlay.position.x + PADDING + (first + i) as f32 * BAR_WIDTH;
+                self.instances.push(TextInstance {
+                    rect: rect(
+                        Vec2::new(x, graph_bottom.y - bar_height),
+                        Vec2::new(x + BAR_WIDTH, graph_bottom.y),
+                    ),
+                    uv_rect: [0.0; 4],
+                    color: frame_time_color(ms),
+                });
+            }
+            let solid_count = self.instances.len() as u32;
+            let _ = writer.write(&mut self.instances, &font, &text);
+            self.instances.flush(&ctx.device, &ctx.queue);
+            solid_count
+        };
+        let text_pipeline = world.resource::<TextPipeline>();
+        let Some(bind_group) = world
+            .resource::<Assets<GlyphAtlas>>()
+            .get(text_pipeline.atlas)
+            .and_then(|atlas| atlas.bind_group().cloned())
+        else {
+            return Ok(());
+        };
+        let Some(instances) = self.instances.buffer().cloned() else {
+            return Ok(());
+        };
+        let mut pipelines = [None, None];
+        for (pipeline, manager) in pipelines.iter_mut().zip([solid_manager, manager]) {
+            world.asset_scope(manager, |world, manager| {
+                *pipeline = manager.get_compatible(self.render_target, world).cloned();
+            });
+        }
+        let [Some(solid_pipeline), Some(pipeline)] = pipelines else {
+            return Ok(());
+        };
+        let Some(mut rt) = self.render_target.get_mut(world) else {
+            return Ok(());
+        };
+        let Some(mut pass) = rt.begin_ending_pass(command_encoder) else {
+            return Ok(());
+        };
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.set_vertex_buffer(0, instances.slice(..));
+        pass.set_pipeline(&solid_pipeline);
+        pass.draw(0..4, 0..solid_count);
+        pass.set_pipeline(&pipeline);
+        pass.draw(0..4, solid_count..self.instances.len() as u32);
+        Ok(())
+    }
+}
+
+/// Green when a frame takes at most 1/60 s, yellow up to 1/30 s, red above
+fn frame_time_color(ms: f32) -> [f32; 4] {
+    match ms {
+        ms if ms <= 1000.0 / 60.0 => [0.3, 0.9, 0.3, 1.0],
+        ms if ms <= 1000.0 / 30.0 => [0.9, 0.8, 0.2, 1.0],
+        _ => [0.9, 0.3, 0.3, 1.0],
+    }
+}
+
+fn overlay_text(world: &World) -> String {
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let stats = world.resource::<FrameStats>();
+    let mut text = format!(
+        "{:.0} fps  {:.2} ms\ncpu {:.2} ms  draw {:.2} ms  present {:.2} ms",
+        stats.fps(),
+        ms(stats.frame_time),
+        ms(stats.cpu_time),
+        ms(stats.draw),
+        ms(stats.present),
+    );
+    let managers = world.resource::<Assets<RenderPipelineManager>>();
+    let pipelines = managers
+        .iter()
+        .map(|(_, manager)| manager.instance_count())
+        .sum::<usize>();
+    let _ = write!(
+        text,
+        "\n{} render pipelines in {} managers",
+        pipelines,
+        managers.len()
+    );
+    // updated after the frame is rendered, so it is the memory of the last frame
+    let memory = world.resource::<GpuMemoryStats>().total();
+    let _ = write!(
+        text,
+        "\n{:.1} MiB in {} textures and buffers",
+        memory.bytes as f64 / (1024.0 * 1024.0),
+        memory.count
+    );
+    if let Some(timings) = world.get_resource::<GpuTimings>() {
+        let mut timings = timings.iter().collect::<Vec<_>>();
+        timings.sort_by(|a, b| a.0.cmp(b.0));
+        for (label, time) in timings {
+            let _ = write!(text, "\n{}: {:.2} ms", label, ms(time));
+        }
+    }
+    text
+}
+
+pub(crate) fn record_frame_times(mut overlay: ResMut<StatsOverlay>, stats: Res<FrameStats>) {
+    if stats.frame == overlay.last_frame {
+        return;
+    }
+    overlay.last_frame = stats.frame;
+    if overlay.frame_times.len() == HISTORY {
+        overlay.frame_times.pop_front();
+    }
+    overlay.frame_times.push_back(stats.frame_time);
+}
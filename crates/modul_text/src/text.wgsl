@@ -0,0 +1,33 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) rect: vec4<f32>,
+    @location(1) uv_rect: vec4<f32>,
+    @location(2) color: vec4<f32>,
+) -> VertexOutput {
+    // triangle strip corners: top left, bottom left, top right, bottom right
+    let corner = vec2<f32>(f32(vertex_index / 2u), f32(vertex_index % 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(mix(rect.xy, rect.zw, corner), 0.0, 1.0);
+    out.uv = mix(uv_rect.xy, uv_rect.zw, corner);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(atlas_texture, atlas_sampler, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
+
+// fills the whole rect, for backgrounds and bars
+@fragment
+fn fs_solid(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,188 @@
+use crate::ImageLoadError;
+use ddsfile::{D3DFormat, Dds, DxgiFormat};
+use modul_asset::{AssetLoadError, AssetLoader};
+use std::path::Path;
+use wgpu::{AstcBlock, AstcChannel, Features, TextureFormat};
+
+/// Texture data stored in a GPU format, usually block compressed.
+/// Only 2D textures with a single layer are supported.
+#[derive(Clone)]
+pub struct CompressedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub mip_level_count: u32,
+    /// All mip levels after each other, starting at the largest
+    pub data: Vec<u8>,
+}
+
+impl CompressedImage {
+    /// Parses a KTX2 file, supercompressed files are not supported
+    pub fn load_ktx2(data: &[u8]) -> Result<Self, ImageLoadError> {
+        let reader = ktx2::Reader::new(data)?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            return Err(ImageLoadError::Unsupported(format!(
+                "KTX2 supercompression {:?}",
+                scheme
+            )));
+        }
+        if header.layer_count > 1 || header.face_count > 1 || header.pixel_depth > 1 {
+            return Err(ImageLoadError::Unsupported(
+                "KTX2 arrays, cubemaps and 3D textures".to_string(),
+            ));
+        }
+        let format = header.format.and_then(ktx2_format).ok_or_else(|| {
+            ImageLoadError::Unsupported(format!("KTX2 format {:?}", header.format))
+        })?;
+        Ok(Self {
+            format,
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            mip_level_count: header.level_count.max(1),
+            data: reader.levels().flat_map(|l| l.data).copied().collect(),
+        })
+    }
+
+    /// Parses a DDS file
+    pub fn load_dds(data: &[u8]) -> Result<Self, ImageLoadError> {
+        let dds = Dds::read(data)?;
+        if dds.get_num_array_layers() > 1 || dds.get_depth() > 1 {
+            return Err(ImageLoadError::Unsupported(
+                "DDS arrays, cubemaps and 3D textures".to_string(),
+            ));
+        }
+        let format = dds
+            .get_dxgi_format()
+            .and_then(dxgi_format)
+            .or_else(|| dds.get_d3d_format().and_then(d3d_format))
+            .ok_or_else(|| ImageLoadError::Unsupported("DDS format".to_string()))?;
+        Ok(Self {
+            format,
+            width: dds.get_width(),
+            height: dds.get_height(),
+            mip_level_count: dds.get_num_mipmap_levels().max(1),
+            data: dds.get_data(0)?.to_vec(),
+        })
+    }
+
+    /// Returns true if the device has the features needed for the format
+    pub fn is_supported(&self, features: Features) -> bool {
+        features.contains(self.format.required_features())
+    }
+}
+
+/// [AssetLoader] for KTX2 and DDS files, the [ImageAssetLoader](crate::ImageAssetLoader) picks which one to upload
+pub struct CompressedImageLoader;
+
+impl AssetLoader for CompressedImageLoader {
+    type Asset = CompressedImage;
+
+    fn extensions(&self) -> &[&str] {
+        &["ktx2", "dds"]
+    }
+
+    fn load(&self, bytes: Vec<u8>, path: &Path) -> Result<CompressedImage, AssetLoadError> {
+        let image = match path.extension().and_then(|e| e.to_str()) {
+            Some("dds") => CompressedImage::load_dds(&bytes)?,
+            _ => CompressedImage::load_ktx2(&bytes)?,
+        };
+        Ok(image)
+    }
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format as F;
+    // the ASTC formats are laid out as unorm / srgb pairs in order of block size
+    const ASTC_BLOCKS: [AstcBlock; 14] = [
+        AstcBlock::B4x4,
+        AstcBlock::B5x4,
+        AstcBlock::B5x5,
+        AstcBlock::B6x5,
+        AstcBlock::B6x6,
+        AstcBlock::B8x5,
+        AstcBlock::B8x6,
+        AstcBlock::B8x8,
+        AstcBlock::B10x5,
+        AstcBlock::B10x6,
+        AstcBlock::B10x8,
+        AstcBlock::B10x10,
+        AstcBlock::B12x10,
+        AstcBlock::B12x12,
+    ];
+    let astc_start = F::ASTC_4x4_UNORM_BLOCK.value();
+    let astc_end = F::ASTC_12x12_SRGB_BLOCK.value();
+    if (astc_start..=astc_end).contains(&format.value()) {
+        let offset = (format.value() - astc_start) as usize;
+        let channel = if offset.is_multiple_of(2) {
+            AstcChannel::Unorm
+        } else {
+            AstcChannel::UnormSrgb
+        };
+        return Some(TextureFormat::Astc {
+            block: ASTC_BLOCKS[offset / 2],
+            channel,
+        });
+    }
+    Some(match format {
+        F::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        F::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGB_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        F::BC1_RGBA_SRGB_BLOCK | F::BC1_RGB_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        F::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        F::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        F::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        F::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        F::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        F::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+        F::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        F::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+        F::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        F::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        F::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        F::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        F::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        F::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        F::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+        F::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        F::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        F::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        F::EAC_R11_UNORM_BLOCK => TextureFormat::EacR11Unorm,
+        F::EAC_R11_SNORM_BLOCK => TextureFormat::EacR11Snorm,
+        F::EAC_R11G11_UNORM_BLOCK => TextureFormat::EacRg11Unorm,
+        F::EAC_R11G11_SNORM_BLOCK => TextureFormat::EacRg11Snorm,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: DxgiFormat) -> Option<TextureFormat> {
+    Some(match format {
+        DxgiFormat::R8G8B8A8_UNorm => TextureFormat::Rgba8Unorm,
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => TextureFormat::Rgba8UnormSrgb,
+        DxgiFormat::BC1_UNorm => TextureFormat::Bc1RgbaUnorm,
+        DxgiFormat::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+        DxgiFormat::BC2_UNorm => TextureFormat::Bc2RgbaUnorm,
+        DxgiFormat::BC2_UNorm_sRGB => TextureFormat::Bc2RgbaUnormSrgb,
+        DxgiFormat::BC3_UNorm => TextureFormat::Bc3RgbaUnorm,
+        DxgiFormat::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
+        DxgiFormat::BC4_UNorm => TextureFormat::Bc4RUnorm,
+        DxgiFormat::BC4_SNorm => TextureFormat::Bc4RSnorm,
+        DxgiFormat::BC5_UNorm => TextureFormat::Bc5RgUnorm,
+        DxgiFormat::BC5_SNorm => TextureFormat::Bc5RgSnorm,
+        DxgiFormat::BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
+        DxgiFormat::BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
+        DxgiFormat::BC7_UNorm => TextureFormat::Bc7RgbaUnorm,
+        DxgiFormat::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn d3d_format(format: D3DFormat) -> Option<TextureFormat> {
+    Some(match format {
+        D3DFormat::A8B8G8R8 => TextureFormat::Rgba8Unorm,
+        D3DFormat::DXT1 => TextureFormat::Bc1RgbaUnorm,
+        D3DFormat::DXT3 => TextureFormat::Bc2RgbaUnorm,
+        D3DFormat::DXT5 => TextureFormat::Bc3RgbaUnorm,
+        _ => return None,
+    })
+}
@@ -0,0 +1,196 @@
+use crate::{Image, ImageAsset, ImageSettings};
+use bevy_ecs::{prelude::*, system::SystemParam};
+use log::warn;
+use modul_asset::{AssetEvent, AssetId, AssetServer, AssetWorldExt, Assets, LoadState};
+use modul_core::RenderContext;
+use modul_render::{Cubemap, EnvironmentMapFilter};
+use std::path::Path;
+use wgpu::{CommandEncoderDescriptor, TextureFormat};
+
+/// How a [Cubemap] is created from its [Images](Image)
+#[derive(Clone, Debug)]
+pub enum CubemapSource {
+    /// Six square faces in the order +X, -X, +Y, -Y, +Z, -Z
+    Faces([AssetId<Image>; 6]),
+    /// An equirectangular image converted to faces of `face_size` with mip levels, see [EnvironmentMapFilter::equirectangular_to_cubemap]
+    Equirectangular {
+        image: AssetId<Image>,
+        face_size: u32,
+    },
+}
+
+impl CubemapSource {
+    fn images(&self) -> &[AssetId<Image>] {
+        match self {
+            CubemapSource::Faces(faces) => faces,
+            CubemapSource::Equirectangular { image, .. } => std::slice::from_ref(image),
+        }
+    }
+}
+
+/// Cubemaps waiting for their images
+#[derive(Resource, Default)]
+pub struct CubemapUploads {
+    uploads: Vec<CubemapUpload>,
+}
+
+struct CubemapUpload {
+    id: AssetId<Cubemap>,
+    source: CubemapSource,
+    srgb: bool,
+    done: bool,
+    reload: bool,
+}
+
+/// Creates [Cubemaps](Cubemap) from images loaded by the [AssetServer], the returned ids are empty until all images are loaded.
+/// Cubemaps are created again when one of their images is reloaded.
+#[derive(SystemParam)]
+pub struct CubemapLoader<'w> {
+    server: ResMut<'w, AssetServer>,
+    images: Res<'w, Assets<Image>>,
+    cubemaps: Res<'w, Assets<Cubemap>>,
+    uploads: ResMut<'w, CubemapUploads>,
+}
+
+impl CubemapLoader<'_> {
+    /// Loads six square faces in the order +X, -X, +Y, -Y, +Z, -Z.
+    /// If `srgb` the faces are treated as sRGB, like [ImageSettings::srgb].
+    pub fn load_faces(&mut self, paths: [impl AsRef<Path>; 6], srgb: bool) -> AssetId<Cubemap> {
+        let faces = paths.map(|path| self.server.load(&self.images, path));
+        self.add_source(CubemapSource::Faces(faces), srgb)
+    }
+
+    /// Loads an equirectangular (latitude/longitude) image, converted to a cubemap with faces of `face_size`
+    pub fn load_equirectangular(
+        &mut self,
+        path: impl AsRef<Path>,
+        face_size: u32,
+        srgb: bool,
+    ) -> AssetId<Cubemap> {
+        let image = self.server.load(&self.images, path);
+        self.add_source(CubemapSource::Equirectangular { image, face_size }, srgb)
+    }
+
+    /// Creates a cubemap from [Images](Image) that are loaded or will be loaded
+    pub fn add_source(&mut self, source: CubemapSource, srgb: bool) -> AssetId<Cubemap> {
+        let id = self.cubemaps.add_empty();
+        self.uploads.uploads.push(CubemapUpload {
+            id,
+            source,
+            srgb,
+            done: false,
+            reload: false,
+        });
+        id
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn upload_cubemaps(
+    mut events: MessageReader<AssetEvent<Image>>,
+    mut uploads: ResMut<CubemapUploads>,
+    mut cubemaps: ResMut<Assets<Cubemap>>,
+    images: Res<Assets<Image>>,
+    server: Res<AssetServer>,
+    filter: Res<EnvironmentMapFilter>,
+    ctx: Res<RenderContext>,
+    mut commands: Commands,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified(id) = event {
+            for upload in &mut uploads.uploads {
+                if upload.source.images().contains(id) {
+                    upload.done = false;
+                    upload.reload = true;
+                }
+            }
+        }
+    }
+    for upload in uploads.uploads.iter_mut().filter(|u| !u.done) {
+        if upload
+            .source
+            .images()
+            .iter()
+            .any(|image| matches!(server.load_state(*image), LoadState::Loading))
+        {
+            continue;
+        }
+        upload.done = true;
+        let Some(cubemap) = create_cubemap(&upload.source, upload.srgb, &images, &filter, &ctx)
+        else {
+            continue;
+        };
+        cubemaps.replace(upload.id, cubemap);
+        if upload.reload {
+            commands.write_message(AssetEvent::Modified(upload.id));
+            let id = upload.id;
+            commands.queue(move |world: &mut World| world.mark_asset_changed(id));
+        } else {
+            commands.write_message(AssetEvent::Loaded(upload.id));
+        }
+    }
+}
+
+fn create_cubemap(
+    source: &CubemapSource,
+    srgb: bool,
+    images: &Assets<Image>,
+    filter: &EnvironmentMapFilter,
+    ctx: &RenderContext,
+) -> Option<Cubemap> {
+    match source {
+        CubemapSource::Faces(faces) => {
+            let Some(faces) = faces
+                .iter()
+                .map(|face| images.get(*face))
+                .collect::<Option<Vec<_>>>()
+            else {
+                warn!("A cubemap face failed to load");
+                return None;
+            };
+            let size = faces[0].width;
+            if faces.iter().any(|f| f.width != size || f.height != size) {
+                warn!("Cubemap faces must be square and have the same size");
+                return None;
+            }
+            let data: Vec<u8> = faces.iter().flat_map(|f| f.data.iter().copied()).collect();
+            let format = match srgb {
+                true => TextureFormat::Rgba8UnormSrgb,
+                false => TextureFormat::Rgba8Unorm,
+            };
+            Some(Cubemap::with_data(
+                &ctx.device,
+                &ctx.queue,
+                size,
+                format,
+                1,
+                &data,
+            ))
+        }
+        CubemapSource::Equirectangular { image, face_size } => {
+            let Some(image) = images.get(*image) else {
+                warn!("A cubemap equirectangular image failed to load");
+                return None;
+            };
+            let settings = ImageSettings {
+                srgb: Some(srgb),
+                sampler: None,
+            };
+            let equirectangular = ImageAsset::new(&ctx.device, &ctx.queue, &image, &settings);
+            let mut command_encoder =
+                ctx.device
+                    .create_command_encoder(&CommandEncoderDescriptor {
+                        label: Some("Equirectangular conversion"),
+                    });
+            let cubemap = filter.equirectangular_to_cubemap(
+                &ctx.device,
+                &mut command_encoder,
+                &equirectangular.view,
+                *face_size,
+                u32::MAX,
+            );
+            ctx.queue.submit([command_encoder.finish()]);
+            Some(cubemap)
+        }
+    }
+}
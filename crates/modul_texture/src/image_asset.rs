@@ -0,0 +1,350 @@
+use crate::{
+    upload_cubemaps, CompressedImage, CompressedImageLoader, CubemapUploads, Image, ImageLoadError,
+};
+use bevy_app::{App, Plugin};
+use bevy_ecs::{prelude::*, system::SystemParam};
+use log::warn;
+use modul_asset::{
+    AssetAppExt, AssetEvent, AssetId, AssetLoadError, AssetLoadSet, AssetLoader, AssetMap,
+    AssetServer, AssetWorldExt, Assets, LoadState,
+};
+use modul_core::{Redraw, RenderContext};
+use modul_render::{GpuMemorySet, GpuMemoryStats, GpuMemoryUsage, RedrawSet};
+use std::path::Path;
+use wgpu::util::{DeviceExt, TextureDataOrder};
+use wgpu::{
+    Device, Extent3d, Origin3d, Queue, Sampler, SamplerDescriptor, TexelCopyBufferLayout,
+    TexelCopyTextureInfo, Texture, TextureAspect, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+/// Loads [ImageAssets](ImageAsset) from png, jpeg, KTX2 and DDS files using the [AssetServer], and [Cubemaps](modul_render::Cubemap) through the [CubemapLoader](crate::CubemapLoader).
+/// [AssetServerPlugin](modul_asset::AssetServerPlugin) must be added first.
+pub struct ImageAssetPlugin;
+
+impl Plugin for ImageAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_assets::<Image>();
+        app.init_assets::<CompressedImage>();
+        app.init_assets::<ImageAsset>();
+        app.register_asset_loader(ImageFileLoader);
+        app.register_asset_loader(CompressedImageLoader);
+        app.insert_resource(ImageUploads {
+            loading: AssetMap::new(),
+            queued: Vec::new(),
+            compressed: Vec::new(),
+        });
+        app.init_resource::<CubemapUploads>();
+        app.add_systems(
+            Redraw,
+            (upload_images, upload_compressed_images, upload_cubemaps)
+                .after(AssetLoadSet)
+                .in_set(RedrawSet::Prepare),
+        );
+        app.add_systems(Redraw, update_image_memory_stats.in_set(GpuMemorySet));
+    }
+}
+
+/// [AssetLoader] decoding png and jpeg files into [Images](Image), used by [ImageAssetPlugin]
+pub struct ImageFileLoader;
+
+impl AssetLoader for ImageFileLoader {
+    type Asset = Image;
+
+    fn extensions(&self) -> &[&str] {
+        &["png", "jpg", "jpeg"]
+    }
+
+    fn load(&self, bytes: Vec<u8>, _path: &Path) -> Result<Image, AssetLoadError> {
+        Ok(Image::load_from_data(&bytes)?)
+    }
+}
+
+/// How an [Image] or [CompressedImage] is turned into an [ImageAsset]
+#[derive(Clone, Debug, Default)]
+pub struct ImageSettings {
+    /// If true the data is treated as sRGB (usual for color textures), if false it is linear (normal maps, masks etc.).
+    /// None treats [Images](Image) as sRGB and keeps the format declared by the file for [CompressedImages](CompressedImage),
+    /// otherwise it overrides that format if it has an sRGB variant.
+    pub srgb: Option<bool>,
+    /// If set, a [Sampler] is created with the image
+    pub sampler: Option<SamplerDescriptor<'static>>,
+}
+
+/// A texture created from an [Image], with a view for reading in shaders
+pub struct ImageAsset {
+    pub texture: Texture,
+    pub view: TextureView,
+    pub sampler: Option<Sampler>,
+    pub width: u32,
+    pub height: u32,
+    /// For [Images](Image) [Rgba8UnormSrgb](TextureFormat::Rgba8UnormSrgb) or [Rgba8Unorm](TextureFormat::Rgba8Unorm) depending on [ImageSettings::srgb]
+    pub format: TextureFormat,
+}
+
+impl ImageAsset {
+    /// Creates the texture and uploads the image through the queue
+    pub fn new(device: &Device, queue: &Queue, image: &Image, settings: &ImageSettings) -> Self {
+        let format = if settings.srgb.unwrap_or(true) {
+            TextureFormat::Rgba8UnormSrgb
+        } else {
+            TextureFormat::Rgba8Unorm
+        };
+        let size = Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &image.data,
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * image.width),
+                rows_per_image: Some(image.height),
+            },
+            size,
+        );
+        Self {
+            view: texture.create_view(&TextureViewDescriptor::default()),
+            texture,
+            sampler: settings.sampler.as_ref().map(|s| device.create_sampler(s)),
+            width: image.width,
+            height: image.height,
+            format,
+        }
+    }
+
+    /// Creates the texture with all mip levels of the [CompressedImage], the device must support its format, see [CompressedImage::is_supported]
+    pub fn from_compressed(
+        device: &Device,
+        queue: &Queue,
+        image: &CompressedImage,
+        settings: &ImageSettings,
+    ) -> Self {
+        let format = match settings.srgb {
+            Some(true) => image.format.add_srgb_suffix(),
+            Some(false) => image.format.remove_srgb_suffix(),
+            None => image.format,
+        };
+        let texture = device.create_texture_with_data(
+            queue,
+            &TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width: image.width,
+                    height: image.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: image.mip_level_count,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            &image.data,
+        );
+        Self {
+            view: texture.create_view(&TextureViewDescriptor::default()),
+            texture,
+            sampler: settings.sampler.as_ref().map(|s| device.create_sampler(s)),
+            width: image.width,
+            height: image.height,
+            format,
+        }
+    }
+}
+
+/// Images waiting to be uploaded
+#[derive(Resource)]
+pub struct ImageUploads {
+    // kept after uploading, so reloads are uploaded again
+    loading: AssetMap<Image, (AssetId<ImageAsset>, ImageSettings)>,
+    queued: Vec<(AssetId<ImageAsset>, Image, ImageSettings)>,
+    compressed: Vec<CompressedUpload>,
+}
+
+struct CompressedUpload {
+    id: AssetId<ImageAsset>,
+    candidates: Vec<AssetId<CompressedImage>>,
+    settings: ImageSettings,
+    done: bool,
+    reload: bool,
+}
+
+/// Creates [ImageAssets](ImageAsset), the returned ids are empty until the image is uploaded
+#[derive(SystemParam)]
+pub struct ImageAssetLoader<'w> {
+    server: ResMut<'w, AssetServer>,
+    images: Res<'w, Assets<Image>>,
+    compressed_images: Res<'w, Assets<CompressedImage>>,
+    image_assets: Res<'w, Assets<ImageAsset>>,
+    uploads: ResMut<'w, ImageUploads>,
+}
+
+impl ImageAssetLoader<'_> {
+    /// Loads an image file using the [AssetServer], it is uploaded again when the file is reloaded
+    pub fn load(&mut self, path: impl AsRef<Path>, settings: ImageSettings) -> AssetId<ImageAsset> {
+        let id = self.image_assets.add_empty();
+        let image_id = self.server.load(&self.images, path);
+        self.uploads.loading.insert(image_id, (id, settings));
+        id
+    }
+
+    /// Loads all KTX2 or DDS candidates, and uploads the first one with a format supported by the device.
+    /// This way the same texture can be shipped in multiple formats, for example BC7 for desktop and ASTC for mobile.
+    pub fn load_compressed(
+        &mut self,
+        candidates: &[impl AsRef<Path>],
+        settings: ImageSettings,
+    ) -> AssetId<ImageAsset> {
+        let id = self.image_assets.add_empty();
+        let candidates = candidates
+            .iter()
+            .map(|path| self.server.load(&self.compressed_images, path))
+            .collect();
+        self.uploads.compressed.push(CompressedUpload {
+            id,
+            candidates,
+            settings,
+            done: false,
+            reload: false,
+        });
+        id
+    }
+
+    /// Uploads an image that is already in memory
+    pub fn add(&mut self, image: Image, settings: ImageSettings) -> AssetId<ImageAsset> {
+        let id = self.image_assets.add_empty();
+        self.uploads.queued.push((id, image, settings));
+        id
+    }
+}
+
+fn upload_images(
+    mut events: MessageReader<AssetEvent<Image>>,
+    mut image_events: MessageWriter<AssetEvent<ImageAsset>>,
+    mut uploads: ResMut<ImageUploads>,
+    mut image_assets: ResMut<Assets<ImageAsset>>,
+    images: Res<Assets<Image>>,
+    ctx: Res<RenderContext>,
+    mut commands: Commands,
+) {
+    for (id, image, settings) in uploads.queued.drain(..) {
+        image_assets.replace(
+            id,
+            ImageAsset::new(&ctx.device, &ctx.queue, &image, &settings),
+        );
+    }
+    for event in events.read() {
+        let (image_id, modified) = match event {
+            AssetEvent::Loaded(id) => (*id, false),
+            AssetEvent::Modified(id) => (*id, true),
+            AssetEvent::DependencyModified(_) => continue,
+        };
+        let (Some((id, settings)), Some(image)) =
+            (uploads.loading.get(&image_id), images.get(image_id))
+        else {
+            continue;
+        };
+        image_assets.replace(
+            *id,
+            ImageAsset::new(&ctx.device, &ctx.queue, &image, settings),
+        );
+        if modified {
+            image_events.write(AssetEvent::Modified(*id));
+            let id = *id;
+            // rebuild whatever was created from the image
+            commands.queue(move |world: &mut World| world.mark_asset_changed(id));
+        } else {
+            image_events.write(AssetEvent::Loaded(*id));
+        }
+    }
+}
+
+fn upload_compressed_images(
+    mut events: MessageReader<AssetEvent<CompressedImage>>,
+    mut uploads: ResMut<ImageUploads>,
+    mut image_assets: ResMut<Assets<ImageAsset>>,
+    images: Res<Assets<CompressedImage>>,
+    server: Res<AssetServer>,
+    ctx: Res<RenderContext>,
+    mut commands: Commands,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified(id) = event {
+            for upload in &mut uploads.compressed {
+                if upload.candidates.contains(id) {
+                    upload.done = false;
+                    upload.reload = true;
+                }
+            }
+        }
+    }
+    let features = ctx.device.features();
+    for upload in uploads.compressed.iter_mut().filter(|u| !u.done) {
+        // the first supported candidate can only be known when all are done loading
+        if upload
+            .candidates
+            .iter()
+            .any(|c| matches!(server.load_state(*c), LoadState::Loading))
+        {
+            continue;
+        }
+        upload.done = true;
+        let Some(image) = upload
+            .candidates
+            .iter()
+            .filter_map(|c| images.get(*c))
+            .find(|image| image.is_supported(features))
+        else {
+            warn!("No compressed image candidate is supported by the device");
+            continue;
+        };
+        image_assets.replace(
+            upload.id,
+            ImageAsset::from_compressed(&ctx.device, &ctx.queue, &image, &upload.settings),
+        );
+        if upload.reload {
+            commands.write_message(AssetEvent::Modified(upload.id));
+            let id = upload.id;
+            commands.queue(move |world: &mut World| world.mark_asset_changed(id));
+        } else {
+            commands.write_message(AssetEvent::Loaded(upload.id));
+        }
+    }
+}
+
+impl From<ImageLoadError> for AssetLoadError {
+    fn from(value: ImageLoadError) -> Self {
+        AssetLoadError::LoaderError(Box::new(value))
+    }
+}
+
+fn update_image_memory_stats(
+    mut stats: ResMut<GpuMemoryStats>,
+    image_assets: Res<Assets<ImageAsset>>,
+) {
+    let mut usage = GpuMemoryUsage::default();
+    for (_, image) in image_assets.iter() {
+        usage.add_texture(&image.texture);
+    }
+    stats.set("images", usage);
+}
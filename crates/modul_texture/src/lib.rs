@@ -1,11 +1,16 @@
 // TODO Handle mipmapping
 
+mod compressed;
+mod cubemap;
+mod image_asset;
+mod recorder;
+
 use bevy_app::{App, Plugin};
 use bevy_ecs::{prelude::*, system::SystemParam};
 use image::{DynamicImage, ImageError, ImageReader};
 use modul_asset::{AssetAppExt, AssetId, Assets};
-use modul_core::RenderContext;
-use modul_render::PreDraw;
+use modul_core::{Redraw, RenderContext};
+use modul_render::{GpuMemorySet, GpuMemoryStats, GpuMemoryUsage, PreDraw};
 use std::{
     error::Error,
     fmt::{self, Display, Formatter},
@@ -19,6 +24,11 @@ use wgpu::{
     TextureViewDescriptor,
 };
 
+pub use compressed::*;
+pub use cubemap::*;
+pub use image_asset::*;
+pub use recorder::*;
+
 /// Systems that load textures during [PreDraw], anything that runs in [PreDraw] and needs textures should run after this
 #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TextureLoadSet;
@@ -30,6 +40,7 @@ impl Plugin for TextureLoadPlugin {
         app.init_assets::<ViewTexture>();
         app.insert_resource(TextureQueue { queue: Vec::new() });
         app.add_systems(PreDraw, load_textures.in_set(TextureLoadSet));
+        app.add_systems(Redraw, update_texture_memory_stats.in_set(GpuMemorySet));
     }
 }
 
@@ -37,6 +48,10 @@ impl Plugin for TextureLoadPlugin {
 pub enum ImageLoadError {
     IOError(io::Error),
     ImageError(ImageError),
+    Ktx2Error(ktx2::ParseError),
+    DdsError(ddsfile::Error),
+    /// The file uses a feature or format that can not be loaded
+    Unsupported(String),
 }
 
 impl Error for ImageLoadError {}
@@ -46,6 +61,9 @@ impl Display for ImageLoadError {
         match self {
             ImageLoadError::IOError(e) => write!(f, "Texture load IOError: {}", e),
             ImageLoadError::ImageError(e) => write!(f, "Texture load ImageError: {}", e),
+            ImageLoadError::Ktx2Error(e) => write!(f, "Texture load KTX2 error: {}", e),
+            ImageLoadError::DdsError(e) => write!(f, "Texture load DDS error: {}", e),
+            ImageLoadError::Unsupported(s) => write!(f, "Texture load unsupported: {}", s),
         }
     }
 }
@@ -62,6 +80,18 @@ impl From<ImageError> for ImageLoadError {
     }
 }
 
+impl From<ktx2::ParseError> for ImageLoadError {
+    fn from(value: ktx2::ParseError) -> Self {
+        Self::Ktx2Error(value)
+    }
+}
+
+impl From<ddsfile::Error> for ImageLoadError {
+    fn from(value: ddsfile::Error) -> Self {
+        Self::DdsError(value)
+    }
+}
+
 /// Actual representation of image data, not a GPU resource.  
 /// This is mostly used as a layer between image files and [Textures](Texture)
 #[derive(Clone)]
@@ -161,7 +191,7 @@ impl MipMapImage {
 
     /// Directly writes to a texture, for most cases [TextureLoader] or [TextureQueue] should be sufficient
     pub fn write_to_texture(&self, queue: &Queue, origin: Origin3d, texture: &Texture) {
-        for (mip_level, image) in self.levels().into_iter().enumerate() {
+        for (mip_level, image) in self.levels().iter().enumerate() {
             queue.write_texture(
                 TexelCopyTextureInfo {
                     texture,
@@ -250,7 +280,7 @@ impl TextureQueue {
 #[derive(SystemParam)]
 pub struct TextureLoader<'w> {
     texture_queue: ResMut<'w, TextureQueue>,
-    texture_assets: ResMut<'w, Assets<ViewTexture>>,
+    texture_assets: Res<'w, Assets<ViewTexture>>,
 }
 
 impl TextureLoader<'_> {
@@ -301,7 +331,7 @@ impl TextureLoader<'_> {
     }
 }
 
-fn validate_layers(images: &Vec<MipMapImage>) -> Option<LayeredTextureError> {
+fn validate_layers(images: &[MipMapImage]) -> Option<LayeredTextureError> {
     if images.is_empty() {
         return Some(LayeredTextureError::NoLayers);
     }
@@ -349,6 +379,17 @@ fn load_textures(
     }
 }
 
+fn update_texture_memory_stats(
+    mut stats: ResMut<GpuMemoryStats>,
+    texture_assets: Res<Assets<ViewTexture>>,
+) {
+    let mut usage = GpuMemoryUsage::default();
+    for (_, texture) in texture_assets.iter() {
+        usage.add_texture(&texture.texture);
+    }
+    stats.set("textures", usage);
+}
+
 fn write_texture(info: TextureWriteInfo, texture_assets: &Assets<ViewTexture>, queue: &Queue) {
     info.image.write_to_texture(
         queue,
@@ -0,0 +1,258 @@
+use bevy_app::{App, Plugin};
+use bevy_ecs::prelude::*;
+use image::ExtendedColorType;
+use log::error;
+use modul_render::{PreDraw, ReadbackImage, RenderTargetSource, Screenshots};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+};
+use wgpu::TextureFormat;
+
+/// Where a [FrameRecorder] writes the captured frames
+#[derive(Debug)]
+pub enum RecordingOutput {
+    /// Numbered `frame_000000.png` files in the directory, which is created if missing
+    PngSequence(PathBuf),
+    /// Numbered `frame_000000.rgba` files with the tightly packed RGBA8 pixels, in a directory created if missing
+    RawFrames(PathBuf),
+    /// A YUV4MPEG2 video file with 4:4:4 sampling
+    Y4m(PathBuf),
+    /// Starts the command and writes a YUV4MPEG2 stream to its stdin, like `ffmpeg -i - video.mp4`
+    Pipe(Command),
+}
+
+/// What a [FrameRecorder] captures
+#[derive(Debug)]
+pub struct RecordingSettings {
+    /// The target read back, the color texture needs [COPY_SRC](wgpu::TextureUsages::COPY_SRC) usage like for [Screenshots]
+    pub target: RenderTargetSource,
+    pub output: RecordingOutput,
+    /// Captures the first drawn frame and then every Nth, 1 captures every frame
+    pub every_nth_frame: u32,
+    /// The frame rate written to YUV4MPEG2 headers
+    pub frame_rate: u32,
+}
+
+impl RecordingSettings {
+    /// Captures every frame of the target, with a frame rate of 60
+    pub fn new(target: RenderTargetSource, output: RecordingOutput) -> Self {
+        Self {
+            target,
+            output,
+            every_nth_frame: 1,
+            frame_rate: 60,
+        }
+    }
+}
+
+/// Captures frames of a target with [Screenshots] between [start](Self::start) and [stop](Self::stop), added by [FrameRecorderPlugin].
+/// Frames are counted during [PreDraw], so only drawn frames are captured.
+/// The frames are written on a separate thread as they arrive, a few frames after they were drawn, so writing continues for a moment after stopping.
+/// Only 8-bit RGBA and BGRA targets can be recorded, other frames are skipped.
+/// Dropping it (e.g. on exit) waits until the frames already read back are written.
+#[derive(Resource, Default)]
+pub struct FrameRecorder {
+    recording: Option<Recording>,
+    writers: Vec<Writer>,
+}
+
+/// Shared with the pending screenshots, the writer stops once it is dropped or taken
+type FrameSender = Arc<Mutex<Option<Sender<ReadbackImage>>>>;
+
+struct Recording {
+    target: RenderTargetSource,
+    every_nth_frame: u32,
+    /// drawn frames since starting
+    frame: u64,
+    sender: FrameSender,
+}
+
+struct Writer {
+    thread: JoinHandle<()>,
+    sender: Weak<Mutex<Option<Sender<ReadbackImage>>>>,
+}
+
+impl FrameRecorder {
+    /// Starts recording, stopping the current recording first
+    pub fn start(&mut self, settings: RecordingSettings) {
+        self.stop();
+        let (sender, receiver) = mpsc::channel();
+        let RecordingSettings {
+            target,
+            output,
+            every_nth_frame,
+            frame_rate,
+        } = settings;
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        self.writers.push(Writer {
+            thread: thread::spawn(move || {
+                if let Err(e) = write_frames(receiver, output, frame_rate) {
+                    error!("Frame recording failed: {}", e);
+                }
+            }),
+            sender: Arc::downgrade(&sender),
+        });
+        self.recording = Some(Recording {
+            target,
+            every_nth_frame: every_nth_frame.max(1),
+            frame: 0,
+            sender,
+        });
+    }
+
+    /// Stops capturing frames, the frames already captured are still written
+    pub fn stop(&mut self) {
+        self.recording = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Whether frames of a recording are still being written, which ends shortly after it is stopped
+    pub fn is_writing(&mut self) -> bool {
+        self.writers.retain(|writer| !writer.thread.is_finished());
+        !self.writers.is_empty()
+    }
+}
+
+impl Drop for FrameRecorder {
+    fn drop(&mut self) {
+        self.stop();
+        for writer in self.writers.drain(..) {
+            // screenshots that are never read back would keep the writer waiting
+            if let Some(sender) = writer.sender.upgrade() {
+                sender.lock().unwrap().take();
+            }
+            if writer.thread.join().is_err() {
+                error!("Frame recording writer panicked");
+            }
+        }
+    }
+}
+
+pub struct FrameRecorderPlugin;
+
+impl Plugin for FrameRecorderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameRecorder>();
+        app.add_systems(PreDraw, capture_frames);
+    }
+}
+
+fn capture_frames(mut recorder: ResMut<FrameRecorder>, mut screenshots: ResMut<Screenshots>) {
+    let Some(recording) = &mut recorder.recording else {
+        return;
+    };
+    let frame = recording.frame;
+    recording.frame += 1;
+    if frame % recording.every_nth_frame as u64 != 0 {
+        return;
+    }
+    let sender = recording.sender.clone();
+    screenshots.take(recording.target, move |image| {
+        if let Some(sender) = sender.lock().unwrap().as_ref() {
+            // the writer has stopped after an error
+            let _ = sender.send(image);
+        }
+    });
+}
+
+/// Writes the frames until every sender is dropped, which happens once the recording is stopped and the last frame is read back
+fn write_frames(
+    receiver: Receiver<ReadbackImage>,
+    mut output: RecordingOutput,
+    frame_rate: u32,
+) -> io::Result<()> {
+    let mut stream: Option<(Box<dyn Write>, (u32, u32))> = None;
+    let mut child = None;
+    for (index, image) in receiver.iter().enumerate() {
+        let Some(rgba) = to_rgba8(&image) else {
+            error!("Frames of {:?} targets can not be recorded", image.format);
+            continue;
+        };
+        let (width, height) = image.size;
+        match &mut output {
+            RecordingOutput::PngSequence(directory) => {
+                fs::create_dir_all(&*directory)?;
+                let path = directory.join(format!("frame_{:06}.png", index));
+                image::save_buffer(path, &rgba, width, height, ExtendedColorType::Rgba8)
+                    .map_err(io::Error::other)?;
+            }
+            RecordingOutput::RawFrames(directory) => {
+                fs::create_dir_all(&*directory)?;
+                fs::write(directory.join(format!("frame_{:06}.rgba", index)), &rgba)?;
+            }
+            video => {
+                if stream.is_none() {
+                    let mut writer: Box<dyn Write> = match video {
+                        RecordingOutput::Pipe(command) => {
+                            let mut spawned = command.stdin(Stdio::piped()).spawn()?;
+                            let stdin = spawned.stdin.take().unwrap();
+                            child = Some(spawned);
+                            Box::new(BufWriter::new(stdin))
+                        }
+                        RecordingOutput::Y4m(path) => Box::new(BufWriter::new(File::create(path)?)),
+                        _ => unreachable!(),
+                    };
+                    writeln!(
+                        writer,
+                        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                        width, height, frame_rate
+                    )?;
+                    stream = Some((writer, image.size));
+                }
+                let (writer, size) = stream.as_mut().unwrap();
+                if *size != image.size {
+                    error!("Recorded frames can not change size in a video, skipping a frame");
+                    continue;
+                }
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&rgba_to_yuv444(&rgba))?;
+            }
+        }
+    }
+    if let Some((mut writer, _)) = stream {
+        writer.flush()?;
+    }
+    if let Some(mut child) = child {
+        child.wait()?;
+    }
+    Ok(())
+}
+
+/// The pixels as RGBA8, None for other formats than 8-bit RGBA and BGRA
+fn to_rgba8(image: &ReadbackImage) -> Option<Vec<u8>> {
+    match image.format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(image.data.clone()),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some(
+            image
+                .data
+                .chunks_exact(4)
+                .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Planar limited range BT.601 YUV, ignoring alpha
+fn rgba_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let pixels = rgba.len() / 4;
+    let mut yuv = vec![0; pixels * 3];
+    for (i, p) in rgba.chunks_exact(4).enumerate() {
+        let [r, g, b] = [p[0], p[1], p[2]].map(|c| c as f32 / 255.0);
+        yuv[i] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+        yuv[pixels + i] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+        yuv[2 * pixels + i] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+    }
+    yuv
+}
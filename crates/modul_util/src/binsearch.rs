@@ -0,0 +1,226 @@
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::Range;
+
+/// Integers that can be searched with [binsearch] and [binsearch_greatest]
+pub trait BinsearchInt: Copy + Ord + Debug {
+    /// The value halfway between self and a larger value, rounded down, without overflowing
+    fn halfway(self, high: Self) -> Self;
+    /// The value after self, only called on values smaller than the end of the range
+    fn next(self) -> Self;
+}
+
+macro_rules! impl_binsearch_int {
+    ($($int:ty => $unsigned:ty),*) => {$(
+        impl BinsearchInt for $int {
+            fn halfway(self, high: Self) -> Self {
+                // the difference always fits in the unsigned type, and half of it in the signed one
+                let half = (high as $unsigned).wrapping_sub(self as $unsigned) / 2;
+                self.wrapping_add(half as $int)
+            }
+
+            fn next(self) -> Self {
+                self + 1
+            }
+        }
+    )*};
+}
+
+impl_binsearch_int!(
+    i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize,
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128, usize => usize
+);
+
+/// Returned by [binsearch] and [binsearch_greatest] when no value is found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BinsearchError<E> {
+    /// The range does not contain any values
+    EmptyRange,
+    /// Every value that was tried gave an error, this is the error of the last one
+    AllFailed(E),
+}
+
+impl<E: Error> Error for BinsearchError<E> {}
+
+impl<E: Display> Display for BinsearchError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BinsearchError::EmptyRange => write!(f, "binsearch on empty range"),
+            BinsearchError::AllFailed(e) => write!(f, "no value in the range is ok: {}", e),
+        }
+    }
+}
+
+/// Binary searches the range for the lowest value giving ok, values above an ok value are assumed to be ok as well.
+/// Returns the value with its result, if all values give an error it is the error of the highest value.
+pub fn binsearch<N: BinsearchInt, T, E>(
+    mut f: impl FnMut(N) -> Result<T, E>,
+    range: impl Into<Range<N>>,
+) -> Result<(N, T), BinsearchError<E>> {
+    let Range { start, end } = range.into();
+    if start >= end {
+        return Err(BinsearchError::EmptyRange);
+    }
+    let (mut low, mut high) = (start, end);
+    let mut found = None;
+    let mut error = None;
+    while low < high {
+        let mid = low.halfway(high);
+        match f(mid) {
+            Ok(value) => {
+                found = Some((mid, value));
+                high = mid;
+            }
+            Err(e) => {
+                error = Some(e);
+                low = mid.next();
+            }
+        }
+    }
+    found.ok_or_else(|| BinsearchError::AllFailed(error.unwrap()))
+}
+
+/// Binary searches the range for the highest value giving ok, values below an ok value are assumed to be ok as well.
+/// Useful for finding the largest size that can be allocated.
+/// Returns the value with its result, if all values give an error it is the error of the lowest value.
+pub fn binsearch_greatest<N: BinsearchInt, T, E>(
+    mut f: impl FnMut(N) -> Result<T, E>,
+    range: impl Into<Range<N>>,
+) -> Result<(N, T), BinsearchError<E>> {
+    let Range { start, end } = range.into();
+    if start >= end {
+        return Err(BinsearchError::EmptyRange);
+    }
+    let (mut low, mut high) = (start, end);
+    let mut found = None;
+    let mut error = None;
+    while low < high {
+        let mid = low.halfway(high);
+        match f(mid) {
+            Ok(value) => {
+                found = Some((mid, value));
+                low = mid.next();
+            }
+            Err(e) => {
+                error = Some(e);
+                high = mid;
+            }
+        }
+    }
+    found.ok_or_else(|| BinsearchError::AllFailed(error.unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_least<N: BinsearchInt>(threshold: N) -> impl FnMut(N) -> Result<N, N> {
+        move |n| if n >= threshold { Ok(n) } else { Err(n) }
+    }
+
+    fn at_most<N: BinsearchInt>(threshold: N) -> impl FnMut(N) -> Result<N, N> {
+        move |n| if n <= threshold { Ok(n) } else { Err(n) }
+    }
+
+    #[test]
+    fn empty_range() {
+        assert_eq!(
+            binsearch(at_least(0u32), 5..5),
+            Err(BinsearchError::EmptyRange)
+        );
+        assert_eq!(
+            binsearch(at_least(0i32), Range { start: 5, end: -5 }),
+            Err(BinsearchError::EmptyRange)
+        );
+        assert_eq!(
+            binsearch_greatest(at_most(0u32), 5..5),
+            Err(BinsearchError::EmptyRange)
+        );
+        assert_eq!(
+            binsearch_greatest(at_most(0i32), Range { start: 5, end: -5 }),
+            Err(BinsearchError::EmptyRange)
+        );
+    }
+
+    #[test]
+    fn all_failed() {
+        assert_eq!(
+            binsearch(|n: u32| Err::<(), _>(n), 0..100),
+            Err(BinsearchError::AllFailed(99))
+        );
+        assert_eq!(
+            binsearch(|n: i32| Err::<(), _>(n), -50..50),
+            Err(BinsearchError::AllFailed(49))
+        );
+        assert_eq!(
+            binsearch_greatest(|n: u32| Err::<(), _>(n), 0..100),
+            Err(BinsearchError::AllFailed(0))
+        );
+        assert_eq!(
+            binsearch_greatest(|n: i32| Err::<(), _>(n), -50..50),
+            Err(BinsearchError::AllFailed(-50))
+        );
+    }
+
+    #[test]
+    fn single_element() {
+        assert_eq!(binsearch(at_least(7u8), 7..8), Ok((7, 7)));
+        assert_eq!(binsearch(at_least(-7i8), -7..-6), Ok((-7, -7)));
+        assert_eq!(
+            binsearch(at_least(8u8), 7..8),
+            Err(BinsearchError::AllFailed(7))
+        );
+        assert_eq!(binsearch_greatest(at_most(7u8), 7..8), Ok((7, 7)));
+        assert_eq!(binsearch_greatest(at_most(-7i8), -7..-6), Ok((-7, -7)));
+        assert_eq!(
+            binsearch_greatest(at_most(6u8), 7..8),
+            Err(BinsearchError::AllFailed(7))
+        );
+    }
+
+    #[test]
+    fn finds_threshold() {
+        assert_eq!(binsearch(at_least(37u32), 0..100), Ok((37, 37)));
+        assert_eq!(binsearch(at_least(-13i32), -50..50), Ok((-13, -13)));
+        assert_eq!(binsearch_greatest(at_most(37u32), 0..100), Ok((37, 37)));
+        assert_eq!(binsearch_greatest(at_most(-13i32), -50..50), Ok((-13, -13)));
+    }
+
+    #[test]
+    fn full_range_bounds() {
+        assert_eq!(binsearch(at_least(0u64), 0..u64::MAX), Ok((0, 0)));
+        assert_eq!(
+            binsearch(at_least(u64::MAX - 1), 0..u64::MAX),
+            Ok((u64::MAX - 1, u64::MAX - 1))
+        );
+        assert_eq!(
+            binsearch(at_least(i64::MIN), i64::MIN..i64::MAX),
+            Ok((i64::MIN, i64::MIN))
+        );
+        assert_eq!(
+            binsearch(at_least(i64::MAX - 1), i64::MIN..i64::MAX),
+            Ok((i64::MAX - 1, i64::MAX - 1))
+        );
+        assert_eq!(
+            binsearch_greatest(at_most(u64::MAX - 1), 0..u64::MAX),
+            Ok((u64::MAX - 1, u64::MAX - 1))
+        );
+        assert_eq!(binsearch_greatest(at_most(0u64), 0..u64::MAX), Ok((0, 0)));
+        assert_eq!(
+            binsearch_greatest(at_most(i64::MAX - 1), i64::MIN..i64::MAX),
+            Ok((i64::MAX - 1, i64::MAX - 1))
+        );
+        assert_eq!(
+            binsearch_greatest(at_most(i64::MIN), i64::MIN..i64::MAX),
+            Ok((i64::MIN, i64::MIN))
+        );
+        assert_eq!(
+            binsearch(|n: u64| Err::<(), _>(n), 0..u64::MAX),
+            Err(BinsearchError::AllFailed(u64::MAX - 1))
+        );
+        assert_eq!(
+            binsearch_greatest(|n: i64| Err::<(), _>(n), i64::MIN..i64::MAX),
+            Err(BinsearchError::AllFailed(i64::MIN))
+        );
+    }
+}
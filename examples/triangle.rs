@@ -3,15 +3,16 @@ use bevy_ecs::prelude::*;
 use modul::asset::{AssetId, AssetWorldExt, Assets};
 use modul::core::{run_app, DefaultGraphicsInitializer, Init, MainWindow, RenderContext};
 use modul::render::{
-    ClearNext, GenericFragmentState, GenericMultisampleState, GenericRenderPipelineDescriptor,
-    GenericVertexState, InitialSurfaceConfig, Operation, OperationBuilder, RenderPipelineManager,
-    RenderPlugin, RenderTargetColorConfig, RenderTargetMultisampleConfig, RenderTargetSource,
+    ClearNext, GenericColorTargetState, GenericFragmentState, GenericMultisampleState,
+    GenericRenderPipelineDescriptor, GenericVertexState, InitialSurfaceConfig, Operation,
+    OperationBuilder, OperationError, RenderPipelineManager, RenderPlugin,
+    RenderTargetColorConfig, RenderTargetMultisampleConfig, RenderTargetSource,
     RunningSequenceQueue, Sequence, SequenceBuilder, SequenceQueue, SurfaceRenderTargetConfig,
 };
 use modul::util::ExitPlugin;
 use modul_render::DirectRenderPipelineResourceProvider;
 use wgpu::{
-    BlendState, Color, ColorWrites, CommandEncoder, Device, FrontFace, PipelineLayout,
+    BlendState, Color, CommandEncoder, CompositeAlphaMode, Device, FrontFace, PipelineLayout,
     PipelineLayoutDescriptor, PolygonMode, PowerPreference, PresentMode, PrimitiveState,
     PrimitiveTopology, ShaderModule, ShaderModuleDescriptor, ShaderSource, TextureUsages,
 };
@@ -48,11 +49,14 @@ fn init_color(mut commands: Commands, query: Query<Entity, With<MainWindow>>) {
                 },
                 usages: TextureUsages::RENDER_ATTACHMENT,
                 format_override: None,
+                view_formats: Vec::new(),
             },
             depth_stencil_config: None,
             desired_maximum_frame_latency: 2,
             present_mode: PresentMode::AutoVsync,
             backup_present_mode: None,
+            alpha_mode: CompositeAlphaMode::Auto,
+            label: Some("Main window".to_string()),
         }));
 }
 
@@ -99,8 +103,7 @@ fn init_pipeline(
         },
         fragment: Some(GenericFragmentState {
             entry_point: "fs_main".to_string(),
-            target_blend: Some(BlendState::REPLACE),
-            target_color_writes: ColorWrites::ALL,
+            targets: vec![GenericColorTargetState::new(Some(BlendState::REPLACE))],
         }),
     };
     let id = piplines.add(RenderPipelineManager::new(desc));
@@ -127,7 +130,11 @@ struct TriangleOperation {
 }
 
 impl Operation for TriangleOperation {
-    fn run(&mut self, world: &mut World, command_encoder: &mut CommandEncoder) {
+    fn run(
+        &mut self,
+        world: &mut World,
+        command_encoder: &mut CommandEncoder,
+    ) -> Result<(), OperationError> {
         let id = world.resource::<TrianglePipeline>().0;
         world.asset_scope(id, |world, pipeline_man| {
             let Some(pipeline) = pipeline_man.get_compatible(self.target, world) else {
@@ -142,6 +149,7 @@ impl Operation for TriangleOperation {
             pass.set_pipeline(pipeline);
             pass.draw(0..3, 0..1);
         });
+        Ok(())
     }
 }
 
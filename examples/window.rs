@@ -1,15 +1,7 @@
-use wgpu::PowerPreference;
-use winit::window::WindowAttributes;
-use modul_core::{run_app, DefaultGraphicsInitializer};
-use modul_render::RenderPlugin;
-use modul_util::ExitPlugin;
+use modul::prelude::*;
 
 fn main() {
-    run_app(DefaultGraphicsInitializer {
-        power_preference: PowerPreference::None,
-        window_attribs: WindowAttributes::default(),
-        ..Default::default()
-    }, |app| {
-        app.add_plugins((RenderPlugin, ExitPlugin));
+    run_app(DefaultGraphicsInitializer::default(), |app| {
+        app.add_plugins(ModulDefaultPlugins);
     });
-}
\ No newline at end of file
+}
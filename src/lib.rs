@@ -1,5 +1,37 @@
+/// `use modul::prelude::*;` imports the types most apps use, together with the bevy_ecs prelude
+pub mod prelude;
+
 pub use modul_render as render;
 pub use modul_core as core;
 pub use modul_asset as asset;
 pub use modul_util as util;
-pub use modul_texture as texture;
\ No newline at end of file
+pub use modul_texture as texture;
+pub use modul_sprite as sprite;
+pub use modul_text as text;
+
+use bevy_app::{PluginGroup, PluginGroupBuilder};
+use modul_asset::AssetServerPlugin;
+use modul_render::RenderPlugin;
+use modul_sprite::SpritePlugin;
+use modul_text::TextPlugin;
+use modul_texture::{ImageAssetPlugin, TextureLoadPlugin};
+use modul_util::ExitPlugin;
+
+/// The plugins of all modul crates, in the order they depend on each other:
+/// [RenderPlugin], [AssetServerPlugin], [TextureLoadPlugin], [ImageAssetPlugin], [SpritePlugin], [TextPlugin] and [ExitPlugin].
+/// Window events are handled by [run_app](modul_core::run_app) itself, so there is no plugin for them.
+/// Single plugins can be replaced with [set](PluginGroupBuilder::set) or removed with [disable](PluginGroupBuilder::disable).
+pub struct ModulDefaultPlugins;
+
+impl PluginGroup for ModulDefaultPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(RenderPlugin)
+            .add(AssetServerPlugin::default())
+            .add(TextureLoadPlugin)
+            .add(ImageAssetPlugin)
+            .add(SpritePlugin)
+            .add(TextPlugin)
+            .add(ExitPlugin)
+    }
+}
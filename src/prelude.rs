@@ -0,0 +1,19 @@
+pub use crate::ModulDefaultPlugins;
+pub use bevy_app::{Plugin, PluginGroup, SubApp};
+pub use bevy_ecs::prelude::*;
+pub use modul_asset::{AssetAppExt, AssetId, AssetServer, AssetWorldExt, Assets};
+pub use modul_core::{
+    headless_app, run_app, wgpu, winit, DefaultGraphicsInitializer, HeadlessGraphicsInitializer,
+    Init, MainWindow, Redraw, RenderContext,
+};
+pub use modul_render::glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+pub use modul_render::{
+    Camera, Draw, DrawSet, GlobalTransform, OffscreenRenderTarget, OffscreenRenderTargetConfig,
+    PostDraw, PreDraw, RedrawSet, RenderPlugin, RenderTarget, RenderTargetSource,
+    RunningSequenceQueue, Sequence, SequenceBuilder, SequenceQueue, SurfaceRenderTarget,
+    Synchronize, SynchronizeSet, Transform,
+};
+pub use modul_sprite::{Sprite, SpriteOperation, SpritePlugin};
+pub use modul_text::{Font, Text, TextOperation, TextPlugin};
+pub use modul_texture::{ImageAsset, ImageAssetPlugin, TextureLoadPlugin};
+pub use modul_util::ExitPlugin;